@@ -23,6 +23,22 @@ layout (set = 1, binding = 0) uniform sampler2D position;
 layout (set = 1, binding = 1) uniform sampler2D normal;
 layout (set = 1, binding = 2) uniform sampler2D albedo;
 layout (set = 1, binding = 3) uniform sampler2D metallic_roughness;
+layout (set = 1, binding = 4) uniform sampler2D emissive; // a is the index of refraction
+layout (set = 1, binding = 5) uniform sampler2D sheen; // a is the sheen roughness
+layout (set = 1, binding = 6) uniform sampler2D clearcoat; // r clearcoat, g its roughness, b transmission
+
+// The bits of MaterialLobe in composition_render_system.rs
+const uint LOBE_BASE = 1;
+const uint LOBE_EMISSIVE = 2;
+const uint LOBE_CLEARCOAT = 4;
+const uint LOBE_TRANSMISSION = 8;
+const uint LOBE_SHEEN = 16;
+
+layout(push_constant) uniform Push {
+  uint enabledLobes;
+  uint isolatedLobe; // 0 shows every enabled lobe
+  uint useIor;
+} push;
 
 const float PI = 3.14159265359;
 
@@ -67,32 +83,71 @@ vec3 fresnelSchlick(float cosTheta, vec3 F0)
     return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
 }
 // ----------------------------------------------------------------------------
+// Charlie distribution of KHR_materials_sheen
+float DistributionCharlie(float NdotH, float roughness)
+{
+    float invR = 1.0 / (roughness * roughness);
+    float sin2h = max(1.0 - NdotH * NdotH, 0.0078125);
+    return (2.0 + invR) * pow(sin2h, invR * 0.5) / (2.0 * PI);
+}
+// ----------------------------------------------------------------------------
+// Neubelt visibility, the cheap sheen approximation of the glTF sample viewer
+float VisibilityNeubelt(float NdotL, float NdotV)
+{
+    return clamp(1.0 / (4.0 * (NdotL + NdotV - NdotL * NdotV)), 0.0, 1.0);
+}
+// ----------------------------------------------------------------------------
+bool lobeEnabled(uint lobe)
+{
+    return (push.enabledLobes & lobe) != 0;
+}
+// ----------------------------------------------------------------------------
+bool lobeShown(uint lobe)
+{
+    return lobeEnabled(lobe) && (push.isolatedLobe == 0 || push.isolatedLobe == lobe);
+}
+// ----------------------------------------------------------------------------
 void main()
-{		
-
+{
     vec4 albedo = texture(albedo, inUV);
-    //vec4 albedo = vec4(1.0);
 
     float metallic = texture(metallic_roughness, inUV).r;
     float roughness = texture(metallic_roughness, inUV).g;
+    float occlusion = texture(metallic_roughness, inUV).b;
 
     vec3 position = texture(position, inUV).rgb;
 
     vec3 N = normalize(texture(normal, inUV).rgb);
     vec3 V = normalize(ubo.cameraPos - position);
+    float NdotV = max(dot(N, V), 0.0);
+
+    // Disabled lobes shade as if the material didn't have the extension
+    vec4 emissiveIor = texture(emissive, inUV);
+    vec3 emission = lobeEnabled(LOBE_EMISSIVE) ? emissiveIor.rgb : vec3(0.0);
+    float ior = push.useIor != 0 ? emissiveIor.a : 1.5;
 
-    // calculate reflectance at normal incidence; if dia-electric (like plastic) use F0 
-    // of 0.04 and if it's a metal, use the albedo color as F0 (metallic workflow)    
+    vec4 sheenColorRoughness = texture(sheen, inUV);
+    vec3 sheenColor = lobeEnabled(LOBE_SHEEN) ? sheenColorRoughness.rgb : vec3(0.0);
+    // The Charlie distribution breaks down at zero roughness
+    float sheenRoughness = max(sheenColorRoughness.a, 0.07);
 
-    vec3 fragColor = vec3(1.0);
+    vec4 layers = texture(clearcoat, inUV);
+    float clearcoatFactor = lobeEnabled(LOBE_CLEARCOAT) ? layers.r : 0.0;
+    float clearcoatRoughness = layers.g;
+    float transmission = lobeEnabled(LOBE_TRANSMISSION) ? layers.b : 0.0;
 
-    vec3 F0 = vec3(0.04); 
-    F0 = mix(F0, fragColor, metallic);
+    // Reflectance of dielectrics at normal incidence from the index of refraction, 0.04 for 1.5.
+    // Metals use the albedo color (metallic workflow).
+    float dielectricF0 = pow((ior - 1.0) / (ior + 1.0), 2.0);
+    vec3 F0 = mix(vec3(dielectricF0), albedo.rgb, metallic);
 
     float bias = 1000.0f;
 
-    // reflectance equation
-    vec3 Lo = vec3(0.0);
+    // Outgoing radiance of each lobe
+    vec3 base = vec3(0.0);
+    vec3 transmitted = vec3(0.0);
+    vec3 sheenLight = vec3(0.0);
+    vec3 coat = vec3(0.0);
     for (int i = 0; i < ubo.numLights; i++) {
         PointLight light = ubo.pointLights[i];
         vec3 L = normalize(light.position.xyz - position);
@@ -100,46 +155,69 @@ void main()
         float distance = length(light.position.xyz - position);
         float attenuation = 1.0 / (distance * distance);
         vec3 radiance = light.color.xyz * light.intensity * bias * attenuation;
+        float NdotL = max(dot(N, L), 0.0);
 
         // Cook-Torrance BRDF
-        float NDF = DistributionGGX(N, H, roughness);   
-        float G   = GeometrySmith(N, V, L, roughness);      
+        float NDF = DistributionGGX(N, H, roughness);
+        float G   = GeometrySmith(N, V, L, roughness);
         vec3 F    = fresnelSchlick(clamp(dot(H, V), 0.0, 1.0), F0);
-           
-        vec3 numerator    = NDF * G * F; 
-        float denominator = 4.0 * max(dot(N, V), 0.0) * max(dot(N, L), 0.0) + 0.0001; // + 0.0001 to prevent divide by zero
+
+        vec3 numerator    = NDF * G * F;
+        float denominator = 4.0 * NdotV * NdotL + 0.0001; // + 0.0001 to prevent divide by zero
         vec3 specular = numerator / denominator;
-        
-        // kS is equal to Fresnel
-        vec3 kS = F;
-        // for energy conservation, the diffuse and specular light can't
-        // be above 1.0 (unless the surface emits light); to preserve this
-        // relationship the diffuse component (kD) should equal 1.0 - kS.
-        vec3 kD = vec3(1.0) - kS;
-        // multiply kD by the inverse metalness such that only non-metals 
-        // have diffuse lighting, or a linear blend if partly metal (pure metals
-        // have no diffuse light).
-        kD *= 1.0 - metallic;	  
-
-        // scale light by NdotL
-        float NdotL = max(dot(N, L), 0.0);        
-
-        // add to outgoing radiance Lo
-        Lo += (kD * fragColor / PI + specular) * radiance * NdotL;  // note that we already multiplied the BRDF by the Fresnel (kS) so we won't multiply by kS again
+
+        // Light that isn't reflected is diffused, unless the surface is a metal or lets it through
+        vec3 kD = (vec3(1.0) - F) * (1.0 - metallic) * (1.0 - transmission);
+        base += (kD * albedo.rgb / PI + specular) * radiance * NdotL;
+
+        // Thin walled transmission: light from behind is mirrored to the front and goes through
+        // a rough specular lobe tinted by the base color
+        vec3 mirroredL = normalize(L + 2.0 * N * dot(-L, N));
+        vec3 mirroredH = normalize(V + mirroredL);
+        float mirroredNdotL = max(dot(N, mirroredL), 0.0);
+        float transmittedNDF = DistributionGGX(N, mirroredH, roughness);
+        float transmittedG = GeometrySmith(N, V, mirroredL, roughness);
+        vec3 transmittedF = fresnelSchlick(clamp(dot(mirroredH, V), 0.0, 1.0), F0);
+        transmitted += (vec3(1.0) - transmittedF) * albedo.rgb * transmittedNDF * transmittedG
+            / (4.0 * NdotV * mirroredNdotL + 0.0001) * radiance * mirroredNdotL;
+
+        sheenLight += sheenColor * DistributionCharlie(max(dot(N, H), 0.0), sheenRoughness)
+            * VisibilityNeubelt(NdotL, NdotV) * radiance * NdotL;
+
+        // A second, dielectric specular layer on top of everything else
+        float coatNDF = DistributionGGX(N, H, clearcoatRoughness);
+        float coatG = GeometrySmith(N, V, L, clearcoatRoughness);
+        vec3 coatF = fresnelSchlick(clamp(dot(H, V), 0.0, 1.0), vec3(0.04));
+        coat += coatNDF * coatG * coatF / (4.0 * NdotV * NdotL + 0.0001) * radiance * NdotL;
     }
     // ambient lighting (note that the next IBL tutorial will replace 
     // this ambient lighting with environment lighting).
-    vec3 ambient = vec3(0.03) * fragColor;
+    vec3 ambient = vec3(0.03) * albedo.rgb * occlusion;
+
+    // The clearcoat reflects some of the light before it reaches the layers below
+    float coatAttenuation = 1.0 - clearcoatFactor * fresnelSchlick(NdotV, vec3(0.04)).x;
 
-    vec3 color = ambient + Lo;
+    vec3 color = vec3(0.0);
+    if (lobeShown(LOBE_BASE)) {
+        color += (ambient + base) * coatAttenuation;
+    }
+    if (lobeShown(LOBE_TRANSMISSION)) {
+        color += transmitted * transmission * (1.0 - metallic) * coatAttenuation;
+    }
+    if (lobeShown(LOBE_SHEEN)) {
+        color += sheenLight * coatAttenuation;
+    }
+    if (lobeShown(LOBE_CLEARCOAT)) {
+        color += coat * clearcoatFactor;
+    }
+    if (lobeShown(LOBE_EMISSIVE)) {
+        color += emission;
+    }
 
     // HDR tonemapping
     color = color / (color + vec3(1.0));
     // gamma correct
     //color = pow(color, vec3(1.0/2.2)); 
 
-    outColor = albedo * vec4(color, 1.0);
-    //outColor = vec4(albedo.rgb, 1.0);
-    //outColor = vec4(albedo.rgb, 1.0);
-    //outColor = vec4(N.rgb, 1.0);
-}
\ No newline at end of file
+    outColor = vec4(color, albedo.a);
+}
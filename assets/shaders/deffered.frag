@@ -5,11 +5,15 @@ layout(location = 1) in vec3 fragPosWorld;
 layout(location = 2) in vec3 fragNormalWorld;
 layout(location = 3) in vec4 fragTangentWorld;
 layout(location = 4) in vec2 fragUV;
+layout(location = 5) in vec2 fragUV1;
 
 layout (location = 0) out vec4 outPosition;
 layout (location = 1) out vec4 outNormal;
 layout (location = 2) out vec4 outAlbedo;
 layout (location = 3) out vec4 outMetallicRoughness;
+layout (location = 4) out vec4 outEmissive;
+layout (location = 5) out vec4 outSheen;
+layout (location = 6) out vec4 outClearcoat;
 
 struct PointLight {
   vec4 position; // ignore w
@@ -26,16 +30,37 @@ layout(set = 0, binding = 0) uniform GlobalUbo {
   int numLights;
 } ubo;
 
+// KHR_texture_transform of a texture slot and the UV set it samples
+struct TextureTransform {
+  vec2 offset;
+  vec2 scale;
+  float rotation;
+  uint texCoord;
+};
+
 layout(set = 1, binding = 0) uniform sampler2D albedo;
 layout(set = 1, binding = 1) uniform sampler2D metallic_roughness;
 layout(set = 1, binding = 2) uniform sampler2D normal;
 layout(set = 1, binding = 3) uniform sampler2D occlusion;
 layout(set = 1, binding = 4) uniform sampler2D emissive;
 layout(set = 1, binding = 5) uniform PbrUbo {
-  vec3 albedo;
+  vec4 baseColor;
+  vec3 emissive;
   float metallic;
   float roughness;
-  vec3 emissive; // w is intensity
+  float alphaCutoff;
+  float normalScale;
+  float occlusionStrength;
+  uint alphaMode; // 0 opaque, 1 mask, 2 blend, the pipeline defines ALPHA_MASK or ALPHA_BLEND to match
+  uint doubleSided;
+  TextureTransform textureTransforms[5]; // base color, metallic-roughness, normal, occlusion, emissive
+  float emissiveStrength;
+  float clearcoat;
+  float clearcoatRoughness;
+  float transmission;
+  vec3 sheenColor;
+  float sheenRoughness;
+  float ior;
 } pbr;
 
 layout(push_constant) uniform Push {
@@ -43,21 +68,56 @@ layout(push_constant) uniform Push {
   mat4 normalMatrix;
 } push;
 
-void main() {		
-    vec3 N = normalize(fragNormalWorld);
-    vec3 T = normalize(fragTangentWorld.xyz);
-    vec3 B = cross(fragNormalWorld, fragTangentWorld.xyz) * fragTangentWorld.w;
-    mat3 TBN = mat3(T, B, N);
-    N = TBN * normalize(texture(normal, fragUV).xyz * 2.0 - vec3(1.0));
-    //N = normalize(fragNormalWorld);
+// Scaled, then rotated, then offset, as KHR_texture_transform specifies
+vec2 slotUV(uint slot) {
+  TextureTransform transform = pbr.textureTransforms[slot];
+  vec2 uv = (transform.texCoord == 1 ? fragUV1 : fragUV) * transform.scale;
+  float c = cos(transform.rotation);
+  float s = sin(transform.rotation);
+  return mat2(c, -s, s, c) * uv + transform.offset;
+}
+
+void main() {
+  vec4 color = texture(albedo, slotUV(0)) * pbr.baseColor;
 
-  vec4 color = texture(albedo, fragUV);
-  if(color.w < 0.0001) {
+#if defined(ALPHA_MASK)
+  if(color.a < pbr.alphaCutoff) {
+    discard;
+  }
+  color.a = 1.0;
+#elif defined(ALPHA_BLEND)
+  if(color.a < 0.0001) {
     discard;
   }
+#else
+  color.a = 1.0;
+#endif
+
+  // Back faces of double sided materials are lit from their own side
+  float facing = (pbr.doubleSided != 0 && !gl_FrontFacing) ? -1.0 : 1.0;
+
+  vec3 N = normalize(fragNormalWorld) * facing;
+  vec3 T = normalize(fragTangentWorld.xyz);
+  vec3 B = cross(N, T) * fragTangentWorld.w;
+  mat3 TBN = mat3(T, B, N);
+  vec3 tangentNormal = texture(normal, slotUV(2)).xyz * 2.0 - vec3(1.0);
+  N = normalize(TBN * normalize(tangentNormal * vec3(pbr.normalScale, pbr.normalScale, 1.0)));
+
+  // glTF packs roughness into green and metallic into blue, occlusion is in red of its own texture
+  vec4 metallicRoughness = texture(metallic_roughness, slotUV(1));
+  float metallic = metallicRoughness.b * pbr.metallic;
+  float roughness = metallicRoughness.g * pbr.roughness;
+  float occlusion = mix(1.0, texture(occlusion, slotUV(3)).r, pbr.occlusionStrength);
 
   outPosition = vec4(fragPosWorld, 1.0);
   outAlbedo = color;
-  outMetallicRoughness = texture(metallic_roughness, fragUV);
+  // Alpha only matters to the blended variant, which blends albedo and these over what is behind
+  outMetallicRoughness = vec4(metallic, roughness, occlusion, color.a);
   outNormal = vec4(N, 1.0);
+
+  // The KHR_materials extensions, composition.frag shades them
+  vec3 emission = texture(emissive, slotUV(4)).rgb * pbr.emissive * pbr.emissiveStrength;
+  outEmissive = vec4(emission, pbr.ior);
+  outSheen = vec4(pbr.sheenColor, pbr.sheenRoughness);
+  outClearcoat = vec4(pbr.clearcoat, pbr.clearcoatRoughness, pbr.transmission, 1.0);
 }
\ No newline at end of file
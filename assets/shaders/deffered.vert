@@ -5,12 +5,18 @@ layout(location = 1) in vec3 color;
 layout(location = 2) in vec3 normal;
 layout(location = 3) in vec4 tangent;
 layout(location = 4) in vec2 uv;
+layout(location = 7) in vec2 uv1;
+#ifdef SKINNED
+layout(location = 5) in uvec4 joints;
+layout(location = 6) in vec4 weights;
+#endif
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec3 fragPosWorld;
 layout(location = 2) out vec3 fragNormalWorld;
 layout(location = 3) out vec4 fragTangentWorld;
 layout(location = 4) out vec2 fragUV;
+layout(location = 5) out vec2 fragUV1;
 
 struct PointLight {
   vec4 position; // ignore w
@@ -31,12 +37,68 @@ layout(push_constant) uniform Push {
   mat4 normalMatrix;
 } push;
 
+#ifdef SKINNED
+// Joint matrices of the skin, bound at the offset where this draw's skin starts
+layout(set = 2, binding = 0) readonly buffer JointPalette {
+  mat4 jointMatrices[];
+} palette;
+#endif
+
+#ifdef MORPHED
+struct MorphDelta {
+  vec4 position;
+  vec4 normal;
+  vec4 tangent;
+};
+
+// Every target's delta of a vertex next to each other
+layout(set = 1, binding = 6) readonly buffer MorphTargets {
+  MorphDelta deltas[];
+} morph;
+
+// The mesh's target count followed by this draw's weights, bound at the offset where they start
+layout(set = 2, binding = 1) readonly buffer MorphWeights {
+  float weights[];
+} morphWeights;
+#endif
+
 void main() {
-  vec4 positionWorld = push.modelMatrix * vec4(position, 1.0);
+  vec3 morphedPosition = position;
+  vec3 morphedNormal = normal;
+  vec4 morphedTangent = tangent;
+
+#ifdef MORPHED
+  uint targetCount = uint(morphWeights.weights[0]);
+  for (uint i = 0; i < targetCount; i++) {
+    float weight = morphWeights.weights[1 + i];
+    MorphDelta delta = morph.deltas[uint(gl_VertexIndex) * targetCount + i];
+    morphedPosition += weight * delta.position.xyz;
+    morphedNormal += weight * delta.normal.xyz;
+    morphedTangent.xyz += weight * delta.tangent.xyz;
+  }
+#endif
+
+#ifdef SKINNED
+  mat4 skinMatrix =
+    weights.x * palette.jointMatrices[joints.x] +
+    weights.y * palette.jointMatrices[joints.y] +
+    weights.z * palette.jointMatrices[joints.z] +
+    weights.w * palette.jointMatrices[joints.w];
+  mat4 modelMatrix = push.modelMatrix * skinMatrix;
+  // Inverse transpose, the joints can scale non-uniformly as well
+  mat3 normalMatrix = transpose(inverse(mat3(modelMatrix)));
+#else
+  mat4 modelMatrix = push.modelMatrix;
+  mat3 normalMatrix = mat3(push.normalMatrix);
+#endif
+
+  vec4 positionWorld = modelMatrix * vec4(morphedPosition, 1.0);
   gl_Position = ubo.projection * ubo.view * positionWorld;
-  fragNormalWorld = normalize(mat3(push.modelMatrix) * normal);
-  fragTangentWorld = normalize(push.modelMatrix * tangent.xyzw);
+  fragNormalWorld = normalize(normalMatrix * morphedNormal);
+  // A direction, w is the bitangent sign and stays as it is
+  fragTangentWorld = vec4(normalize(mat3(modelMatrix) * morphedTangent.xyz), morphedTangent.w);
   fragPosWorld = positionWorld.xyz;
   fragColor = color;
-  fragUV = uv;   
+  fragUV = uv;
+  fragUV1 = uv1;
 }
\ No newline at end of file
@@ -5,6 +5,7 @@ layout(location = 1) in vec3 fragPosWorld;
 layout(location = 2) in vec3 fragNormalWorld;
 layout(location = 3) in vec4 fragTangentWorld;
 layout(location = 4) in vec2 fragUV;
+layout(location = 5) in vec2 fragUV1;
 
 layout (location = 0) out vec4 outColor;
 
@@ -23,16 +24,37 @@ layout(set = 0, binding = 0) uniform GlobalUbo {
   int numLights;
 } ubo;
 
+// KHR_texture_transform of a texture slot and the UV set it samples
+struct TextureTransform {
+  vec2 offset;
+  vec2 scale;
+  float rotation;
+  uint texCoord;
+};
+
 layout (set = 1, binding = 0) uniform sampler2D albedo;
 layout (set = 1, binding = 1) uniform sampler2D metallic_roughness;
 layout (set = 1, binding = 2) uniform sampler2D normal;
 layout (set = 1, binding = 3) uniform sampler2D occlusion;
 layout (set = 1, binding = 4) uniform sampler2D emissive;
 layout(set = 1, binding = 5) uniform PbrUbo {
-  vec3 albedo;
+  vec4 baseColor;
+  vec3 emissive;
   float metallic;
   float roughness;
-  vec3 emissive; // w is intensity
+  float alphaCutoff;
+  float normalScale;
+  float occlusionStrength;
+  uint alphaMode;
+  uint doubleSided;
+  TextureTransform textureTransforms[5]; // base color, metallic-roughness, normal, occlusion, emissive
+  float emissiveStrength;
+  float clearcoat;
+  float clearcoatRoughness;
+  float transmission;
+  vec3 sheenColor;
+  float sheenRoughness;
+  float ior;
 } pbr;
 
 layout(push_constant) uniform Push {
@@ -92,23 +114,32 @@ vec3 fresnelSchlick(float cosTheta, vec3 F0)
     return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
 }
 // ----------------------------------------------------------------------------
+// Scaled, then rotated, then offset, as KHR_texture_transform specifies
+vec2 slotUV(uint slot) {
+  TextureTransform transform = pbr.textureTransforms[slot];
+  vec2 uv = (transform.texCoord == 1 ? fragUV1 : fragUV) * transform.scale;
+  float c = cos(transform.rotation);
+  float s = sin(transform.rotation);
+  return mat2(c, -s, s, c) * uv + transform.offset;
+}
+
 void main()
 {		
 
-    vec4 albedo = texture(albedo, fragUV);
+    vec4 albedo = texture(albedo, slotUV(0));
     if (albedo.w < 0.00001) { 
       discard; 
     }
 
-    float metallic = texture(metallic_roughness, fragUV).r;
-    float roughness = texture(metallic_roughness, fragUV).g;
+    float metallic = texture(metallic_roughness, slotUV(1)).r;
+    float roughness = texture(metallic_roughness, slotUV(1)).g;
 
     vec3 N = normalize(fragNormalWorld);
     vec3 T = normalize(fragTangentWorld.xyz);
     if(fragTangentWorld != vec4(0.0)) {
       vec3 B = cross(fragNormalWorld, fragTangentWorld.xyz) * fragTangentWorld.w;
       mat3 TBN = mat3(T, B, N);
-      N = TBN * normalize(texture(normal, fragUV).xyz * 2.0 - vec3(1.0));
+      N = TBN * normalize(texture(normal, slotUV(2)).xyz * 2.0 - vec3(1.0));
     }
     vec3 V = normalize(ubo.cameraPos - fragPosWorld);
 
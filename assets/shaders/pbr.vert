@@ -5,12 +5,14 @@ layout(location = 1) in vec3 color;
 layout(location = 2) in vec3 normal;
 layout(location = 3) in vec4 tangent;
 layout(location = 4) in vec2 uv;
+layout(location = 7) in vec2 uv1;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec3 fragPosWorld;
 layout(location = 2) out vec3 fragNormalWorld;
 layout(location = 3) out vec4 fragTangentWorld;
 layout(location = 4) out vec2 fragUV;
+layout(location = 5) out vec2 fragUV1;
 
 struct PointLight {
   vec4 position; // ignore w
@@ -38,5 +40,6 @@ void main() {
   fragTangentWorld = normalize(push.modelMatrix * tangent.xyzw);
   fragPosWorld = positionWorld.xyz;
   fragColor = color;
-  fragUV = uv;   
+  fragUV = uv;
+  fragUV1 = uv1;
 }
\ No newline at end of file
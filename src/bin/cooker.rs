@@ -0,0 +1,32 @@
+use little_vulkan_engine::first_app::ecs::cooked_model::*;
+
+use std::path::Path;
+
+/// Converts a glTF or OBJ model into the engine's cooked format, which `Model::new` maps and
+/// uploads without parsing or decoding anything
+fn main() {
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <input.gltf|input.glb|input.obj> [output.{}]", args[0], COOKED_EXTENSION);
+        std::process::exit(2);
+    }
+
+    let input = &args[1];
+    let output = match args.get(2) {
+        Some(output) => output.clone(),
+        None => Path::new(input).with_extension(COOKED_EXTENSION).to_string_lossy().into_owned(),
+    };
+
+    match cook(input, &output) {
+        Ok(stats) => println!(
+            "Cooked {} primitives and {} images into {} ({} bytes)",
+            stats.primitives, stats.images, output, stats.bytes
+        ),
+        Err(e) => {
+            log::error!("Unable to cook {}: {}", input, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,681 @@
+use super::model::*;
+
+use nalgebra as na;
+
+/// Skins and animation clips of a model, both empty for models without any
+#[derive(Clone, Default, Debug)]
+pub struct ModelRig {
+    pub skins: Vec<ModelSkin>,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl ModelRig {
+    pub fn read(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Self {
+        Self {
+            skins: read_skins(document, buffers),
+            clips: read_animations(document, buffers),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.skins.is_empty() && self.clips.is_empty()
+    }
+}
+
+/// A glTF skin, `joints` are node indices
+#[derive(Clone, Debug)]
+pub struct ModelSkin {
+    pub name: Option<String>,
+    pub joints: Vec<usize>,
+    /// One per joint, identities if the file has none
+    pub inverse_bind_matrices: Vec<na::Matrix4<f32>>,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Linear = 0,
+    Step = 1,
+    CubicSpline = 2,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 3] = [Interpolation::Linear, Interpolation::Step, Interpolation::CubicSpline];
+
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|&interpolation| interpolation as u32 == raw)
+    }
+}
+
+/// What a channel animates, rotations are `[x, y, z, w]` quaternions
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AnimationProperty {
+    Translation = 0,
+    Rotation = 1,
+    Scale = 2,
+    /// Morph target weights, as many components as the mesh has targets
+    Weights = 3,
+}
+
+impl AnimationProperty {
+    pub const ALL: [AnimationProperty; 4] = [
+        AnimationProperty::Translation,
+        AnimationProperty::Rotation,
+        AnimationProperty::Scale,
+        AnimationProperty::Weights,
+    ];
+
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|&property| property as u32 == raw)
+    }
+}
+
+/// Keyframes of one property of one node
+#[derive(Clone, Debug)]
+pub struct AnimationChannel {
+    pub node: usize,
+    pub property: AnimationProperty,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, ascending
+    pub times: Vec<f32>,
+    /// `components` values per keyframe, cubic splines store in-tangent, value and out-tangent of each
+    pub values: Vec<f32>,
+    pub components: usize,
+}
+
+impl AnimationChannel {
+    /// The value at `time` into `out`, which holds `components` values. Times outside the
+    /// keyframes are clamped to the first and last one.
+    pub fn sample(&self, time: f32, out: &mut [f32]) {
+        let components = self.components;
+        let count = self.times.len();
+        if count == 0 || components == 0 {
+            return;
+        }
+
+        let cubic = self.interpolation == Interpolation::CubicSpline;
+        let value = |key: usize| {
+            let start = if cubic { (3 * key + 1) * components } else { key * components };
+            &self.values[start..start + components]
+        };
+
+        let next = self.times.partition_point(|&key_time| key_time <= time);
+        if next == 0 || next == count {
+            out.copy_from_slice(value(next.min(count - 1)));
+            return;
+        }
+
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = if delta > 0.0 { (time - self.times[previous]) / delta } else { 0.0 };
+        let (from, to) = (value(previous), value(next));
+
+        match self.interpolation {
+            Interpolation::Step => out.copy_from_slice(from),
+            Interpolation::Linear if self.property == AnimationProperty::Rotation => {
+                let rotation = slerp(quaternion(from), quaternion(to), t);
+                out.copy_from_slice(rotation.coords.as_slice());
+            }
+            Interpolation::Linear => {
+                for (i, out) in out.iter_mut().enumerate() {
+                    *out = from[i] + (to[i] - from[i]) * t;
+                }
+            }
+            Interpolation::CubicSpline => {
+                let out_tangent = &self.values[(3 * previous + 2) * components..][..components];
+                let in_tangent = &self.values[3 * next * components..][..components];
+
+                let (t2, t3) = (t * t, t * t * t);
+                for (i, out) in out.iter_mut().enumerate() {
+                    *out = (2.0 * t3 - 3.0 * t2 + 1.0) * from[i]
+                        + (t3 - 2.0 * t2 + t) * delta * out_tangent[i]
+                        + (-2.0 * t3 + 3.0 * t2) * to[i]
+                        + (t3 - t2) * delta * in_tangent[i];
+                }
+
+                if self.property == AnimationProperty::Rotation {
+                    let rotation = na::UnitQuaternion::new_normalize(quaternion(out)).into_inner();
+                    out.copy_from_slice(rotation.coords.as_slice());
+                }
+            }
+        }
+    }
+}
+
+/// Whole channels, a node without one keeps its transform from the file
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+    /// Time of the last keyframe of any channel
+    pub duration: f32,
+}
+
+/// A node transform split up the way glTF animates it
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NodeTransform {
+    pub translation: na::Vector3<f32>,
+    pub rotation: na::UnitQuaternion<f32>,
+    pub scale: na::Vector3<f32>,
+}
+
+impl NodeTransform {
+    /// Only exact for matrices without shear, which glTF requires of animated nodes
+    pub fn from_matrix(matrix: &na::Matrix4<f32>) -> Self {
+        let translation = matrix.fixed_slice::<3, 1>(0, 3).into_owned();
+        let mut basis = matrix.fixed_slice::<3, 3>(0, 0).into_owned();
+
+        let mut scale = na::vector![basis.column(0).norm(), basis.column(1).norm(), basis.column(2).norm()];
+        if basis.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        for (i, &axis_scale) in scale.iter().enumerate() {
+            if axis_scale.abs() > f32::EPSILON {
+                basis.column_mut(i).unscale_mut(axis_scale);
+            }
+        }
+
+        let rotation = na::UnitQuaternion::from_rotation_matrix(&na::Rotation3::from_matrix_unchecked(basis));
+
+        Self { translation, rotation, scale }
+    }
+
+    pub fn matrix(&self) -> na::Matrix4<f32> {
+        na::Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * na::Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+}
+
+/// Node transforms of one instance of a model, so entities sharing a model animate independently
+#[derive(Clone, Debug)]
+pub struct ModelPose {
+    /// The transforms from the file
+    rest: Vec<NodeTransform>,
+    /// The weights nodes go back to on `reset`, from the file until they are edited
+    pub rest_weights: Vec<Vec<f32>>,
+    pub transforms: Vec<NodeTransform>,
+    /// Nodes a channel has touched use `transforms`, the rest keep their exact matrix from the file
+    animated: Vec<bool>,
+    pub world_transforms: Vec<na::Matrix4<f32>>,
+    /// For every skin, the matrices that take a vertex from bind pose to its place in the model
+    pub joint_matrices: Vec<Vec<na::Matrix4<f32>>>,
+    /// Morph target weights of every node, empty for nodes without a morphed mesh
+    pub weights: Vec<Vec<f32>>,
+}
+
+impl ModelPose {
+    /// The rest pose, with world transforms and joint matrices already computed
+    pub fn new(nodes: &[ModelNode], root_nodes: &[usize], skins: &[ModelSkin]) -> Self {
+        let rest = nodes.iter().map(|node| NodeTransform::from_matrix(&node.local_transform)).collect::<Vec<_>>();
+
+        let mut pose = Self {
+            transforms: rest.clone(),
+            rest,
+            animated: vec![false; nodes.len()],
+            world_transforms: vec![na::Matrix4::identity(); nodes.len()],
+            joint_matrices: Vec::new(),
+            rest_weights: nodes.iter().map(|node| node.weights.clone()).collect(),
+            weights: nodes.iter().map(|node| node.weights.clone()).collect(),
+        };
+        pose.update(nodes, root_nodes, skins);
+
+        pose
+    }
+
+    /// Back to the transforms from the file and the rest weights, `update` has to run before the pose is drawn again
+    pub fn reset(&mut self) {
+        self.transforms.copy_from_slice(&self.rest);
+        self.animated.iter_mut().for_each(|animated| *animated = false);
+        self.weights.clone_from(&self.rest_weights);
+    }
+
+    /// Sets every node the clip animates to its value at `time`
+    pub fn apply(&mut self, clip: &AnimationClip, time: f32) {
+        let mut value = [0.0f32; 4];
+
+        for channel in clip.channels.iter() {
+            // Weights channels have a component per target of the node's mesh
+            if channel.property == AnimationProperty::Weights {
+                if let Some(weights) = self.weights.get_mut(channel.node).filter(|weights| weights.len() == channel.components) {
+                    channel.sample(time, weights);
+                }
+                continue;
+            }
+
+            let transform = match self.transforms.get_mut(channel.node) {
+                Some(transform) => transform,
+                None => continue,
+            };
+
+            match channel.property {
+                AnimationProperty::Translation if channel.components == 3 => {
+                    channel.sample(time, &mut value[..3]);
+                    transform.translation = na::Vector3::from_column_slice(&value[..3]);
+                }
+                AnimationProperty::Rotation if channel.components == 4 => {
+                    channel.sample(time, &mut value);
+                    transform.rotation = na::UnitQuaternion::new_normalize(quaternion(&value));
+                }
+                AnimationProperty::Scale if channel.components == 3 => {
+                    channel.sample(time, &mut value[..3]);
+                    transform.scale = na::Vector3::from_column_slice(&value[..3]);
+                }
+                _ => continue,
+            }
+
+            self.animated[channel.node] = true;
+        }
+    }
+
+    /// Moves every node either pose animates `factor` of the way towards `other`, a pose of the same model
+    pub fn blend(&mut self, other: &ModelPose, factor: f32) {
+        for (index, to) in other.transforms.iter().enumerate() {
+            if !self.animated[index] && !other.animated[index] {
+                continue;
+            }
+
+            let from = &mut self.transforms[index];
+            from.translation = from.translation.lerp(&to.translation, factor);
+            from.rotation = na::UnitQuaternion::new_normalize(slerp(from.rotation.into_inner(), to.rotation.into_inner(), factor));
+            from.scale = from.scale.lerp(&to.scale, factor);
+            self.animated[index] = true;
+        }
+
+        for (weights, to) in self.weights.iter_mut().zip(other.weights.iter()) {
+            for (weight, to) in weights.iter_mut().zip(to.iter()) {
+                *weight += (to - *weight) * factor;
+            }
+        }
+    }
+
+    /// Recomputes world transforms and joint matrices from the node transforms
+    pub fn update(&mut self, nodes: &[ModelNode], root_nodes: &[usize], skins: &[ModelSkin]) {
+        let mut stack: Vec<(usize, na::Matrix4<f32>)> = root_nodes
+            .iter()
+            .map(|&root| (root, na::Matrix4::identity()))
+            .collect();
+
+        while let Some((index, parent_transform)) = stack.pop() {
+            let local_transform = if self.animated[index] { self.transforms[index].matrix() } else { nodes[index].local_transform };
+            let world_transform = parent_transform * local_transform;
+            self.world_transforms[index] = world_transform;
+
+            stack.extend(nodes[index].children.iter().map(|&child| (child, world_transform)));
+        }
+
+        self.joint_matrices = skins
+            .iter()
+            .map(|skin| {
+                skin.joints
+                    .iter()
+                    .zip(skin.inverse_bind_matrices.iter())
+                    .map(|(&joint, inverse_bind_matrix)| self.world_transforms[joint] * inverse_bind_matrix)
+                    .collect()
+            })
+            .collect();
+    }
+}
+
+/// The clip a player switched away from, it keeps playing while it fades out
+#[derive(Clone, Copy, Debug)]
+struct FadingClip {
+    clip: usize,
+    time: f32,
+    /// Seconds since the switch
+    elapsed: f32,
+}
+
+/// Plays the clips of an entity's model on its pose, one at a time with a crossfade between them
+#[derive(Clone, Debug)]
+pub struct AnimationPlayerComponent {
+    /// Index into the model's clips
+    pub clip: usize,
+    /// Seconds into the clip
+    pub time: f32,
+    /// Negative plays the clip backwards
+    pub speed: f32,
+    pub playing: bool,
+    /// Wraps around at the end of the clip, otherwise playback stops there
+    pub looping: bool,
+    /// Seconds `play` fades from the previous clip to the new one, 0 switches at once
+    pub blend_duration: f32,
+    fading: Option<FadingClip>,
+}
+
+impl Default for AnimationPlayerComponent {
+    /// Loops the first clip
+    fn default() -> Self {
+        Self {
+            clip: 0,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: true,
+            blend_duration: 0.25,
+            fading: None,
+        }
+    }
+}
+
+impl AnimationPlayerComponent {
+    /// Starts `clip` from the beginning, fading out the current one if it is a different clip
+    pub fn play(&mut self, clip: usize) {
+        self.fading = (clip != self.clip && self.blend_duration > 0.0).then_some(FadingClip { clip: self.clip, time: self.time, elapsed: 0.0 });
+        self.clip = clip;
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Continues where playback was paused, a clip that ran to its end starts over
+    pub fn resume(&mut self, clips: &[AnimationClip]) {
+        let duration = clips.get(self.clip).map_or(0.0, |clip| clip.duration);
+        let finished = if self.speed >= 0.0 { self.time >= duration } else { self.time <= 0.0 };
+        if !self.looping && finished {
+            self.time = if self.speed >= 0.0 { 0.0 } else { duration };
+        }
+
+        self.playing = true;
+    }
+
+    /// Jumps to `time` in the current clip, ending any crossfade
+    pub fn scrub(&mut self, time: f32) {
+        self.time = time;
+        self.fading = None;
+    }
+
+    /// Where playback of a clip of `duration` seconds is `elapsed` seconds after `time`, and
+    /// whether it ran into an end
+    fn step(&self, time: f32, elapsed: f32, duration: f32) -> (f32, bool) {
+        let time = time + elapsed * self.speed;
+        if duration <= 0.0 {
+            (0.0, false)
+        } else if self.looping {
+            (time.rem_euclid(duration), false)
+        } else {
+            (time.clamp(0.0, duration), !(0.0..duration).contains(&time))
+        }
+    }
+
+    /// Moves playback `frame_time` seconds ahead, a clip that doesn't loop pauses at its end
+    pub fn advance(&mut self, frame_time: f32, clips: &[AnimationClip]) {
+        let duration = |clip: usize| clips.get(clip).map_or(0.0, |clip| clip.duration);
+
+        if let Some(mut fade) = self.fading {
+            fade.elapsed += frame_time;
+            fade.time = self.step(fade.time, frame_time, duration(fade.clip)).0;
+            self.fading = (fade.elapsed < self.blend_duration).then_some(fade);
+        }
+
+        if self.playing {
+            let (time, finished) = self.step(self.time, frame_time, duration(self.clip));
+            self.time = time;
+            self.playing = !finished;
+        }
+    }
+
+    /// Poses `pose`, which should be at rest, with the current clip, blended with the one fading out
+    pub fn apply(&self, pose: &mut ModelPose, clips: &[AnimationClip]) {
+        let clip = match clips.get(self.clip) {
+            Some(clip) => clip,
+            None => return,
+        };
+
+        match self.fading.and_then(|fade| Some((fade, clips.get(fade.clip)?))) {
+            Some((fade, previous)) if self.blend_duration > 0.0 => {
+                let mut target = pose.clone();
+                target.apply(clip, self.time);
+                pose.apply(previous, fade.time);
+                pose.blend(&target, (fade.elapsed / self.blend_duration).clamp(0.0, 1.0));
+            }
+            _ => pose.apply(clip, self.time),
+        }
+    }
+}
+
+/// `[x, y, z, w]` as glTF stores it
+fn quaternion(value: &[f32]) -> na::Quaternion<f32> {
+    na::Quaternion::new(value[3], value[0], value[1], value[2])
+}
+
+/// Along the shorter arc, falls back to a normalized lerp when the two are almost the same
+fn slerp(from: na::Quaternion<f32>, to: na::Quaternion<f32>, t: f32) -> na::Quaternion<f32> {
+    let from = na::UnitQuaternion::new_normalize(from);
+    let mut to = na::UnitQuaternion::new_normalize(to);
+    if from.coords.dot(&to.coords) < 0.0 {
+        to = na::UnitQuaternion::new_unchecked(-to.into_inner());
+    }
+
+    from.try_slerp(&to, t, 1.0e-6).unwrap_or_else(|| from.nlerp(&to, t)).into_inner()
+}
+
+pub fn read_skins(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<ModelSkin> {
+    document.skins().map(|skin| {
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        let joints = skin.joints().map(|joint| joint.index()).collect::<Vec<_>>();
+
+        let mut inverse_bind_matrices = reader
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(na::Matrix4::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        inverse_bind_matrices.resize(joints.len(), na::Matrix4::identity());
+
+        ModelSkin {
+            name: skin.name().map(str::to_string),
+            joints,
+            inverse_bind_matrices,
+        }
+    }).collect()
+}
+
+/// Channels without keyframes or with a target that has no node are left out
+pub fn read_animations(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Vec<AnimationClip> {
+    use gltf::animation::util::ReadOutputs;
+
+    document.animations().map(|animation| {
+        let channels = animation.channels().filter_map(|channel| {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times = reader.read_inputs()?.collect::<Vec<_>>();
+
+            let (property, values): (_, Vec<f32>) = match reader.read_outputs()? {
+                ReadOutputs::Translations(values) => (AnimationProperty::Translation, values.flatten().collect()),
+                ReadOutputs::Rotations(values) => (AnimationProperty::Rotation, values.into_f32().flatten().collect()),
+                ReadOutputs::Scales(values) => (AnimationProperty::Scale, values.flatten().collect()),
+                ReadOutputs::MorphTargetWeights(values) => (AnimationProperty::Weights, values.into_f32().collect()),
+            };
+
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+
+            let keys = if interpolation == Interpolation::CubicSpline { times.len() * 3 } else { times.len() };
+            if keys == 0 || values.len() % keys != 0 {
+                return None;
+            }
+
+            Some(AnimationChannel {
+                node: channel.target().node().index(),
+                property,
+                interpolation,
+                components: values.len() / keys,
+                times,
+                values,
+            })
+        }).collect::<Vec<_>>();
+
+        let duration = channels.iter().filter_map(|channel| channel.times.last()).fold(0.0, |a: f32, &b| a.max(b));
+
+        AnimationClip { name: animation.name().map(str::to_string), channels, duration }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(property: AnimationProperty, interpolation: Interpolation, times: Vec<f32>, values: Vec<f32>, components: usize) -> AnimationChannel {
+        AnimationChannel { node: 0, property, interpolation, times, values, components }
+    }
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1.0e-4)
+    }
+
+    #[test]
+    fn linear_interpolates_and_clamps() {
+        let channel = channel(AnimationProperty::Translation, Interpolation::Linear, vec![1.0, 3.0], vec![0.0, 0.0, 0.0, 4.0, 2.0, -2.0], 3);
+        let mut out = [0.0; 3];
+
+        channel.sample(2.0, &mut out);
+        assert!(close(&out, &[2.0, 1.0, -1.0]));
+
+        channel.sample(0.0, &mut out);
+        assert!(close(&out, &[0.0, 0.0, 0.0]));
+
+        channel.sample(5.0, &mut out);
+        assert!(close(&out, &[4.0, 2.0, -2.0]));
+    }
+
+    #[test]
+    fn step_holds_the_previous_key() {
+        let channel = channel(AnimationProperty::Scale, Interpolation::Step, vec![0.0, 1.0], vec![1.0, 1.0, 1.0, 2.0, 2.0, 2.0], 3);
+        let mut out = [0.0; 3];
+
+        channel.sample(0.99, &mut out);
+        assert!(close(&out, &[1.0, 1.0, 1.0]));
+
+        channel.sample(1.0, &mut out);
+        assert!(close(&out, &[2.0, 2.0, 2.0]));
+    }
+
+    #[test]
+    fn cubic_spline_hits_keys_and_follows_tangents() {
+        // One component, value 0 then 1, zero tangents give a smoothstep
+        let values = vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let channel = channel(AnimationProperty::Translation, Interpolation::CubicSpline, vec![0.0, 2.0], values, 1);
+        let mut out = [0.0; 1];
+
+        channel.sample(0.0, &mut out);
+        assert!(close(&out, &[0.0]));
+
+        channel.sample(1.0, &mut out);
+        assert!(close(&out, &[0.5]));
+
+        channel.sample(0.5, &mut out);
+        assert!(close(&out, &[0.15625]));
+    }
+
+    #[test]
+    fn rotations_slerp_along_the_short_arc() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        // Identity, then 90 degrees around y stored with a negated sign
+        let values = vec![0.0, 0.0, 0.0, 1.0, 0.0, -half, 0.0, -half];
+        let channel = channel(AnimationProperty::Rotation, Interpolation::Linear, vec![0.0, 1.0], values, 4);
+        let mut out = [0.0; 4];
+
+        channel.sample(0.5, &mut out);
+        let rotation = na::UnitQuaternion::new_normalize(quaternion(&out));
+        assert!((rotation.angle() - std::f32::consts::FRAC_PI_4).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn weight_channels_drive_node_weights() {
+        let node = ModelNode { sub_meshes: vec![0], weights: vec![0.0, 0.0], ..single_node() };
+        let clip = AnimationClip {
+            name: None,
+            channels: vec![channel(AnimationProperty::Weights, Interpolation::Linear, vec![0.0, 1.0], vec![0.0, 1.0, 1.0, 0.0], 2)],
+            duration: 1.0,
+        };
+
+        let mut pose = ModelPose::new(&[node], &[0], &[]);
+        pose.apply(&clip, 0.25);
+        assert!(close(&pose.weights[0], &[0.25, 0.75]));
+
+        pose.reset();
+        assert!(close(&pose.weights[0], &[0.0, 0.0]));
+    }
+
+    fn translation_clip(to: f32) -> AnimationClip {
+        AnimationClip {
+            name: None,
+            channels: vec![channel(AnimationProperty::Translation, Interpolation::Linear, vec![0.0, 2.0], vec![0.0, 0.0, 0.0, to, 0.0, 0.0], 3)],
+            duration: 2.0,
+        }
+    }
+
+    fn single_node() -> ModelNode {
+        ModelNode {
+            name: None,
+            parent: None,
+            children: Vec::new(),
+            local_transform: na::Matrix4::identity(),
+            world_transform: na::Matrix4::identity(),
+            sub_meshes: Vec::new(),
+            skin: None,
+            weights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn player_loops_or_stops_at_the_end() {
+        let clips = [translation_clip(4.0)];
+        let mut player = AnimationPlayerComponent::default();
+
+        player.advance(2.5, &clips);
+        assert!(player.playing);
+        assert!((player.time - 0.5).abs() < 1.0e-5);
+
+        player.looping = false;
+        player.advance(2.0, &clips);
+        assert!(!player.playing);
+        assert_eq!(player.time, 2.0);
+
+        player.resume(&clips);
+        assert!(player.playing);
+        assert_eq!(player.time, 0.0);
+
+        player.speed = -1.0;
+        player.advance(1.0, &clips);
+        assert!(!player.playing);
+        assert_eq!(player.time, 0.0);
+    }
+
+    #[test]
+    fn player_crossfades_between_clips() {
+        let clips = [translation_clip(4.0), translation_clip(-4.0)];
+        let mut pose = ModelPose::new(&[single_node()], &[0], &[]);
+        let mut player = AnimationPlayerComponent { blend_duration: 1.0, ..Default::default() };
+
+        player.advance(1.0, &clips);
+        player.play(1);
+        player.advance(0.5, &clips);
+        player.apply(&mut pose, &clips);
+
+        // Halfway from 3 in the first clip to -1 in the second
+        assert!((pose.transforms[0].translation.x - 1.0).abs() < 1.0e-5);
+
+        player.advance(0.5, &clips);
+        pose.reset();
+        player.apply(&mut pose, &clips);
+        assert!((pose.transforms[0].translation.x + 2.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn node_transform_round_trips() {
+        let transform = NodeTransform {
+            translation: na::vector![1.0, 2.0, 3.0],
+            rotation: na::UnitQuaternion::from_euler_angles(0.3, -0.7, 1.1),
+            scale: na::vector![2.0, 0.5, 1.5],
+        };
+
+        let decomposed = NodeTransform::from_matrix(&transform.matrix());
+        assert!((decomposed.matrix() - transform.matrix()).abs().max() < 1.0e-5);
+    }
+}
@@ -0,0 +1,175 @@
+use super::mesh::*;
+use super::model::*;
+
+use crate::first_app::vulkan::lve_descriptor_set::*;
+use crate::first_app::vulkan::lve_device::*;
+use crate::first_app::vulkan::lve_error::*;
+use crate::first_app::vulkan::lve_image::*;
+use crate::first_app::vulkan::lve_sampler::*;
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+/// Files are keyed by their path, images that only exist in memory (embedded in a GLB or a data
+/// URI) by a hash of their pixels
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AssetKey {
+    Path(String),
+    Hash(u64),
+}
+
+impl AssetKey {
+    pub fn path(path: &str) -> Self {
+        // "./a/../a/b.png" and "a/b.png" are the same file
+        let path = std::fs::canonicalize(path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| path.to_string());
+
+        Self::Path(path)
+    }
+
+    pub fn hash(image: &image::RgbaImage) -> Self {
+        let mut hasher = DefaultHasher::new();
+        image.dimensions().hash(&mut hasher);
+        image.as_raw().hash(&mut hasher);
+
+        Self::Hash(hasher.finish())
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct AssetStats {
+    pub images: usize,
+    pub meshes: usize,
+    pub models: usize,
+    /// Strong references held to all loaded assets, by models, meshes and the app
+    pub references: usize,
+}
+
+/// Hands out shared handles to GPU assets so the same file or image is only uploaded once.
+/// Only weak references are kept here, an asset is unloaded as soon as its last handle is dropped.
+pub struct AssetManager {
+    lve_device: Rc<LveDevice>,
+    global_pool: Rc<LveDescriptorPool>,
+    images: RefCell<HashMap<(AssetKey, ash::vk::Format), Weak<LveImage>>>,
+    samplers: RefCell<HashMap<SamplerSettings, Weak<LveSampler>>>,
+    meshes: RefCell<HashMap<String, Weak<Mesh>>>,
+    models: RefCell<HashMap<AssetKey, Weak<Model>>>,
+}
+
+impl AssetManager {
+    pub fn new(lve_device: Rc<LveDevice>, global_pool: Rc<LveDescriptorPool>) -> Self {
+        Self {
+            lve_device,
+            global_pool,
+            images: RefCell::new(HashMap::new()),
+            samplers: RefCell::new(HashMap::new()),
+            meshes: RefCell::new(HashMap::new()),
+            models: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn lve_device(&self) -> &Rc<LveDevice> {
+        &self.lve_device
+    }
+
+    pub fn global_pool(&self) -> &Rc<LveDescriptorPool> {
+        &self.global_pool
+    }
+
+    pub fn load_image(&self, path: &str, role: TextureRole) -> LveResult<Rc<LveImage>> {
+        Self::get_or_insert(&self.images, (AssetKey::path(path), role.format()), || {
+            LveImage::new(self.lve_device.clone(), path, role)
+        })
+    }
+
+    pub fn default_image(&self, role: TextureRole) -> LveResult<Rc<LveImage>> {
+        self.load_image(DEFAULT_TEXTURE_PATH, role)
+    }
+
+    /// Uploads an image decoded off the main thread, shared by its key
+    pub fn image_from_decoded(&self, image: &DecodedImage, role: TextureRole) -> LveResult<Rc<LveImage>> {
+        Self::get_or_insert(&self.images, (image.key.clone(), role.format()), || match &image.pixels {
+            DecodedPixels::Rgba8(pixels) => LveImage::from_rgba8(self.lve_device.clone(), pixels.clone(), role.format()),
+            DecodedPixels::TextureFile(texture) => {
+                LveImage::from_texture_file(self.lve_device.clone(), texture, role.format() == ash::vk::Format::R8G8B8A8_SRGB)
+            }
+        })
+    }
+
+    /// For images that aren't a file of their own, `key` names the image inside its file
+    pub fn image(&self, key: AssetKey, role: TextureRole, create: impl FnOnce() -> LveResult<LveImage>) -> LveResult<Rc<LveImage>> {
+        Self::get_or_insert(&self.images, (key, role.format()), create)
+    }
+
+    pub fn sampler(&self, settings: SamplerSettings) -> LveResult<Rc<LveSampler>> {
+        Self::get_or_insert(&self.samplers, settings, || LveSampler::new(self.lve_device.clone(), settings))
+    }
+
+    /// `key` names the mesh inside its file, e.g. `Sponza.gltf#12/0` for primitive 0 of mesh 12
+    pub fn mesh(&self, key: &str, create: impl FnOnce() -> LveResult<Rc<Mesh>>) -> LveResult<Rc<Mesh>> {
+        if let Some(mesh) = self.meshes.borrow().get(key).and_then(Weak::upgrade) {
+            return Ok(mesh);
+        }
+
+        let mesh = create()?;
+        self.meshes.borrow_mut().insert(key.to_string(), Rc::downgrade(&mesh));
+
+        Ok(mesh)
+    }
+
+    #[allow(dead_code)]
+    pub fn load_model(&self, path: &str) -> LveResult<Rc<Model>> {
+        Self::get_or_insert(&self.models, AssetKey::path(path), || Model::new(self, path))
+    }
+
+    /// The model if it is already loaded, nothing is loaded otherwise
+    pub fn loaded_model(&self, path: &str) -> Option<Rc<Model>> {
+        self.models.borrow().get(&AssetKey::path(path)).and_then(Weak::upgrade)
+    }
+
+    /// Registers a model that was loaded elsewhere, e.g. streamed by a `ModelLoadHandle`
+    pub fn insert_model(&self, path: &str, model: &Rc<Model>) {
+        self.models.borrow_mut().insert(AssetKey::path(path), Rc::downgrade(model));
+    }
+
+    /// Forgets assets that have been unloaded and counts the rest
+    pub fn stats(&self) -> AssetStats {
+        self.images.borrow_mut().retain(|_, image| image.strong_count() > 0);
+        self.samplers.borrow_mut().retain(|_, sampler| sampler.strong_count() > 0);
+        self.meshes.borrow_mut().retain(|_, mesh| mesh.strong_count() > 0);
+        self.models.borrow_mut().retain(|_, model| model.strong_count() > 0);
+
+        let images = self.images.borrow();
+        let meshes = self.meshes.borrow();
+        let models = self.models.borrow();
+
+        AssetStats {
+            images: images.len(),
+            meshes: meshes.len(),
+            models: models.len(),
+            references: images.values().map(Weak::strong_count).sum::<usize>()
+                + meshes.values().map(Weak::strong_count).sum::<usize>()
+                + models.values().map(Weak::strong_count).sum::<usize>(),
+        }
+    }
+
+    /// The cache isn't borrowed while `create` runs, loading a model loads its images through here too
+    fn get_or_insert<K: Hash + Eq, T>(
+        cache: &RefCell<HashMap<K, Weak<T>>>,
+        key: K,
+        create: impl FnOnce() -> LveResult<T>,
+    ) -> LveResult<Rc<T>> {
+        if let Some(asset) = cache.borrow().get(&key).and_then(Weak::upgrade) {
+            return Ok(asset);
+        }
+
+        let asset = Rc::new(create()?);
+        cache.borrow_mut().insert(key, Rc::downgrade(&asset));
+
+        Ok(asset)
+    }
+}
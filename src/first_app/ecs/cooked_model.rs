@@ -0,0 +1,789 @@
+use super::animation::*;
+use super::asset_manager::*;
+use super::mesh::*;
+use super::model::*;
+
+use crate::first_app::vulkan::lve_block_encode::*;
+use crate::first_app::vulkan::lve_error::*;
+use crate::first_app::vulkan::lve_image::*;
+use crate::first_app::vulkan::lve_sampler::*;
+use crate::first_app::vulkan::lve_texture_file::*;
+
+use ash::vk;
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use nalgebra as na;
+
+/// Extension of the files the cooker writes, `Model::new` and the model loader pick these up
+pub const COOKED_EXTENSION: &str = "lvemodel";
+
+const MAGIC: [u8; 8] = *b"LVEMODEL";
+/// Bumped whenever the layout changes, older files have to be cooked again
+const VERSION: u32 = 6;
+/// Magic, version, vertex size, metadata offset and length
+const HEADER_SIZE: usize = 32;
+/// Every vertex, index and texture blob starts at a multiple of this
+const ALIGNMENT: usize = 16;
+
+/// A primitive of a cooked model, the ranges are byte ranges of the file
+pub struct CookedPrimitive {
+    pub mesh: usize,
+    pub primitive: usize,
+    pub vertices: Range<usize>,
+    pub indices: Range<usize>,
+    pub index_type: vk::IndexType,
+    /// `morph_target_count` `MorphDelta`s per vertex, empty without morph targets
+    pub morph_targets: Range<usize>,
+    pub morph_target_count: usize,
+    pub material: PrimitiveMaterial,
+}
+
+/// An image of a cooked model, every level packed one after the other in `data`. `format` is the
+/// UNORM variant, the texture role picks sRGB when it is uploaded.
+pub struct CookedImage {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    pub data: Range<usize>,
+}
+
+/// Compressed image data for `CookedModel::write`
+pub struct CookedImageData {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    pub data: Vec<u8>,
+}
+
+/// Sizes reported by `cook`
+#[derive(Clone, Copy, Debug)]
+pub struct CookStats {
+    pub primitives: usize,
+    pub images: usize,
+    pub bytes: usize,
+}
+
+/// A memory mapped cooked model. The file holds a 32 byte header, then the vertex, index and
+/// texture blobs and finally the metadata describing them, all little-endian. Vertices are stored
+/// as `Vertex` is laid out in memory, so they are uploaded without any conversion.
+pub struct CookedModel {
+    path: String,
+    map: Mmap,
+    pub primitives: Vec<CookedPrimitive>,
+    pub images: Vec<CookedImage>,
+    /// World transforms are already computed
+    pub nodes: Vec<ModelNode>,
+    pub root_nodes: Vec<usize>,
+    pub rig: ModelRig,
+}
+
+impl CookedModel {
+    pub fn is_cooked(path: &str) -> bool {
+        Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case(COOKED_EXTENSION))
+    }
+
+    /// Maps the file and reads its metadata, the blobs are only touched once they are uploaded
+    pub fn open(path: &str) -> LveResult<Self> {
+        let io_error = |source| LveError::Io { path: path.to_string(), source };
+        let file = std::fs::File::open(path).map_err(io_error)?;
+        // The file must not be changed while it is mapped, the cooker writes a new one instead
+        let map = unsafe { Mmap::map(&file) }.map_err(io_error)?;
+
+        let error = |reason| LveError::Cooked { path: path.to_string(), reason };
+
+        if map.len() < HEADER_SIZE || map[0..8] != MAGIC {
+            return Err(error("not a cooked model"));
+        }
+
+        // Can't fail, the header was checked to be long enough
+        let mut header = Reader::new(&map[8..HEADER_SIZE]);
+        let (version, vertex_size) = (header.u32().unwrap_or(0), header.u32().unwrap_or(0));
+        let (metadata_offset, metadata_length) = (header.u64().unwrap_or(0) as usize, header.u64().unwrap_or(0) as usize);
+
+        if version != VERSION {
+            return Err(error("written by another version of the cooker, cook it again"));
+        }
+        if vertex_size != size_of::<Vertex>() as u32 {
+            return Err(error("vertex layout doesn't match this build"));
+        }
+
+        let metadata = map
+            .get(metadata_offset..metadata_offset.saturating_add(metadata_length))
+            .ok_or_else(|| error("metadata is truncated"))?;
+
+        let (primitives, images, mut nodes, root_nodes, rig) = Self::read_metadata(&mut Reader::new(metadata), map.len())
+            .ok_or_else(|| error("metadata is truncated or corrupt"))?;
+
+        Model::compute_world_transforms(&mut nodes, &root_nodes);
+
+        Ok(Self { path: path.to_string(), map, primitives, images, nodes, root_nodes, rig })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn read_metadata(reader: &mut Reader, file_length: usize) -> Option<(Vec<CookedPrimitive>, Vec<CookedImage>, Vec<ModelNode>, Vec<usize>, ModelRig)> {
+        let blob = |reader: &mut Reader| {
+            let start = reader.u64()? as usize;
+            let end = start.checked_add(reader.u64()? as usize)?;
+            (end <= file_length).then_some(start..end)
+        };
+
+        let primitive_count = reader.u32()?;
+        let primitives = (0..primitive_count)
+            .map(|_| {
+                let mesh = reader.u32()? as usize;
+                let primitive = reader.u32()? as usize;
+                let vertices = blob(reader)?;
+                let index_type = match reader.u32()? {
+                    2 => vk::IndexType::UINT16,
+                    4 => vk::IndexType::UINT32,
+                    _ => return None,
+                };
+                let indices = blob(reader)?;
+                let morph_targets = blob(reader)?;
+                let morph_target_count = reader.u32()? as usize;
+                let material = reader.material()?;
+
+                let vertex_count = vertices.len() / size_of::<Vertex>();
+                let valid = vertices.len() % size_of::<Vertex>() == 0
+                    && morph_targets.len() == vertex_count * morph_target_count * size_of::<MorphDelta>();
+                valid.then_some(CookedPrimitive { mesh, primitive, vertices, indices, index_type, morph_targets, morph_target_count, material })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let image_count = reader.u32()?;
+        let images = (0..image_count)
+            .map(|_| {
+                Some(CookedImage {
+                    format: vk::Format::from_raw(reader.u32()? as i32),
+                    width: reader.u32()?,
+                    height: reader.u32()?,
+                    mip_levels: reader.u32()?,
+                    data: blob(reader)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let node_count = reader.u32()? as usize;
+        let nodes = (0..node_count)
+            .map(|_| {
+                let name = reader.string()?;
+                let parent = reader.u32()?;
+                let children = reader.indices()?;
+                let mut local_transform = [0.0; 16];
+                for value in local_transform.iter_mut() {
+                    *value = reader.f32()?;
+                }
+                let sub_meshes = reader.indices()?;
+                let skin = reader.u32()?;
+                let weights = reader.floats()?;
+
+                let in_range = children.iter().all(|&child| child < node_count) && sub_meshes.iter().all(|&sub_mesh| sub_mesh < primitives.len());
+                in_range.then(|| ModelNode {
+                    name,
+                    parent: (parent != u32::MAX).then_some(parent as usize),
+                    children,
+                    local_transform: na::Matrix4::from_column_slice(&local_transform),
+                    world_transform: na::Matrix4::identity(),
+                    sub_meshes,
+                    skin: (skin != u32::MAX).then_some(skin as usize),
+                    weights,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let root_nodes = reader.indices()?;
+
+        let skin_count = reader.u32()?;
+        let skins = (0..skin_count)
+            .map(|_| {
+                let name = reader.string()?;
+                let joints = reader.indices()?;
+                let inverse_bind_matrices = reader.floats()?
+                    .chunks_exact(16)
+                    .map(na::Matrix4::from_column_slice)
+                    .collect::<Vec<_>>();
+
+                let valid = inverse_bind_matrices.len() == joints.len() && joints.iter().all(|&joint| joint < nodes.len());
+                valid.then_some(ModelSkin { name, joints, inverse_bind_matrices })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let clip_count = reader.u32()?;
+        let clips = (0..clip_count)
+            .map(|_| {
+                let name = reader.string()?;
+                let channel_count = reader.u32()?;
+                let channels = (0..channel_count)
+                    .map(|_| {
+                        let node = reader.u32()? as usize;
+                        let property = AnimationProperty::from_raw(reader.u32()?)?;
+                        let interpolation = Interpolation::from_raw(reader.u32()?)?;
+                        let components = reader.u32()? as usize;
+                        let times = reader.floats()?;
+                        let values = reader.floats()?;
+
+                        let keys = if interpolation == Interpolation::CubicSpline { times.len() * 3 } else { times.len() };
+                        let valid = node < nodes.len() && values.len() == keys * components;
+                        valid.then_some(AnimationChannel { node, property, interpolation, times, values, components })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                let duration = reader.f32()?;
+
+                Some(AnimationClip { name, channels, duration })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let images_in_range = primitives
+            .iter()
+            .all(|primitive| primitive.material.textures.iter().flatten().all(|&image| image < images.len()));
+        let skins_in_range = nodes.iter().filter_map(|node| node.skin).all(|skin| skin < skins.len());
+
+        (images_in_range && skins_in_range && root_nodes.iter().all(|&root| root < nodes.len()))
+            .then_some((primitives, images, nodes, root_nodes, ModelRig { skins, clips }))
+    }
+
+    pub fn bytes(&self, range: &Range<usize>) -> &[u8] {
+        &self.map[range.clone()]
+    }
+
+    /// Copies the vertices out of the mapping, the blob needn't be aligned for `Vertex`
+    pub fn vertices(&self, primitive: &CookedPrimitive) -> Vec<Vertex> {
+        self.bytes(&primitive.vertices)
+            .chunks_exact(size_of::<Vertex>())
+            .map(|bytes| unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Vertex) })
+            .collect()
+    }
+
+    /// Copies the deltas out of the mapping like `vertices`
+    pub fn morph_targets(&self, primitive: &CookedPrimitive) -> MorphTargets {
+        let deltas = self.bytes(&primitive.morph_targets)
+            .chunks_exact(size_of::<MorphDelta>())
+            .map(|bytes| unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const MorphDelta) })
+            .collect();
+
+        MorphTargets { count: primitive.morph_target_count, deltas }
+    }
+
+    pub fn indices(&self, primitive: &CookedPrimitive) -> Vec<u32> {
+        let bytes = self.bytes(&primitive.indices);
+        if primitive.index_type == vk::IndexType::UINT16 {
+            bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]]) as u32).collect()
+        } else {
+            bytes.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+        }
+    }
+
+    /// Images are shared by file and index, like the meshes are by `Model::mesh_key`
+    pub fn image_key(&self, index: usize) -> AssetKey {
+        match AssetKey::path(&self.path) {
+            AssetKey::Path(path) => AssetKey::Path(format!("{}#image{}", path, index)),
+            key => key,
+        }
+    }
+
+    /// Splits an image into levels for code that wants an `LveTextureFile`
+    pub fn texture_file(&self, index: usize) -> LveTextureFile {
+        let image = &self.images[index];
+        let data = self.bytes(&image.data);
+
+        let mut offset = 0;
+        let levels = (0..image.mip_levels)
+            .map(|level| {
+                let (width, height) = mip_extent(image.width, image.height, level);
+                let size = LveTextureFile::level_size(image.format, width, height).unwrap_or(0).min(data.len() - offset);
+                offset += size;
+                data[offset - size..offset].to_vec()
+            })
+            .collect();
+
+        LveTextureFile { format: image.format, width: image.width, height: image.height, levels }
+    }
+
+    /// Writes a cooked model, `images` are indexed by the materials of `primitives`
+    pub fn write(path: &str, primitives: &[PrimitiveData], images: &[CookedImageData], nodes: &[ModelNode], root_nodes: &[usize], rig: &ModelRig) -> LveResult<usize> {
+        let mut file = vec![0u8; HEADER_SIZE];
+        let mut metadata = Writer::default();
+
+        let blob = |file: &mut Vec<u8>, metadata: &mut Writer, bytes: &[u8]| {
+            file.resize(file.len().next_multiple_of(ALIGNMENT), 0);
+            metadata.u64(file.len() as u64);
+            metadata.u64(bytes.len() as u64);
+            file.extend_from_slice(bytes);
+        };
+
+        metadata.u32(primitives.len() as u32);
+        for primitive in primitives {
+            metadata.u32(primitive.mesh as u32);
+            metadata.u32(primitive.primitive as u32);
+
+            let vertex_bytes = unsafe {
+                std::slice::from_raw_parts(primitive.vertices.as_ptr() as *const u8, std::mem::size_of_val(primitive.vertices.as_slice()))
+            };
+            blob(&mut file, &mut metadata, vertex_bytes);
+
+            // Half the index memory whenever every index fits
+            if primitive.vertices.len() <= u16::MAX as usize + 1 {
+                metadata.u32(2);
+                let indices = primitive.indices.iter().flat_map(|&index| (index as u16).to_le_bytes()).collect::<Vec<_>>();
+                blob(&mut file, &mut metadata, &indices);
+            } else {
+                metadata.u32(4);
+                let indices = primitive.indices.iter().flat_map(|&index| index.to_le_bytes()).collect::<Vec<_>>();
+                blob(&mut file, &mut metadata, &indices);
+            }
+
+            let deltas = &primitive.morph_targets.deltas;
+            let morph_bytes = unsafe {
+                std::slice::from_raw_parts(deltas.as_ptr() as *const u8, std::mem::size_of_val(deltas.as_slice()))
+            };
+            blob(&mut file, &mut metadata, morph_bytes);
+            metadata.u32(primitive.morph_targets.count as u32);
+
+            metadata.material(&primitive.material);
+        }
+
+        metadata.u32(images.len() as u32);
+        for image in images {
+            metadata.u32(LveTextureFile::with_srgb(image.format, false).as_raw() as u32);
+            metadata.u32(image.width);
+            metadata.u32(image.height);
+            metadata.u32(image.mip_levels);
+            blob(&mut file, &mut metadata, &image.data);
+        }
+
+        metadata.u32(nodes.len() as u32);
+        for node in nodes {
+            metadata.string(node.name.as_deref());
+            metadata.u32(node.parent.map_or(u32::MAX, |parent| parent as u32));
+            metadata.indices(&node.children);
+            for &value in node.local_transform.as_slice() {
+                metadata.f32(value);
+            }
+            metadata.indices(&node.sub_meshes);
+            metadata.u32(node.skin.map_or(u32::MAX, |skin| skin as u32));
+            metadata.floats(&node.weights);
+        }
+
+        metadata.indices(root_nodes);
+
+        metadata.u32(rig.skins.len() as u32);
+        for skin in &rig.skins {
+            metadata.string(skin.name.as_deref());
+            metadata.indices(&skin.joints);
+            let matrices = skin.inverse_bind_matrices.iter().flat_map(|matrix| matrix.iter().copied()).collect::<Vec<_>>();
+            metadata.floats(&matrices);
+        }
+
+        metadata.u32(rig.clips.len() as u32);
+        for clip in &rig.clips {
+            metadata.string(clip.name.as_deref());
+            metadata.u32(clip.channels.len() as u32);
+            for channel in &clip.channels {
+                metadata.u32(channel.node as u32);
+                metadata.u32(channel.property as u32);
+                metadata.u32(channel.interpolation as u32);
+                metadata.u32(channel.components as u32);
+                metadata.floats(&channel.times);
+                metadata.floats(&channel.values);
+            }
+            metadata.f32(clip.duration);
+        }
+
+        let metadata_offset = file.len().next_multiple_of(ALIGNMENT);
+        file.resize(metadata_offset, 0);
+        file.extend_from_slice(&metadata.0);
+
+        let mut header = Writer::default();
+        header.0.extend_from_slice(&MAGIC);
+        header.u32(VERSION);
+        header.u32(size_of::<Vertex>() as u32);
+        header.u64(metadata_offset as u64);
+        header.u64(metadata.0.len() as u64);
+        file[..HEADER_SIZE].copy_from_slice(&header.0);
+
+        // A new file rather than overwriting, a running engine may still have the old one mapped
+        let temporary = format!("{}.tmp", path);
+        let io_error = |source| LveError::Io { path: path.to_string(), source };
+        std::fs::write(&temporary, &file).map_err(io_error)?;
+        std::fs::rename(&temporary, path).map_err(io_error)?;
+
+        Ok(file.len())
+    }
+}
+
+/// Reads a glTF or OBJ model the same way `Model::new` does, compresses its images to BC1 or BC3
+/// with a full mip chain and writes it to `output`
+pub fn cook(input: &str, output: &str) -> LveResult<CookStats> {
+    let start = Instant::now();
+
+    let (primitives, images, nodes, root_nodes, rig) = if CookedModel::is_cooked(input) {
+        return Err(LveError::Unsupported("The model is already cooked"));
+    } else if Model::is_obj(input) {
+        let ObjContents { primitives, image_paths, nodes, root_nodes } = Model::read_obj(input)?;
+        let images = image_paths
+            .into_par_iter()
+            .map(|image_path| Model::decode_obj_image(&image_path))
+            .collect::<LveResult<Vec<_>>>()?;
+
+        (primitives, images, nodes, root_nodes, ModelRig::default())
+    } else {
+        let (document, buffers) = Model::read_gltf(input)?;
+        let images = document.images().collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|image| Model::decode_image(input, image, &buffers))
+            .collect::<LveResult<Vec<_>>>()?;
+        let primitives = Model::primitives(&document)
+            .into_par_iter()
+            .map(|(mesh, primitive)| Model::assemble_primitive(input, mesh, &primitive, &buffers))
+            .collect::<LveResult<Vec<_>>>()?;
+        let (nodes, root_nodes) = Model::read_nodes(&document);
+        let rig = ModelRig::read(&document, &buffers);
+
+        (primitives, images, nodes, root_nodes, rig)
+    };
+    log::info!("Read {} in {:?}", input, start.elapsed());
+
+    let stage = Instant::now();
+    let images = images.into_par_iter().map(compress_image).collect::<LveResult<Vec<_>>>()?;
+    log::info!("Compressed {} images in {:?}", images.len(), stage.elapsed());
+
+    let bytes = CookedModel::write(output, &primitives, &images, &nodes, &root_nodes, &rig)?;
+
+    Ok(CookStats { primitives: primitives.len(), images: images.len(), bytes })
+}
+
+/// Container textures keep the blocks and levels they come with
+fn compress_image(image: DecodedImage) -> LveResult<CookedImageData> {
+    match image.pixels {
+        DecodedPixels::TextureFile(texture) => Ok(CookedImageData {
+            format: texture.format,
+            width: texture.width,
+            height: texture.height,
+            mip_levels: texture.levels.len() as u32,
+            data: texture.levels.concat(),
+        }),
+        DecodedPixels::Rgba8(pixels) => {
+            let (width, height) = pixels.dimensions();
+            let format = block_format(pixels.as_raw(), false);
+            let mip_levels = LveImage::mip_levels(width, height);
+
+            let data = LveImage::generate_mipmaps_cpu(pixels, mip_levels)
+                .iter()
+                .map(|level| compress(format, level.width(), level.height(), level.as_raw()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(LveError::Unsupported("No encoder for the texture format"))?
+                .concat();
+
+            Ok(CookedImageData { format, width, height, mip_levels, data })
+        }
+    }
+}
+
+/// Little-endian values appended to a byte buffer
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Length prefixed, `u32::MAX` for none
+    fn string(&mut self, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                self.u32(value.len() as u32);
+                self.0.extend_from_slice(value.as_bytes());
+            }
+            None => self.u32(u32::MAX),
+        }
+    }
+
+    fn indices(&mut self, indices: &[usize]) {
+        self.u32(indices.len() as u32);
+        for &index in indices {
+            self.u32(index as u32);
+        }
+    }
+
+    fn floats(&mut self, values: &[f32]) {
+        self.u32(values.len() as u32);
+        for &value in values {
+            self.f32(value);
+        }
+    }
+
+    fn material(&mut self, material: &PrimitiveMaterial) {
+        for texture in material.textures {
+            self.u32(texture.map_or(u32::MAX, |texture| texture as u32));
+        }
+
+        for sampler in material.samplers {
+            self.u32(sampler.mag_filter.as_raw() as u32);
+            self.u32(sampler.min_filter.as_raw() as u32);
+            self.u32(sampler.mipmap_mode.as_raw() as u32);
+            self.u32(sampler.mipmaps as u32);
+            self.u32(sampler.address_mode_u.as_raw() as u32);
+            self.u32(sampler.address_mode_v.as_raw() as u32);
+        }
+
+        let uniforms = &material.uniforms;
+        let scalars = [uniforms.metallic, uniforms.roughness, uniforms.alpha_cutoff, uniforms.normal_scale, uniforms.occlusion_strength];
+        for value in uniforms.base_color.iter().chain(uniforms.emissive.iter()).chain(scalars.iter()) {
+            self.f32(*value);
+        }
+
+        self.u32(uniforms.alpha_mode as u32);
+        self.u32(uniforms.double_sided);
+
+        for transform in uniforms.texture_transforms.iter() {
+            for value in transform.offset.iter().chain(transform.scale.iter()).chain([transform.rotation].iter()) {
+                self.f32(*value);
+            }
+            self.u32(transform.tex_coord);
+        }
+
+        let extensions = [uniforms.emissive_strength, uniforms.clearcoat, uniforms.clearcoat_roughness, uniforms.transmission];
+        for value in extensions.iter().chain(uniforms.sheen_color.iter()).chain([uniforms.sheen_roughness, uniforms.ior].iter()) {
+            self.f32(*value);
+        }
+    }
+}
+
+/// Counterpart of `Writer`, every read fails once the bytes run out
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.position..self.position.checked_add(count)?)?;
+        self.position += count;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<Option<String>> {
+        match self.u32()? {
+            u32::MAX => Some(None),
+            length => Some(Some(String::from_utf8(self.take(length as usize)?.to_vec()).ok()?)),
+        }
+    }
+
+    fn indices(&mut self) -> Option<Vec<usize>> {
+        let count = self.u32()? as usize;
+        (0..count).map(|_| self.u32().map(|index| index as usize)).collect()
+    }
+
+    fn floats(&mut self) -> Option<Vec<f32>> {
+        let count = self.u32()? as usize;
+        (0..count).map(|_| self.f32()).collect()
+    }
+
+    fn material(&mut self) -> Option<PrimitiveMaterial> {
+        let mut textures = [None; 5];
+        for texture in textures.iter_mut() {
+            *texture = Some(self.u32()?).filter(|&index| index != u32::MAX).map(|index| index as usize);
+        }
+
+        let mut samplers = [SamplerSettings::default(); 5];
+        for sampler in samplers.iter_mut() {
+            *sampler = SamplerSettings {
+                mag_filter: vk::Filter::from_raw(self.u32()? as i32),
+                min_filter: vk::Filter::from_raw(self.u32()? as i32),
+                mipmap_mode: vk::SamplerMipmapMode::from_raw(self.u32()? as i32),
+                mipmaps: self.u32()? != 0,
+                address_mode_u: vk::SamplerAddressMode::from_raw(self.u32()? as i32),
+                address_mode_v: vk::SamplerAddressMode::from_raw(self.u32()? as i32),
+            };
+        }
+
+        let mut uniforms = MeshUniforms {
+            base_color: na::vector![self.f32()?, self.f32()?, self.f32()?, self.f32()?],
+            emissive: na::vector![self.f32()?, self.f32()?, self.f32()?],
+            metallic: self.f32()?,
+            roughness: self.f32()?,
+            alpha_cutoff: self.f32()?,
+            normal_scale: self.f32()?,
+            occlusion_strength: self.f32()?,
+            alpha_mode: AlphaMode::from_raw(self.u32()?)?,
+            double_sided: self.u32()?,
+            ..MeshUniforms::new()
+        };
+
+        for transform in uniforms.texture_transforms.iter_mut() {
+            *transform = TextureTransform {
+                offset: na::vector![self.f32()?, self.f32()?],
+                scale: na::vector![self.f32()?, self.f32()?],
+                rotation: self.f32()?,
+                tex_coord: self.u32()?,
+            };
+        }
+
+        uniforms.emissive_strength = self.f32()?;
+        uniforms.clearcoat = self.f32()?;
+        uniforms.clearcoat_roughness = self.f32()?;
+        uniforms.transmission = self.f32()?;
+        uniforms.sheen_color = na::vector![self.f32()?, self.f32()?, self.f32()?];
+        uniforms.sheen_roughness = self.f32()?;
+        uniforms.ior = self.f32()?;
+
+        Some(PrimitiveMaterial { textures, samplers, uniforms })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooked_model_round_trips() {
+        let vertex = |x: f32| Vertex {
+            position: na::vector![x, 0.0, 0.0],
+            color: na::vector![1.0, 1.0, 1.0],
+            normal: na::vector![0.0, 0.0, 1.0],
+            tangent: na::vector![1.0, 0.0, 0.0, 1.0],
+            tex_coord: na::vector![x, 1.0],
+            tex_coord_1: na::vector![1.0, x],
+            joints: na::vector![0, 1, 0, 0],
+            weights: na::vector![0.5, 0.5, 0.0, 0.0],
+        };
+
+        let mut material = Model::default_obj_material();
+        material.textures[0] = Some(0);
+        material.uniforms.roughness = 0.25;
+        material.uniforms.alpha_mode = AlphaMode::Mask;
+        material.samplers[0].address_mode_u = vk::SamplerAddressMode::CLAMP_TO_EDGE;
+        material.uniforms.texture_transforms[3] = TextureTransform {
+            offset: na::vector![0.5, 0.0],
+            scale: na::vector![2.0, 2.0],
+            rotation: 1.5,
+            tex_coord: 1,
+        };
+        material.uniforms.emissive_strength = 4.0;
+        material.uniforms.clearcoat = 0.75;
+        material.uniforms.sheen_color = na::vector![0.5, 0.25, 1.0];
+        material.uniforms.ior = 1.33;
+
+        let delta = |y: f32| MorphDelta {
+            position: na::vector![0.0, y, 0.0, 0.0],
+            normal: na::Vector4::zeros(),
+            tangent: na::vector![y, 0.0, 0.0, 0.0],
+        };
+        let morph_targets = MorphTargets {
+            count: 2,
+            deltas: (0..6).map(|i| delta(i as f32)).collect(),
+        };
+
+        let primitives = vec![PrimitiveData {
+            mesh: 3,
+            primitive: 1,
+            vertices: vec![vertex(0.0), vertex(1.0), vertex(2.0)],
+            indices: vec![0, 1, 2],
+            morph_targets,
+            material,
+        }];
+        let images = vec![CookedImageData {
+            format: vk::Format::BC1_RGB_SRGB_BLOCK,
+            width: 4,
+            height: 4,
+            mip_levels: 3,
+            data: vec![7; 24],
+        }];
+        let node = ModelNode {
+            name: Some("root".to_string()),
+            parent: None,
+            children: Vec::new(),
+            local_transform: na::Matrix4::new_translation(&na::vector![1.0, 2.0, 3.0]),
+            world_transform: na::Matrix4::identity(),
+            sub_meshes: vec![0],
+            skin: Some(0),
+            weights: vec![0.5, 0.25],
+        };
+        let rig = ModelRig {
+            skins: vec![ModelSkin {
+                name: None,
+                joints: vec![0],
+                inverse_bind_matrices: vec![na::Matrix4::new_scaling(2.0)],
+            }],
+            clips: vec![AnimationClip {
+                name: Some("wave".to_string()),
+                channels: vec![AnimationChannel {
+                    node: 0,
+                    property: AnimationProperty::Rotation,
+                    interpolation: Interpolation::Step,
+                    times: vec![0.0, 1.5],
+                    values: vec![0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0],
+                    components: 4,
+                }],
+                duration: 1.5,
+            }],
+        };
+
+        let path = std::env::temp_dir().join(format!("round_trip_{}.{}", std::process::id(), COOKED_EXTENSION));
+        let path = path.to_string_lossy().into_owned();
+        CookedModel::write(&path, &primitives, &images, &[node], &[0], &rig).unwrap();
+
+        let cooked = CookedModel::open(&path).unwrap();
+        let primitive = &cooked.primitives[0];
+        assert_eq!((primitive.mesh, primitive.primitive), (3, 1));
+        assert_eq!(primitive.index_type, vk::IndexType::UINT16);
+        assert!(cooked.vertices(primitive) == primitives[0].vertices);
+        assert_eq!(cooked.indices(primitive), vec![0, 1, 2]);
+        assert_eq!(cooked.morph_targets(primitive), primitives[0].morph_targets);
+        assert_eq!(primitive.material.textures[0], Some(0));
+        assert_eq!(primitive.material.uniforms, material.uniforms);
+        assert_eq!(primitive.material.samplers, material.samplers);
+
+        let image = &cooked.images[0];
+        assert_eq!(image.format, vk::Format::BC1_RGB_UNORM_BLOCK);
+        assert_eq!(cooked.bytes(&image.data).len(), 24);
+        assert_eq!(image.data.start % ALIGNMENT, 0);
+
+        assert_eq!(cooked.nodes[0].name.as_deref(), Some("root"));
+        assert_eq!(cooked.nodes[0].world_transform, na::Matrix4::new_translation(&na::vector![1.0, 2.0, 3.0]));
+        assert_eq!(cooked.nodes[0].skin, Some(0));
+        assert_eq!(cooked.nodes[0].weights, vec![0.5, 0.25]);
+
+        assert_eq!(cooked.rig.skins[0].inverse_bind_matrices, rig.skins[0].inverse_bind_matrices);
+        let (channel, expected) = (&cooked.rig.clips[0].channels[0], &rig.clips[0].channels[0]);
+        assert_eq!((channel.property, channel.interpolation), (expected.property, expected.interpolation));
+        assert_eq!((&channel.times, &channel.values), (&expected.times, &expected.values));
+        assert_eq!(cooked.rig.clips[0].duration, 1.5);
+
+        drop(cooked);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,8 +1,13 @@
 use std::str::FromStr;
 
+use super::animation::*;
+use super::asset_manager::*;
 use super::model::*;
+use super::model_loader::*;
 //use Model as ModelComponent;
 
+use crate::first_app::vulkan::lve_error::*;
+
 use nalgebra as na;
 
 use std::rc::Rc;
@@ -30,6 +35,7 @@ impl NewTransformComponent {
                 )
     }
 
+    #[allow(dead_code)]
     pub fn normal_matrix(&self) -> na::Matrix4<f32> {
 
         let c3 = self.rotation[2].cos();
@@ -75,6 +81,12 @@ pub struct Entity {
     pub selected: bool,
     pub transform: NewTransformComponent,
     pub model: Option<Rc<Model>>,
+    /// Set while the model is streamed in, `model` grows as parts of it become resident
+    pub model_load: Option<ModelLoadHandle>,
+    /// Where this entity's instance of the model has its nodes, `None` for models without skins or animations
+    pub pose: Option<ModelPose>,
+    /// Plays the model's clips on `pose`, models with clips get one that loops the first clip
+    pub animation_player: Option<AnimationPlayerComponent>,
     pub point_light: Option<PointLightComponent>,
     pub spot_light: Option<SpotLightComponent>,
     pub directional_light: Option<DirectionalLightComponent>,
@@ -87,6 +99,9 @@ impl Entity {
             selected: false,
             transform,
             model: None,
+            model_load: None,
+            pose: None,
+            animation_player: None,
             point_light: None,
             spot_light: None,
             directional_light: None,
@@ -98,6 +113,64 @@ impl Entity {
         self.model = Some(component);
     }
 
+    /// Loads the model in the background, the entity renders whatever is resident until it is done
+    pub fn load_model(&mut self, assets: &AssetManager, path: &str, frames_in_flight: usize) {
+        match assets.loaded_model(path) {
+            Some(model) => self.set_model(model),
+            None => self.model_load = Some(ModelLoadHandle::new(path, frames_in_flight)),
+        }
+    }
+
+    /// Attaches whatever the background load has made resident since the last call
+    pub fn poll_model_load(&mut self, assets: &AssetManager) -> LveResult<()> {
+        let load = match self.model_load.as_mut() {
+            Some(load) => load,
+            None => return Ok(()),
+        };
+
+        if let Some(model) = load.poll(assets, self.model.clone())? {
+            self.model = Some(model);
+        }
+
+        Ok(())
+    }
+
+    /// Advances the animation player and poses the nodes and joints for rendering
+    pub fn update(&mut self, frame_time: f32) {
+        let model = match self.model.as_ref() {
+            Some(model) => model,
+            None => {
+                self.pose = None;
+                return;
+            }
+        };
+
+        // Streaming and reloads swap the model, a pose only carries over while the nodes still match
+        if self.pose.as_ref().is_none_or(|pose| pose.world_transforms.len() != model.nodes().len()) {
+            self.pose = model.new_pose();
+        }
+
+        let pose = match self.pose.as_mut() {
+            Some(pose) => pose,
+            None => return,
+        };
+
+        let rig = model.rig();
+        if rig.clips.is_empty() {
+            self.animation_player = None;
+        } else if self.animation_player.is_none() {
+            self.animation_player = Some(AnimationPlayerComponent::default());
+        }
+
+        pose.reset();
+        if let Some(player) = self.animation_player.as_mut() {
+            player.advance(frame_time, &rig.clips);
+            player.apply(pose, &rig.clips);
+        }
+
+        pose.update(model.nodes(), model.root_nodes(), &rig.skins);
+    }
+
     #[allow(dead_code)]
     pub fn set_point_light(&mut self, component: PointLightComponent) {
         self.point_light = Some(component);
@@ -182,6 +255,57 @@ impl Entity {
             self.transform.scale.z = scale[2];
         }
 
+        if let (Some(model), Some(player)) = (self.model.as_ref(), self.animation_player.as_mut()) {
+            let clips = &model.rig().clips;
+            ui.separator();
+
+            let names = clips
+                .iter()
+                .enumerate()
+                .map(|(index, clip)| clip.name.clone().unwrap_or_else(|| format!("Clip {}", index)))
+                .collect::<Vec<_>>();
+            let mut clip = player.clip.min(clips.len().saturating_sub(1));
+            if ui.combo_simple_string("Clip", &mut clip, &names) && clip != player.clip {
+                player.play(clip);
+            }
+
+            if ui.button(if player.playing { "Pause" } else { "Play" }) {
+                if player.playing {
+                    player.pause();
+                } else {
+                    player.resume(clips);
+                }
+            }
+            ui.same_line();
+            ui.checkbox("Loop", &mut player.looping);
+
+            // The timeline, dragging it scrubs through the clip
+            let duration = clips.get(player.clip).map_or(0.0, |clip| clip.duration);
+            let mut time = player.time;
+            if imgui::Slider::new("Time", 0.0, duration).display_format("%.2f s").build(ui, &mut time) {
+                player.scrub(time);
+            }
+
+            imgui::Slider::new("Speed", -2.0, 2.0).build(ui, &mut player.speed);
+            ui.input_float("Blend duration", &mut player.blend_duration).build();
+        }
+
+        // These are the weights at rest, channels of the playing clip override them
+        if let (Some(model), Some(pose)) = (self.model.as_ref(), self.pose.as_mut()) {
+            for (index, (node, weights)) in model.nodes().iter().zip(pose.rest_weights.iter_mut()).enumerate() {
+                if weights.is_empty() {
+                    continue;
+                }
+
+                ui.separator();
+                ui.text(format!("Morph weights of {}", node.name.as_deref().unwrap_or("node")));
+                for (target, weight) in weights.iter_mut().enumerate() {
+                    // The node index keeps the ids of same named nodes apart
+                    imgui::Slider::new(format!("Target {}##{}", target, index), 0.0, 1.0).build(ui, weight);
+                }
+            }
+        }
+
         match &self.point_light {
             Some(_) => {
                 ui.separator();
@@ -2,6 +2,11 @@ use crate::first_app::vulkan::lve_descriptor_set::*;
 use crate::first_app::vulkan::lve_device::*;
 use crate::first_app::vulkan::lve_buffer::*;
 use crate::first_app::vulkan::lve_image::*;
+use crate::first_app::vulkan::lve_sampler::*;
+use crate::first_app::vulkan::lve_error::*;
+
+/// OBJ and glTF models share one vertex layout
+pub use crate::first_app::vulkan::lve_model::Vertex;
 
 use ash::{vk, Device};
 
@@ -10,129 +15,273 @@ use std::rc::Rc;
 
 use nalgebra as na;
 
+/// A texture slot of a material, the image is shared with every other mesh using the same texture
+/// and the sampler with every texture using the same settings
+#[derive(Clone)]
+pub struct MeshTexture {
+    pub image: Rc<LveImage>,
+    pub sampler: Rc<LveSampler>,
+}
+
+impl MeshTexture {
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler.sampler,
+            ..self.image.image_info
+        }
+    }
+}
+
+/// What one morph target adds to a vertex, vec4s so the layout matches std430 in `deffered.vert`
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct MorphDelta {
+    pub position: na::Vector4<f32>,
+    pub normal: na::Vector4<f32>,
+    pub tangent: na::Vector4<f32>,
+}
+
+/// The morph targets of a primitive, `deltas` holds `count` deltas for every vertex, vertex after vertex
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct MorphTargets {
+    pub count: usize,
+    pub deltas: Vec<MorphDelta>,
+}
+
 pub struct MeshTextures {
-    pub base_color: LveImage,
-    pub metallic_roughness: LveImage,
-    pub normal: LveImage,
-    pub occlusion: LveImage,
-    pub emissive: LveImage,
+    pub base_color: MeshTexture,
+    pub metallic_roughness: MeshTexture,
+    pub normal: MeshTexture,
+    pub occlusion: MeshTexture,
+    pub emissive: MeshTexture,
 }
 
-impl MeshTextures {
-    pub fn new(lve_device: Rc<LveDevice>) -> Self {
+/// How the base color alpha is used, the values match `alphaMode` in `deffered.frag`
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AlphaMode {
+    /// Alpha is ignored
+    Opaque = 0,
+    /// Fragments below `alpha_cutoff` are discarded, the rest are opaque
+    Mask = 1,
+    /// Blended over what is behind, drawn back to front after everything else
+    Blend = 2,
+}
+
+impl AlphaMode {
+    pub const ALL: [AlphaMode; 3] = [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend];
+
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|&mode| mode as u32 == raw)
+    }
+}
+
+/// Picks the `AdvancedRenderSystem` pipeline a mesh is drawn with
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MaterialPipeline {
+    pub alpha_mode: AlphaMode,
+    /// Single sided materials cull back faces
+    pub double_sided: bool,
+}
+
+/// `KHR_texture_transform` of a texture slot and the texture coordinate set it samples, the
+/// `TextureTransform` of `deffered.frag`. Aligned like a std140 struct.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextureTransform {
+    pub offset: na::Vector2<f32>,
+    pub scale: na::Vector2<f32>,
+    /// Radians, counter-clockwise in UV space
+    pub rotation: f32,
+    /// 0 for `Vertex::tex_coord`, 1 for `Vertex::tex_coord_1`
+    pub tex_coord: u32,
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
         Self {
-            base_color: LveImage::default(lve_device.clone()),
-            metallic_roughness: LveImage::default(lve_device.clone()),
-            normal: LveImage::default(lve_device.clone()),
-            occlusion: LveImage::default(lve_device.clone()),
-            emissive: LveImage::default(lve_device.clone()),
+            offset: na::Vector2::zeros(),
+            scale: na::vector![1.0, 1.0],
+            rotation: 0.0,
+            tex_coord: 0,
         }
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+/// The `PbrUbo` of `deffered.frag`, laid out for std140. Factors multiply their textures, slots
+/// without a texture sample a white default one. The `KHR_materials_*` factors come last, their
+/// defaults leave the extensions off.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct MeshUniforms {
-    pub base_color: na::Vector3<f32>,
+    pub base_color: na::Vector4<f32>,
+    pub emissive: na::Vector3<f32>,
     pub metallic: f32,
     pub roughness: f32,
-    pub emissive: na::Vector3<f32>,
+    pub alpha_cutoff: f32,
+    /// Scales the X and Y of the sampled normal
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    /// `vk::TRUE` or `vk::FALSE`, back faces of double sided materials get flipped normals
+    pub double_sided: vk::Bool32,
+    /// For each of `TEXTURE_ROLES`
+    pub texture_transforms: [TextureTransform; 5],
+    /// `KHR_materials_emissive_strength`, scales `emissive` past 1
+    pub emissive_strength: f32,
+    /// `KHR_materials_clearcoat`
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// `KHR_materials_transmission`, light passing through a thin surface
+    pub transmission: f32,
+    /// `KHR_materials_sheen`
+    pub sheen_color: na::Vector3<f32>,
+    pub sheen_roughness: f32,
+    /// `KHR_materials_ior`, sets the reflectance of dielectrics
+    pub ior: f32,
 }
 
-impl MeshUniforms {
-    pub fn new() -> Self {
+impl Default for MeshUniforms {
+    /// The glTF defaults
+    fn default() -> Self {
         Self {
-            base_color: na::vector![1.0, 1.0, 1.0], // Maybe add alpha channel
+            base_color: na::vector![1.0, 1.0, 1.0, 1.0],
+            emissive: na::vector![0.0, 0.0, 0.0],
             metallic: 1.0,
             roughness: 1.0,
-            emissive: na::vector![1.0, 1.0, 1.0],
+            alpha_cutoff: 0.5,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: vk::FALSE,
+            texture_transforms: [TextureTransform::default(); 5],
+            emissive_strength: 1.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            transmission: 0.0,
+            sheen_color: na::vector![0.0, 0.0, 0.0],
+            sheen_roughness: 0.0,
+            ior: 1.5,
         }
     }
 }
 
-
-
-#[derive(Clone, Copy, PartialEq)]
-pub struct Vertex {
-    pub position: na::Vector3<f32>,
-    pub color: na::Vector3<f32>,
-    pub normal: na::Vector3<f32>,
-    pub tangent: na::Vector4<f32>,
-    pub tex_coord: na::Vector2<f32>,
-}
-
-impl Vertex {
-    #[allow(dead_code)]
-    pub fn get_binding_descriptions() -> Vec<vk::VertexInputBindingDescription> {
-        let vertex_size = size_of::<Vertex>() as u32;
-
-        vec![vk::VertexInputBindingDescription::builder()
-            .binding(0)
-            .stride(vertex_size)
-            .input_rate(vk::VertexInputRate::VERTEX)
-            .build()]
+impl MeshUniforms {
+    /// The glTF defaults
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    #[allow(dead_code)]
-    pub fn get_attribute_descriptions() -> Vec<vk::VertexInputAttributeDescription> {
-        vec![
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(0)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(memoffset::offset_of!(Vertex, position) as u32)
-                .build(),
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(1)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(memoffset::offset_of!(Vertex, color) as u32) // Using size of the position field
-                .build(),
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(2)
-                .format(vk::Format::R32G32B32_SFLOAT)
-                .offset(memoffset::offset_of!(Vertex, normal) as u32) // Using size of the position field
-                .build(),
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(3)
-                .format(vk::Format::R32G32B32A32_SFLOAT)
-                .offset(memoffset::offset_of!(Vertex, tangent) as u32) // Using size of the position field
-                .build(),
-            vk::VertexInputAttributeDescription::builder()
-                .binding(0)
-                .location(4)
-                .format(vk::Format::R32G32_SFLOAT)
-                .offset(memoffset::offset_of!(Vertex, tex_coord) as u32) // Using size of the position field
-                .build(),
-        ]
+    pub fn pipeline(&self) -> MaterialPipeline {
+        MaterialPipeline {
+            alpha_mode: self.alpha_mode,
+            double_sided: self.double_sided == vk::TRUE,
+        }
     }
 }
 
-#[allow(dead_code)]
-pub struct Mesh {
+/// Vertex and index buffers, shared by meshes that only differ in their textures
+struct MeshGeometry {
     vertex_buffer: LveBuffer<Vertex>,
     vertex_count: u32,
     has_index_buffer: bool,
-    index_buffer: LveBuffer<u32>,
+    /// Sized in bytes, holds `u16` or `u32` indices depending on `index_type`
+    index_buffer: LveBuffer<u8>,
+    index_type: vk::IndexType,
     index_count: u32,
+    /// Read by the vertex shader with `gl_VertexIndex`, `None` for meshes without morph targets
+    morph_buffer: Option<LveBuffer<MorphDelta>>,
+    morph_target_count: u32,
+}
+
+#[allow(dead_code)]
+pub struct Mesh {
+    lve_device: Rc<LveDevice>,
+    geometry: Rc<MeshGeometry>,
+    uniforms: MeshUniforms,
     uniform_buffer: LveBuffer<MeshUniforms>,
     textures: MeshTextures,
     pub descriptor_set: ash::vk::DescriptorSet,
+    /// Needs `FREE_DESCRIPTOR_SET`, the set is returned to it when the mesh is dropped
+    global_pool: Rc<LveDescriptorPool>,
     //descriptor_layout: Rc<LveDescriptorSetLayout>
 }
 
 impl Mesh {
-    pub fn new(lve_device: Rc<LveDevice>, vertices: Vec<Vertex>, indices: Vec<u32>, textures: MeshTextures, uniforms: MeshUniforms, global_pool: Rc<LveDescriptorPool>) -> Rc<Self> {
-        let (vertex_buffer, vertex_count) = Self::create_vertex_buffers(&lve_device, &vertices);
-        let (has_index_buffer, index_buffer, index_count) = Self::create_index_buffers(&lve_device, &indices);
+    pub fn new(lve_device: Rc<LveDevice>, vertices: Vec<Vertex>, indices: Vec<u32>, morph_targets: &MorphTargets, textures: MeshTextures, uniforms: MeshUniforms, global_pool: Rc<LveDescriptorPool>) -> LveResult<Rc<Self>> {
+        let (vertex_buffer, vertex_count) = Self::create_vertex_buffers(&lve_device, &vertices, vertices.len())?;
+        let (has_index_buffer, index_buffer, index_count) = Self::create_index_buffers(&lve_device, &indices, indices.len())?;
+        let morph_buffer = Self::create_morph_buffer(&lve_device, &morph_targets.deltas, morph_targets.deltas.len())?;
+
+        let geometry = Rc::new(MeshGeometry {
+            vertex_buffer,
+            vertex_count,
+            has_index_buffer,
+            index_buffer,
+            index_type: vk::IndexType::UINT32,
+            index_count,
+            morph_buffer,
+            morph_target_count: morph_targets.count as u32,
+        });
+
+        Self::with_geometry(lve_device, geometry, textures, uniforms, global_pool)
+    }
+
+    /// Uploads vertices and indices that are already laid out for the GPU, e.g. straight out of a
+    /// memory mapped cooked model. `vertex_bytes` must hold whole `Vertex`es and `morph_bytes`
+    /// `morph_target_count` `MorphDelta`s for each of them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_bytes(
+        lve_device: Rc<LveDevice>,
+        vertex_bytes: &[u8],
+        index_bytes: &[u8],
+        index_type: vk::IndexType,
+        morph_bytes: &[u8],
+        morph_target_count: u32,
+        textures: MeshTextures,
+        uniforms: MeshUniforms,
+        global_pool: Rc<LveDescriptorPool>,
+    ) -> LveResult<Rc<Self>> {
+        let index_size = if index_type == vk::IndexType::UINT16 { 2 } else { 4 };
+        let (vertex_buffer, vertex_count) = Self::create_vertex_buffers(&lve_device, vertex_bytes, vertex_bytes.len() / size_of::<Vertex>())?;
+        let (has_index_buffer, index_buffer, index_count) = Self::create_index_buffers(&lve_device, index_bytes, index_bytes.len() / index_size)?;
+        let morph_buffer = Self::create_morph_buffer(&lve_device, morph_bytes, morph_bytes.len() / size_of::<MorphDelta>())?;
+
+        let geometry = Rc::new(MeshGeometry {
+            vertex_buffer,
+            vertex_count,
+            has_index_buffer,
+            index_buffer,
+            index_type,
+            index_count,
+            morph_buffer,
+            morph_target_count,
+        });
+
+        Self::with_geometry(lve_device, geometry, textures, uniforms, global_pool)
+    }
+
+    pub fn pipeline(&self) -> MaterialPipeline {
+        self.uniforms.pipeline()
+    }
+
+    /// Zero for meshes without morph targets
+    pub fn morph_target_count(&self) -> usize {
+        self.geometry.morph_target_count as usize
+    }
+
+    /// The same mesh with other textures, the vertices aren't uploaded again
+    pub fn with_textures(&self, textures: MeshTextures) -> LveResult<Rc<Self>> {
+        Self::with_geometry(self.lve_device.clone(), self.geometry.clone(), textures, self.uniforms, self.global_pool.clone())
+    }
 
+    fn with_geometry(lve_device: Rc<LveDevice>, geometry: Rc<MeshGeometry>, textures: MeshTextures, uniforms: MeshUniforms, global_pool: Rc<LveDescriptorPool>) -> LveResult<Rc<Self>> {
         let mut uniform_buffer = LveBuffer::new(
             Rc::clone(&lve_device),
             1,
             ash::vk::BufferUsageFlags::UNIFORM_BUFFER,
-            ash::vk::MemoryPropertyFlags::HOST_VISIBLE,
-        );
+            ash::vk::MemoryPropertyFlags::HOST_VISIBLE | ash::vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
 
         uniform_buffer.map(0);
 
@@ -145,29 +294,49 @@ impl Mesh {
             .add_binding(3, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(4, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(5, ash::vk::DescriptorType::UNIFORM_BUFFER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
-            .build().unwrap();
-
-        let descriptor_set = LveDescriptorSetWriter::new(descriptor_layout, global_pool.clone())
-            .write_image(0, &[textures.base_color.image_info])
-            .write_image(1, &[textures.metallic_roughness.image_info])
-            .write_image(2, &[textures.normal.image_info])
-            .write_image(3, &[textures.occlusion.image_info])
-            .write_image(4, &[textures.emissive.image_info])
-            .write_to_buffer(5, &[uniform_buffer.descriptor_info()])
-            .build().unwrap();
-
-        
-        Rc::new(Self {
-            vertex_buffer,
-            vertex_count,
-            has_index_buffer,
-            index_buffer,
-            index_count,
+            .add_binding(6, ash::vk::DescriptorType::STORAGE_BUFFER, ash::vk::ShaderStageFlags::VERTEX, 1)
+            .build()
+            .map_err(LveError::vulkan("Unable to create mesh descriptor set layout"))?;
+
+        // The writer keeps pointers to the infos until it is built
+        let image_infos = [
+            textures.base_color.descriptor_info(),
+            textures.metallic_roughness.descriptor_info(),
+            textures.normal.descriptor_info(),
+            textures.occlusion.descriptor_info(),
+            textures.emissive.descriptor_info(),
+        ];
+        let uniform_info = uniform_buffer.descriptor_info();
+        let morph_info = geometry.morph_buffer.as_ref().map(|buffer| buffer.descriptor_info());
+
+        let mut writer = LveDescriptorSetWriter::new(descriptor_layout, global_pool.clone());
+        for (binding, image_info) in image_infos.iter().enumerate() {
+            writer = writer.write_image(binding as u32, std::slice::from_ref(image_info));
+        }
+        writer = writer.write_to_buffer(5, std::slice::from_ref(&uniform_info));
+
+        // Only the morphing pipelines read the deltas, the binding stays empty for other meshes
+        if let Some(morph_info) = morph_info.as_ref() {
+            writer = writer.write_to_buffer(6, std::slice::from_ref(morph_info));
+        }
+
+        let descriptor_set = writer
+            .build()
+            .ok_or(LveError::Vulkan {
+                context: "Unable to allocate mesh descriptor set",
+                result: vk::Result::ERROR_OUT_OF_POOL_MEMORY,
+            })?;
+
+        Ok(Rc::new(Self {
+            lve_device,
+            geometry,
+            uniforms,
             uniform_buffer,
             textures,
             descriptor_set,
+            global_pool,
             //descriptor_layout
-        })
+        }))
     }
 
     /*#[allow(dead_code)]
@@ -185,82 +354,95 @@ impl Mesh {
         })
     }*/
 
+    /// # Safety
+    /// `command_buffer` must be recording inside a render pass, with this mesh bound by `bind`
+    /// and a compatible pipeline and descriptor sets bound.
     pub unsafe fn draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
-        if self.has_index_buffer {
-            device.cmd_draw_indexed(command_buffer, self.index_count, 1, 0, 0, 0);
+        let geometry = &self.geometry;
+        if geometry.has_index_buffer {
+            device.cmd_draw_indexed(command_buffer, geometry.index_count, 1, 0, 0, 0);
         } else {
-            device.cmd_draw(command_buffer, self.vertex_count, 1, 0, 0);
+            device.cmd_draw(command_buffer, geometry.vertex_count, 1, 0, 0);
         }
     }
 
+    /// # Safety
+    /// `command_buffer` must be recording, and the mesh must outlive its execution.
     pub unsafe fn bind(&self, command_buffer: vk::CommandBuffer) {
-        self.vertex_buffer.bind_vertex(command_buffer);
+        let geometry = &self.geometry;
+        geometry.vertex_buffer.bind_vertex(command_buffer);
 
-        if self.has_index_buffer {
+        if geometry.has_index_buffer {
             //device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT32);
-            self.index_buffer.bind_index(command_buffer, vk::IndexType::UINT32);
+            geometry.index_buffer.bind_index(command_buffer, geometry.index_type);
         }
     }
 
-    fn create_vertex_buffers(lve_device: &Rc<LveDevice>, vertices: &Vec<Vertex>) -> (LveBuffer<Vertex>, u32) {
-        let vertex_count = vertices.len();
-        assert!(vertex_count >= 3, "Vertex count must be at least 3");
-
-        let buffer_size: vk::DeviceSize = (size_of::<Vertex>() * vertex_count) as u64;
+    /// `data` is either `Vertex`es or their bytes
+    fn create_vertex_buffers<T: Copy>(lve_device: &Rc<LveDevice>, data: &[T], vertex_count: usize) -> LveResult<(LveBuffer<Vertex>, u32)> {
+        if vertex_count < 3 {
+            return Err(LveError::Unsupported("meshes with fewer than 3 vertices"));
+        }
 
-        let mut staging_buffer = LveBuffer::new(
+        let vertex_buffer = LveBuffer::new(
             lve_device.clone(),
             vertex_count,
-            ash::vk::BufferUsageFlags::TRANSFER_SRC,
-            ash::vk::MemoryPropertyFlags::HOST_VISIBLE | ash::vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
+            ash::vk::BufferUsageFlags::VERTEX_BUFFER | ash::vk::BufferUsageFlags::TRANSFER_DST,
+            ash::vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
 
-        staging_buffer.map(0);
-        staging_buffer.write_to_buffer(vertices);
+        lve_device.upload_to_buffer(vertex_buffer.buffer, data)?;
 
-        let vertex_buffer = LveBuffer::new(
+        Ok((vertex_buffer, vertex_count as u32))
+    }
+
+    /// `data` is either `MorphDelta`s or their bytes, no buffer is made for meshes without morph targets
+    fn create_morph_buffer<T: Copy>(lve_device: &Rc<LveDevice>, data: &[T], delta_count: usize) -> LveResult<Option<LveBuffer<MorphDelta>>> {
+        if delta_count == 0 {
+            return Ok(None);
+        }
+
+        let morph_buffer = LveBuffer::new(
             lve_device.clone(),
-            vertex_count,
-            ash::vk::BufferUsageFlags::VERTEX_BUFFER | ash::vk::BufferUsageFlags::TRANSFER_DST,
+            delta_count,
+            ash::vk::BufferUsageFlags::STORAGE_BUFFER | ash::vk::BufferUsageFlags::TRANSFER_DST,
             ash::vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        );
+        )?;
 
-        lve_device.copy_buffer(staging_buffer.buffer, vertex_buffer.buffer, buffer_size);
+        lve_device.upload_to_buffer(morph_buffer.buffer, data)?;
 
-        (vertex_buffer, vertex_count as u32)
+        Ok(Some(morph_buffer))
     }
 
-    fn create_index_buffers(lve_device: &Rc<LveDevice>, indices: &Vec<u32>) -> (bool, LveBuffer<u32>, u32) {
-        let index_count = indices.len();
+    /// `data` is either indices or their bytes, the buffer is as large as `data`
+    fn create_index_buffers<T: Copy>(lve_device: &Rc<LveDevice>, data: &[T], index_count: usize) -> LveResult<(bool, LveBuffer<u8>, u32)> {
         let has_index_buffer = index_count > 0;
 
         if !has_index_buffer {
             let index_buffer = LveBuffer::null(lve_device.clone());
             //let index_buffer_memory = vk::DeviceMemory::null();
-            return (has_index_buffer, index_buffer, 0);
+            return Ok((has_index_buffer, index_buffer, 0));
         }
 
-        let buffer_size: vk::DeviceSize = (size_of::<u32>() * index_count) as u64;
-
-        let mut staging_buffer = LveBuffer::new(
-            lve_device.clone(),
-            index_count,
-            ash::vk::BufferUsageFlags::TRANSFER_SRC,
-            ash::vk::MemoryPropertyFlags::HOST_VISIBLE | ash::vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-
-        staging_buffer.map(0);
-        staging_buffer.write_to_buffer(indices);
-
         let index_buffer = LveBuffer::new(
             lve_device.clone(),
-            index_count,
+            std::mem::size_of_val(data),
             ash::vk::BufferUsageFlags::INDEX_BUFFER | ash::vk::BufferUsageFlags::TRANSFER_DST,
             ash::vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        );
+        )?;
+
+        lve_device.upload_to_buffer(index_buffer.buffer, data)?;
+
+        Ok((has_index_buffer, index_buffer, index_count as u32))
+    }
+}
 
-        lve_device.copy_buffer(staging_buffer.buffer, index_buffer.buffer, buffer_size);
+impl Drop for Mesh {
+    fn drop(&mut self) {
+        log::debug!("Dropping mesh");
 
-        (has_index_buffer, index_buffer, index_count as u32)
+        if let Err(result) = self.global_pool.free_descriptors(&vec![self.descriptor_set]) {
+            log::error!("Unable to free mesh descriptor set: {}", result);
+        }
     }
 }
\ No newline at end of file
@@ -1,4 +1,9 @@
 pub mod scene;
 pub mod entity;
 pub mod model;
-pub mod mesh;
\ No newline at end of file
+pub mod mesh;
+pub mod asset_manager;
+pub mod model_loader;
+pub mod tangent_space;
+pub mod cooked_model;
+pub mod animation;
\ No newline at end of file
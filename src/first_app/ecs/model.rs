@@ -1,196 +1,859 @@
+use super::animation::*;
+use super::asset_manager::*;
+use super::cooked_model::*;
 use super::mesh::*;
+use super::tangent_space::*;
 
-use crate::first_app::vulkan::lve_descriptor_set::*;
-use crate::first_app::vulkan::lve_device::*;
-use crate::first_app::vulkan::lve_frame_info::*;
 use crate::first_app::vulkan::lve_image::*;
+use crate::first_app::vulkan::lve_sampler::*;
+use crate::first_app::vulkan::lve_texture_file::*;
+use crate::first_app::vulkan::lve_model;
+use crate::first_app::vulkan::lve_error::*;
 
+use rayon::prelude::*;
+
+use std::path::Path;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::time::{Instant};
 
 use nalgebra as na;
 
+/// Texture slots of a material, in the order `MeshTextures` binds them
+pub const TEXTURE_ROLES: [TextureRole; 5] = [
+    TextureRole::BaseColor,
+    TextureRole::MetallicRoughness,
+    TextureRole::Normal,
+    TextureRole::Occlusion,
+    TextureRole::Emissive,
+];
+
+/// A glTF node, `world_transform` is relative to the model and includes all parents
+#[allow(dead_code)]
+#[derive(Clone)]
+pub struct ModelNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub local_transform: na::Matrix4<f32>,
+    pub world_transform: na::Matrix4<f32>,
+    pub sub_meshes: Vec<usize>,
+    /// Index into the model's skins, its sub-meshes follow those joints instead of the node
+    pub skin: Option<usize>,
+    /// Morph target weights of the node's mesh, empty if it has no targets
+    pub weights: Vec<f32>,
+}
+
+/// An image of the file, decoded but not uploaded yet
+pub struct DecodedImage {
+    pub key: AssetKey,
+    pub pixels: DecodedPixels,
+}
+
+/// Compressed KTX2 and DDS textures keep their blocks and mip levels until upload
+pub enum DecodedPixels {
+    Rgba8(image::RgbaImage),
+    TextureFile(LveTextureFile),
+}
+
+/// The parts of a glTF material the renderer uses, copied out of the document so it can cross threads
+#[derive(Clone, Copy)]
+pub struct PrimitiveMaterial {
+    /// Image index for each of `TEXTURE_ROLES`
+    pub textures: [Option<usize>; 5],
+    /// Sampler for each of `TEXTURE_ROLES`, slots without a texture use the default
+    pub samplers: [SamplerSettings; 5],
+    pub uniforms: MeshUniforms,
+}
+
+/// A primitive with its vertices assembled, ready to be uploaded
+pub struct PrimitiveData {
+    pub mesh: usize,
+    pub primitive: usize,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub morph_targets: MorphTargets,
+    pub material: PrimitiveMaterial,
+}
+
+/// An OBJ file read into the same parts a glTF model is built from
+pub struct ObjContents {
+    pub primitives: Vec<PrimitiveData>,
+    /// Texture files of the materials, `PrimitiveMaterial::textures` index these
+    pub image_paths: Vec<String>,
+    pub nodes: Vec<ModelNode>,
+    pub root_nodes: Vec<usize>,
+}
+
+/// A sub-mesh and the transform it is drawn with
+pub struct MeshDraw<'a> {
+    pub mesh: &'a Mesh,
+    pub transform: na::Matrix4<f32>,
+    /// Joint matrices of skinned sub-meshes, `transform` then only places the model
+    pub joints: Option<&'a [na::Matrix4<f32>]>,
+    /// Morph target weights of meshes with targets
+    pub weights: Option<&'a [f32]>,
+}
+
 #[allow(dead_code)]
 pub struct Model {
-    sub_meshes: Vec<Rc<Mesh>>, // in gltf it is primitive
+    /// In glTF these are primitives, `None` until a streamed primitive is uploaded
+    sub_meshes: Vec<Option<Rc<Mesh>>>,
+    /// Same order as the glTF nodes
+    nodes: Vec<ModelNode>,
+    /// Nodes of the scene that gets rendered
+    root_nodes: Vec<usize>,
+    rig: ModelRig,
     file_path: String
 }
 
 impl Model {
-    pub fn new(lve_device: &Rc<LveDevice>, path: &str, global_pool: Rc<LveDescriptorPool>) -> Self{
+    /// Use `AssetManager::load_model` instead, so the file is only loaded once
+    #[allow(dead_code)]
+    pub fn new(assets: &AssetManager, path: &str) -> LveResult<Self> {
+        if Self::is_obj(path) {
+            return Self::new_obj(assets, path);
+        }
+
+        if CookedModel::is_cooked(path) {
+            return Self::new_cooked(assets, path);
+        }
+
         let mut sub_meshes = Vec::new();
 
-        let relative_path = std::path::Path::new(path).parent().unwrap().to_str().unwrap();
+        let start = Instant::now();
+
+        log::info!("Loading {}", path);
+        let (document, buffers) = Self::read_gltf(path)?;
+        let parse_time = start.elapsed();
+
+        // Decoding the JPEGs and PNGs is most of the loading time, so every image gets its own thread
+        let stage = Instant::now();
+        let images = document.images().collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|image| Self::decode_image(path, image, &buffers).map(Some))
+            .collect::<LveResult<Vec<_>>>()?;
+        let decode_time = stage.elapsed();
+
+        let stage = Instant::now();
+        let primitives = Self::primitives(&document)
+            .into_par_iter()
+            .map(|(mesh, primitive)| Self::assemble_primitive(path, mesh, &primitive, &buffers))
+            .collect::<LveResult<Vec<_>>>()?;
+        let assemble_time = stage.elapsed();
+
+        // Vulkan objects are only ever touched from the main thread
+        let stage = Instant::now();
+        let primitive_count = primitives.len();
+        for data in primitives {
+            sub_meshes.push(Self::upload_primitive(assets, path, data, &images)?);
+        }
+        assets.lve_device().flush_uploads()?;
+        let upload_time = stage.elapsed();
+
+        let (nodes, root_nodes) = Self::read_nodes(&document);
+        let rig = ModelRig::read(&document, &buffers);
+
+        log::info!("Loaded {} in {:?}", path, start.elapsed());
+        log::debug!("{}: parse {:?}", path, parse_time);
+        log::debug!("{}: decode {} images {:?}", path, images.len(), decode_time);
+        log::debug!("{}: assemble {} primitives {:?}", path, primitive_count, assemble_time);
+        log::debug!("{}: upload {:?}", path, upload_time);
+
+        Ok(Self::from_parts(sub_meshes, nodes, root_nodes, rig, path))
+    }
 
+    fn new_obj(assets: &AssetManager, path: &str) -> LveResult<Self> {
         let start = Instant::now();
 
-        println!("Loading {}", path);
-        let (document, buffers, _) = gltf::import(path).unwrap();
-        for mesh in document.meshes() {
-            let primitives: Vec<gltf::Primitive> = mesh.primitives().collect();
-            primitives.iter().for_each(|primitive| {
-                // Vectors for mesh
-                let mut vertices: Vec<Vertex> = Vec::new();
-
-                // Get reader
-                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-
-                // Read data to the iter
-                let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
-                let normals: Vec<[f32; 3]> = reader.read_normals().unwrap().collect();
-                let tangents_option = reader.read_tangents();
-                //let mut colors_iter = reader.read_colors(0).unwrap();
-                let tex_coords: Vec<[f32; 2]> = reader.read_tex_coords(0).unwrap().into_f32().collect();
-                let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
-
-                let mut has_tangents = false;
-                let mut tangents: Vec<[f32; 4]> = Vec::new();
-
-                match tangents_option {
-                    Some(_) => {
-                        tangents = tangents_option.unwrap().collect();
-                        has_tangents = true;
-                    }
+        log::info!("Loading {}", path);
+        let ObjContents { primitives, image_paths, nodes, root_nodes } = Self::read_obj(path)?;
+        let images = image_paths
+            .into_par_iter()
+            .map(|image_path| Self::decode_obj_image(&image_path).map(Some))
+            .collect::<LveResult<Vec<_>>>()?;
 
-                    None => {}
-                }
+        let sub_meshes = primitives
+            .into_iter()
+            .map(|data| Self::upload_primitive(assets, path, data, &images))
+            .collect::<LveResult<Vec<_>>>()?;
+        assets.lve_device().flush_uploads()?;
 
-                let count = positions.len();
+        log::info!("Loaded {} in {:?}", path, start.elapsed());
 
-                for i in 0..count {
-                    // Get vertex informations from vectros
-                    let position = positions[i];
-                    let normal = normals[i];
-                    let mut tangent = [0.0, 0.0, 0.0, 0.0];
-                    if has_tangents {
-                        tangent = tangents[i];
-                    }
-                    let tex_coord = tex_coords[i];
+        Ok(Self::from_parts(sub_meshes, nodes, root_nodes, ModelRig::default(), path))
+    }
 
-                    let vertex = Vertex {
-                        position: na::vector![position[0], position[1], position[2]],
-                        color: na::vector![1.0, 1.0, 1.0],
-                        normal: na::vector![normal[0], normal[1], normal[2]],
-                        tangent: na::vector![tangent[0], tangent[1], tangent[2], tangent[3]],
-                        tex_coord: na::vector![tex_coord[0], tex_coord[1]],
-                    };
+    /// Vertices, indices and compressed textures are uploaded straight from the memory mapped file
+    fn new_cooked(assets: &AssetManager, path: &str) -> LveResult<Self> {
+        let start = Instant::now();
 
-                    vertices.push(vertex);
+        let cooked = CookedModel::open(path)?;
+        let lve_device = assets.lve_device();
+
+        let sub_meshes = cooked.primitives
+            .iter()
+            .map(|primitive| {
+                if !Self::is_drawable(path, cooked.bytes(&primitive.vertices).len() / std::mem::size_of::<Vertex>()) {
+                    return Ok(None);
                 }
 
-                let mut textures = MeshTextures::new(lve_device.clone());
-                let mut uniforms = MeshUniforms::new();
+                assets.mesh(&Self::mesh_key(path, primitive.mesh, primitive.primitive), || {
+                    let (textures, _) = Self::material_textures(assets, &primitive.material, |index, role| {
+                        let image = &cooked.images[index];
+                        let srgb = role.format() == ash::vk::Format::R8G8B8A8_SRGB;
+                        let image = assets.image(cooked.image_key(index), role, || {
+                            LveImage::from_compressed(lve_device.clone(), image.format, image.width, image.height, image.mip_levels, cooked.bytes(&image.data), srgb)
+                        })?;
+
+                        Ok(Some(image))
+                    })?;
+
+                    Mesh::from_bytes(
+                        lve_device.clone(),
+                        cooked.bytes(&primitive.vertices),
+                        cooked.bytes(&primitive.indices),
+                        primitive.index_type,
+                        cooked.bytes(&primitive.morph_targets),
+                        primitive.morph_target_count as u32,
+                        textures,
+                        primitive.material.uniforms,
+                        assets.global_pool().clone(),
+                    )
+                })
+                .map(Some)
+            })
+            .collect::<LveResult<Vec<_>>>()?;
+        lve_device.flush_uploads()?;
+
+        log::info!("Loaded {} in {:?}", path, start.elapsed());
+
+        let CookedModel { nodes, root_nodes, rig, .. } = cooked;
+        Ok(Self::from_parts(sub_meshes, nodes, root_nodes, rig, path))
+    }
 
-                let albedo_texture = primitive.material().pbr_metallic_roughness().base_color_texture();
-                match albedo_texture {
-                    Some(_) => {
-                        let mut image_path: String = "".to_string();
-                        let texture = albedo_texture.unwrap().texture().source().source();
-                        if let gltf::image::Source::Uri {uri, ..} = texture {
-                            image_path =  relative_path.to_owned() + "/" + uri;
-                        }
+    /// OBJ files go through `read_obj`, everything else is treated as glTF
+    pub fn is_obj(path: &str) -> bool {
+        Path::new(path).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("obj"))
+    }
 
-                        textures.base_color = LveImage::new(lve_device.clone(), image_path.as_str());
-                    },
-                    None => {
-                        let color = primitive.material().pbr_metallic_roughness().base_color_factor();
-                        uniforms.base_color = na::vector![color[0], color[1], color[2]];
-                    }
-                }
+    /// Shared through the asset manager, so a mesh already loaded by another model is reused
+    /// `None` for primitives that are skipped, see `is_drawable`
+    fn upload_primitive(assets: &AssetManager, path: &str, data: PrimitiveData, images: &[Option<DecodedImage>]) -> LveResult<Option<Rc<Mesh>>> {
+        if !Self::is_drawable(path, data.vertices.len()) {
+            return Ok(None);
+        }
 
-                let metallic_roughness_texture = primitive.material().pbr_metallic_roughness().metallic_roughness_texture();
-                match metallic_roughness_texture {
-                    Some(_) => {
-                        let mut image_path: String = "".to_string();
-                        let texture = metallic_roughness_texture.unwrap().texture().source().source();
-                        if let gltf::image::Source::Uri {uri, ..} = texture {
-                            image_path =  relative_path.to_owned() + "/" + uri;
-                        }
-
-                        textures.metallic_roughness = LveImage::new(lve_device.clone(), image_path.as_str());
-                    },
-                    None => {
-                        uniforms.metallic = primitive.material().pbr_metallic_roughness().metallic_factor();
-                        uniforms.roughness = primitive.material().pbr_metallic_roughness().roughness_factor();
-                    }
-                }
+        assets.mesh(&Self::mesh_key(path, data.mesh, data.primitive), || {
+            let (textures, _) = Self::mesh_textures(assets, &data.material, images)?;
+            Mesh::new(assets.lve_device().clone(), data.vertices, data.indices, &data.morph_targets, textures, data.material.uniforms, assets.global_pool().clone())
+        }).map(Some)
+    }
 
-                let normal_texture = primitive.material().normal_texture();
-                match normal_texture {
-                    Some(_) => {
-                        let mut image_path: String = "".to_string();
-                        let texture = normal_texture.unwrap().texture().source().source();
-                        if let gltf::image::Source::Uri {uri, ..} = texture {
-                            image_path =  relative_path.to_owned() + "/" + uri;
-                        }
-
-                        textures.normal = LveImage::new(lve_device.clone(), image_path.as_str());
-                    },
-                    None => {}
-                }
+    /// A primitive with fewer than 3 vertices is valid glTF but can't make a triangle, the model
+    /// loads without it instead of failing
+    pub fn is_drawable(path: &str, vertex_count: usize) -> bool {
+        if vertex_count < 3 {
+            log::warn!("Skipping a primitive of {} with only {} vertices", path, vertex_count);
+        }
+
+        vertex_count >= 3
+    }
+
+    /// `nodes` must already have their world transforms
+    pub fn from_parts(sub_meshes: Vec<Option<Rc<Mesh>>>, nodes: Vec<ModelNode>, root_nodes: Vec<usize>, rig: ModelRig, path: &str) -> Self {
+        Self {
+            sub_meshes,
+            nodes,
+            root_nodes,
+            rig,
+            file_path: String::from_str(path).unwrap()
+        }
+    }
+
+    /// Parses the document and reads its buffers, images are left for `decode_image`
+    pub fn read_gltf(path: &str) -> LveResult<(gltf::Document, Vec<gltf::buffer::Data>)> {
+        let gltf_error = |source| LveError::Gltf { path: path.to_string(), source };
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(path).map_err(gltf_error)?;
+        let buffers = gltf::import_buffers(&document, Some(Self::base_dir(path)), blob).map_err(gltf_error)?;
+
+        Ok((document, buffers))
+    }
+
+    /// Every primitive with the index of its mesh, sub-meshes use the same order
+    pub fn primitives(document: &gltf::Document) -> Vec<(usize, gltf::Primitive<'_>)> {
+        document.meshes()
+            .flat_map(|mesh| mesh.primitives().map(move |primitive| (mesh.index(), primitive)))
+            .collect()
+    }
+
+    /// The node hierarchy with world transforms and the indices of the root nodes
+    pub fn read_nodes(document: &gltf::Document) -> (Vec<ModelNode>, Vec<usize>) {
+        // Sub-mesh indices of every glTF mesh, a mesh can be used by more than one node
+        let mut mesh_primitives: Vec<Vec<usize>> = vec![Vec::new(); document.meshes().len()];
+        for (sub_mesh, (mesh, _)) in Self::primitives(document).into_iter().enumerate() {
+            mesh_primitives[mesh].push(sub_mesh);
+        }
+
+        let mut nodes: Vec<ModelNode> = document.nodes().map(|node| ModelNode {
+            name: node.name().map(str::to_string),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            local_transform: na::Matrix4::from(node.transform().matrix()),
+            world_transform: na::Matrix4::identity(),
+            sub_meshes: node.mesh().map(|mesh| mesh_primitives[mesh.index()].clone()).unwrap_or_default(),
+            skin: node.skin().map(|skin| skin.index()),
+            weights: node.mesh().map(|mesh| Self::default_weights(&node, &mesh)).unwrap_or_default(),
+        }).collect();
+
+        for index in 0..nodes.len() {
+            for child in nodes[index].children.clone() {
+                nodes[child].parent = Some(index);
+            }
+        }
+
+        // Files without scenes still get drawn, every top level node is treated as a root
+        let root_nodes: Vec<usize> = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => (0..nodes.len()).filter(|&index| nodes[index].parent.is_none()).collect(),
+        };
+
+        Self::compute_world_transforms(&mut nodes, &root_nodes);
+
+        (nodes, root_nodes)
+    }
+
+    /// The node's weights, else the mesh's, else zeros. Padded or cut to the number of targets.
+    fn default_weights(node: &gltf::Node, mesh: &gltf::Mesh) -> Vec<f32> {
+        let target_count = mesh.primitives().map(|primitive| primitive.morph_targets().len()).max().unwrap_or(0);
+
+        let mut weights = node.weights().or_else(|| mesh.weights()).unwrap_or_default().to_vec();
+        weights.resize(target_count, 0.0);
+        weights
+    }
+
+    /// Keys the mesh by file, glTF mesh and primitive so a reload of the same file shares it
+    pub fn mesh_key(path: &str, mesh: usize, primitive: usize) -> String {
+        let file = match AssetKey::path(path) {
+            AssetKey::Path(path) => path,
+            AssetKey::Hash(hash) => hash.to_string(),
+        };
+
+        format!("{}#{}/{}", file, mesh, primitive)
+    }
 
-                let occlusion_texture = primitive.material().occlusion_texture();
-                match occlusion_texture {
-                    Some(_) => {
-                        let mut image_path: String = "".to_string();
-                        let texture = occlusion_texture.unwrap().texture().source().source();
-                        if let gltf::image::Source::Uri {uri, ..} = texture {
-                            image_path =  relative_path.to_owned() + "/" + uri;
-                        }
-
-                        textures.occlusion = LveImage::new(lve_device.clone(), image_path.as_str());
-                    },
-                    None => {}
+    /// Runs on the rayon pool, so it must not touch the device
+    pub fn assemble_primitive(path: &str, mesh: usize, primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> LveResult<PrimitiveData> {
+        let missing = |attribute| LveError::MissingAttribute { path: path.to_string(), attribute };
+
+        // Get reader
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        // Only positions are required, everything else has a default or can be generated
+        let positions: Vec<[f32; 3]> = reader.read_positions().ok_or_else(|| missing("POSITION"))?.collect();
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| normals.collect());
+        // Tangents only make sense together with the normals they were made for
+        let tangents: Option<Vec<[f32; 4]>> = reader.read_tangents().filter(|_| normals.is_some()).map(|tangents| tangents.collect());
+        //let mut colors_iter = reader.read_colors(0).unwrap();
+        let material = Self::read_material(&primitive.material());
+        let tex_coords: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|tex_coords| tex_coords.into_f32().collect());
+        let tex_coords_1: Option<Vec<[f32; 2]>> = reader.read_tex_coords(1).map(|tex_coords| tex_coords.into_f32().collect());
+        // Joints without weights can't be skinned, such vertices stay where the node puts them
+        let joints: Option<Vec<[u16; 4]>> = reader.read_joints(0).map(|joints| joints.into_u16().collect());
+        let weights: Option<Vec<[f32; 4]>> = reader.read_weights(0).filter(|_| joints.is_some()).map(|weights| weights.into_f32().collect());
+        let mut indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let mut vertices: Vec<Vertex> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, position)| {
+                let normal = normals.as_ref().map_or([0.0, 0.0, 1.0], |normals| normals[i]);
+                let tangent = tangents.as_ref().map_or([0.0, 0.0, 0.0, 1.0], |tangents| tangents[i]);
+                let tex_coord = tex_coords.as_ref().map_or([0.0, 0.0], |tex_coords| tex_coords[i]);
+                let tex_coord_1 = tex_coords_1.as_ref().map_or(tex_coord, |tex_coords| tex_coords[i]);
+                let joint = joints.as_ref().map_or([0; 4], |joints| joints[i]).map(u32::from);
+                let weight = weights.as_ref().map_or([0.0; 4], |weights| weights[i]);
+
+                Vertex {
+                    position: na::vector![position[0], position[1], position[2]],
+                    color: na::vector![1.0, 1.0, 1.0],
+                    normal: na::vector![normal[0], normal[1], normal[2]],
+                    tangent: na::vector![tangent[0], tangent[1], tangent[2], tangent[3]],
+                    tex_coord: na::vector![tex_coord[0], tex_coord[1]],
+                    tex_coord_1: na::vector![tex_coord_1[0], tex_coord_1[1]],
+                    joints: na::Vector4::from(joint),
+                    weights: na::Vector4::from(weight),
                 }
+            })
+            .collect();
 
-                let emessive_texture = primitive.material().emissive_texture();
-                match emessive_texture {
-                    Some(_) => {
-                        let mut image_path: String = "".to_string();
-                        let texture = emessive_texture.unwrap().texture().source().source();
-                        if let gltf::image::Source::Uri {uri, ..} = texture {
-                            image_path =  relative_path.to_owned() + "/" + uri;
-                        }
-
-                        textures.metallic_roughness = LveImage::new(lve_device.clone(), image_path.as_str());
-                    },
+        // Generated normals and tangents duplicate vertices, this is where each one came from
+        let mut sources: Vec<usize> = (0..vertices.len()).collect();
+
+        if normals.is_none() {
+            sources = indices.iter().map(|&index| index as usize).collect();
+            generate_flat_normals(&mut vertices, &mut indices);
+        }
+
+        if tangents.is_none() {
+            let copies = generate_tangents(&mut vertices, &mut indices);
+            let copied = copies.into_iter().map(|copy| sources[copy]).collect::<Vec<_>>();
+            sources.extend(copied);
+        }
+
+        let morph_targets = Self::read_morph_targets(&reader, positions.len(), &sources);
+
+        Ok(PrimitiveData {
+            mesh,
+            primitive: primitive.index(),
+            vertices,
+            indices,
+            morph_targets,
+            material,
+        })
+    }
+
+    /// Deltas of every target for every vertex, `sources` maps the final vertices to the file's.
+    /// Targets without normal or tangent deltas leave those alone.
+    fn read_morph_targets<'a, 's, F>(reader: &gltf::mesh::Reader<'a, 's, F>, vertex_count: usize, sources: &[usize]) -> MorphTargets
+    where
+        F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+    {
+        let targets = reader.read_morph_targets().map(|(positions, normals, tangents)| {
+            let read = |deltas: Option<Vec<[f32; 3]>>| deltas.filter(|deltas| deltas.len() == vertex_count);
+            (
+                read(positions.map(|positions| positions.collect())),
+                read(normals.map(|normals| normals.collect())),
+                read(tangents.map(|tangents| tangents.collect())),
+            )
+        }).collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return MorphTargets::default();
+        }
+
+        let delta = |deltas: &Option<Vec<[f32; 3]>>, source: usize| {
+            deltas.as_ref().map_or(na::Vector4::zeros(), |deltas| na::Vector3::from(deltas[source]).push(0.0))
+        };
+
+        let deltas = sources
+            .iter()
+            .flat_map(|&source| targets.iter().map(move |(positions, normals, tangents)| MorphDelta {
+                position: delta(positions, source),
+                normal: delta(normals, source),
+                tangent: delta(tangents, source),
+            }))
+            .collect();
+
+        MorphTargets { count: targets.len(), deltas }
+    }
+
+    fn read_material(material: &gltf::Material) -> PrimitiveMaterial {
+        let pbr = material.pbr_metallic_roughness();
+        let normal_texture = material.normal_texture();
+        let occlusion_texture = material.occlusion_texture();
+
+        let mut uniforms = MeshUniforms {
+            base_color: na::Vector4::from(pbr.base_color_factor()),
+            emissive: na::Vector3::from(material.emissive_factor()),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            normal_scale: normal_texture.as_ref().map_or(1.0, |info| info.scale()),
+            occlusion_strength: occlusion_texture.as_ref().map_or(1.0, |info| info.strength()),
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            double_sided: if material.double_sided() { ash::vk::TRUE } else { ash::vk::FALSE },
+            ..MeshUniforms::new()
+        };
+
+        // Image, sampler and UV transform of each slot
+        let slot = |texture: gltf::Texture, transform| (texture.source().index(), SamplerSettings::from_gltf(&texture.sampler()), transform);
+        const EXTENSION: &str = "KHR_texture_transform";
+        let slots = [
+            pbr.base_color_texture().map(|info| slot(info.texture(), Self::texture_transform(info.extension_value(EXTENSION), info.tex_coord()))),
+            pbr.metallic_roughness_texture().map(|info| slot(info.texture(), Self::texture_transform(info.extension_value(EXTENSION), info.tex_coord()))),
+            normal_texture.as_ref().map(|info| slot(info.texture(), Self::texture_transform(info.extension_value(EXTENSION), info.tex_coord()))),
+            occlusion_texture.as_ref().map(|info| slot(info.texture(), Self::texture_transform(info.extension_value(EXTENSION), info.tex_coord()))),
+            material.emissive_texture().map(|info| slot(info.texture(), Self::texture_transform(info.extension_value(EXTENSION), info.tex_coord()))),
+        ];
+
+        for (transform, slot) in uniforms.texture_transforms.iter_mut().zip(slots.iter()) {
+            let mut slot_transform = slot.as_ref().map_or_else(TextureTransform::default, |(_, _, transform)| *transform);
+
+            // Vertices carry two sets of texture coordinates
+            if slot_transform.tex_coord > 1 {
+                log::warn!("Material {:?} uses TEXCOORD_{}, it samples TEXCOORD_0 instead", material.name(), slot_transform.tex_coord);
+                slot_transform.tex_coord = 0;
+            }
+
+            *transform = slot_transform;
+        }
+
+        Self::read_material_extensions(material, &mut uniforms);
+
+        PrimitiveMaterial {
+            textures: slots.map(|slot| slot.map(|(image, _, _)| image)),
+            samplers: slots.map(|slot| slot.map_or_else(SamplerSettings::default, |(_, sampler, _)| sampler)),
+            uniforms,
+        }
+    }
+
+    /// The factors of the `KHR_materials_*` extensions, their textures aren't sampled. Missing or
+    /// malformed properties keep the defaults of `MeshUniforms::new`, which match the extensions'.
+    fn read_material_extensions(material: &gltf::Material, uniforms: &mut MeshUniforms) {
+        let property = |extension: &str, name: &str| material.extension_value(extension).and_then(|extension| extension.get(name));
+        let scalar = |extension: &str, name: &str, default: f32| {
+            property(extension, name).and_then(|value| value.as_f64()).map_or(default, |value| value as f32)
+        };
+
+        uniforms.emissive_strength = scalar("KHR_materials_emissive_strength", "emissiveStrength", uniforms.emissive_strength);
+        uniforms.clearcoat = scalar("KHR_materials_clearcoat", "clearcoatFactor", uniforms.clearcoat);
+        uniforms.clearcoat_roughness = scalar("KHR_materials_clearcoat", "clearcoatRoughnessFactor", uniforms.clearcoat_roughness);
+        uniforms.transmission = scalar("KHR_materials_transmission", "transmissionFactor", uniforms.transmission);
+        uniforms.sheen_roughness = scalar("KHR_materials_sheen", "sheenRoughnessFactor", uniforms.sheen_roughness);
+        uniforms.ior = scalar("KHR_materials_ior", "ior", uniforms.ior);
+
+        let sheen_color = property("KHR_materials_sheen", "sheenColorFactor").and_then(|value| value.as_array());
+        if let Some([r, g, b]) = sheen_color.map(|values| values.as_slice()) {
+            if let (Some(r), Some(g), Some(b)) = (r.as_f64(), g.as_f64(), b.as_f64()) {
+                uniforms.sheen_color = na::vector![r as f32, g as f32, b as f32];
+            }
+        }
+    }
+
+    /// The `KHR_texture_transform` of a slot, whose `texCoord` replaces the slot's own `tex_coord`.
+    /// Missing or malformed properties keep their defaults.
+    fn texture_transform(extension: Option<&gltf::json::Value>, tex_coord: u32) -> TextureTransform {
+        let property = |name: &str| extension.and_then(|extension| extension.get(name));
+        let vector = |name: &str| {
+            let values = property(name)?.as_array()?;
+            match values.as_slice() {
+                [x, y] => Some(na::vector![x.as_f64()? as f32, y.as_f64()? as f32]),
+                _ => None,
+            }
+        };
+
+        let default = TextureTransform::default();
+        TextureTransform {
+            offset: vector("offset").unwrap_or(default.offset),
+            scale: vector("scale").unwrap_or(default.scale),
+            rotation: property("rotation").and_then(|rotation| rotation.as_f64()).map_or(default.rotation, |rotation| rotation as f32),
+            tex_coord: property("texCoord").and_then(|tex_coord| tex_coord.as_u64()).map_or(tex_coord, |tex_coord| tex_coord as u32),
+        }
+    }
+
+    /// Uploads the material's images, slots whose image isn't decoded yet get the default texture.
+    /// The flag is false if any slot is still waiting for its image.
+    pub fn mesh_textures(assets: &AssetManager, material: &PrimitiveMaterial, images: &[Option<DecodedImage>]) -> LveResult<(MeshTextures, bool)> {
+        Self::material_textures(assets, material, |index, role| {
+            images[index].as_ref().map(|image| assets.image_from_decoded(image, role)).transpose()
+        })
+    }
+
+    /// `image` returns the texture for an image index of the material, `None` if it isn't ready
+    fn material_textures(
+        assets: &AssetManager,
+        material: &PrimitiveMaterial,
+        mut image: impl FnMut(usize, TextureRole) -> LveResult<Option<Rc<LveImage>>>,
+    ) -> LveResult<(MeshTextures, bool)> {
+        let mut slots = Vec::with_capacity(TEXTURE_ROLES.len());
+        let mut complete = true;
+
+        for ((&role, &texture), &sampler) in TEXTURE_ROLES.iter().zip(material.textures.iter()).zip(material.samplers.iter()) {
+            let image = match texture {
+                Some(index) => match image(index, role)? {
+                    Some(image) => image,
                     None => {
-                        let emissive = primitive.material().emissive_factor();
-                        uniforms.emissive = na::vector![emissive[0], emissive[1], emissive[2]];
+                        complete = false;
+                        assets.default_image(role)?
                     }
+                },
+                None => assets.default_image(role)?,
+            };
+
+            slots.push(MeshTexture { image, sampler: assets.sampler(sampler)? });
+        }
+
+        let mut slots = slots.into_iter();
+        let mut next = || slots.next().unwrap();
+
+        Ok((MeshTextures {
+            base_color: next(),
+            metallic_roughness: next(),
+            normal: next(),
+            occlusion: next(),
+            emissive: next(),
+        }, complete))
+    }
+
+    /// Every OBJ object becomes mesh `i` with a single primitive and a node of its own under one
+    /// root, OBJ has no hierarchy. Must not touch the device either.
+    pub fn read_obj(path: &str) -> LveResult<ObjContents> {
+        let mut builder = lve_model::Builder::new();
+        builder.load_from_file(path)?;
+
+        let base = Self::base_dir(path);
+        let mut image_paths = Vec::new();
+        let materials = builder.materials
+            .iter()
+            .map(|material| Self::read_obj_material(material, base, &mut image_paths))
+            .collect::<Vec<_>>();
+
+        // Groups without faces have nothing to draw
+        let sub_models = builder.sub_models.iter().filter(|sub_model| !sub_model.indices.is_empty()).collect::<Vec<_>>();
+
+        let primitives = sub_models
+            .par_iter()
+            .enumerate()
+            .map(|(mesh, &sub_model)| {
+                let first_vertex = sub_model.vertices.start as u32;
+                let mut vertices = builder.vertices[sub_model.vertices.clone()].to_vec();
+                let mut indices = builder.indices[sub_model.indices.clone()]
+                    .iter()
+                    .map(|index| index - first_vertex)
+                    .collect::<Vec<_>>();
+
+                if !sub_model.has_normals {
+                    generate_smooth_normals(&mut vertices, &indices);
+                }
+                generate_tangents(&mut vertices, &mut indices);
+
+                PrimitiveData {
+                    mesh,
+                    primitive: 0,
+                    vertices,
+                    indices,
+                    morph_targets: MorphTargets::default(),
+                    material: sub_model.material.map_or_else(Self::default_obj_material, |material| materials[material]),
                 }
+            })
+            .collect::<Vec<_>>();
+
+        let node = |name, parent, children, sub_meshes| ModelNode {
+            name,
+            parent,
+            children,
+            local_transform: na::Matrix4::identity(),
+            world_transform: na::Matrix4::identity(),
+            sub_meshes,
+            skin: None,
+            weights: Vec::new(),
+        };
 
-                let mesh = Mesh::new(lve_device.clone(), vertices, indices, textures, uniforms, global_pool.clone());
+        let mut nodes = vec![node(None, None, (1..=sub_models.len()).collect(), Vec::new())];
+        nodes.extend(sub_models.iter().enumerate().map(|(mesh, sub_model)| {
+            node(Some(sub_model.name.clone()), Some(0), Vec::new(), vec![mesh])
+        }));
+
+        Ok(ObjContents { primitives, image_paths, nodes, root_nodes: vec![0] })
+    }
 
-                sub_meshes.push(mesh);
-            });
+    /// Maps MTL parameters onto the metallic-roughness model. `Pm`, `Pr` and `Ke` come from the PBR
+    /// extension of the format, without `Pr` the roughness is derived from the Phong exponent.
+    fn read_obj_material(material: &tobj::Material, base: &Path, image_paths: &mut Vec<String>) -> PrimitiveMaterial {
+        let param = |name: &str| material.unknown_param.get(name).map(|value| value.trim());
+        let scalar = |name| param(name).and_then(|value| value.parse::<f32>().ok());
+        let color = |name| {
+            let values = param(name)?.split_whitespace().map(|value| value.parse::<f32>().ok()).collect::<Option<Vec<_>>>()?;
+            match values[..] {
+                [r, g, b] => Some(na::vector![r, g, b]),
+                _ => None,
+            }
+        };
+
+        let mut texture = |map: &str| {
+            // Options like `-bm 0.5` come before the file name
+            let file = map.split_whitespace().last()?.replace('\\', "/");
+            let path = base.join(file).to_string_lossy().into_owned();
+
+            Some(image_paths.iter().position(|known| *known == path).unwrap_or_else(|| {
+                image_paths.push(path);
+                image_paths.len() - 1
+            }))
+        };
+
+        let base_color_texture = texture(&material.diffuse_texture);
+        let normal_texture = texture(&material.normal_texture).or_else(|| texture(param("norm")?));
+        let emissive_texture = param("map_Ke").and_then(&mut texture);
+
+        let mut uniforms = MeshUniforms::new();
+
+        // Kd is usually a grey preview of `map_Kd`, so only materials without the map use it
+        let diffuse = if base_color_texture.is_none() { na::Vector3::from(material.diffuse) } else { na::vector![1.0, 1.0, 1.0] };
+        uniforms.base_color = diffuse.push(material.dissolve);
+        if material.dissolve < 1.0 {
+            uniforms.alpha_mode = AlphaMode::Blend;
         }
-        println!("Loaded {}", path);
-        let duration = start.elapsed();
 
-        println!("It took only: {:?}", duration);
+        uniforms.metallic = scalar("Pm").unwrap_or(0.0);
+        uniforms.roughness = scalar("Pr").unwrap_or_else(|| (2.0 / (material.shininess + 2.0)).sqrt());
 
-        Self {
-            sub_meshes,
-            file_path: String::from_str(path).unwrap()
+        uniforms.emissive = match emissive_texture {
+            Some(_) => na::vector![1.0, 1.0, 1.0],
+            None => color("Ke").unwrap_or_else(na::Vector3::zeros),
+        };
+
+        PrimitiveMaterial {
+            textures: [base_color_texture, None, normal_texture, None, emissive_texture],
+            samplers: [SamplerSettings::default(); 5],
+            uniforms,
         }
     }
 
-    pub fn render(&self, device: &ash::Device, frame_info: &FrameInfo, pipeline_layout: ash::vk::PipelineLayout) {
-        for mesh in self.sub_meshes.iter() {
-            unsafe {
-                device.cmd_bind_descriptor_sets(
-                    frame_info.command_buffer,
-                    ash::vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
-                    0,
-                    &[frame_info.global_descriptor_set, mesh.descriptor_set],
-                    &[],
-                );
+    /// For objects without a `usemtl`, a white dielectric
+    pub fn default_obj_material() -> PrimitiveMaterial {
+        PrimitiveMaterial {
+            textures: [None; 5],
+            samplers: [SamplerSettings::default(); 5],
+            uniforms: MeshUniforms {
+                metallic: 0.0,
+                ..MeshUniforms::new()
+            },
+        }
+    }
+
+    /// A texture file of an MTL material. Missing files are common in OBJ downloads, those get the
+    /// default texture instead of failing the whole model.
+    pub fn decode_obj_image(path: &str) -> LveResult<DecodedImage> {
+        let open = |path: &str| {
+            let pixels = if LveTextureFile::is_container(path) {
+                DecodedPixels::TextureFile(LveTextureFile::open(path)?)
+            } else {
+                let image = image::open(path).map_err(|source| LveError::Image { path: path.to_string(), source })?;
+                DecodedPixels::Rgba8(image.to_rgba8())
+            };
+
+            Ok(DecodedImage { key: AssetKey::path(path), pixels })
+        };
+
+        open(path).or_else(|e| {
+            log::warn!("{}, using the default texture", e);
+            open(DEFAULT_TEXTURE_PATH)
+        })
+    }
+
+    /// Runs on the rayon pool, so it must not touch the device
+    pub fn decode_image(path: &str, image: gltf::Image, buffers: &[gltf::buffer::Data]) -> LveResult<DecodedImage> {
+        let base = Self::base_dir(path);
+        let data = gltf::image::Data::from_source(image.source(), Some(base), buffers)
+            .map_err(|source| LveError::Gltf { path: path.to_string(), source })?;
+        let pixels = Self::to_rgba8(&data).ok_or(LveError::Unsupported("glTF image data doesn't match its size"))?;
+
+        // Textures from image files are shared by path, embedded ones by their pixels
+        let key = match image.source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                AssetKey::path(&base.join(uri).to_string_lossy())
+            }
+            _ => AssetKey::hash(&pixels),
+        };
+
+        Ok(DecodedImage { key, pixels: DecodedPixels::Rgba8(pixels) })
+    }
+
+    /// Relative URIs in the file are resolved against this
+    fn base_dir(path: &str) -> &Path {
+        Path::new(path).parent().unwrap_or_else(|| Path::new("./"))
+    }
+
+    /// Expands whatever pixel format the image was decoded to into RGBA8
+    fn to_rgba8(data: &gltf::image::Data) -> Option<image::RgbaImage> {
+        use gltf::image::Format;
+        use image::{DynamicImage, ImageBuffer};
+
+        let (width, height) = (data.width, data.height);
+        let pixels = || data.pixels.clone();
+        // 16 and 32 bit formats are stored as native endian bytes
+        let pixels_u16 = || data.pixels.chunks_exact(2).map(|b| u16::from_ne_bytes([b[0], b[1]])).collect::<Vec<_>>();
+        let pixels_f32 = || data.pixels.chunks_exact(4).map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]])).collect::<Vec<_>>();
+
+        let image = match data.format {
+            Format::R8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, pixels())?),
+            Format::R8G8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, pixels())?),
+            Format::R8G8B8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, pixels())?),
+            Format::R8G8B8A8 => return ImageBuffer::from_raw(width, height, pixels()),
+            Format::R16 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, pixels_u16())?),
+            Format::R16G16 => DynamicImage::ImageLumaA16(ImageBuffer::from_raw(width, height, pixels_u16())?),
+            Format::R16G16B16 => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, pixels_u16())?),
+            Format::R16G16B16A16 => DynamicImage::ImageRgba16(ImageBuffer::from_raw(width, height, pixels_u16())?),
+            Format::R32G32B32FLOAT => DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, pixels_f32())?),
+            Format::R32G32B32A32FLOAT => DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, pixels_f32())?),
+        };
+
+        Some(image.to_rgba8())
+    }
+
+    #[allow(dead_code)]
+    pub fn nodes(&self) -> &[ModelNode] {
+        &self.nodes
+    }
+
+    pub fn root_nodes(&self) -> &[usize] {
+        &self.root_nodes
+    }
+
+    pub fn rig(&self) -> &ModelRig {
+        &self.rig
+    }
+
+    /// A pose for an entity drawing this model, `None` for models that have nothing to animate
+    pub fn new_pose(&self) -> Option<ModelPose> {
+        let morphed = self.nodes.iter().any(|node| !node.weights.is_empty());
+        (morphed || !self.rig.is_empty()).then(|| ModelPose::new(&self.nodes, &self.root_nodes, &self.rig.skins))
+    }
+
+    /// Recomputes every `world_transform` from the local transforms, call after changing those
+    #[allow(dead_code)]
+    pub fn update_world_transforms(&mut self) {
+        Self::compute_world_transforms(&mut self.nodes, &self.root_nodes);
+    }
+
+    pub fn compute_world_transforms(nodes: &mut [ModelNode], root_nodes: &[usize]) {
+        let mut stack: Vec<(usize, na::Matrix4<f32>)> = root_nodes
+            .iter()
+            .map(|&root| (root, na::Matrix4::identity()))
+            .collect();
+
+        while let Some((index, parent_transform)) = stack.pop() {
+            let node = &mut nodes[index];
+            node.world_transform = parent_transform * node.local_transform;
+
+            let world_transform = node.world_transform;
+            stack.extend(node.children.iter().map(|&child| (child, world_transform)));
+        }
+    }
 
-                mesh.bind(frame_info.command_buffer);
-                mesh.draw(device, frame_info.command_buffer);
+    /// Adds every sub-mesh of the nodes reachable from the scene roots, `model_matrix` places the whole model.
+    /// With a pose the nodes are drawn where it puts them, otherwise at their transforms from the file.
+    pub fn collect_draws<'a>(&'a self, model_matrix: &na::Matrix4<f32>, pose: Option<&'a ModelPose>, draws: &mut Vec<MeshDraw<'a>>) {
+        // A pose made for a model that has since been replaced by a reload doesn't fit its nodes
+        let pose = pose.filter(|pose| pose.world_transforms.len() == self.nodes.len());
+        let mut stack = self.root_nodes.clone();
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            stack.extend(node.children.iter());
+
+            let world_transform = pose.map_or(&node.world_transform, |pose| &pose.world_transforms[index]);
+            let joints = node.skin.and_then(|skin| pose?.joint_matrices.get(skin)).map(Vec::as_slice);
+            let weights = pose.map_or(&node.weights, |pose| &pose.weights[index]);
+
+            // Joint matrices already include the node hierarchy, glTF ignores the transform of skinned nodes
+            let transform = match joints {
+                Some(_) => *model_matrix,
+                None => model_matrix * world_transform,
+            };
+
+            // Streamed sub-meshes that aren't uploaded yet are skipped
+            for mesh in node.sub_meshes.iter().filter_map(|&sub_mesh| self.sub_meshes[sub_mesh].as_ref()) {
+                let weights = (mesh.morph_target_count() > 0 && !weights.is_empty()).then_some(weights.as_slice());
+                draws.push(MeshDraw { mesh, transform, joints, weights });
             }
         }
     }
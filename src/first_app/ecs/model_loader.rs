@@ -0,0 +1,342 @@
+use super::animation::*;
+use super::asset_manager::*;
+use super::cooked_model::*;
+use super::mesh::*;
+use super::model::*;
+
+use crate::first_app::vulkan::lve_error::*;
+
+use rayon::prelude::*;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// How long a poll may spend uploading before it hands the frame back
+const UPLOAD_BUDGET: Duration = Duration::from_millis(8);
+
+/// Sent by the loading thread, in this order: the structure, every primitive, every image
+enum LoadEvent {
+    Structure {
+        nodes: Vec<ModelNode>,
+        root_nodes: Vec<usize>,
+        rig: Box<ModelRig>,
+        primitive_count: usize,
+        image_count: usize,
+    },
+    Primitive(usize, Box<PrimitiveData>),
+    Image(usize, DecodedImage),
+    Failed(LveError),
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LoadProgress {
+    pub primitives_loaded: usize,
+    pub primitive_count: usize,
+    pub images_loaded: usize,
+    pub image_count: usize,
+    /// Textures that are attached, primitives still waiting for some of theirs show the default ones
+    pub textured_primitives: usize,
+    pub done: bool,
+}
+
+impl LoadProgress {
+    /// 0 to 1, primitives and images weigh the same
+    pub fn fraction(&self) -> f32 {
+        let total = self.primitive_count * 2 + self.image_count;
+        if total == 0 {
+            return if self.done { 1.0 } else { 0.0 };
+        }
+
+        (self.primitives_loaded + self.textured_primitives + self.images_loaded) as f32 / total as f32
+    }
+}
+
+/// A glTF model being loaded on a background thread. Parsing, decoding and vertex assembly happen
+/// there, `poll` uploads whatever has arrived and hands out a new `Model` with everything resident so far.
+pub struct ModelLoadHandle {
+    path: String,
+    receiver: mpsc::Receiver<LoadEvent>,
+    nodes: Vec<ModelNode>,
+    root_nodes: Vec<usize>,
+    rig: ModelRig,
+    sub_meshes: Vec<Option<Rc<Mesh>>>,
+    /// Mesh key and material of sub-meshes that still render with default textures
+    pending: Vec<Option<(String, PrimitiveMaterial)>>,
+    images: Vec<Option<DecodedImage>>,
+    progress: LoadProgress,
+    /// Models handed out earlier may still be drawn by frames in flight
+    retired: VecDeque<(usize, Rc<Model>)>,
+    polls: usize,
+    frames_in_flight: usize,
+}
+
+impl ModelLoadHandle {
+    /// Starts loading right away, `frames_in_flight` is how long replaced models are kept alive
+    pub fn new(path: &str, frames_in_flight: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_path = path.to_string();
+        std::thread::spawn(move || {
+            if let Err(e) = Self::load(&thread_path, &sender) {
+                let _ = sender.send(LoadEvent::Failed(e));
+            }
+        });
+
+        Self {
+            path: path.to_string(),
+            receiver,
+            nodes: Vec::new(),
+            root_nodes: Vec::new(),
+            rig: ModelRig::default(),
+            sub_meshes: Vec::new(),
+            pending: Vec::new(),
+            images: Vec::new(),
+            progress: LoadProgress::default(),
+            retired: VecDeque::new(),
+            polls: 0,
+            frames_in_flight,
+        }
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.progress.done
+    }
+
+    /// Uploads what the loading thread has sent so far. Returns a model to replace the entity's
+    /// current one if anything new became resident, the final model is registered with `assets`.
+    pub fn poll(&mut self, assets: &AssetManager, current: Option<Rc<Model>>) -> LveResult<Option<Rc<Model>>> {
+        self.polls += 1;
+        let polls = self.polls;
+        let frames_in_flight = self.frames_in_flight;
+        while self.retired.front().is_some_and(|&(poll, _)| poll + frames_in_flight < polls) {
+            self.retired.pop_front();
+        }
+
+        if self.progress.done {
+            return Ok(None);
+        }
+
+        let start = Instant::now();
+        let mut changed = false;
+        let mut finished = false;
+
+        while start.elapsed() < UPLOAD_BUDGET {
+            let event = match self.receiver.try_recv() {
+                Ok(event) => event,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    finished = true;
+                    break;
+                }
+            };
+
+            match event {
+                LoadEvent::Structure { nodes, root_nodes, rig, primitive_count, image_count } => {
+                    self.nodes = nodes;
+                    self.root_nodes = root_nodes;
+                    self.rig = *rig;
+                    self.sub_meshes = vec![None; primitive_count];
+                    self.pending = vec![None; primitive_count];
+                    self.images = (0..image_count).map(|_| None).collect();
+                    self.progress.primitive_count = primitive_count;
+                    self.progress.image_count = image_count;
+                }
+                LoadEvent::Primitive(index, data) => {
+                    self.upload_primitive(assets, index, *data)?;
+                    self.progress.primitives_loaded += 1;
+                    changed = true;
+                }
+                LoadEvent::Image(index, image) => {
+                    self.images[index] = Some(image);
+                    self.progress.images_loaded += 1;
+                }
+                LoadEvent::Failed(e) => {
+                    self.progress.done = true;
+                    return Err(e);
+                }
+            }
+        }
+
+        changed |= self.attach_textures(assets)?;
+
+        if finished {
+            self.progress.done = true;
+            // Nothing is pending anymore, the decoded pixels are only needed for uploads
+            self.images.clear();
+            log::info!("Streamed {}", self.path);
+        }
+
+        if !changed && !finished {
+            return Ok(None);
+        }
+
+        let model = Rc::new(Model::from_parts(self.sub_meshes.clone(), self.nodes.clone(), self.root_nodes.clone(), self.rig.clone(), &self.path));
+        if let Some(current) = current {
+            self.retired.push_back((polls, current));
+        }
+
+        if finished {
+            assets.insert_model(&self.path, &model);
+        }
+
+        Ok(Some(model))
+    }
+
+    fn upload_primitive(&mut self, assets: &AssetManager, index: usize, data: PrimitiveData) -> LveResult<()> {
+        if !Model::is_drawable(&self.path, data.vertices.len()) {
+            return Ok(());
+        }
+
+        let (textures, complete) = Model::mesh_textures(assets, &data.material, &self.images)?;
+        let key = Model::mesh_key(&self.path, data.mesh, data.primitive);
+        let create = || Mesh::new(
+            assets.lve_device().clone(),
+            data.vertices,
+            data.indices,
+            &data.morph_targets,
+            textures,
+            data.material.uniforms,
+            assets.global_pool().clone(),
+        );
+
+        if complete {
+            self.sub_meshes[index] = Some(assets.mesh(&key, create)?);
+            self.progress.textured_primitives += 1;
+        } else {
+            // Only the final mesh is shared through the asset manager
+            self.sub_meshes[index] = Some(create()?);
+            self.pending[index] = Some((key, data.material));
+        }
+
+        Ok(())
+    }
+
+    /// Swaps in the real textures for sub-meshes whose images have all arrived
+    fn attach_textures(&mut self, assets: &AssetManager) -> LveResult<bool> {
+        let mut changed = false;
+
+        for index in 0..self.pending.len() {
+            let (key, material) = match &self.pending[index] {
+                Some(pending) => pending,
+                None => continue,
+            };
+
+            if material.textures.iter().flatten().any(|&image| self.images[image].is_none()) {
+                continue;
+            }
+
+            let (textures, _) = Model::mesh_textures(assets, material, &self.images)?;
+            let placeholder = self.sub_meshes[index].as_ref().unwrap();
+            let mesh = assets.mesh(key, || placeholder.with_textures(textures))?;
+
+            self.sub_meshes[index] = Some(mesh);
+            self.pending[index] = None;
+            self.progress.textured_primitives += 1;
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// Runs on the loading thread, every step sends what it produced as soon as it has it
+    fn load(path: &str, sender: &mpsc::Sender<LoadEvent>) -> LveResult<()> {
+        if Model::is_obj(path) {
+            return Self::load_obj(path, sender);
+        }
+
+        if CookedModel::is_cooked(path) {
+            return Self::load_cooked(path, sender);
+        }
+
+        let cancelled = |_| LveError::Unsupported("Model load was cancelled");
+
+        let (document, buffers) = Model::read_gltf(path)?;
+        let (nodes, root_nodes) = Model::read_nodes(&document);
+        let primitives = Model::primitives(&document);
+
+        sender.send(LoadEvent::Structure {
+            nodes,
+            root_nodes,
+            rig: Box::new(ModelRig::read(&document, &buffers)),
+            primitive_count: primitives.len(),
+            image_count: document.images().len(),
+        }).map_err(cancelled)?;
+
+        // Geometry first, so the model shows up with default textures while the images decode
+        primitives.into_par_iter().enumerate().try_for_each_with(sender.clone(), |sender, (index, (mesh, primitive))| {
+            let data = Model::assemble_primitive(path, mesh, &primitive, &buffers)?;
+            sender.send(LoadEvent::Primitive(index, Box::new(data))).map_err(cancelled)
+        })?;
+
+        document.images().collect::<Vec<_>>().into_par_iter().try_for_each_with(sender.clone(), |sender, image| {
+            let index = image.index();
+            let image = Model::decode_image(path, image, &buffers)?;
+            sender.send(LoadEvent::Image(index, image)).map_err(cancelled)
+        })
+    }
+
+    /// Same order of events as for glTF, the OBJ is parsed in one go so all primitives arrive together
+    fn load_obj(path: &str, sender: &mpsc::Sender<LoadEvent>) -> LveResult<()> {
+        let cancelled = |_| LveError::Unsupported("Model load was cancelled");
+
+        let ObjContents { primitives, image_paths, nodes, root_nodes } = Model::read_obj(path)?;
+
+        sender.send(LoadEvent::Structure {
+            nodes,
+            root_nodes,
+            rig: Box::default(),
+            primitive_count: primitives.len(),
+            image_count: image_paths.len(),
+        }).map_err(cancelled)?;
+
+        for (index, data) in primitives.into_iter().enumerate() {
+            sender.send(LoadEvent::Primitive(index, Box::new(data))).map_err(cancelled)?;
+        }
+
+        image_paths.into_par_iter().enumerate().try_for_each_with(sender.clone(), |sender, (index, image_path)| {
+            let image = Model::decode_obj_image(&image_path)?;
+            sender.send(LoadEvent::Image(index, image)).map_err(cancelled)
+        })
+    }
+
+    /// Nothing to parse or decode, the thread only copies geometry out of the mapping and splits
+    /// the images into levels. `Model::new` uploads straight from the mapping instead.
+    fn load_cooked(path: &str, sender: &mpsc::Sender<LoadEvent>) -> LveResult<()> {
+        let cancelled = |_| LveError::Unsupported("Model load was cancelled");
+
+        let cooked = CookedModel::open(path)?;
+
+        sender.send(LoadEvent::Structure {
+            nodes: cooked.nodes.clone(),
+            root_nodes: cooked.root_nodes.clone(),
+            rig: Box::new(cooked.rig.clone()),
+            primitive_count: cooked.primitives.len(),
+            image_count: cooked.images.len(),
+        }).map_err(cancelled)?;
+
+        for (index, primitive) in cooked.primitives.iter().enumerate() {
+            let data = PrimitiveData {
+                mesh: primitive.mesh,
+                primitive: primitive.primitive,
+                vertices: cooked.vertices(primitive),
+                indices: cooked.indices(primitive),
+                morph_targets: cooked.morph_targets(primitive),
+                material: primitive.material,
+            };
+            sender.send(LoadEvent::Primitive(index, Box::new(data))).map_err(cancelled)?;
+        }
+
+        for index in 0..cooked.images.len() {
+            let image = DecodedImage { key: cooked.image_key(index), pixels: DecodedPixels::TextureFile(cooked.texture_file(index)) };
+            sender.send(LoadEvent::Image(index, image)).map_err(cancelled)?;
+        }
+
+        Ok(())
+    }
+}
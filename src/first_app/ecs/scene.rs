@@ -1,5 +1,8 @@
+use super::asset_manager::*;
 use super::entity::*;
 
+use crate::first_app::vulkan::lve_error::*;
+
 use std::str::FromStr;
 
 #[allow(dead_code)]
@@ -32,9 +35,11 @@ impl Scene {
         self.entities.push(entity);
     }
 
-    #[allow(dead_code)]
-    pub fn update(&mut self, _frame_time: f32) {
-        todo!();
+    /// Advances the animations of every entity, call once per frame before rendering
+    pub fn update(&mut self, frame_time: f32) {
+        for entity in self.entities.iter_mut() {
+            entity.update(frame_time);
+        }
     }
 
     /*pub fn render(&self) {
@@ -46,6 +51,14 @@ impl Scene {
         }
     }*/
 
+    /// Polls every streamed model load, errors are collected so one broken file doesn't stop the rest
+    pub fn poll_model_loads(&mut self, assets: &AssetManager) -> Vec<LveError> {
+        self.entities
+            .iter_mut()
+            .filter_map(|entity| entity.poll_model_load(assets).err())
+            .collect()
+    }
+
     pub fn display_info(&mut self, ui: &imgui::Ui) {
         imgui::Window::new("Scene").size([300.0, 100.0], imgui::Condition::FirstUseEver)
         .build(&ui, || {
@@ -58,6 +71,15 @@ impl Scene {
                     self.entity_id = current_id -1;
                     bruh = true;
                 }
+                if let Some(load) = entity.model_load.as_ref().filter(|load| !load.is_done()) {
+                    let progress = load.progress();
+                    imgui::ProgressBar::new(progress.fraction())
+                        .overlay_text(format!(
+                            "{} / {} meshes, {} / {} images",
+                            progress.primitives_loaded, progress.primitive_count, progress.images_loaded, progress.image_count
+                        ))
+                        .build(ui);
+                }
             }
             if bruh {
                 self.entities[self.entity_id].selected = false;
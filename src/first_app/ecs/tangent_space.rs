@@ -0,0 +1,240 @@
+use super::mesh::*;
+
+use std::collections::HashMap;
+
+use nalgebra as na;
+
+/// Index of the first vertex with the same key, for every vertex
+fn weld(vertices: &[Vertex], key: impl Fn(&Vertex) -> [u32; 3]) -> Vec<usize> {
+    let mut first = HashMap::new();
+    vertices
+        .iter()
+        .enumerate()
+        .map(|(index, vertex)| *first.entry(key(vertex)).or_insert(index))
+        .collect()
+}
+
+/// Angle of the triangle corner at `a`, used to weight per-face vectors so a vertex result doesn't
+/// depend on how the surface around it is triangulated
+fn corner_angle(a: na::Vector3<f32>, b: na::Vector3<f32>, c: na::Vector3<f32>) -> f32 {
+    match ((b - a).try_normalize(f32::EPSILON), (c - a).try_normalize(f32::EPSILON)) {
+        (Some(ab), Some(ac)) => ab.dot(&ac).clamp(-1.0, 1.0).acos(),
+        _ => 0.0,
+    }
+}
+
+/// Some unit vector perpendicular to `normal`
+fn any_perpendicular(normal: na::Vector3<f32>) -> na::Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 { na::Vector3::x() } else { na::Vector3::y() };
+    normal.cross(&axis).try_normalize(f32::EPSILON).unwrap_or_else(na::Vector3::x)
+}
+
+/// Gives every triangle its own vertices with the face normal, what glTF asks for when a
+/// primitive has no normals. `indices` become `0..n`.
+pub fn generate_flat_normals(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>) {
+    let mut flat = indices.iter().map(|&index| vertices[index as usize]).collect::<Vec<_>>();
+
+    for triangle in flat.chunks_exact_mut(3) {
+        let (a, b, c) = (triangle[0].position, triangle[1].position, triangle[2].position);
+        let normal = (b - a).cross(&(c - a)).try_normalize(f32::EPSILON).unwrap_or_else(na::Vector3::y);
+
+        for vertex in triangle {
+            vertex.normal = normal;
+        }
+    }
+
+    *indices = (0..flat.len() as u32).collect();
+    *vertices = flat;
+}
+
+/// Averages the angle weighted face normals around every position, for meshes that should look
+/// smooth but come without normals
+pub fn generate_smooth_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let welded = weld(vertices, |vertex| {
+        let p = vertex.position;
+        [p.x, p.y, p.z].map(f32::to_bits)
+    });
+    let mut normals = vec![na::Vector3::zeros(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| vertices[triangle[corner] as usize].position);
+        let normal = match (b - a).cross(&(c - a)).try_normalize(f32::EPSILON) {
+            Some(normal) => normal,
+            None => continue,
+        };
+
+        normals[welded[triangle[0] as usize]] += normal * corner_angle(a, b, c);
+        normals[welded[triangle[1] as usize]] += normal * corner_angle(b, c, a);
+        normals[welded[triangle[2] as usize]] += normal * corner_angle(c, a, b);
+    }
+
+    for (index, vertex) in vertices.iter_mut().enumerate() {
+        vertex.normal = normals[welded[index]].try_normalize(f32::EPSILON).unwrap_or_else(na::Vector3::y);
+    }
+}
+
+/// Hands an indexed triangle list to `bevy_mikktspace`, which reports a tangent for every corner
+struct MikkGeometry<'a> {
+    vertices: &'a [Vertex],
+    indices: &'a [u32],
+    tangents: Vec<na::Vector4<f32>>,
+}
+
+impl MikkGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &Vertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl bevy_mikktspace::Geometry for MikkGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position.into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal.into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coord.into()
+    }
+
+    fn set_tangent(&mut self, tangent_space: Option<bevy_mikktspace::TangentSpace>, face: usize, vert: usize) {
+        if let Some(tangent_space) = tangent_space {
+            self.tangents[face * 3 + vert] = na::Vector4::from(tangent_space.tangent_encoded());
+        }
+    }
+}
+
+/// MikkTSpace tangents, `w` is the bitangent sign. A vertex whose corners end up with different
+/// tangents, like one shared by triangles with mirrored UVs, is split.
+/// Returns the vertex each split off copy was made from, in the order the copies were appended.
+pub fn generate_tangents(vertices: &mut Vec<Vertex>, indices: &mut [u32]) -> Vec<usize> {
+    // Corners without a result, degenerate triangles with no neighbours for one, keep some tangent on the surface
+    let tangents = indices
+        .iter()
+        .map(|&index| {
+            let tangent = any_perpendicular(vertices[index as usize].normal);
+            na::vector![tangent.x, tangent.y, tangent.z, 1.0]
+        })
+        .collect();
+
+    let mut geometry = MikkGeometry { vertices, indices, tangents };
+    if let Err(e) = bevy_mikktspace::generate_tangents(&mut geometry) {
+        log::warn!("Unable to generate tangents, using arbitrary ones: {}", e);
+    }
+    let tangents = geometry.tangents;
+
+    // The first tangent seen for a vertex keeps its index, every other one gets a copy
+    let mut outputs: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+    let mut assigned = vec![false; vertices.len()];
+    let mut copies = Vec::new();
+
+    for (index, tangent) in indices.iter_mut().zip(tangents) {
+        let original = *index as usize;
+        let key = (*index, tangent.data.0[0].map(f32::to_bits));
+
+        if let Some(&output) = outputs.get(&key) {
+            *index = output;
+            continue;
+        }
+
+        let output = if !assigned[original] {
+            assigned[original] = true;
+            original
+        } else {
+            vertices.push(vertices[original]);
+            copies.push(original);
+            vertices.len() - 1
+        };
+
+        vertices[output].tangent = tangent;
+        outputs.insert(key, output as u32);
+        *index = output as u32;
+    }
+
+    copies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coord: [f32; 2]) -> Vertex {
+        Vertex {
+            position: na::Vector3::from(position),
+            color: na::vector![1.0, 1.0, 1.0],
+            normal: na::vector![0.0, 0.0, 1.0],
+            tangent: na::Vector4::zeros(),
+            tex_coord: na::Vector2::from(tex_coord),
+            tex_coord_1: na::Vector2::from(tex_coord),
+            joints: na::Vector4::zeros(),
+            weights: na::Vector4::zeros(),
+        }
+    }
+
+    /// Unit quad in the xy plane, u along +x and v along +y
+    fn quad() -> (Vec<Vertex>, Vec<u32>) {
+        let vertices = vec![
+            vertex([0.0, 0.0, 0.0], [0.0, 0.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 0.0]),
+            vertex([1.0, 1.0, 0.0], [1.0, 1.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 1.0]),
+        ];
+
+        (vertices, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        let (mut vertices, mut indices) = quad();
+        generate_tangents(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 4);
+        for vertex in &vertices {
+            assert!((vertex.tangent - na::vector![1.0, 0.0, 0.0, 1.0]).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_vertices() {
+        let (mut vertices, _) = quad();
+        // Shares vertices 0 and 2 with the first triangle, but its UVs wind the other way
+        vertices.push(vertex([0.0, 1.0, 0.0], [2.0, 1.0]));
+        let mut indices = vec![0, 1, 2, 0, 2, 4];
+
+        let copies = generate_tangents(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 7);
+        assert_eq!(copies, vec![0, 2]);
+        assert_eq!(&indices[..3], &[0, 1, 2]);
+        assert_eq!(vertices[0].tangent.w, 1.0);
+
+        let split = indices[3] as usize;
+        assert!(split >= 5);
+        assert_eq!(vertices[split].position, vertices[0].position);
+        assert_eq!(vertices[split].tangent.w, -1.0);
+    }
+
+    #[test]
+    fn flat_normals_unweld_triangles() {
+        let (mut vertices, mut indices) = quad();
+        for vertex in &mut vertices {
+            vertex.normal = na::Vector3::zeros();
+        }
+
+        generate_flat_normals(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+        assert!(vertices.iter().all(|vertex| vertex.normal == na::vector![0.0, 0.0, 1.0]));
+    }
+}
@@ -0,0 +1,415 @@
+//! Golden image tests for the deferred path.
+//!
+//! Every scene is rendered on a headless device into an offscreen target and compared with
+//! `tests/golden/<name>.png`. Pixels count as different when their CIE76 delta E is above
+//! `MAX_DELTA_E`, a scene fails when more than `MAX_BAD_PIXEL_RATIO` of its pixels differ.
+//! On failure the actual, expected and diff images are written to `target/golden-diff`.
+//!
+//! The render tests skip themselves when no Vulkan device can be created. CI runs them on
+//! lavapipe with `LVE_GOLDEN_REQUIRE_DEVICE=1`, which turns a missing device into a failure.
+//! Set `LVE_GOLDEN_BLESS=1` to create missing reference images, or to rewrite them after an
+//! intended change. A missing reference fails the test otherwise, nothing is written implicitly.
+
+use super::ecs::{asset_manager::*, entity::*, scene::*};
+use super::systems::{advanced_render_system::*, composition_render_system::*, deffered_rendering_system::*};
+use super::vulkan::{lve_buffer::*, lve_camera::*, lve_descriptor_set::*, lve_device::*, lve_frame_info::*, lve_offscreen_renderer::*};
+
+use ash::vk;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+extern crate nalgebra as na;
+
+const MAX_DELTA_E: f32 = 3.0;
+const MAX_BAD_PIXEL_RATIO: f32 = 0.002;
+/// Set by CI, where skipping for lack of a device would hide every regression
+const REQUIRE_DEVICE_VAR: &str = "LVE_GOLDEN_REQUIRE_DEVICE";
+/// Writes the renders to `tests/golden` instead of comparing them
+const BLESS_VAR: &str = "LVE_GOLDEN_BLESS";
+
+struct GoldenScene {
+    name: &'static str,
+    model_path: &'static str,
+    transform: NewTransformComponent,
+    camera_position: na::Vector3<f32>,
+    camera_target: na::Vector3<f32>,
+    lights: Vec<PointLight>,
+    extent: vk::Extent2D,
+}
+
+struct GoldenDiff {
+    bad_pixels: usize,
+    max_delta_e: f32,
+    mean_delta_e: f32,
+    image: image::RgbaImage,
+}
+
+/// `None` if there is no Vulkan device and `LVE_GOLDEN_REQUIRE_DEVICE` isn't set
+fn headless_device(name: &str) -> Option<Rc<LveDevice>> {
+    match LveDevice::new_headless() {
+        Ok(lve_device) => Some(lve_device),
+        Err(e) if std::env::var_os(REQUIRE_DEVICE_VAR).is_none() => {
+            eprintln!("skipping golden {}: no Vulkan device ({})", name, e);
+            None
+        }
+        Err(e) => panic!("{} is set but no Vulkan device could be created: {}", REQUIRE_DEVICE_VAR, e),
+    }
+}
+
+/// `None` if the scene was skipped for lack of a device
+fn render(scene: &GoldenScene) -> Option<image::RgbaImage> {
+    let lve_device = headless_device(scene.name)?;
+
+    let mut offscreen_renderer = LveOffscreenRenderer::new(lve_device.clone(), scene.extent, 1).unwrap();
+
+    let global_pool = LveDescriptorPool::new(Rc::clone(&lve_device))
+        .set_max_sets(1000)
+        .set_pool_flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+        .add_pool_size(vk::DescriptorType::UNIFORM_BUFFER, 1000)
+        .add_pool_size(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 5000)
+        .build().unwrap();
+
+    let mut ubo_buffer = LveBuffer::new(
+        Rc::clone(&lve_device),
+        1,
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    ).unwrap();
+    ubo_buffer.map(0);
+
+    let global_set_layout = LveDescriptorSetLayout::new(Rc::clone(&lve_device))
+        .add_binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .build().unwrap();
+
+    let global_descriptor_set = LveDescriptorSetWriter::new(global_set_layout.clone(), global_pool.clone())
+        .write_to_buffer(0, &[ubo_buffer.descriptor_info()])
+        .build().unwrap();
+
+    let descriptor_layout = LveDescriptorSetLayout::new(Rc::clone(&lve_device))
+        .add_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(4, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(5, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(6, vk::DescriptorType::STORAGE_BUFFER, vk::ShaderStageFlags::VERTEX, 1)
+        .build().unwrap();
+
+    let deffered_rendering_system = DefferedRenderingSystem::new(lve_device.clone(), scene.extent.width, scene.extent.height).unwrap();
+
+    let deffered_set_layout = LveDescriptorSetLayout::new(Rc::clone(&lve_device))
+        .add_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(2, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(3, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(4, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(5, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .add_binding(6, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+        .build().unwrap();
+
+    let attachment_infos = deffered_rendering_system.attachment_infos();
+
+    let deffered_descriptor_set = LveDescriptorSetWriter::new(deffered_set_layout.clone(), global_pool.clone())
+        .write_image(0, &[attachment_infos[0]])
+        .write_image(1, &[attachment_infos[1]])
+        .write_image(2, &[attachment_infos[2]])
+        .write_image(3, &[attachment_infos[3]])
+        .write_image(4, &[attachment_infos[4]])
+        .write_image(5, &[attachment_infos[5]])
+        .write_image(6, &[attachment_infos[6]])
+        .build().unwrap();
+
+    let mut advanced_render_system = AdvancedRenderSystem::new(
+        Rc::clone(&lve_device),
+        &deffered_rendering_system.get_render_pass(),
+        &[global_set_layout.layout, descriptor_layout.layout],
+        1
+    ).unwrap();
+
+    let mut composition_render_system = CompositionRenderSystem::new(
+        Rc::clone(&lve_device),
+        &offscreen_renderer.get_render_pass(),
+        &[global_set_layout.layout, deffered_set_layout.layout]
+    ).unwrap();
+
+    let mut ecs_scene = Scene::new_null(scene.name);
+    let mut entity = Entity::new(scene.name, NewTransformComponent {
+        translation: scene.transform.translation,
+        rotation: scene.transform.rotation,
+        scale: scene.transform.scale,
+    });
+    let assets = AssetManager::new(Rc::clone(&lve_device), global_pool.clone());
+    entity.set_model(assets.load_model(scene.model_path).unwrap());
+    ecs_scene.add_entity(entity);
+
+    let camera = LveCameraBuilder::new()
+        .set_view_target(scene.camera_position, scene.camera_target, None)
+        .set_perspective_projection(70_f32.to_radians(), offscreen_renderer.get_aspect_ratio(), 0.001, 1000.0)
+        .build();
+
+    let mut point_lights = [PointLight { position: na::vector![0.0, 0.0, 0.0, 0.0], color: na::vector![0.0, 0.0, 0.0, 0.0] }; MAX_LIGHTS];
+    point_lights[..scene.lights.len()].copy_from_slice(&scene.lights);
+
+    let ubo = GlobalUbo {
+        projection_matrix: Align16(camera.projection_matrix),
+        view_matrix: Align16(camera.view_matrix),
+        camera_position: Align16(scene.camera_position),
+        ambient_light_color: Align16(na::vector![1.0, 1.0, 1.0, 0.02]),
+        point_lights,
+        num_lights: scene.lights.len() as u32,
+    };
+    ubo_buffer.write_to_buffer(&[ubo]);
+
+    let game_objects = Vec::new();
+    let command_buffer = offscreen_renderer.begin_frame().unwrap();
+    let frame_info = FrameInfo {
+        frame_index: offscreen_renderer.get_frame_index(),
+        frame_time: 0.0,
+        command_buffer,
+        camera,
+        global_descriptor_set,
+        image_descriptor_set: vk::DescriptorSet::null(),
+        game_objects: &game_objects,
+    };
+
+    deffered_rendering_system.start(&frame_info);
+    advanced_render_system.render_scene(&frame_info, &ecs_scene);
+    deffered_rendering_system.end(&frame_info);
+
+    offscreen_renderer.begin_render_pass(command_buffer);
+    composition_render_system.render(&frame_info, deffered_descriptor_set, &MaterialLobes::default());
+    offscreen_renderer.end_render_pass(command_buffer);
+
+    let mut readback = offscreen_renderer.capture_color_target(command_buffer).unwrap();
+    offscreen_renderer.end_frame();
+
+    Some(readback.read().unwrap().to_rgba8().unwrap())
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden-diff")
+}
+
+fn check_golden(name: &str, actual: &image::RgbaImage) {
+    let reference_path = golden_dir().join(format!("{}.png", name));
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save(&reference_path).unwrap();
+        eprintln!("blessed {}", reference_path.display());
+        return;
+    }
+
+    if !reference_path.exists() {
+        std::fs::create_dir_all(diff_dir()).unwrap();
+        let actual_path = diff_dir().join(format!("{}_actual.png", name));
+        actual.save(&actual_path).unwrap();
+        panic!(
+            "No reference image {}, the render is in {}. Run with {}=1 to create it.",
+            reference_path.display(),
+            actual_path.display(),
+            BLESS_VAR
+        );
+    }
+
+    let expected = image::open(&reference_path).unwrap().to_rgba8();
+
+    let dump = |diff: Option<&image::RgbaImage>| {
+        std::fs::create_dir_all(diff_dir()).unwrap();
+        actual.save(diff_dir().join(format!("{}_actual.png", name))).unwrap();
+        expected.save(diff_dir().join(format!("{}_expected.png", name))).unwrap();
+        if let Some(diff) = diff {
+            diff.save(diff_dir().join(format!("{}_diff.png", name))).unwrap();
+        }
+    };
+
+    if expected.dimensions() != actual.dimensions() {
+        dump(None);
+        panic!(
+            "{}: size {:?} does not match reference size {:?}",
+            name,
+            actual.dimensions(),
+            expected.dimensions()
+        );
+    }
+
+    let diff = compare(&expected, actual);
+    let bad_ratio = diff.bad_pixels as f32 / (actual.width() * actual.height()) as f32;
+
+    if bad_ratio > MAX_BAD_PIXEL_RATIO {
+        dump(Some(&diff.image));
+        panic!(
+            "{}: {} pixels ({:.3}%) differ, max delta E {:.2}, mean delta E {:.3}. Images written to {}",
+            name,
+            diff.bad_pixels,
+            bad_ratio * 100.0,
+            diff.max_delta_e,
+            diff.mean_delta_e,
+            diff_dir().display()
+        );
+    }
+}
+
+/// Compares two images in CIE Lab space. The diff image is a dimmed copy of `expected` with
+/// every pixel over the tolerance painted red.
+fn compare(expected: &image::RgbaImage, actual: &image::RgbaImage) -> GoldenDiff {
+    let mut image = image::RgbaImage::new(expected.width(), expected.height());
+    let mut bad_pixels = 0;
+    let mut max_delta_e: f32 = 0.0;
+    let mut total_delta_e = 0.0;
+
+    for ((e, a), d) in expected.pixels().zip(actual.pixels()).zip(image.pixels_mut()) {
+        let delta_e = delta_e(e.0, a.0);
+
+        total_delta_e += delta_e as f64;
+        max_delta_e = max_delta_e.max(delta_e);
+
+        if delta_e > MAX_DELTA_E {
+            bad_pixels += 1;
+            *d = image::Rgba([255, 0, 0, 255]);
+        } else {
+            let luma = ((e.0[0] as u32 + e.0[1] as u32 + e.0[2] as u32) / 12) as u8;
+            *d = image::Rgba([luma, luma, luma, 255]);
+        }
+    }
+
+    GoldenDiff {
+        bad_pixels,
+        max_delta_e,
+        mean_delta_e: (total_delta_e / (expected.width() * expected.height()).max(1) as f64) as f32,
+        image,
+    }
+}
+
+/// CIE76 colour difference of two sRGB pixels, alpha is ignored
+fn delta_e(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let a = srgb_to_lab(a);
+    let b = srgb_to_lab(b);
+
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn srgb_to_lab(pixel: [u8; 4]) -> [f32; 3] {
+    let linear = |value: u8| {
+        let value = value as f32 / 255.0;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    };
+
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+
+    // D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 }
+    };
+
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn light(position: [f32; 3], color: [f32; 3], intensity: f32) -> PointLight {
+    PointLight {
+        position: na::vector![position[0], position[1], position[2], 1.0],
+        color: na::vector![color[0], color[1], color[2], intensity],
+    }
+}
+
+#[test]
+fn golden_cube() {
+    let scene = GoldenScene {
+        name: "cube",
+        model_path: "./assets/models/cube.gltf",
+        transform: NewTransformComponent { translation: na::vector![0.0, 0.0, 0.0], rotation: na::vector![0.5, 0.7, 0.0], scale: na::vector![0.5, 0.5, 0.5] },
+        camera_position: na::vector![0.0, -0.5, -2.0],
+        camera_target: na::vector![0.0, 0.0, 0.0],
+        lights: vec![light([1.0, -1.5, -1.5], [1.0, 1.0, 1.0], 4.0)],
+        extent: vk::Extent2D { width: 128, height: 128 },
+    };
+
+    if let Some(actual) = render(&scene) {
+        check_golden(scene.name, &actual);
+    }
+}
+
+fn normal_tangent_scene(model_path: &'static str) -> GoldenScene {
+    GoldenScene {
+        name: "normal_tangent",
+        model_path,
+        transform: NewTransformComponent { translation: na::vector![0.0, 0.0, 0.0], rotation: na::vector![0.0, 0.0, 3.141], scale: na::vector![1.0, 1.0, 1.0] },
+        camera_position: na::vector![0.0, 0.0, -2.5],
+        camera_target: na::vector![0.0, 0.0, 0.0],
+        lights: vec![
+            light([-1.0, -1.0, -1.5], [1.0, 0.9, 0.8], 3.0),
+            light([1.0, 1.0, -1.5], [0.6, 0.7, 1.0], 2.0),
+        ],
+        extent: vk::Extent2D { width: 192, height: 192 },
+    }
+}
+
+#[test]
+fn golden_normal_tangent() {
+    let scene = normal_tangent_scene("./assets/models/Normal/glTF/NormalTangentTest.gltf");
+    if let Some(actual) = render(&scene) {
+        check_golden(scene.name, &actual);
+    }
+}
+
+/// Same asset with its textures embedded in the binary chunk, has to match the .gltf reference
+#[test]
+fn golden_normal_tangent_glb() {
+    let scene = normal_tangent_scene("./assets/models/Normal/glTF-Binary/NormalTangentTest.glb");
+    if let Some(actual) = render(&scene) {
+        check_golden(scene.name, &actual);
+    }
+}
+
+#[test]
+fn golden_sponza() {
+    let scene = GoldenScene {
+        name: "sponza",
+        model_path: "./assets/models/Sponza/glTF/Sponza.gltf",
+        transform: NewTransformComponent { translation: na::vector![0.0, 0.0, 3.0], rotation: na::vector![0.0, 0.0, 3.141], scale: na::vector![0.01, 0.01, 0.01] },
+        camera_position: na::vector![0.0, -0.2, -1.0],
+        camera_target: na::vector![0.0, -0.2, 3.0],
+        lights: vec![
+            light([0.0, -1.0, 1.0], [1.0, 0.9, 0.8], 5.0),
+            light([0.0, -1.0, 4.0], [1.0, 0.9, 0.8], 5.0),
+        ],
+        extent: vk::Extent2D { width: 320, height: 180 },
+    };
+
+    if let Some(actual) = render(&scene) {
+        check_golden(scene.name, &actual);
+    }
+}
+
+#[test]
+fn compare_identical_images() {
+    let image = image::RgbaImage::from_fn(16, 16, |x, y| image::Rgba([(x * 16) as u8, (y * 16) as u8, 128, 255]));
+    let diff = compare(&image, &image);
+
+    assert_eq!(diff.bad_pixels, 0);
+    assert_eq!(diff.max_delta_e, 0.0);
+}
+
+#[test]
+fn compare_ignores_small_differences() {
+    let expected = image::RgbaImage::from_pixel(8, 8, image::Rgba([120, 60, 30, 255]));
+    let mut actual = expected.clone();
+    actual.put_pixel(0, 0, image::Rgba([121, 61, 30, 255]));
+    actual.put_pixel(1, 0, image::Rgba([30, 60, 120, 255]));
+
+    let diff = compare(&expected, &actual);
+
+    assert_eq!(diff.bad_pixels, 1);
+    assert_eq!(*diff.image.get_pixel(1, 0), image::Rgba([255, 0, 0, 255]));
+}
@@ -1,11 +1,13 @@
-mod ecs;
-mod systems;
-mod vulkan;
+pub mod ecs;
+pub mod systems;
+pub mod vulkan;
 mod keyboard_movement_controller;
+#[cfg(test)]
+mod golden_tests;
 
 use systems::{advanced_render_system::*, simple_render_system::*, point_render_system::*, deffered_rendering_system::*, composition_render_system::*};
-use vulkan::{lve_camera::*, lve_device::*, lve_game_object::*, lve_model::*, lve_renderer::*, lve_frame_info::*, lve_descriptor_set::*, lve_image::*, lve_buffer::*, lve_swapchain::*};
-use ecs::{scene::*, entity::*, model::*};
+use vulkan::{lve_camera::*, lve_device::*, lve_game_object::*, lve_model::*, lve_renderer::*, lve_frame_info::*, lve_descriptor_set::*, lve_image::*, lve_buffer::*, lve_swapchain::*, lve_readback::*, lve_error::*};
+use ecs::{scene::*, entity::*, asset_manager::*};
 use keyboard_movement_controller::*;
 
 use winit::{
@@ -44,7 +46,7 @@ pub struct VulkanApp {
     global_descriptor_sets: Vec<ash::vk::DescriptorSet>,
     ubo_buffers: Vec<LveBuffer<GlobalUbo>>,
     image_set_layout: Rc<LveDescriptorSetLayout>,
-    image: LveImage,
+    image: Rc<LveImage>,
     image_descriptor_set: ash::vk::DescriptorSet,
     pub imgui: Context,
     font_size: f32,
@@ -53,21 +55,33 @@ pub struct VulkanApp {
     lve_device: Rc<LveDevice>,
     rebuild: bool,
     scene: Scene,
+    assets: AssetManager,
     deffered_rendering_system: DefferedRenderingSystem,
     composition_render_system: CompositionRenderSystem,
+    material_lobes: MaterialLobes,
     deffered_set_layout: Rc<LveDescriptorSetLayout>,
-    deffered_descriptor_set: ash::vk::DescriptorSet
+    deffered_descriptor_set: ash::vk::DescriptorSet,
+    capture_keys_down: Vec<VirtualKeyCode>,
+    pending_captures: Vec<(String, LveReadback)>,
+    /// Failures that didn't stop the app, shown in the errors window until cleared
+    errors: Vec<String>
 }
 
 impl VulkanApp {
-    pub fn new() -> (Self, EventLoop<()>) {
+    /// Fails only if the app can't run at all, e.g. no suitable GPU or a broken built-in shader,
+    /// asset loading errors end up in the errors window instead
+    pub fn new() -> LveResult<(Self, EventLoop<()>)> {
         let (event_loop, window) = Self::new_window(WIDTH, HEIGHT, NAME);
 
-        let lve_device = LveDevice::new(&window);
+        let lve_device = LveDevice::new(&window)?;
 
-        let lve_renderer = LveRenderer::new(Rc::clone(&lve_device), &window);
+        let frames_in_flight = Self::frames_in_flight_setting();
 
-        let game_objects = Self::load_game_objects(&lve_device);
+        let lve_renderer = LveRenderer::new(Rc::clone(&lve_device), &window, frames_in_flight)?;
+
+        let mut errors = Vec::new();
+
+        let game_objects = Self::load_game_objects(&lve_device)?;
 
         let camera_transform = Some(TransformComponent {
             translation: na::vector![0.0, -0.20, -1.0],
@@ -85,19 +99,20 @@ impl VulkanApp {
 
         let global_pool = LveDescriptorPool::new(Rc::clone(&lve_device))
             .set_max_sets(1000 as u32)
-            .add_pool_size(ash::vk::DescriptorType::UNIFORM_BUFFER, MAX_FRAMES_IN_FLIGHT as u32)
-            .add_pool_size(ash::vk::DescriptorType::SAMPLED_IMAGE, MAX_FRAMES_IN_FLIGHT as u32)
-            .add_pool_size(ash::vk::DescriptorType::STORAGE_BUFFER, MAX_FRAMES_IN_FLIGHT as u32)
+            .set_pool_flags(ash::vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .add_pool_size(ash::vk::DescriptorType::UNIFORM_BUFFER, frames_in_flight as u32)
+            .add_pool_size(ash::vk::DescriptorType::SAMPLED_IMAGE, frames_in_flight as u32)
+            .add_pool_size(ash::vk::DescriptorType::STORAGE_BUFFER, frames_in_flight as u32)
             .build().unwrap();
 
-        let mut ubo_buffers = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
-        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        let mut ubo_buffers = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
             let mut buffer = LveBuffer::new(
                 Rc::clone(&lve_device),
                 1,
                 ash::vk::BufferUsageFlags::UNIFORM_BUFFER,
-                ash::vk::MemoryPropertyFlags::HOST_VISIBLE,
-            );
+                ash::vk::MemoryPropertyFlags::HOST_VISIBLE | ash::vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
 
             buffer.map(0);
 
@@ -108,8 +123,8 @@ impl VulkanApp {
             .add_binding(0, ash::vk::DescriptorType::UNIFORM_BUFFER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .build().unwrap();
 
-        let mut global_descriptor_sets = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
-        for i in 0..MAX_FRAMES_IN_FLIGHT {
+        let mut global_descriptor_sets = Vec::with_capacity(frames_in_flight);
+        for i in 0..frames_in_flight {
             let buffer_info = ubo_buffers[i].descriptor_info();
             let set = LveDescriptorSetWriter::new(global_set_layout.clone(), global_pool.clone())
                 .write_to_buffer(0, &[buffer_info])
@@ -129,9 +144,17 @@ impl VulkanApp {
             .add_binding(3, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(4, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(5, ash::vk::DescriptorType::UNIFORM_BUFFER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(6, ash::vk::DescriptorType::STORAGE_BUFFER, ash::vk::ShaderStageFlags::VERTEX, 1)
             .build().unwrap();
 
-        let image = LveImage::new(Rc::clone(&lve_device), "./assets/textures/poggers.png");
+        let assets = AssetManager::new(Rc::clone(&lve_device), global_pool.clone());
+
+        let image = assets.load_image("./assets/textures/poggers.png", TextureRole::BaseColor)
+            .or_else(|e| {
+                log::error!("{}", e);
+                errors.push(e.to_string());
+                assets.default_image(TextureRole::BaseColor)
+            })?;
 
         let image_info = ash::vk::DescriptorImageInfo::builder()
             .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
@@ -165,17 +188,17 @@ impl VulkanApp {
             lve_renderer.get_swapchain_render_pass(),
             &mut imgui,
             Some(Options {
-                in_flight_frames: 2,
+                in_flight_frames: frames_in_flight,
                 ..Default::default()
             }),
         ).unwrap();
 
         let mut scene = Scene::new_null("test");
         let mut entity_1 = Entity::new("test_1", NewTransformComponent { translation: na::vector![0.0, 0.0, 3.0], rotation: na::vector![0.0, 0.0, 3.141], scale: na::vector![0.01, 0.01, 0.01]});
-        let model = Rc::new(Model::new(&Rc::clone(&lve_device), "./assets/models/Sponza/glTF/Sponza.gltf", global_pool.clone()));
-        //let model = Rc::new(Model::new(&Rc::clone(&lve_device), "./assets/models/Map/scene.gltf", global_pool.clone()));
-        //let model = Rc::new(Model::new(&Rc::clone(&lve_device), "./assets/models/deccer-cubes/SM_Deccer_Cubes_Textured.gltf", global_pool.clone()));
-        entity_1.set_model(model);
+        // Streamed in while the window is already up, failures show in the errors window
+        entity_1.load_model(&assets, "./assets/models/Sponza/glTF/Sponza.gltf", frames_in_flight);
+        //entity_1.load_model(&assets, "./assets/models/Map/scene.gltf", frames_in_flight);
+        //entity_1.load_model(&assets, "./assets/models/deccer-cubes/SM_Deccer_Cubes_Textured.gltf", frames_in_flight);
         let mut entity_2 = Entity::new("test_2", NewTransformComponent { translation: na::vector![0.0, 0.0, 0.0], rotation: na::vector![0.0, 0.0, 0.0], scale: na::vector![0.0, 0.0, 0.0]});
         entity_2.add_spot_light();
         let mut entity_3 = Entity::new("test_3", NewTransformComponent { translation: na::vector![0.0, 0.0, 0.0], rotation: na::vector![0.0, 0.0, 0.0], scale: na::vector![0.0, 0.0, 0.0]});
@@ -185,44 +208,28 @@ impl VulkanApp {
         scene.add_entity(entity_2);
 
         println!("deffered");
-        let deffered_rendering_system = DefferedRenderingSystem::new(lve_device.clone(), WIDTH, HEIGHT);
+        let deffered_rendering_system = DefferedRenderingSystem::new(lve_device.clone(), WIDTH, HEIGHT)?;
 
         let deffered_set_layout = LveDescriptorSetLayout::new(Rc::clone(&lve_device))
             .add_binding(0, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(1, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(2, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(3, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(4, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(5, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(6, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .build().unwrap();
 
-        let position_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(deffered_rendering_system.things.position.image_view)
-            .sampler(deffered_rendering_system.sampler)
-            .build();
-
-        let normal_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(deffered_rendering_system.things.normal.image_view)
-            .sampler(deffered_rendering_system.sampler)
-            .build();
-
-        let albedo_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(deffered_rendering_system.things.albedo.image_view)
-            .sampler(deffered_rendering_system.sampler)
-            .build();
-
-        let metallic_roughness_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(deffered_rendering_system.things.metallic_roughness.image_view)
-            .sampler(deffered_rendering_system.sampler)
-            .build();
+        let attachment_infos = deffered_rendering_system.attachment_infos();
 
         let deffered_descriptor_set = LveDescriptorSetWriter::new(deffered_set_layout.clone(), global_pool.clone())
-                .write_image(0, &[position_image_info])
-                .write_image(1, &[normal_image_info])
-                .write_image(2, &[albedo_image_info])
-                .write_image(3, &[metallic_roughness_image_info])
+                .write_image(0, &[attachment_infos[0]])
+                .write_image(1, &[attachment_infos[1]])
+                .write_image(2, &[attachment_infos[2]])
+                .write_image(3, &[attachment_infos[3]])
+                .write_image(4, &[attachment_infos[4]])
+                .write_image(5, &[attachment_infos[5]])
+                .write_image(6, &[attachment_infos[6]])
                 .build().unwrap();
 
         println!("simple");
@@ -230,30 +237,31 @@ impl VulkanApp {
             Rc::clone(&lve_device),
             &lve_renderer.get_swapchain_render_pass(),
             &[global_set_layout.layout, descriptor_layout.layout]
-        );
+        )?;
 
         println!("advanced");
         let advanced_render_system = AdvancedRenderSystem::new(
             Rc::clone(&lve_device),
             &deffered_rendering_system.get_render_pass(),
-            &[global_set_layout.layout, descriptor_layout.layout]
-        );
+            &[global_set_layout.layout, descriptor_layout.layout],
+            frames_in_flight
+        )?;
 
         println!("point");
         let point_render_system = PointRenderSystem::new(
             Rc::clone(&lve_device),
             &deffered_rendering_system.get_render_pass(),
             &[global_set_layout.layout]
-        );
+        )?;
 
         println!("composition");
         let composition_render_system = CompositionRenderSystem::new(
             Rc::clone(&lve_device),
             &lve_renderer.get_swapchain_render_pass(),
             &[global_set_layout.layout, deffered_set_layout.layout]
-        );
+        )?;
 
-        (
+        Ok((
             Self {
                 window,
                 lve_renderer,
@@ -277,13 +285,18 @@ impl VulkanApp {
                 lve_device,
                 rebuild: false,
                 scene,
+                assets,
                 deffered_rendering_system,
                 composition_render_system,
+                material_lobes: MaterialLobes::default(),
                 deffered_set_layout,
-                deffered_descriptor_set
+                deffered_descriptor_set,
+                capture_keys_down: Vec::new(),
+                pending_captures: Vec::new(),
+                errors
             },
             event_loop,
-        )
+        ))
     }
 
     pub fn run(&mut self, keys_pressed: &[VirtualKeyCode], frame_time: f32) {
@@ -321,16 +334,41 @@ impl VulkanApp {
             return;
         }
 
+        // F12 saves a screenshot, F11 dumps the G-buffer, only once per key press
+        let take_screenshot = self.key_just_pressed(keys_pressed, VirtualKeyCode::F12);
+        let dump_gbuffer = self.key_just_pressed(keys_pressed, VirtualKeyCode::F11);
+
+        for e in self.scene.poll_model_loads(&self.assets) {
+            self.report_error(e);
+        }
+
+        self.scene.update(frame_time);
+
+        // Everything uploaded this frame is on the GPU before the frame reads it
+        if let Err(e) = self.lve_device.flush_uploads() {
+            self.report_error(e);
+        }
+
         if self.rebuild {
-            self.simple_render_system.recreate_pipeline(self.lve_device.clone(), &self.lve_renderer.get_swapchain_render_pass());
-            self.point_render_system.recreate_pipeline(self.lve_device.clone(), &self.deffered_rendering_system.get_render_pass());
-            self.advanced_render_system.recreate_pipeline(self.lve_device.clone(), &self.deffered_rendering_system.get_render_pass());
-            self.composition_render_system.recreate_pipeline(self.lve_device.clone(), &self.lve_renderer.get_swapchain_render_pass());
+            // The old pipelines may still be used by frames in flight
+            self.lve_device.wait_idle();
+            let results = [
+                self.simple_render_system.recreate_pipeline(self.lve_device.clone(), &self.lve_renderer.get_swapchain_render_pass()),
+                self.point_render_system.recreate_pipeline(self.lve_device.clone(), &self.deffered_rendering_system.get_render_pass()),
+                self.advanced_render_system.recreate_pipeline(self.lve_device.clone(), &self.deffered_rendering_system.get_render_pass()),
+                self.composition_render_system.recreate_pipeline(self.lve_device.clone(), &self.lve_renderer.get_swapchain_render_pass()),
+            ];
+
+            for result in results {
+                if let Err(e) = result {
+                    self.report_error(e);
+                }
+            }
             self.rebuild = false;
         }
 
         match self.lve_renderer.begin_frame(&self.window) {
-            Some(command_buffer) => {
+            Ok(Some(command_buffer)) => {
                 let frame_index = self.lve_renderer.get_frame_index();
                 let frame_info = FrameInfo {
                     frame_index,
@@ -366,10 +404,13 @@ impl VulkanApp {
                 //self.advanced_render_system.render_scene(&frame_info, &self.scene);
                 //self.point_render_system.render(&frame_info);
 
-                self.composition_render_system.render(&frame_info, self.deffered_descriptor_set);
+                self.composition_render_system.render(&frame_info, self.deffered_descriptor_set, &self.material_lobes);
 
 
 
+                let memory_stats = self.lve_device.allocator.stats();
+                let asset_stats = self.assets.stats();
+
                 self.platform.prepare_frame(self.imgui.io_mut(), &self.window).expect("Failed to prepare frame");
                 let ui = self.imgui.frame();
 
@@ -388,6 +429,81 @@ impl VulkanApp {
                         ));*/
                     });
 
+                let material_lobes = &mut self.material_lobes;
+                imgui::Window::new("materials")
+                    .size([300.0, 220.0], Condition::FirstUseEver)
+                    .build(&ui, || {
+                        for lobe in MaterialLobe::ALL {
+                            let mut enabled = material_lobes.is_enabled(lobe);
+                            if ui.checkbox(lobe.name(), &mut enabled) {
+                                material_lobes.set_enabled(lobe, enabled);
+                            }
+                        }
+                        ui.checkbox("index of refraction", &mut material_lobes.ior);
+
+                        ui.separator();
+                        let views: Vec<&str> = std::iter::once("all lobes")
+                            .chain(MaterialLobe::ALL.iter().map(|lobe| lobe.name()))
+                            .collect();
+                        let mut view = material_lobes.isolated
+                            .and_then(|isolated| MaterialLobe::ALL.iter().position(|&lobe| lobe == isolated))
+                            .map_or(0, |index| index + 1);
+                        if ui.combo_simple_string("view", &mut view, &views) {
+                            material_lobes.isolated = view.checked_sub(1).map(|index| MaterialLobe::ALL[index]);
+                        }
+                    });
+
+                imgui::Window::new("memory")
+                    .size([300.0, 200.0], Condition::FirstUseEver)
+                    .build(&ui, || {
+                        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+                        ui.text(format!(
+                            "device memory objects: {} / {}",
+                            memory_stats.device_memory_objects(),
+                            memory_stats.max_memory_allocation_count
+                        ));
+                        ui.text(format!("allocations: {}", memory_stats.allocations()));
+                        ui.text(format!(
+                            "assets: {} images, {} meshes, {} models ({} references)",
+                            asset_stats.images, asset_stats.meshes, asset_stats.models, asset_stats.references
+                        ));
+                        ui.text(format!(
+                            "used: {:.1} / {:.1} MiB",
+                            mib(memory_stats.used_bytes()),
+                            mib(memory_stats.reserved_bytes())
+                        ));
+
+                        for memory_type in memory_stats.memory_types.iter() {
+                            ui.separator();
+                            ui.text(format!("type {}: {:?}", memory_type.memory_type, memory_type.property_flags));
+                            ui.text(format!(
+                                "{} blocks, {} dedicated, {} allocations",
+                                memory_type.blocks, memory_type.dedicated_allocations, memory_type.allocations
+                            ));
+                            ui.text(format!(
+                                "{:.1} / {:.1} MiB",
+                                mib(memory_type.used_bytes),
+                                mib(memory_type.reserved_bytes)
+                            ));
+                        }
+                    });
+
+                if !self.errors.is_empty() {
+                    let errors = &mut self.errors;
+                    imgui::Window::new("errors")
+                        .size([500.0, 200.0], Condition::FirstUseEver)
+                        .build(&ui, || {
+                            for error in errors.iter() {
+                                ui.text_wrapped(error);
+                            }
+                            ui.separator();
+                            if ui.button("clear") {
+                                errors.clear();
+                            }
+                        });
+                }
+
                 self.scene.display_info(&ui);
 
                 self.platform.prepare_render(&ui, &self.window);
@@ -395,66 +511,156 @@ impl VulkanApp {
                 self.renderer.cmd_draw(command_buffer, draw_data).unwrap();
 
                 self.lve_renderer.end_swapchain_render_pass(command_buffer);
+
+                if take_screenshot {
+                    match self.lve_renderer.capture_swapchain_image(command_buffer) {
+                        Ok(readback) => self.pending_captures.push(("screenshot".to_string(), readback)),
+                        Err(e) => self.report_error(e),
+                    }
+                }
+
+                if dump_gbuffer {
+                    let extent = self.deffered_rendering_system.get_extent();
+                    let captures: Vec<_> = self.deffered_rendering_system.color_attachments()
+                        .into_iter()
+                        .map(|(name, attachment)| {
+                            self.lve_renderer.capture_image(
+                                command_buffer,
+                                attachment.image,
+                                attachment.format,
+                                extent,
+                                ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                            ).map(|readback| (format!("gbuffer_{}", name), readback))
+                        })
+                        .collect();
+
+                    for capture in captures {
+                        match capture {
+                            Ok(capture) => self.pending_captures.push(capture),
+                            Err(e) => self.report_error(e),
+                        }
+                    }
+                }
+
+                if let Err(e) = self.lve_renderer.end_frame(&self.window) {
+                    self.report_error(e);
+                }
             }
-            None => {}
+            Ok(None) => {}
+            Err(e) => self.report_error(e),
+        }
+
+        self.save_captures();
+    }
+
+    /// Logs the error and shows it in the errors window, the same message is only listed once
+    fn report_error(&mut self, error: LveError) {
+        let message = error.to_string();
+        if !self.errors.contains(&message) {
+            log::error!("{}", message);
+            self.errors.push(message);
+        }
+    }
+
+    fn key_just_pressed(&mut self, keys_pressed: &[VirtualKeyCode], key: VirtualKeyCode) -> bool {
+        let is_down = keys_pressed.contains(&key);
+        let was_down = self.capture_keys_down.contains(&key);
+
+        if is_down && !was_down {
+            self.capture_keys_down.push(key);
+        } else if !is_down && was_down {
+            self.capture_keys_down.retain(|k| *k != key);
         }
 
-        self.lve_renderer.end_frame(&self.window);
+        is_down && !was_down
+    }
+
+    fn save_captures(&mut self) {
+        if self.pending_captures.is_empty() {
+            return;
+        }
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+
+        for (name, mut readback) in self.pending_captures.drain(..) {
+            let path = std::path::PathBuf::from(format!("./screenshots/{}_{}", name, timestamp));
+
+            match readback.read().and_then(|capture| capture.save(&path)) {
+                Ok(path) => log::info!("saved {}", path.display()),
+                Err(e) => log::error!("Unable to save {}: {}", path.display(), e),
+            }
+        }
     }
 
     pub fn resize(&mut self) {
-        self.lve_renderer.recreate_swapchain(&self.window);
+        // The G-buffer and composition pipeline are replaced below while frames may be in flight
+        self.lve_device.wait_idle();
+
+        if let Err(e) = self.lve_renderer.recreate_swapchain(&self.window) {
+            self.report_error(e);
+        }
 
         let window_inner_size = self.window.inner_size();
         println!("{:?}", window_inner_size);
-        self.deffered_rendering_system = DefferedRenderingSystem::new(Rc::clone(&self.lve_device), window_inner_size.width, window_inner_size.height);
+        // Keeps the old G-buffer, it matches the descriptor set that is still bound
+        match DefferedRenderingSystem::new(Rc::clone(&self.lve_device), window_inner_size.width, window_inner_size.height) {
+            Ok(deffered_rendering_system) => self.deffered_rendering_system = deffered_rendering_system,
+            Err(e) => {
+                self.report_error(e);
+                return;
+            }
+        }
 
         let deffered_set_layout = LveDescriptorSetLayout::new(Rc::clone(&self.lve_device))
             .add_binding(0, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(1, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(2, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .add_binding(3, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(4, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(5, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
+            .add_binding(6, ash::vk::DescriptorType::COMBINED_IMAGE_SAMPLER, ash::vk::ShaderStageFlags::ALL_GRAPHICS, 1)
             .build().unwrap();
 
-        let position_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.deffered_rendering_system.things.position.image_view)
-            .sampler(self.deffered_rendering_system.sampler)
-            .build();
-
-        let normal_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.deffered_rendering_system.things.normal.image_view)
-            .sampler(self.deffered_rendering_system.sampler)
-            .build();
-
-        let albedo_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.deffered_rendering_system.things.albedo.image_view)
-            .sampler(self.deffered_rendering_system.sampler)
-            .build();
-
-        let metallic_roughness_image_info = ash::vk::DescriptorImageInfo::builder()
-            .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(self.deffered_rendering_system.things.metallic_roughness.image_view)
-            .sampler(self.deffered_rendering_system.sampler)
-            .build();
+        let attachment_infos = self.deffered_rendering_system.attachment_infos();
 
         let deffered_descriptor_set = LveDescriptorSetWriter::new(deffered_set_layout.clone(), self.global_pool.clone())
-            .write_image(0, &[position_image_info])
-            .write_image(1, &[normal_image_info])
-            .write_image(2, &[albedo_image_info])
-            .write_image(3, &[metallic_roughness_image_info])
+            .write_image(0, &[attachment_infos[0]])
+            .write_image(1, &[attachment_infos[1]])
+            .write_image(2, &[attachment_infos[2]])
+            .write_image(3, &[attachment_infos[3]])
+            .write_image(4, &[attachment_infos[4]])
+            .write_image(5, &[attachment_infos[5]])
+            .write_image(6, &[attachment_infos[6]])
             .build().unwrap();
 
         self.deffered_descriptor_set = deffered_descriptor_set;
         self.deffered_set_layout = deffered_set_layout;
 
-        self.composition_render_system = CompositionRenderSystem::new(
+        match CompositionRenderSystem::new(
             Rc::clone(&self.lve_device),
             &self.lve_renderer.get_swapchain_render_pass(),
             &[self.global_set_layout.layout, self.deffered_set_layout.layout]
-        );
+        ) {
+            Ok(composition_render_system) => self.composition_render_system = composition_render_system,
+            Err(e) => self.report_error(e),
+        }
+    }
+
+    /// Number of frames the CPU may record ahead of the GPU, `LVE_FRAMES_IN_FLIGHT` overrides the default
+    fn frames_in_flight_setting() -> usize {
+        match std::env::var("LVE_FRAMES_IN_FLIGHT") {
+            Ok(value) => match value.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    log::warn!("Invalid LVE_FRAMES_IN_FLIGHT value {:?}, using {}", value, DEFAULT_FRAMES_IN_FLIGHT);
+                    DEFAULT_FRAMES_IN_FLIGHT
+                }
+            },
+            Err(_) => DEFAULT_FRAMES_IN_FLIGHT,
+        }
     }
 
     fn new_window(w: u32, h: u32, name: &str) -> (EventLoop<()>, Window) {
@@ -472,8 +678,8 @@ impl VulkanApp {
         (event_loop, winit_window)
     }
 
-    fn load_game_objects(lve_device: &Rc<LveDevice>) -> Vec<LveGameObject> {
-        let vase = LveModel::new_from_file(Rc::clone(lve_device), "./assets/models/smooth_vase.obj");
+    fn load_game_objects(lve_device: &Rc<LveDevice>) -> LveResult<Vec<LveGameObject>> {
+        let vase = LveModel::new_from_file(Rc::clone(lve_device), "./assets/models/smooth_vase.obj")?;
 
         let vase_transform = Some(TransformComponent {
             translation: na::vector![0.0, 0.0, 0.2],
@@ -510,12 +716,13 @@ impl VulkanApp {
         game_objects.push(LveGameObject::make_point_light(0.5, 0.05, light_colors[2]));
         game_objects[3].transform.translation = na::vector![-4.5, -0.4, 2.0];
 
-        game_objects
+        Ok(game_objects)
     }
 }
 
 impl Drop for VulkanApp {
     fn drop(&mut self) {
         log::debug!("Dropping application");
+        self.lve_device.wait_idle();
     }
 }
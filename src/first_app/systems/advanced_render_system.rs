@@ -1,10 +1,16 @@
+use crate::first_app::vulkan::lve_buffer::*;
+use crate::first_app::vulkan::lve_descriptor_set::*;
 use crate::first_app::vulkan::lve_device::*;
 use crate::first_app::vulkan::lve_pipeline::*;
+use crate::first_app::vulkan::lve_error::*;
 use crate::first_app::vulkan::lve_frame_info::*;
 use crate::first_app::ecs::scene::*;
+use crate::first_app::ecs::mesh::*;
+use crate::first_app::ecs::model::*;
 
 use ash::{vk, Device};
 
+use std::collections::HashMap;
 use std::rc::Rc;
 
 extern crate nalgebra as na;
@@ -17,6 +23,24 @@ pub struct SimplePushConstantData {
 }
 
 impl SimplePushConstantData {
+    pub fn new(model_matrix: na::Matrix4<f32>) -> Self {
+        // Inverse transpose, so non-uniform scale doesn't skew normals
+        let normal_matrix = model_matrix
+            .fixed_slice::<3, 3>(0, 0)
+            .into_owned()
+            .try_inverse()
+            .unwrap_or_else(na::Matrix3::identity)
+            .transpose();
+
+        Self {
+            model_matrix: Align16(model_matrix),
+            normal_matrix: Align16(normal_matrix.to_homogeneous()),
+        }
+    }
+
+    /// # Safety
+    /// Meant for `cmd_push_constants` only, padding between the fields is uninitialized and must
+    /// not be read on the CPU.
     pub unsafe fn as_bytes(&self) -> &[u8] {
         let size_in_bytes = std::mem::size_of::<Self>();
         let size_in_u8 = size_in_bytes / std::mem::size_of::<u8>();
@@ -24,6 +48,8 @@ impl SimplePushConstantData {
         std::slice::from_raw_parts(start_ptr, size_in_u8)
     }
 
+    /// # Safety
+    /// Reads the struct as `f32`s, every field must be made of `f32`s.
     pub unsafe fn _print_buffer(&self) {
         let size_in_bytes = std::mem::size_of::<Self>();
         let size_in_u8 = size_in_bytes / std::mem::size_of::<f32>();
@@ -33,26 +59,175 @@ impl SimplePushConstantData {
     }
 }
 
+/// Every material pipeline, opaque ones before masked ones so masked fragments fail the depth test early
+const MATERIAL_PIPELINES: [MaterialPipeline; 6] = [
+    MaterialPipeline { alpha_mode: AlphaMode::Opaque, double_sided: false },
+    MaterialPipeline { alpha_mode: AlphaMode::Opaque, double_sided: true },
+    MaterialPipeline { alpha_mode: AlphaMode::Mask, double_sided: false },
+    MaterialPipeline { alpha_mode: AlphaMode::Mask, double_sided: true },
+    MaterialPipeline { alpha_mode: AlphaMode::Blend, double_sided: false },
+    MaterialPipeline { alpha_mode: AlphaMode::Blend, double_sided: true },
+];
+
+/// How the vertex shader moves vertices before the model matrix, each way gets its own pipelines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Deformation {
+    skinned: bool,
+    morphed: bool,
+}
+
+impl Deformation {
+    const ALL: [Deformation; 4] = [
+        Deformation { skinned: false, morphed: false },
+        Deformation { skinned: true, morphed: false },
+        Deformation { skinned: false, morphed: true },
+        Deformation { skinned: true, morphed: true },
+    ];
+}
+
+/// Joint matrices of every skinned draw and target weights of every morphed draw of a frame,
+/// packed one after the other
+struct DeformationPalette {
+    joints: LveBuffer<na::Matrix4<f32>>,
+    /// In matrices
+    joint_capacity: usize,
+    /// The target count followed by the weights of each morphed draw
+    weights: LveBuffer<f32>,
+    /// In floats
+    weight_capacity: usize,
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// Matrices and weights the palette buffers start out with, they grow when a frame needs more
+const INITIAL_JOINT_CAPACITY: usize = 256;
+const INITIAL_WEIGHT_CAPACITY: usize = 256;
+
+/// Where each draw's data starts in a palette buffer, in elements. Draws with the same key share
+/// their data. Returns the starts and the elements used, every start is a multiple of `stride`.
+fn pack<K: PartialEq>(entries: impl Iterator<Item = Option<(K, usize)>>, stride: usize) -> (Vec<Option<usize>>, usize) {
+    let mut packed: Vec<(K, usize)> = Vec::new();
+    let mut used = 0;
+    let starts = entries
+        .map(|entry| {
+            let (key, len) = entry?;
+            let start = match packed.iter().find(|(other, _)| *other == key) {
+                Some(&(_, start)) => start,
+                None => {
+                    let start = used;
+                    used = (used + len).div_ceil(stride) * stride;
+                    packed.push((key, start));
+                    start
+                }
+            };
+
+            Some(start)
+        })
+        .collect();
+
+    (starts, used)
+}
+
 pub struct AdvancedRenderSystem {
     lve_device: Rc<LveDevice>,
-    lve_pipeline: LvePipeline,
+    /// Keyed by material and what the vertex shader deforms
+    pipelines: HashMap<(MaterialPipeline, Deformation), LvePipeline>,
     pipeline_layout: vk::PipelineLayout,
+    palette_set_layout: Rc<LveDescriptorSetLayout>,
+    palette_pool: Rc<LveDescriptorPool>,
+    /// One per frame in flight, the GPU may still read the others
+    palettes: Vec<DeformationPalette>,
 }
 
 impl AdvancedRenderSystem {
-    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> Self {
-        let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
+    /// `global_set_layout` holds the global and mesh layouts, the system adds the joint and weight palette as set 2
+    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout], frames_in_flight: usize) -> LveResult<Self> {
+        let palette_set_layout = LveDescriptorSetLayout::new(Rc::clone(&lve_device))
+            .add_binding(0, vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, vk::ShaderStageFlags::VERTEX, 1)
+            .add_binding(1, vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, vk::ShaderStageFlags::VERTEX, 1)
+            .build()
+            .map_err(LveError::vulkan("Failed to create palette descriptor set layout"))?;
 
-        let lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+        let palette_pool = LveDescriptorPool::new(Rc::clone(&lve_device))
+            .set_max_sets(frames_in_flight as u32)
+            .add_pool_size(vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 2 * frames_in_flight as u32)
+            .build()
+            .map_err(LveError::vulkan("Failed to create palette descriptor pool"))?;
 
-        Self {
+        let palettes = (0..frames_in_flight)
+            .map(|_| Self::create_palette(&lve_device, &palette_set_layout, &palette_pool, INITIAL_JOINT_CAPACITY, INITIAL_WEIGHT_CAPACITY, None))
+            .collect::<LveResult<Vec<_>>>()?;
+
+        let set_layouts = global_set_layout.iter().copied().chain([palette_set_layout.layout]).collect::<Vec<_>>();
+        let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, &set_layouts);
+
+        let pipelines = match Self::create_pipelines(&lve_device, render_pass, &pipeline_layout) {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                unsafe { lve_device.device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
             lve_device,
-            lve_pipeline,
+            pipelines,
             pipeline_layout,
-        }
+            palette_set_layout,
+            palette_pool,
+            palettes,
+        })
     }
 
-    fn create_pipeline(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout,) -> LvePipeline {
+    /// Rewrites `descriptor_set` if given, it must not be in use by a frame in flight
+    fn create_palette(
+        lve_device: &Rc<LveDevice>,
+        set_layout: &Rc<LveDescriptorSetLayout>,
+        pool: &Rc<LveDescriptorPool>,
+        joint_capacity: usize,
+        weight_capacity: usize,
+        descriptor_set: Option<vk::DescriptorSet>,
+    ) -> LveResult<DeformationPalette> {
+        let mut joints = LveBuffer::new(
+            Rc::clone(lve_device),
+            joint_capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        joints.map(0);
+
+        let mut weights = LveBuffer::new(
+            Rc::clone(lve_device),
+            weight_capacity,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        weights.map(0);
+
+        // The writer keeps pointers to these until the set is written
+        let joint_info = [joints.descriptor_info()];
+        let weight_info = [weights.descriptor_info()];
+        let mut writer = LveDescriptorSetWriter::new(set_layout.clone(), pool.clone())
+            .write_to_buffer(0, &joint_info)
+            .write_to_buffer(1, &weight_info);
+        let descriptor_set = match descriptor_set {
+            Some(set) => writer.overwrite(set),
+            None => writer.build().ok_or(LveError::Unsupported("Palette descriptor pool is exhausted"))?,
+        };
+
+        Ok(DeformationPalette { joints, joint_capacity, weights, weight_capacity, descriptor_set })
+    }
+
+    fn create_pipelines(lve_device: &Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout) -> LveResult<HashMap<(MaterialPipeline, Deformation), LvePipeline>> {
+        MATERIAL_PIPELINES
+            .iter()
+            .flat_map(|&material| Deformation::ALL.map(|deformation| (material, deformation)))
+            .map(|(material, deformation)| {
+                Ok(((material, deformation), Self::create_pipeline(Rc::clone(lve_device), render_pass, pipeline_layout, material, deformation)?))
+            })
+            .collect()
+    }
+
+    fn create_pipeline(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout, material: MaterialPipeline, deformation: Deformation) -> LveResult<LvePipeline> {
         assert!(
             pipeline_layout != &vk::PipelineLayout::null(),
             "Cannot create pipeline before pipeline layout"
@@ -60,29 +235,9 @@ impl AdvancedRenderSystem {
 
         let mut pipeline_config = LvePipeline::default_pipline_config_info();
 
-        let color_blend_attachment_1 = Rc::new(vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE) // optional
-            .dst_color_blend_factor(vk::BlendFactor::ZERO) // optional
-            .color_blend_op(vk::BlendOp::ADD) // optional
-            .src_alpha_blend_factor(vk::BlendFactor::ONE) // optional
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO) // optional
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build()); // optional
-
-        let color_blend_attachment_2 = Rc::new(vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE) // optional
-            .dst_color_blend_factor(vk::BlendFactor::ZERO) // optional
-            .color_blend_op(vk::BlendOp::ADD) // optional
-            .src_alpha_blend_factor(vk::BlendFactor::ONE) // optional
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO) // optional
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build()); // optional
+        let blend = material.alpha_mode == AlphaMode::Blend;
 
-        let color_blend_attachment_3 = Rc::new(vk::PipelineColorBlendAttachmentState::builder()
+        let opaque_attachment = vk::PipelineColorBlendAttachmentState::builder()
             .color_write_mask(vk::ColorComponentFlags::RGBA)
             .blend_enable(false)
             .src_color_blend_factor(vk::BlendFactor::ONE) // optional
@@ -91,20 +246,32 @@ impl AdvancedRenderSystem {
             .src_alpha_blend_factor(vk::BlendFactor::ONE) // optional
             .dst_alpha_blend_factor(vk::BlendFactor::ZERO) // optional
             .alpha_blend_op(vk::BlendOp::ADD)
-            .build()); // optional
+            .build(); // optional
 
-        let color_blend_attachment_4 = Rc::new(vk::PipelineColorBlendAttachmentState::builder()
+        let blended_attachment = vk::PipelineColorBlendAttachmentState::builder()
             .color_write_mask(vk::ColorComponentFlags::RGBA)
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE) // optional
-            .dst_color_blend_factor(vk::BlendFactor::ZERO) // optional
-            .color_blend_op(vk::BlendOp::ADD) // optional
-            .src_alpha_blend_factor(vk::BlendFactor::ONE) // optional
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO) // optional
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
             .alpha_blend_op(vk::BlendOp::ADD)
-            .build()); // optional
+            .build();
 
-        let color_blend_attachments_array = [*color_blend_attachment_1, *color_blend_attachment_2, *color_blend_attachment_3, *color_blend_attachment_4];
+        // Blended surfaces replace the position and normal of what is behind them, so they get lit
+        // themselves, and mix their albedo and metallic-roughness into it. The extension lobes and
+        // emission are replaced as well.
+        let material_attachment = if blend { blended_attachment } else { opaque_attachment };
+        let color_blend_attachments_array = [
+            opaque_attachment,
+            opaque_attachment,
+            material_attachment,
+            material_attachment,
+            opaque_attachment,
+            opaque_attachment,
+            opaque_attachment,
+        ];
 
         let color_blend_info = Rc::new(vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
@@ -112,11 +279,29 @@ impl AdvancedRenderSystem {
             .attachments(&color_blend_attachments_array)
             .blend_constants([0.0, 0.0, 0.0, 0.0])
             .build()); // optional
-            
-        let color_blend_attachments_vec = vec![color_blend_attachment_1, color_blend_attachment_2, color_blend_attachment_3, color_blend_attachment_4];
-        pipeline_config.color_blend_attachments = color_blend_attachments_vec;
+
+        pipeline_config.color_blend_attachments = color_blend_attachments_array.iter().map(|&attachment| Rc::new(attachment)).collect();
         pipeline_config.color_blend_info = color_blend_info;
 
+        // glTF front faces are counter-clockwise
+        pipeline_config.rasterization_info.cull_mode = if material.double_sided { vk::CullModeFlags::NONE } else { vk::CullModeFlags::BACK };
+        pipeline_config.rasterization_info.front_face = vk::FrontFace::COUNTER_CLOCKWISE;
+
+        // Blended surfaces are tested against the depth buffer, but don't hide what is drawn after them
+        pipeline_config.depth_stencil_info.depth_write_enable = if blend { vk::FALSE } else { vk::TRUE };
+
+        pipeline_config.shader_defines = match material.alpha_mode {
+            AlphaMode::Opaque => vec![],
+            AlphaMode::Mask => vec!["ALPHA_MASK"],
+            AlphaMode::Blend => vec!["ALPHA_BLEND"],
+        };
+        if deformation.skinned {
+            pipeline_config.shader_defines.push("SKINNED");
+        }
+        if deformation.morphed {
+            pipeline_config.shader_defines.push("MORPHED");
+        }
+
         LvePipeline::new(
             lve_device,
             "./assets/shaders/deffered.vert",
@@ -147,46 +332,166 @@ impl AdvancedRenderSystem {
         }
     }
 
-    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) {
-        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout);
+    /// Keeps the current pipelines if any new one fails to build, e.g. because of a shader error
+    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) -> LveResult<()> {
+        self.pipelines = Self::create_pipelines(&lve_device, render_pass, &self.pipeline_layout)?;
+
+        Ok(())
     }
 
+    /// Copies the joint matrices of every skinned draw and the weights of every morphed one into
+    /// this frame's palette, growing it if needed. Returns the dynamic offsets of each draw's joints
+    /// and weights, draws of the same skin or node share theirs.
+    fn write_palette(&mut self, frame_index: usize, draws: &[MeshDraw]) -> LveResult<Vec<(Option<u32>, Option<u32>)>> {
+        let matrix_size = std::mem::size_of::<na::Matrix4<f32>>();
+        let float_size = std::mem::size_of::<f32>();
+        // Offsets are powers of two, so draws either start on every element or every few
+        let alignment = self.lve_device.properties.limits.min_storage_buffer_offset_alignment as usize;
+
+        let (joint_starts, joints_used) = pack(
+            draws.iter().map(|draw| draw.joints.map(|joints| (joints.as_ptr(), joints.len()))),
+            alignment.div_ceil(matrix_size).max(1),
+        );
+
+        // The mesh decides how many weights it reads, the node's are padded or cut to that
+        let target_count = |draw: &MeshDraw| draw.mesh.morph_target_count();
+        let (weight_starts, weights_used) = pack(
+            draws.iter().map(|draw| draw.weights.map(|weights| ((weights.as_ptr(), target_count(draw)), 1 + target_count(draw)))),
+            alignment.div_ceil(float_size).max(1),
+        );
+
+        let palette = &self.palettes[frame_index];
+        if joints_used > palette.joint_capacity || weights_used > palette.weight_capacity {
+            // This frame's fence has been waited on, so neither the buffers nor the set are in use anymore
+            let joint_capacity = palette.joint_capacity.max(joints_used.next_power_of_two());
+            let weight_capacity = palette.weight_capacity.max(weights_used.next_power_of_two());
+            let descriptor_set = palette.descriptor_set;
+            self.palettes[frame_index] = Self::create_palette(
+                &self.lve_device,
+                &self.palette_set_layout,
+                &self.palette_pool,
+                joint_capacity,
+                weight_capacity,
+                Some(descriptor_set),
+            )?;
+        }
+
+        let palette = &mut self.palettes[frame_index];
+        for (draw, (joint_start, weight_start)) in draws.iter().zip(joint_starts.iter().zip(weight_starts.iter())) {
+            if let (Some(joints), Some(start)) = (draw.joints, *joint_start) {
+                palette.joints.map(start);
+                palette.joints.write_to_buffer(joints);
+            }
+
+            if let (Some(weights), Some(start)) = (draw.weights, *weight_start) {
+                let count = target_count(draw);
+                let packed = std::iter::once(count as f32)
+                    .chain(weights.iter().copied().chain(std::iter::repeat(0.0)).take(count))
+                    .collect::<Vec<_>>();
+                palette.weights.map(start);
+                palette.weights.write_to_buffer(&packed);
+            }
+        }
+        palette.joints.map(0);
+        palette.weights.map(0);
+
+        let offset = |start: Option<usize>, size: usize| start.map(|start| (start * size) as u32);
+        Ok(joint_starts
+            .into_iter()
+            .zip(weight_starts)
+            .map(|(joints, weights)| (offset(joints, matrix_size), offset(weights, float_size)))
+            .collect())
+    }
+
+    /// Draws opaque and masked meshes grouped by pipeline, then blended ones back to front
     pub fn render_scene(&mut self, frame_info: &FrameInfo, scene: &Scene) {
-        unsafe { 
-            self.lve_pipeline.bind(&self.lve_device.device, frame_info.command_buffer);
-            /*self.lve_device.device.cmd_bind_descriptor_sets(
-                frame_info.command_buffer,
-                ash::vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout,
-                0,
-                &[frame_info.global_descriptor_set, frame_info.image_descriptor_set],
-                &[],
-            );*/
+        let mut draws = Vec::new();
+        for entity in scene.entities.iter() {
+            // The model pushes its own transforms, one per glTF node
+            if let Some(model) = &entity.model {
+                model.collect_draws(&entity.transform.mat4(), entity.pose.as_ref(), &mut draws);
+            }
+        }
+
+        // Without room in the palette, skinned meshes are drawn in their bind pose and morphed ones without targets
+        let palette_offsets = self.write_palette(frame_info.frame_index, &draws).unwrap_or_else(|e| {
+            log::error!("Unable to write joint matrices and morph weights: {}", e);
+            vec![(None, None); draws.len()]
+        });
+
+        // Mirrored transforms flip the winding, those fall back to the double sided pipeline
+        let pipeline = |draw: &MeshDraw| {
+            let mut pipeline = draw.mesh.pipeline();
+            pipeline.double_sided |= draw.transform.fixed_slice::<3, 3>(0, 0).determinant() < 0.0;
+            pipeline
         };
+        let order = |pipeline: MaterialPipeline| MATERIAL_PIPELINES.iter().position(|&other| other == pipeline);
+        // The camera looks down +z, larger is further away
+        let depth = |draw: &MeshDraw| (frame_info.camera.view_matrix * draw.transform.column(3)).z;
 
-        for entity in scene.entities.iter() {
-            let push = SimplePushConstantData {
-                model_matrix: Align16(entity.transform.mat4()),
-                normal_matrix: Align16(entity.transform.normal_matrix())
-            };
+        let deformation = |(joints, weights): (Option<u32>, Option<u32>)| Deformation { skinned: joints.is_some(), morphed: weights.is_some() };
+
+        let mut draws = draws
+            .into_iter()
+            .zip(palette_offsets)
+            .map(|(draw, offsets)| (pipeline(&draw), draw, offsets))
+            .collect::<Vec<_>>();
+        draws.sort_by(|(a_pipeline, a, a_offsets), (b_pipeline, b, b_offsets)| {
+            let blended = a_pipeline.alpha_mode == AlphaMode::Blend && b_pipeline.alpha_mode == AlphaMode::Blend;
+            if blended {
+                depth(b).total_cmp(&depth(a))
+            } else {
+                // Deformed draws of a material after the rest, so each variant is bound once
+                let variant = |offsets: &(Option<u32>, Option<u32>)| Deformation::ALL.iter().position(|&other| other == deformation(*offsets));
+                order(*a_pipeline).cmp(&order(*b_pipeline)).then(variant(a_offsets).cmp(&variant(b_offsets)))
+            }
+        });
+
+        let device = &self.lve_device.device;
+        let palette_set = self.palettes[frame_info.frame_index].descriptor_set;
+        let mut bound = None;
+
+        for (material, draw, offsets) in draws {
+            let key = (material, deformation(offsets));
 
             unsafe {
-                let push_ptr = push.as_bytes();
+                if bound != Some(key) {
+                    self.pipelines[&key].bind(device, frame_info.command_buffer);
+                    bound = Some(key);
+                }
 
-                self.lve_device.device.cmd_push_constants(
+                let push = SimplePushConstantData::new(draw.transform);
+                device.cmd_push_constants(
                     frame_info.command_buffer,
                     self.pipeline_layout,
                     vk::ShaderStageFlags::VERTEX,
                     0,
-                    push_ptr,
+                    push.as_bytes(),
                 );
 
-                match &entity.model {
-                    Some(_) => {
-                        entity.model.as_ref().unwrap().render(&self.lve_device.device, frame_info, self.pipeline_layout);
-                    }
-                    _ => (),
+                device.cmd_bind_descriptor_sets(
+                    frame_info.command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &[frame_info.global_descriptor_set, draw.mesh.descriptor_set],
+                    &[],
+                );
+
+                // Both bindings need an offset, the one the pipeline doesn't read stays at the start
+                if offsets.0.is_some() || offsets.1.is_some() {
+                    device.cmd_bind_descriptor_sets(
+                        frame_info.command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline_layout,
+                        2,
+                        &[palette_set],
+                        &[offsets.0.unwrap_or(0), offsets.1.unwrap_or(0)],
+                    );
                 }
+
+                draw.mesh.bind(frame_info.command_buffer);
+                draw.mesh.draw(device, frame_info.command_buffer);
             }
         }
     }
@@ -1,33 +1,100 @@
 use crate::first_app::vulkan::lve_device::*;
 use crate::first_app::vulkan::lve_pipeline::*;
+use crate::first_app::vulkan::lve_error::*;
 use crate::first_app::vulkan::lve_frame_info::*;
 
 use ash::{vk, Device};
 
 use std::rc::Rc;
 
-extern crate nalgebra as na;
+/// A lobe of the extended glTF material model, the values are the bits of `enabledLobes` in
+/// `composition.frag`
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaterialLobe {
+    /// Diffuse and specular of the core metallic-roughness model
+    Base = 1,
+    Emissive = 2,
+    Clearcoat = 4,
+    Transmission = 8,
+    Sheen = 16,
+}
+
+impl MaterialLobe {
+    pub const ALL: [MaterialLobe; 5] = [
+        MaterialLobe::Base,
+        MaterialLobe::Emissive,
+        MaterialLobe::Clearcoat,
+        MaterialLobe::Transmission,
+        MaterialLobe::Sheen,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MaterialLobe::Base => "base",
+            MaterialLobe::Emissive => "emissive",
+            MaterialLobe::Clearcoat => "clearcoat",
+            MaterialLobe::Transmission => "transmission",
+            MaterialLobe::Sheen => "sheen",
+        }
+    }
+}
+
+/// Which material lobes the composition pass shades, toggled from the materials window
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MaterialLobes {
+    /// `MaterialLobe` bits, a disabled lobe shades as if the material didn't have it
+    pub enabled: u32,
+    /// Shows only the light of this lobe
+    pub isolated: Option<MaterialLobe>,
+    /// Off treats every material as having the default index of refraction of 1.5
+    pub ior: bool,
+}
 
-#[allow(dead_code)]
-pub struct SimplePushConstantData {
-    model_matrix: Align16<na::Matrix4<f32>>,
-    normal_matrix: Align16<na::Matrix4<f32>>
+impl Default for MaterialLobes {
+    fn default() -> Self {
+        Self {
+            enabled: MaterialLobe::ALL.iter().fold(0, |bits, &lobe| bits | lobe as u32),
+            isolated: None,
+            ior: true,
+        }
+    }
 }
 
-impl SimplePushConstantData {
-    pub unsafe fn as_bytes(&self) -> &[u8] {
-        let size_in_bytes = std::mem::size_of::<Self>();
-        let size_in_u8 = size_in_bytes / std::mem::size_of::<u8>();
-        let start_ptr = self as *const Self as *const u8;
-        std::slice::from_raw_parts(start_ptr, size_in_u8)
+impl MaterialLobes {
+    pub fn is_enabled(&self, lobe: MaterialLobe) -> bool {
+        self.enabled & lobe as u32 != 0
+    }
+
+    pub fn set_enabled(&mut self, lobe: MaterialLobe, enabled: bool) {
+        if enabled {
+            self.enabled |= lobe as u32;
+        } else {
+            self.enabled &= !(lobe as u32);
+        }
+    }
+
+    fn push_constants(&self) -> CompositionPushConstants {
+        CompositionPushConstants {
+            enabled_lobes: self.enabled,
+            isolated_lobe: self.isolated.map_or(0, |lobe| lobe as u32),
+            use_ior: self.ior as u32,
+        }
     }
+}
 
-    pub unsafe fn _print_buffer(&self) {
-        let size_in_bytes = std::mem::size_of::<Self>();
-        let size_in_u8 = size_in_bytes / std::mem::size_of::<f32>();
-        let start_ptr = self as *const Self as *const f32;
-        let buffer = std::slice::from_raw_parts(start_ptr, size_in_u8);
-        log::debug!("{:?}", buffer);
+/// The `Push` block of `composition.frag`
+#[repr(C)]
+struct CompositionPushConstants {
+    enabled_lobes: u32,
+    /// 0 shows every enabled lobe
+    isolated_lobe: u32,
+    use_ior: u32,
+}
+
+impl CompositionPushConstants {
+    unsafe fn as_bytes(&self) -> &[u8] {
+        std::slice::from_raw_parts(self as *const Self as *const u8, std::mem::size_of::<Self>())
     }
 }
 
@@ -38,19 +105,25 @@ pub struct CompositionRenderSystem {
 }
 
 impl CompositionRenderSystem {
-    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> Self {
+    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> LveResult<Self> {
         let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
 
-        let lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+        let lve_pipeline = match Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout) {
+            Ok(lve_pipeline) => lve_pipeline,
+            Err(e) => {
+                unsafe { lve_device.device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(e);
+            }
+        };
 
-        Self {
+        Ok(Self {
             lve_device,
             lve_pipeline,
             pipeline_layout,
-        }
+        })
     }
 
-    fn create_pipeline(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout,) -> LvePipeline {
+    fn create_pipeline(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout,) -> LveResult<LvePipeline> {
         assert!(
             pipeline_layout != &vk::PipelineLayout::null(),
             "Cannot create pipeline before pipeline layout"
@@ -70,9 +143,9 @@ impl CompositionRenderSystem {
 
     fn create_pipeline_layout(device: &Device, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> vk::PipelineLayout {
         let push_constant_range = vk::PushConstantRange::builder()
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
             .offset(0)
-            .size(std::mem::size_of::<SimplePushConstantData>() as u32)
+            .size(std::mem::size_of::<CompositionPushConstants>() as u32)
             .build();
 
         let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
@@ -88,12 +161,15 @@ impl CompositionRenderSystem {
         }
     }
 
-    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) {
-        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout);
+    /// Keeps the current pipeline if the new one fails to build, e.g. because of a shader error
+    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) -> LveResult<()> {
+        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout)?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
-    pub fn render(&mut self, frame_info: &FrameInfo, set: vk::DescriptorSet) {
+    pub fn render(&mut self, frame_info: &FrameInfo, set: vk::DescriptorSet, lobes: &MaterialLobes) {
         unsafe { 
             self.lve_pipeline.bind(&self.lve_device.device, frame_info.command_buffer);
             self.lve_device.device.cmd_bind_descriptor_sets(
@@ -105,6 +181,14 @@ impl CompositionRenderSystem {
                 &[],
             );
 
+            self.lve_device.device.cmd_push_constants(
+                frame_info.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                lobes.push_constants().as_bytes(),
+            );
+
             self.lve_device.device.cmd_draw(frame_info.command_buffer, 3, 1, 0, 0);
         };
     }
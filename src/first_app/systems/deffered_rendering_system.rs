@@ -1,14 +1,14 @@
 use ash::vk;
 use winit::window::Window;
-use crate::first_app::vulkan::{lve_device::*, lve_frame_info::FrameInfo};
+use crate::first_app::vulkan::{lve_device::*, lve_allocator::*, lve_error::*, lve_frame_info::FrameInfo};
 
 use std::rc::Rc;
 
 pub struct FrameBufferAttachment {
-    image: vk::Image,
-    image_memory: vk::DeviceMemory,
+    pub image: vk::Image,
+    allocation: LveAllocation,
     pub image_view: vk::ImageView,
-    format: vk::Format
+    pub format: vk::Format
 }
 
 pub struct Framebuffer {
@@ -19,6 +19,12 @@ pub struct Framebuffer {
     pub normal: FrameBufferAttachment,
     pub albedo: FrameBufferAttachment,
     pub metallic_roughness: FrameBufferAttachment,
+    /// Emission in RGB, index of refraction in alpha
+    pub emissive: FrameBufferAttachment,
+    /// Sheen color in RGB, sheen roughness in alpha
+    pub sheen: FrameBufferAttachment,
+    /// Clearcoat in red, its roughness in green, transmission in blue
+    pub clearcoat: FrameBufferAttachment,
     pub depth: FrameBufferAttachment,
     render_pass: vk::RenderPass
 }
@@ -30,11 +36,14 @@ pub struct DefferedRenderingSystem {
 }
 
 impl DefferedRenderingSystem {
-    pub fn new(lve_device: Rc<LveDevice>, width: u32, height: u32) -> Self {
-        let position = Self::create_attachment(&lve_device, width, height, vk::Format::R16G16B16A16_SFLOAT, vk::ImageUsageFlags::COLOR_ATTACHMENT);
-        let normal = Self::create_attachment(&lve_device, width, height, vk::Format::R16G16B16A16_SFLOAT, vk::ImageUsageFlags::COLOR_ATTACHMENT);
-        let albedo = Self::create_attachment(&lve_device, width, height, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::COLOR_ATTACHMENT);
-        let metallic_roughness = Self::create_attachment(&lve_device, width, height, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::COLOR_ATTACHMENT);
+    pub fn new(lve_device: Rc<LveDevice>, width: u32, height: u32) -> LveResult<Self> {
+        let position = Self::create_attachment(&lve_device, width, height, vk::Format::R16G16B16A16_SFLOAT, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
+        let normal = Self::create_attachment(&lve_device, width, height, vk::Format::R16G16B16A16_SFLOAT, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
+        let albedo = Self::create_attachment(&lve_device, width, height, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
+        let metallic_roughness = Self::create_attachment(&lve_device, width, height, vk::Format::R8G8B8A8_SRGB, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
+        let emissive = Self::create_attachment(&lve_device, width, height, vk::Format::R16G16B16A16_SFLOAT, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
+        let sheen = Self::create_attachment(&lve_device, width, height, vk::Format::R8G8B8A8_UNORM, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
+        let clearcoat = Self::create_attachment(&lve_device, width, height, vk::Format::R8G8B8A8_UNORM, vk::ImageUsageFlags::COLOR_ATTACHMENT)?;
         
         let candidates = vec![
             //vk::Format::D32_SFLOAT,
@@ -45,11 +54,11 @@ impl DefferedRenderingSystem {
             &candidates,
             vk::ImageTiling::OPTIMAL,
             vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
-        );
+        ).ok_or(LveError::Unsupported("a G-buffer depth format"))?;
 
-        let depth = Self::create_attachment(&lve_device, width, height, valid_depth, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT);
+        let depth = Self::create_attachment(&lve_device, width, height, valid_depth, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)?;
 
-        let attachment_desc_1 = vk::AttachmentDescription::builder()
+        let color_attachment_desc = |attachment: &FrameBufferAttachment| vk::AttachmentDescription::builder()
             .samples(vk::SampleCountFlags::TYPE_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
@@ -57,43 +66,12 @@ impl DefferedRenderingSystem {
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
             .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .format(position.format)
+            .format(attachment.format)
             .build();
 
-        let attachment_desc_2 = vk::AttachmentDescription::builder()
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .format(normal.format)
-            .build();
+        let color_attachments = [&position, &normal, &albedo, &metallic_roughness, &emissive, &sheen, &clearcoat];
 
-        let attachment_desc_3 = vk::AttachmentDescription::builder()
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .format(albedo.format)
-            .build();
-
-        let attachment_desc_4 = vk::AttachmentDescription::builder()
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .format(metallic_roughness.format)
-            .build();
-
-        let attachment_desc_5 = vk::AttachmentDescription::builder()
+        let depth_attachment_desc = vk::AttachmentDescription::builder()
             .samples(vk::SampleCountFlags::TYPE_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::STORE)
@@ -104,32 +82,20 @@ impl DefferedRenderingSystem {
             .format(depth.format)
             .build();
 
-        let attachment_descs = [attachment_desc_1, attachment_desc_2, attachment_desc_3, attachment_desc_4, attachment_desc_5];
+        let attachment_descs: Vec<_> = color_attachments.iter()
+            .map(|attachment| color_attachment_desc(attachment))
+            .chain([depth_attachment_desc])
+            .collect();
 
-        let color_attachment_ref_1 = vk::AttachmentReference::builder()
-            .attachment(0)
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .build();
-        
-        let color_attachment_ref_2 = vk::AttachmentReference::builder()
-            .attachment(1)
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .build();
-
-        let color_attachment_ref_3 = vk::AttachmentReference::builder()
-            .attachment(2)
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .build();
-
-        let color_attachment_ref_4 = vk::AttachmentReference::builder()
-            .attachment(3)
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .build();
-
-        let attachment_refs = [color_attachment_ref_1, color_attachment_ref_2, color_attachment_ref_3, color_attachment_ref_4];
+        let attachment_refs: Vec<_> = (0..color_attachments.len() as u32)
+            .map(|attachment| vk::AttachmentReference::builder()
+                .attachment(attachment)
+                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .build())
+            .collect();
 
         let depth_attachment_ref = vk::AttachmentReference::builder()
-            .attachment(4)
+            .attachment(color_attachments.len() as u32)
             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .build();
 
@@ -170,10 +136,14 @@ impl DefferedRenderingSystem {
         let render_pass = unsafe {
             lve_device
                 .device
-                .create_render_pass(&render_pass_info, None).unwrap()
+                .create_render_pass(&render_pass_info, None)
+                .map_err(LveError::vulkan("Unable to create G-buffer render pass"))?
         };
 
-        let image_views = [position.image_view, normal.image_view, albedo.image_view, metallic_roughness.image_view, depth.image_view];
+        let image_views: Vec<_> = color_attachments.iter()
+            .map(|attachment| attachment.image_view)
+            .chain([depth.image_view])
+            .collect();
 
         let frame_buffer_info = vk::FramebufferCreateInfo::builder()
             .render_pass(render_pass)
@@ -186,8 +156,7 @@ impl DefferedRenderingSystem {
             lve_device
                 .device
                 .create_framebuffer(&frame_buffer_info, None)
-                .map_err(|e| log::error!("Unable to create render pass: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create G-buffer framebuffer"))?
         };
 
         let sampler_info = vk::SamplerCreateInfo::builder()
@@ -208,8 +177,7 @@ impl DefferedRenderingSystem {
             lve_device
                 .device
                 .create_sampler(&sampler_info, None)
-                .map_err(|e| log::error!("Unable to create render pass: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create G-buffer sampler"))?
         };
 
         let things = Framebuffer {
@@ -220,15 +188,18 @@ impl DefferedRenderingSystem {
             normal,
             albedo,
             metallic_roughness,
+            emissive,
+            sheen,
+            clearcoat,
             depth,
             render_pass
         };
 
-        DefferedRenderingSystem {
+        Ok(DefferedRenderingSystem {
             lve_device,
             things,
             sampler
-        }
+        })
 
     }
 
@@ -236,13 +207,45 @@ impl DefferedRenderingSystem {
         self.things.render_pass
     }
 
-    fn create_attachment(lve_device: &Rc<LveDevice>, width: u32, height: u32, format: vk::Format, usage: vk::ImageUsageFlags) -> FrameBufferAttachment {
+    pub fn get_extent(&self) -> vk::Extent2D {
+        vk::Extent2D { width: self.things.width, height: self.things.height }
+    }
+
+    /// The color attachments in the order of their bindings in `composition.frag`, named for G-buffer dumps
+    pub fn color_attachments(&self) -> [(&'static str, &FrameBufferAttachment); 7] {
+        [
+            ("position", &self.things.position),
+            ("normal", &self.things.normal),
+            ("albedo", &self.things.albedo),
+            ("metallic_roughness", &self.things.metallic_roughness),
+            ("emissive", &self.things.emissive),
+            ("sheen", &self.things.sheen),
+            ("clearcoat", &self.things.clearcoat),
+        ]
+    }
+
+    /// What the composition pass samples, one info per `color_attachments` entry
+    pub fn attachment_infos(&self) -> [vk::DescriptorImageInfo; 7] {
+        self.color_attachments().map(|(_, attachment)| {
+            vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(attachment.image_view)
+                .sampler(self.sampler)
+                .build()
+        })
+    }
+
+    fn create_attachment(lve_device: &Rc<LveDevice>, width: u32, height: u32, format: vk::Format, usage: vk::ImageUsageFlags) -> LveResult<FrameBufferAttachment> {
         let mut aspect_mask = vk::ImageAspectFlags::COLOR;
         let mut image_layout = vk::ImageLayout::UNDEFINED;
 
+        let mut extra_usage = vk::ImageUsageFlags::SAMPLED;
+
         if usage == vk::ImageUsageFlags::COLOR_ATTACHMENT {
             aspect_mask = vk::ImageAspectFlags::COLOR;
             image_layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+            // Allows copying the G-buffer back to the host for debugging
+            extra_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
         }
 
         if usage == vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT {
@@ -258,9 +261,9 @@ impl DefferedRenderingSystem {
             .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(usage | vk::ImageUsageFlags::SAMPLED);
+            .usage(usage | extra_usage);
 
-        let (image, image_memory) = lve_device.create_image_with_info(&image_create_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+        let (image, allocation) = lve_device.create_image_with_info(&image_create_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
 
         let imageview_create_info = vk::ImageViewCreateInfo {
             s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
@@ -287,42 +290,33 @@ impl DefferedRenderingSystem {
         let image_view = unsafe {
             lve_device.device
                 .create_image_view(&imageview_create_info, None)
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create G-buffer image view"))?
         };
 
-        FrameBufferAttachment {
+        Ok(FrameBufferAttachment {
             image,
-            image_memory,
+            allocation,
             image_view,
             format
-        }
+        })
     }
 
     pub fn start(&self, frame_info: &FrameInfo) {
 
-        let color_clear_1 = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.01, 0.01, 0.01, 1.0],
-            },
-        };
-
-        let color_clear_2 = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.01, 0.01, 0.01, 1.0],
-            },
+        let color_clear = |float32| vk::ClearValue {
+            color: vk::ClearColorValue { float32 },
         };
 
-        let color_clear_3 = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.01, 0.01, 0.01, 1.0],
-            },
-        };
-
-        let color_clear_4 = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.01, 0.01, 0.01, 1.0],
-            },
-        };
+        // Empty pixels have no extension lobes and the default index of refraction
+        let color_clears = [
+            color_clear([0.01, 0.01, 0.01, 1.0]),
+            color_clear([0.01, 0.01, 0.01, 1.0]),
+            color_clear([0.01, 0.01, 0.01, 1.0]),
+            color_clear([0.01, 0.01, 0.01, 1.0]),
+            color_clear([0.0, 0.0, 0.0, 1.5]),
+            color_clear([0.0, 0.0, 0.0, 0.0]),
+            color_clear([0.0, 0.0, 0.0, 0.0]),
+        ];
 
         let depth_clear = vk::ClearValue {
             depth_stencil: vk::ClearDepthStencilValue {
@@ -331,7 +325,7 @@ impl DefferedRenderingSystem {
             },
         };
 
-        let clear_values = [color_clear_1, color_clear_2, color_clear_3, color_clear_4, depth_clear];
+        let clear_values: Vec<_> = color_clears.into_iter().chain([depth_clear]).collect();
 
         let render_area = vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
@@ -391,18 +385,27 @@ impl Drop for DefferedRenderingSystem {
             self.lve_device.device.destroy_image(self.things.normal.image, None);
             self.lve_device.device.destroy_image(self.things.albedo.image, None);
             self.lve_device.device.destroy_image(self.things.metallic_roughness.image, None);
+            self.lve_device.device.destroy_image(self.things.emissive.image, None);
+            self.lve_device.device.destroy_image(self.things.sheen.image, None);
+            self.lve_device.device.destroy_image(self.things.clearcoat.image, None);
             self.lve_device.device.destroy_image(self.things.depth.image, None);
 
-            self.lve_device.device.free_memory(self.things.position.image_memory, None);
-            self.lve_device.device.free_memory(self.things.normal.image_memory, None);
-            self.lve_device.device.free_memory(self.things.albedo.image_memory, None);
-            self.lve_device.device.free_memory(self.things.metallic_roughness.image_memory, None);
-            self.lve_device.device.free_memory(self.things.depth.image_memory, None);
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.position.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.normal.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.albedo.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.metallic_roughness.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.emissive.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.sheen.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.clearcoat.allocation));
+            self.lve_device.allocator.free(std::mem::take(&mut self.things.depth.allocation));
 
             self.lve_device.device.destroy_image_view(self.things.position.image_view, None);
             self.lve_device.device.destroy_image_view(self.things.normal.image_view, None);
             self.lve_device.device.destroy_image_view(self.things.albedo.image_view, None);
             self.lve_device.device.destroy_image_view(self.things.metallic_roughness.image_view, None);
+            self.lve_device.device.destroy_image_view(self.things.emissive.image_view, None);
+            self.lve_device.device.destroy_image_view(self.things.sheen.image_view, None);
+            self.lve_device.device.destroy_image_view(self.things.clearcoat.image_view, None);
             self.lve_device.device.destroy_image_view(self.things.depth.image_view, None);
 
             self.lve_device.device.destroy_sampler(self.sampler, None);
@@ -1,5 +1,6 @@
 use crate::first_app::vulkan::lve_device::*;
 use crate::first_app::vulkan::lve_pipeline::*;
+use crate::first_app::vulkan::lve_error::*;
 use crate::first_app::vulkan::lve_frame_info::*;
 
 use ash::{vk, Device};
@@ -32,23 +33,29 @@ pub struct PointRenderSystem {
 }
 
 impl PointRenderSystem {
-    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> Self {
+    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> LveResult<Self> {
         let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
 
-        let lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+        let lve_pipeline = match Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout) {
+            Ok(lve_pipeline) => lve_pipeline,
+            Err(e) => {
+                unsafe { lve_device.device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(e);
+            }
+        };
 
-        Self {
+        Ok(Self {
             lve_device,
             lve_pipeline,
             pipeline_layout,
-        }
+        })
     }
 
     fn create_pipeline(
         lve_device: Rc<LveDevice>,
         render_pass: &vk::RenderPass,
         pipeline_layout: &vk::PipelineLayout,
-    ) -> LvePipeline {
+    ) -> LveResult<LvePipeline> {
         assert!(
             pipeline_layout != &vk::PipelineLayout::null(),
             "Cannot create pipeline before pipeline layout"
@@ -100,7 +107,21 @@ impl PointRenderSystem {
             .alpha_blend_op(vk::BlendOp::ADD)
             .build()); // optional
 
-        let color_blend_attachments_array = [*color_blend_attachment_1, *color_blend_attachment_2, *color_blend_attachment_3, *color_blend_attachment_4];
+        // The billboards only write albedo, they leave the extension lobes of what is behind alone
+        let unwritten_attachment = Rc::new(vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::empty())
+            .blend_enable(false)
+            .build());
+
+        let color_blend_attachments_array = [
+            *color_blend_attachment_1,
+            *color_blend_attachment_2,
+            *color_blend_attachment_3,
+            *color_blend_attachment_4,
+            *unwritten_attachment,
+            *unwritten_attachment,
+            *unwritten_attachment,
+        ];
 
         let color_blend_info = Rc::new(vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
@@ -109,7 +130,15 @@ impl PointRenderSystem {
             .blend_constants([0.0, 0.0, 0.0, 0.0])
             .build()); // optional
             
-        let color_blend_attachments_vec = vec![color_blend_attachment_1, color_blend_attachment_2, color_blend_attachment_3, color_blend_attachment_4];
+        let color_blend_attachments_vec = vec![
+            color_blend_attachment_1,
+            color_blend_attachment_2,
+            color_blend_attachment_3,
+            color_blend_attachment_4,
+            Rc::clone(&unwritten_attachment),
+            Rc::clone(&unwritten_attachment),
+            unwritten_attachment,
+        ];
         pipeline_config.color_blend_attachments = color_blend_attachments_vec;
         pipeline_config.color_blend_info = color_blend_info;
 
@@ -143,8 +172,11 @@ impl PointRenderSystem {
         }
     }
 
-    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) {
-        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout);
+    /// Keeps the current pipeline if the new one fails to build, e.g. because of a shader error
+    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) -> LveResult<()> {
+        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout)?;
+
+        Ok(())
     }
 
     pub fn update(&self, frame_info: &FrameInfo, ubo: &mut GlobalUbo) {
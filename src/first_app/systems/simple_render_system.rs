@@ -1,5 +1,6 @@
 use crate::first_app::vulkan::lve_device::*;
 use crate::first_app::vulkan::lve_pipeline::*;
+use crate::first_app::vulkan::lve_error::*;
 use crate::first_app::vulkan::lve_frame_info::*;
 
 use ash::{vk, Device};
@@ -15,6 +16,8 @@ pub struct SimplePushConstantData {
 }
 
 impl SimplePushConstantData {
+    /// # Safety
+    /// The slice covers padding bytes too, only hand it to `cmd_push_constants`.
     pub unsafe fn as_bytes(&self) -> &[u8] {
         let size_in_bytes = std::mem::size_of::<Self>();
         let size_in_u8 = size_in_bytes / std::mem::size_of::<u8>();
@@ -22,6 +25,8 @@ impl SimplePushConstantData {
         std::slice::from_raw_parts(start_ptr, size_in_u8)
     }
 
+    /// # Safety
+    /// Only sound while the struct holds nothing but `f32`s.
     pub unsafe fn _print_buffer(&self) {
         let size_in_bytes = std::mem::size_of::<Self>();
         let size_in_u8 = size_in_bytes / std::mem::size_of::<f32>();
@@ -38,19 +43,25 @@ pub struct SimpleRenderSystem {
 }
 
 impl SimpleRenderSystem {
-    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> Self {
+    pub fn new(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, global_set_layout: &[ash::vk::DescriptorSetLayout]) -> LveResult<Self> {
         let pipeline_layout = Self::create_pipeline_layout(&lve_device.device, global_set_layout);
 
-        let lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout);
+        let lve_pipeline = match Self::create_pipeline(Rc::clone(&lve_device), render_pass, &pipeline_layout) {
+            Ok(lve_pipeline) => lve_pipeline,
+            Err(e) => {
+                unsafe { lve_device.device.destroy_pipeline_layout(pipeline_layout, None) };
+                return Err(e);
+            }
+        };
 
-        Self {
+        Ok(Self {
             lve_device,
             lve_pipeline,
             pipeline_layout,
-        }
+        })
     }
 
-    fn create_pipeline(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout,) -> LvePipeline {
+    fn create_pipeline(lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass, pipeline_layout: &vk::PipelineLayout,) -> LveResult<LvePipeline> {
         assert!(
             pipeline_layout != &vk::PipelineLayout::null(),
             "Cannot create pipeline before pipeline layout"
@@ -88,8 +99,11 @@ impl SimpleRenderSystem {
         }
     }
 
-    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) {
-        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout);
+    /// Keeps the current pipeline if the new one fails to build, e.g. because of a shader error
+    pub fn recreate_pipeline(&mut self, lve_device: Rc<LveDevice>, render_pass: &vk::RenderPass) -> LveResult<()> {
+        self.lve_pipeline = Self::create_pipeline(Rc::clone(&lve_device), render_pass, &self.pipeline_layout)?;
+
+        Ok(())
     }
 
     #[allow(dead_code)]
@@ -0,0 +1,585 @@
+use super::lve_error::*;
+
+use ash::{vk, Device, Instance};
+
+use std::{cell::RefCell, ffi::c_void};
+
+/// Size of the blocks small allocations are carved out of, smaller on heaps that can't fit a few
+const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Resources at least this big always get their own `VkDeviceMemory`
+const DEDICATED_THRESHOLD: vk::DeviceSize = BLOCK_SIZE / 2;
+
+/// Buffers and optimal tiling images never share a block, so `bufferImageGranularity`
+/// can't make two neighbouring allocations alias
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResourceKind {
+    Linear,
+    Optimal,
+}
+
+/// A piece of device memory owned by one buffer or image, give it back with `LveAllocator::free`
+pub struct LveAllocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    memory_size: vk::DeviceSize,
+    memory_type: u32,
+    mapped: *mut c_void,
+    block_id: Option<u64>,
+}
+
+impl LveAllocation {
+    pub fn null() -> Self {
+        Self {
+            memory: vk::DeviceMemory::null(),
+            offset: 0,
+            size: 0,
+            memory_size: 0,
+            memory_type: 0,
+            mapped: std::ptr::null_mut(),
+            block_id: None,
+        }
+    }
+
+    /// Host pointer to the start of the allocation, `None` unless the memory is `HOST_VISIBLE`.
+    /// Host visible memory stays mapped for its whole lifetime.
+    pub fn mapped_ptr(&self) -> Option<*mut c_void> {
+        if self.mapped.is_null() {
+            None
+        } else {
+            Some(self.mapped)
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_dedicated(&self) -> bool {
+        self.memory != vk::DeviceMemory::null() && self.block_id.is_none()
+    }
+}
+
+impl Default for LveAllocation {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct LveMemoryTypeStats {
+    pub memory_type: u32,
+    pub property_flags: vk::MemoryPropertyFlags,
+    pub blocks: usize,
+    pub dedicated_allocations: usize,
+    pub allocations: usize,
+    /// Bytes allocated from Vulkan, blocks plus dedicated allocations
+    pub reserved_bytes: vk::DeviceSize,
+    /// Bytes handed out to buffers and images
+    pub used_bytes: vk::DeviceSize,
+}
+
+#[derive(Clone, Default)]
+pub struct LveAllocatorStats {
+    /// Only memory types that have been allocated from
+    pub memory_types: Vec<LveMemoryTypeStats>,
+    pub max_memory_allocation_count: u32,
+}
+
+impl LveAllocatorStats {
+    /// Number of live `vkAllocateMemory` allocations, the thing `maxMemoryAllocationCount` limits
+    pub fn device_memory_objects(&self) -> usize {
+        self.memory_types.iter().map(|t| t.blocks + t.dedicated_allocations).sum()
+    }
+
+    pub fn allocations(&self) -> usize {
+        self.memory_types.iter().map(|t| t.allocations).sum()
+    }
+
+    pub fn reserved_bytes(&self) -> vk::DeviceSize {
+        self.memory_types.iter().map(|t| t.reserved_bytes).sum()
+    }
+
+    pub fn used_bytes(&self) -> vk::DeviceSize {
+        self.memory_types.iter().map(|t| t.used_bytes).sum()
+    }
+}
+
+struct MemoryBlock {
+    id: u64,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    mapped: *mut c_void,
+    /// Unused `(offset, size)` ranges sorted by offset, neighbours are always merged
+    free_ranges: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+    allocation_count: usize,
+}
+
+impl MemoryBlock {
+    fn new(id: u64, memory: vk::DeviceMemory, size: vk::DeviceSize, mapped: *mut c_void) -> Self {
+        Self {
+            id,
+            memory,
+            size,
+            mapped,
+            free_ranges: vec![(0, size)],
+            allocation_count: 0,
+        }
+    }
+
+    /// First fit, returns the offset of the allocation
+    fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let index = self.free_ranges.iter().position(|&(offset, range_size)| {
+            align_up(offset, alignment) - offset + size <= range_size
+        })?;
+
+        let (offset, range_size) = self.free_ranges.remove(index);
+        let aligned_offset = align_up(offset, alignment);
+        let end = offset + range_size;
+
+        let mut insert_at = index;
+        if aligned_offset > offset {
+            self.free_ranges.insert(insert_at, (offset, aligned_offset - offset));
+            insert_at += 1;
+        }
+        if aligned_offset + size < end {
+            self.free_ranges.insert(insert_at, (aligned_offset + size, end - aligned_offset - size));
+        }
+
+        self.allocation_count += 1;
+        Some(aligned_offset)
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let mut index = self.free_ranges.partition_point(|&(o, _)| o < offset);
+        self.free_ranges.insert(index, (offset, size));
+
+        if index + 1 < self.free_ranges.len() {
+            let (next_offset, next_size) = self.free_ranges[index + 1];
+            if offset + size == next_offset {
+                self.free_ranges[index].1 += next_size;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+
+        if index > 0 {
+            let (previous_offset, previous_size) = self.free_ranges[index - 1];
+            if previous_offset + previous_size == offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+                index -= 1;
+            }
+        }
+
+        debug_assert!(self.free_ranges[index].0 + self.free_ranges[index].1 <= self.size);
+        self.allocation_count -= 1;
+    }
+
+    fn used_bytes(&self) -> vk::DeviceSize {
+        self.size - self.free_ranges.iter().map(|&(_, size)| size).sum::<vk::DeviceSize>()
+    }
+}
+
+struct MemoryPool {
+    memory_type: u32,
+    kind: ResourceKind,
+    blocks: Vec<MemoryBlock>,
+}
+
+#[derive(Default)]
+struct DedicatedStats {
+    count: usize,
+    bytes: vk::DeviceSize,
+}
+
+struct AllocatorState {
+    pools: Vec<MemoryPool>,
+    dedicated: Vec<DedicatedStats>,
+    next_block_id: u64,
+}
+
+/// Sub-allocates buffers and images out of large `VkDeviceMemory` blocks, one set of blocks per
+/// memory type. Render targets and anything bigger than half a block get a dedicated allocation.
+pub struct LveAllocator {
+    device: Device,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: vk::DeviceSize,
+    max_memory_allocation_count: u32,
+    state: RefCell<AllocatorState>,
+}
+
+impl LveAllocator {
+    pub fn new(instance: &Instance, physical_device: vk::PhysicalDevice, device: Device) -> Self {
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let dedicated = (0..memory_properties.memory_type_count).map(|_| DedicatedStats::default()).collect();
+
+        Self {
+            device,
+            memory_properties,
+            non_coherent_atom_size: properties.limits.non_coherent_atom_size.max(1),
+            max_memory_allocation_count: properties.limits.max_memory_allocation_count,
+            state: RefCell::new(AllocatorState {
+                pools: Vec::new(),
+                dedicated,
+                next_block_id: 0,
+            }),
+        }
+    }
+
+    pub fn find_memory_type(&self, type_filter: u32, properties: vk::MemoryPropertyFlags) -> Option<u32> {
+        (0..self.memory_properties.memory_type_count).find(|&index| {
+            (type_filter & (1 << index)) != 0
+                && self.memory_properties.memory_types[index as usize].property_flags.contains(properties)
+        })
+    }
+
+    /// Allocates memory for `buffer` and binds it
+    pub fn allocate_buffer(&self, buffer: vk::Buffer, properties: vk::MemoryPropertyFlags) -> LveResult<LveAllocation> {
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+        let dedicated = requirements.size >= DEDICATED_THRESHOLD;
+
+        let allocation = self.allocate(requirements, properties, ResourceKind::Linear, dedicated, None)?;
+
+        let result = unsafe { self.device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) };
+        if let Err(result) = result {
+            self.free(allocation);
+            return Err(LveError::Vulkan { context: "Unable to bind buffer memory", result });
+        }
+
+        Ok(allocation)
+    }
+
+    /// Allocates memory for `image` and binds it, `prefer_dedicated` is meant for render targets
+    pub fn allocate_image(
+        &self,
+        image: vk::Image,
+        properties: vk::MemoryPropertyFlags,
+        prefer_dedicated: bool,
+    ) -> LveResult<LveAllocation> {
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements2 = vk::MemoryRequirements2::builder().push_next(&mut dedicated_requirements);
+        let info = vk::ImageMemoryRequirementsInfo2::builder().image(image);
+
+        unsafe { self.device.get_image_memory_requirements2(&info, &mut requirements2) };
+        let requirements = requirements2.memory_requirements;
+
+        let dedicated = prefer_dedicated
+            || requirements.size >= DEDICATED_THRESHOLD
+            || dedicated_requirements.prefers_dedicated_allocation == vk::TRUE
+            || dedicated_requirements.requires_dedicated_allocation == vk::TRUE;
+
+        let allocation = self.allocate(requirements, properties, ResourceKind::Optimal, dedicated, Some(image))?;
+
+        let result = unsafe { self.device.bind_image_memory(image, allocation.memory, allocation.offset) };
+        if let Err(result) = result {
+            self.free(allocation);
+            return Err(LveError::Vulkan { context: "Unable to bind image memory", result });
+        }
+
+        Ok(allocation)
+    }
+
+    fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        kind: ResourceKind,
+        dedicated: bool,
+        dedicated_image: Option<vk::Image>,
+    ) -> LveResult<LveAllocation> {
+        let memory_type = self
+            .find_memory_type(requirements.memory_type_bits, properties)
+            .ok_or(LveError::Unsupported("No memory type with the requested properties"))?;
+
+        if dedicated {
+            return self.allocate_dedicated(requirements.size, memory_type, dedicated_image);
+        }
+
+        let mut state = self.state.borrow_mut();
+
+        let pool_index = match state.pools.iter().position(|p| p.memory_type == memory_type && p.kind == kind) {
+            Some(index) => index,
+            None => {
+                state.pools.push(MemoryPool { memory_type, kind, blocks: Vec::new() });
+                state.pools.len() - 1
+            }
+        };
+
+        let pool = &mut state.pools[pool_index];
+        for block in pool.blocks.iter_mut() {
+            if let Some(offset) = block.allocate(requirements.size, requirements.alignment) {
+                return Ok(Self::sub_allocation(block, memory_type, offset, requirements.size));
+            }
+        }
+
+        let block_size = self.block_size(memory_type);
+        if requirements.size > block_size {
+            drop(state);
+            return self.allocate_dedicated(requirements.size, memory_type, dedicated_image);
+        }
+
+        let (memory, mapped) = self.allocate_device_memory(block_size, memory_type, None)?;
+
+        let id = state.next_block_id;
+        state.next_block_id += 1;
+
+        let pool = &mut state.pools[pool_index];
+        pool.blocks.push(MemoryBlock::new(id, memory, block_size, mapped));
+
+        let block = pool.blocks.last_mut().unwrap();
+        let offset = block.allocate(requirements.size, requirements.alignment).unwrap();
+        Ok(Self::sub_allocation(block, memory_type, offset, requirements.size))
+    }
+
+    fn sub_allocation(block: &MemoryBlock, memory_type: u32, offset: vk::DeviceSize, size: vk::DeviceSize) -> LveAllocation {
+        let mapped = if block.mapped.is_null() {
+            std::ptr::null_mut()
+        } else {
+            unsafe { (block.mapped as *mut u8).add(offset as usize) as *mut c_void }
+        };
+
+        LveAllocation {
+            memory: block.memory,
+            offset,
+            size,
+            memory_size: block.size,
+            memory_type,
+            mapped,
+            block_id: Some(block.id),
+        }
+    }
+
+    fn allocate_dedicated(
+        &self,
+        size: vk::DeviceSize,
+        memory_type: u32,
+        image: Option<vk::Image>,
+    ) -> LveResult<LveAllocation> {
+        let (memory, mapped) = self.allocate_device_memory(size, memory_type, image)?;
+
+        let mut state = self.state.borrow_mut();
+        let stats = &mut state.dedicated[memory_type as usize];
+        stats.count += 1;
+        stats.bytes += size;
+
+        Ok(LveAllocation {
+            memory,
+            offset: 0,
+            size,
+            memory_size: size,
+            memory_type,
+            mapped,
+            block_id: None,
+        })
+    }
+
+    fn allocate_device_memory(
+        &self,
+        size: vk::DeviceSize,
+        memory_type: u32,
+        dedicated_image: Option<vk::Image>,
+    ) -> LveResult<(vk::DeviceMemory, *mut c_void)> {
+        let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::builder()
+            .image(dedicated_image.unwrap_or_default());
+
+        let mut alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+
+        if dedicated_image.is_some() {
+            alloc_info = alloc_info.push_next(&mut dedicated_info);
+        }
+
+        let memory = unsafe {
+            self.device
+                .allocate_memory(&alloc_info, None)
+                .map_err(LveError::vulkan("Unable to allocate device memory"))?
+        };
+
+        let property_flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        if !property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            return Ok((memory, std::ptr::null_mut()));
+        }
+
+        let mapped = unsafe {
+            self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+        };
+
+        match mapped {
+            Ok(mapped) => Ok((memory, mapped)),
+            Err(result) => {
+                unsafe { self.device.free_memory(memory, None) };
+                Err(LveError::Vulkan { context: "Unable to map device memory", result })
+            }
+        }
+    }
+
+    pub fn free(&self, allocation: LveAllocation) {
+        if allocation.memory == vk::DeviceMemory::null() {
+            return;
+        }
+
+        let mut state = self.state.borrow_mut();
+
+        let block_id = match allocation.block_id {
+            Some(block_id) => block_id,
+            None => {
+                unsafe { self.device.free_memory(allocation.memory, None) };
+                let stats = &mut state.dedicated[allocation.memory_type as usize];
+                stats.count -= 1;
+                stats.bytes -= allocation.size;
+                return;
+            }
+        };
+
+        let pool = state
+            .pools
+            .iter_mut()
+            .find(|p| p.blocks.iter().any(|b| b.id == block_id))
+            .expect("Freeing an allocation from an unknown block");
+
+        let index = pool.blocks.iter().position(|b| b.id == block_id).unwrap();
+        pool.blocks[index].free(allocation.offset, allocation.size);
+
+        // Keep one empty block around so a load/unload loop doesn't hit vkAllocateMemory every time
+        let empty_blocks = pool.blocks.iter().filter(|b| b.allocation_count == 0).count();
+        if pool.blocks[index].allocation_count == 0 && empty_blocks > 1 {
+            let block = pool.blocks.remove(index);
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+
+    /// Makes host writes visible to the device, only needed for memory without `HOST_COHERENT`
+    pub fn flush(&self, allocation: &LveAllocation) -> LveResult<()> {
+        let atom = self.non_coherent_atom_size;
+        let offset = allocation.offset - allocation.offset % atom;
+        let end = align_up(allocation.offset + allocation.size, atom).min(allocation.memory_size);
+
+        let range = vk::MappedMemoryRange::builder()
+            .memory(allocation.memory)
+            .offset(offset)
+            .size(end - offset)
+            .build();
+
+        unsafe {
+            self.device
+                .flush_mapped_memory_ranges(&[range])
+                .map_err(LveError::vulkan("Unable to flush mapped memory"))
+        }
+    }
+
+    pub fn stats(&self) -> LveAllocatorStats {
+        let state = self.state.borrow();
+        let mut stats = LveAllocatorStats {
+            max_memory_allocation_count: self.max_memory_allocation_count,
+            ..Default::default()
+        };
+
+        for pool in state.pools.iter() {
+            for block in pool.blocks.iter() {
+                let memory_type = self.memory_type_stats(&mut stats, pool.memory_type);
+                memory_type.blocks += 1;
+                memory_type.allocations += block.allocation_count;
+                memory_type.reserved_bytes += block.size;
+                memory_type.used_bytes += block.used_bytes();
+            }
+        }
+
+        for (index, dedicated) in state.dedicated.iter().enumerate().filter(|(_, d)| d.count > 0) {
+            let memory_type = self.memory_type_stats(&mut stats, index as u32);
+            memory_type.dedicated_allocations += dedicated.count;
+            memory_type.allocations += dedicated.count;
+            memory_type.reserved_bytes += dedicated.bytes;
+            memory_type.used_bytes += dedicated.bytes;
+        }
+
+        stats.memory_types.sort_by_key(|t| t.memory_type);
+        stats
+    }
+
+    fn memory_type_stats<'a>(&self, stats: &'a mut LveAllocatorStats, memory_type: u32) -> &'a mut LveMemoryTypeStats {
+        let index = match stats.memory_types.iter().position(|t| t.memory_type == memory_type) {
+            Some(index) => index,
+            None => {
+                stats.memory_types.push(LveMemoryTypeStats {
+                    memory_type,
+                    property_flags: self.memory_properties.memory_types[memory_type as usize].property_flags,
+                    ..Default::default()
+                });
+                stats.memory_types.len() - 1
+            }
+        };
+
+        &mut stats.memory_types[index]
+    }
+
+    /// Frees every block, called by the device right before it is destroyed
+    pub fn free_all(&self) {
+        let mut state = self.state.borrow_mut();
+
+        let leaked: usize = state.pools.iter().flat_map(|p| p.blocks.iter()).map(|b| b.allocation_count).sum::<usize>()
+            + state.dedicated.iter().map(|d| d.count).sum::<usize>();
+        if leaked > 0 {
+            log::warn!("{} allocations are still alive while destroying the allocator", leaked);
+        }
+
+        for pool in state.pools.drain(..) {
+            for block in pool.blocks {
+                unsafe { self.device.free_memory(block.memory, None) };
+            }
+        }
+    }
+
+    fn block_size(&self, memory_type: u32) -> vk::DeviceSize {
+        let heap_index = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+
+        align_up(BLOCK_SIZE.min(heap_size / 8), self.non_coherent_atom_size)
+    }
+}
+
+/// Rounds `value` up to a multiple of `alignment`
+pub fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    if alignment <= 1 {
+        value
+    } else {
+        value.div_ceil(alignment) * alignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> MemoryBlock {
+        MemoryBlock::new(0, vk::DeviceMemory::null(), size, std::ptr::null_mut())
+    }
+
+    #[test]
+    fn allocations_respect_alignment() {
+        let mut block = block(1024);
+
+        assert_eq!(block.allocate(10, 1), Some(0));
+        assert_eq!(block.allocate(16, 256), Some(256));
+        assert_eq!(block.allocate(10, 4), Some(12));
+        assert_eq!(block.used_bytes(), 36);
+    }
+
+    #[test]
+    fn freed_ranges_are_merged() {
+        let mut block = block(300);
+
+        let a = block.allocate(100, 1).unwrap();
+        let b = block.allocate(100, 1).unwrap();
+        let c = block.allocate(100, 1).unwrap();
+        assert_eq!(block.allocate(1, 1), None);
+
+        block.free(a, 100);
+        block.free(c, 100);
+        block.free(b, 100);
+
+        assert_eq!(block.free_ranges, vec![(0, 300)]);
+        assert_eq!(block.allocation_count, 0);
+        assert_eq!(block.allocate(300, 1), Some(0));
+    }
+}
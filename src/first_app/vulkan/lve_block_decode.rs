@@ -0,0 +1,733 @@
+use super::lve_texture_file::*;
+
+use ash::vk;
+
+/// Texels of a 4x4 block in row-major order
+type Block = [[u8; 4]; 16];
+
+/// Decompresses one level to RGBA8 for devices that can't sample `format`. Returns the pixels and
+/// the matching `R8G8B8A8` format, `None` if there is no CPU decoder for `format`.
+pub fn decompress(format: vk::Format, width: u32, height: u32, data: &[u8]) -> Option<(vk::Format, Vec<u8>)> {
+    use vk::Format as F;
+
+    let rgba_format = if LveTextureFile::is_srgb(format) { F::R8G8B8A8_SRGB } else { F::R8G8B8A8_UNORM };
+
+    if format == F::B8G8R8A8_UNORM || format == F::B8G8R8A8_SRGB {
+        let pixels = data.chunks_exact(4).flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]]).collect();
+        return Some((rgba_format, pixels));
+    }
+
+    let decode_block: fn(&[u8]) -> Block = match format {
+        F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK => decode_bc1_opaque,
+        F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGBA_SRGB_BLOCK => decode_bc1,
+        F::BC2_UNORM_BLOCK | F::BC2_SRGB_BLOCK => decode_bc2,
+        F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK => decode_bc3,
+        F::BC4_UNORM_BLOCK => decode_bc4,
+        F::BC5_UNORM_BLOCK => decode_bc5,
+        F::BC7_UNORM_BLOCK | F::BC7_SRGB_BLOCK => decode_bc7,
+        F::ETC2_R8G8B8_UNORM_BLOCK | F::ETC2_R8G8B8_SRGB_BLOCK => decode_etc2_rgb,
+        F::ETC2_R8G8B8A1_UNORM_BLOCK | F::ETC2_R8G8B8A1_SRGB_BLOCK => decode_etc2_rgb_a1,
+        F::ETC2_R8G8B8A8_UNORM_BLOCK | F::ETC2_R8G8B8A8_SRGB_BLOCK => decode_etc2_rgba,
+        _ => return None,
+    };
+
+    let (_, _, block_bytes) = LveTextureFile::block_size(format)?;
+    let (width, height) = (width as usize, height as usize);
+    let blocks_x = width.div_ceil(4).max(1);
+    let mut pixels = vec![0u8; width * height * 4];
+
+    for (block_index, block) in data.chunks_exact(block_bytes).enumerate() {
+        let (block_x, block_y) = (block_index % blocks_x * 4, block_index / blocks_x * 4);
+        let texels = decode_block(block);
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let (pixel_x, pixel_y) = (block_x + x, block_y + y);
+                // Blocks on the right and bottom edge may hang over the image
+                if pixel_x < width && pixel_y < height {
+                    let offset = (pixel_y * width + pixel_x) * 4;
+                    pixels[offset..offset + 4].copy_from_slice(&texels[y * 4 + x]);
+                }
+            }
+        }
+    }
+
+    Some((rgba_format, pixels))
+}
+
+fn u16_le(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn rgb565(color: u16) -> [u32; 3] {
+    let (r, g, b) = ((color >> 11) as u32 & 31, (color >> 5) as u32 & 63, color as u32 & 31);
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// Palette of a BC1 color block, BC2 and BC3 always use the four color mode
+fn bc1_palette(block: &[u8], always_four_colors: bool) -> [[u8; 4]; 4] {
+    let (c0, c1) = (u16_le(&block[0..2]), u16_le(&block[2..4]));
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32, total: u32| {
+        let channel = |i: usize| ((a[i] * wa + b[i] * wb) / total) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+
+    if always_four_colors || c0 > c1 {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [mix(1, 0, 1), mix(0, 1, 1), mix(1, 1, 2), [0, 0, 0, 0]]
+    }
+}
+
+fn bc1_texels(block: &[u8], always_four_colors: bool) -> Block {
+    let palette = bc1_palette(block, always_four_colors);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * i)) as usize & 3];
+    }
+    texels
+}
+
+fn decode_bc1(block: &[u8]) -> Block {
+    bc1_texels(block, false)
+}
+
+/// `BC1_RGB` ignores the punch-through alpha
+fn decode_bc1_opaque(block: &[u8]) -> Block {
+    let mut texels = bc1_texels(block, false);
+    for texel in texels.iter_mut() {
+        texel[3] = 255;
+    }
+    texels
+}
+
+fn decode_bc2(block: &[u8]) -> Block {
+    let alpha = u64::from_le_bytes(block[0..8].try_into().unwrap());
+    let mut texels = bc1_texels(&block[8..16], true);
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = ((alpha >> (4 * i)) & 15) as u8 * 17;
+    }
+    texels
+}
+
+/// A BC4 block, also the alpha of BC3 and each channel of BC5
+fn bc4_values(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut index_bytes = [0u8; 8];
+    index_bytes[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(indices >> (3 * i)) as usize & 7];
+    }
+    values
+}
+
+fn decode_bc3(block: &[u8]) -> Block {
+    let alpha = bc4_values(&block[0..8]);
+    let mut texels = bc1_texels(&block[8..16], true);
+
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+/// Sampled like the GPU does, red only
+fn decode_bc4(block: &[u8]) -> Block {
+    bc4_values(block).map(|red| [red, 0, 0, 255])
+}
+
+fn decode_bc5(block: &[u8]) -> Block {
+    let (red, green) = (bc4_values(&block[0..8]), bc4_values(&block[8..16]));
+
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = [red[i], green[i], 0, 255];
+    }
+    texels
+}
+
+/// Reads a little-endian bit stream from the least significant bit up
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn new(block: &[u8]) -> Self {
+        Self { bits: u128::from_le_bytes(block[0..16].try_into().unwrap()), position: 0 }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+/// Per mode: subsets, partition bits, rotation bits, index selection bits, color bits, alpha bits,
+/// a p-bit per endpoint, a p-bit per subset, index bits, secondary index bits
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn bc7_mode(subsets: usize, partition_bits: u32, rotation_bits: u32, index_selection_bits: u32, color_bits: u32, alpha_bits: u32, endpoint_pbits: bool, shared_pbits: bool, index_bits: u32, secondary_index_bits: u32) -> Bc7Mode {
+    Bc7Mode { subsets, partition_bits, rotation_bits, index_selection_bits, color_bits, alpha_bits, endpoint_pbits, shared_pbits, index_bits, secondary_index_bits }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, 4, 0, true, false, 3, 0),
+    bc7_mode(2, 6, 0, 0, 6, 0, false, true, 3, 0),
+    bc7_mode(3, 6, 0, 0, 5, 0, false, false, 2, 0),
+    bc7_mode(2, 6, 0, 0, 7, 0, true, false, 2, 0),
+    bc7_mode(1, 0, 2, 1, 5, 6, false, false, 2, 3),
+    bc7_mode(1, 0, 2, 0, 7, 8, false, false, 2, 2),
+    bc7_mode(1, 0, 0, 0, 7, 7, true, false, 4, 0),
+    bc7_mode(2, 6, 0, 0, 5, 5, true, false, 2, 0),
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Subset of every texel for the 64 two-subset partitions, one row per partition
+const BC7_PARTITIONS_2: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1], [0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1], [0, 0, 0, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 1, 1], [0, 0, 1, 1, 0, 1, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1], [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1], [0, 0, 1, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1],
+    [0, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1],
+    [0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 1, 1, 1, 1], [0, 1, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0], [0, 1, 1, 1, 0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0],
+    [0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0], [0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0], [0, 1, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 1],
+    [0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0], [0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 0, 0],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0], [0, 0, 1, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 1, 0, 0],
+    [0, 0, 0, 1, 0, 1, 1, 1, 1, 1, 1, 0, 1, 0, 0, 0], [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0],
+    [0, 1, 1, 1, 0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 1, 0], [0, 0, 1, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 1, 0, 0],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1], [0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1],
+    [0, 1, 0, 1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0, 1, 0], [0, 0, 1, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 0, 0],
+    [0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1, 0, 0], [0, 1, 0, 1, 0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0],
+    [0, 1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0, 1, 0, 0, 1], [0, 1, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0, 0, 1, 0, 1],
+    [0, 1, 1, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 1, 0], [0, 0, 0, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 0, 0, 0],
+    [0, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1, 0, 0], [0, 0, 1, 1, 1, 0, 1, 1, 1, 1, 0, 1, 1, 1, 0, 0],
+    [0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 0], [0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 1, 1],
+    [0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1], [0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0], [0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0], [0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 1, 0, 0, 1, 0, 0],
+    [0, 1, 1, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1], [0, 0, 1, 1, 0, 1, 1, 0, 1, 1, 0, 0, 1, 0, 0, 1],
+    [0, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 1, 1, 0, 0], [0, 0, 1, 1, 1, 0, 0, 1, 1, 1, 0, 0, 0, 1, 1, 0],
+    [0, 1, 1, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 0, 0, 1], [0, 1, 1, 0, 0, 0, 1, 1, 0, 0, 1, 1, 1, 0, 0, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1], [0, 0, 0, 1, 1, 0, 0, 0, 1, 1, 1, 0, 0, 1, 1, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1], [0, 0, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 0, 1, 0, 1, 1, 1, 0, 1, 1, 1, 0], [0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 1, 1, 1],
+];
+
+/// Subset of every texel for the 64 three-subset partitions
+const BC7_PARTITIONS_3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2], [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1], [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2], [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2], [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2], [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2], [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2], [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2], [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0], [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0], [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2], [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1], [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2], [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2], [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0], [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0], [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1], [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1], [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1], [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1], [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2], [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2], [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2], [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2], [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1], [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2], [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// Anchor texel of the second subset of two-subset partitions, the first subset's is always 0
+const BC7_ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor texels of the second and third subset of three-subset partitions
+const BC7_ANCHORS_3: [[usize; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+fn bc7_weight(bits: u32, index: u32) -> u32 {
+    match bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    }
+}
+
+fn decode_bc7(block: &[u8]) -> Block {
+    let mut bits = BitReader::new(block);
+
+    // The mode is the number of zero bits before the first one
+    let mode = match (0..8).find(|_| bits.read(1) == 1) {
+        Some(mode) => &BC7_MODES[mode],
+        None => return [[0; 4]; 16],
+    };
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bits.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        endpoint[3] = bits.read(mode.alpha_bits);
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let pbits: Vec<u32> = if mode.endpoint_pbits {
+            (0..endpoint_count).map(|_| bits.read(1)).collect()
+        } else {
+            (0..mode.subsets).flat_map(|_| [bits.read(1); 2]).collect()
+        };
+
+        for (endpoint, pbit) in endpoints.iter_mut().zip(pbits) {
+            for channel in endpoint.iter_mut() {
+                *channel = (*channel << 1) | pbit;
+            }
+        }
+
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    // Endpoints are stored with fewer bits, the top bits are repeated into the bottom ones
+    let expand = |value: u32, bits: u32| {
+        let value = value << (8 - bits);
+        value | (value >> bits)
+    };
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for channel in endpoint.iter_mut().take(3) {
+            *channel = expand(*channel, color_bits);
+        }
+        endpoint[3] = if alpha_bits > 0 { expand(endpoint[3], alpha_bits) } else { 255 };
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => BC7_PARTITIONS_2[partition][texel] as usize,
+        3 => BC7_PARTITIONS_3[partition][texel] as usize,
+        _ => 0,
+    };
+
+    // Anchor texels drop the top bit of their index, it is always zero
+    let is_anchor = |texel: usize| {
+        texel == 0
+            || (mode.subsets == 2 && texel == BC7_ANCHORS_2[partition])
+            || (mode.subsets == 3 && (texel == BC7_ANCHORS_3[0][partition] || texel == BC7_ANCHORS_3[1][partition]))
+    };
+
+    let mut indices = [0u32; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(if is_anchor(texel) { mode.index_bits - 1 } else { mode.index_bits });
+    }
+
+    let mut secondary_indices = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (texel, index) in secondary_indices.iter_mut().enumerate() {
+            *index = bits.read(if texel == 0 { mode.secondary_index_bits - 1 } else { mode.secondary_index_bits });
+        }
+    }
+
+    let interpolate = |e0: u32, e1: u32, weight: u32| (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8;
+
+    let mut texels = [[0; 4]; 16];
+    for (texel, out) in texels.iter_mut().enumerate() {
+        let subset = subset_of(texel);
+        let (e0, e1) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = bc7_weight(mode.index_bits, indices[texel]);
+            (weight, weight)
+        } else if index_selection == 0 {
+            (bc7_weight(mode.index_bits, indices[texel]), bc7_weight(mode.secondary_index_bits, secondary_indices[texel]))
+        } else {
+            (bc7_weight(mode.secondary_index_bits, secondary_indices[texel]), bc7_weight(mode.index_bits, indices[texel]))
+        };
+
+        *out = [
+            interpolate(e0[0], e1[0], color_weight),
+            interpolate(e0[1], e1[1], color_weight),
+            interpolate(e0[2], e1[2], color_weight),
+            interpolate(e0[3], e1[3], alpha_weight),
+        ];
+
+        if rotation > 0 {
+            out.swap(3, rotation as usize - 1);
+        }
+    }
+
+    texels
+}
+
+/// Intensity modifiers of ETC1 sub-blocks, indexed by table codeword and pixel index
+const ETC1_MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+
+/// Distances of the ETC2 T and H modes
+const ETC2_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+fn clamp_color(color: [i32; 3], offset: i32) -> [u8; 4] {
+    let channel = |value: i32| (value + offset).clamp(0, 255) as u8;
+    [channel(color[0]), channel(color[1]), channel(color[2]), 255]
+}
+
+fn extend(value: i32, bits: u32) -> i32 {
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
+
+/// An ETC2 color block. With `punchthrough` the differential bit says whether the block is opaque,
+/// pixel index 2 of a non-opaque block is transparent black.
+fn etc2_color(block: &[u8], punchthrough: bool) -> Block {
+    let bits = u64::from_be_bytes(block[0..8].try_into().unwrap());
+    // `count` bits whose most significant one is bit `high`
+    let field = |high: u32, count: u32| ((bits >> (high + 1 - count)) & ((1 << count) - 1)) as i32;
+
+    let differential_bit = field(33, 1) == 1;
+    let flip = field(32, 1) == 1;
+    let (differential, opaque) = if punchthrough { (true, differential_bit) } else { (differential_bit, true) };
+
+    // Pixels are stored column by column, the most significant index bits in the upper half
+    let pixel_index = |pixel: usize| ((bits >> (16 + pixel)) & 1) << 1 | ((bits >> pixel) & 1);
+    let texel = |pixel: usize| (pixel % 4) * 4 + pixel / 4;
+
+    let mut texels = [[0; 4]; 16];
+
+    let paint = |texels: &mut Block, colors: [[u8; 4]; 4]| {
+        for pixel in 0..16 {
+            let index = pixel_index(pixel) as usize;
+            texels[texel(pixel)] = if !opaque && index == 2 { [0, 0, 0, 0] } else { colors[index] };
+        }
+    };
+
+    let sub_blocks = |texels: &mut Block, colors: [[i32; 3]; 2]| {
+        let tables = [field(39, 3) as usize, field(36, 3) as usize];
+        for pixel in 0..16 {
+            let (x, y) = (pixel / 4, pixel % 4);
+            let sub_block = if flip { (y >= 2) as usize } else { (x >= 2) as usize };
+            let index = pixel_index(pixel) as usize;
+
+            texels[texel(pixel)] = match (opaque, index) {
+                (false, 2) => [0, 0, 0, 0],
+                (false, 0) => clamp_color(colors[sub_block], 0),
+                _ => clamp_color(colors[sub_block], ETC1_MODIFIERS[tables[sub_block]][index]),
+            };
+        }
+    };
+
+    if !differential {
+        let color = |r, g, b| [r, g, b].map(|value| extend(value, 4));
+        let colors = [
+            color(field(63, 4), field(55, 4), field(47, 4)),
+            color(field(59, 4), field(51, 4), field(43, 4)),
+        ];
+        sub_blocks(&mut texels, colors);
+        return texels;
+    }
+
+    let signed = |value: i32| if value >= 4 { value - 8 } else { value };
+    let (r, g, b) = (field(63, 5), field(55, 5), field(47, 5));
+    let (dr, dg, db) = (signed(field(58, 3)), signed(field(50, 3)), signed(field(42, 3)));
+
+    if !(0..32).contains(&(r + dr)) {
+        // T mode
+        let c1 = [(field(60, 2) << 2) | field(57, 2), field(55, 4), field(51, 4)].map(|value| extend(value, 4));
+        let c2 = [field(47, 4), field(43, 4), field(39, 4)].map(|value| extend(value, 4));
+        let distance = ETC2_DISTANCES[((field(35, 2) << 1) | field(32, 1)) as usize];
+        paint(&mut texels, [clamp_color(c1, 0), clamp_color(c2, distance), clamp_color(c2, 0), clamp_color(c2, -distance)]);
+    } else if !(0..32).contains(&(g + dg)) {
+        // H mode, the order of the two colors stores the lowest distance bit
+        let c1 = [field(62, 4), (field(58, 3) << 1) | field(52, 1), (field(51, 1) << 3) | field(49, 3)];
+        let c2 = [field(46, 4), field(42, 4), field(38, 4)];
+        let value = |c: [i32; 3]| (c[0] << 8) | (c[1] << 4) | c[2];
+        let distance_index = (field(34, 1) << 2) | (field(32, 1) << 1) | (value(c1) >= value(c2)) as i32;
+        let distance = ETC2_DISTANCES[distance_index as usize];
+        let (c1, c2) = (c1.map(|value| extend(value, 4)), c2.map(|value| extend(value, 4)));
+        paint(&mut texels, [clamp_color(c1, distance), clamp_color(c1, -distance), clamp_color(c2, distance), clamp_color(c2, -distance)]);
+    } else if !(0..32).contains(&(b + db)) {
+        // Planar mode, a gradient from three colors that is always opaque
+        let origin = [extend(field(62, 6), 6), extend((field(56, 1) << 6) | field(54, 6), 7), extend((field(48, 1) << 5) | (field(44, 2) << 3) | field(41, 3), 6)];
+        let horizontal = [extend((field(38, 5) << 1) | field(32, 1), 6), extend(field(31, 7), 7), extend(field(24, 6), 6)];
+        let vertical = [extend(field(18, 6), 6), extend(field(12, 7), 7), extend(field(5, 6), 6)];
+
+        for y in 0..4 {
+            for x in 0..4 {
+                let channel = |c: usize| ((x * (horizontal[c] - origin[c]) + y * (vertical[c] - origin[c]) + 4 * origin[c] + 2) >> 2).clamp(0, 255) as u8;
+                texels[(y * 4 + x) as usize] = [channel(0), channel(1), channel(2), 255];
+            }
+        }
+    } else {
+        let colors = [[r, g, b].map(|value| extend(value, 5)), [r + dr, g + dg, b + db].map(|value| extend(value, 5))];
+        sub_blocks(&mut texels, colors);
+    }
+
+    texels
+}
+
+/// An EAC block, the alpha of `ETC2_R8G8B8A8`
+fn eac_values(block: &[u8]) -> [u8; 16] {
+    let bits = u64::from_be_bytes(block[0..8].try_into().unwrap());
+    let base = (bits >> 56) as i32;
+    let multiplier = ((bits >> 52) & 15) as i32;
+    let table = &EAC_MODIFIERS[((bits >> 48) & 15) as usize];
+
+    let mut values = [0u8; 16];
+    for pixel in 0..16 {
+        let index = ((bits >> (45 - 3 * pixel)) & 7) as usize;
+        values[(pixel % 4) * 4 + pixel / 4] = (base + table[index] * multiplier).clamp(0, 255) as u8;
+    }
+    values
+}
+
+fn decode_etc2_rgb(block: &[u8]) -> Block {
+    etc2_color(block, false)
+}
+
+fn decode_etc2_rgb_a1(block: &[u8]) -> Block {
+    etc2_color(block, true)
+}
+
+fn decode_etc2_rgba(block: &[u8]) -> Block {
+    let alpha = eac_values(&block[0..8]);
+    let mut texels = etc2_color(&block[8..16], false);
+
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs `(value, bit count)` pairs from the least significant bit up, like BC7 is read
+    fn pack_bits(fields: &[(u32, u32)]) -> [u8; 16] {
+        let mut bits = 0u128;
+        let mut position = 0;
+        for &(value, count) in fields {
+            bits |= (value as u128) << position;
+            position += count;
+        }
+        assert_eq!(position, 128);
+        bits.to_le_bytes()
+    }
+
+    #[test]
+    fn bc1_interpolates_between_endpoints() {
+        // Red and blue endpoints, texel 0 uses endpoint 0, texel 1 the color a third of the way
+        let mut block = [0u8; 8];
+        block[0..2].copy_from_slice(&0xF800u16.to_le_bytes());
+        block[2..4].copy_from_slice(&0x001Fu16.to_le_bytes());
+        block[4] = 0b1000;
+
+        let texels = decode_bc1(&block);
+
+        assert_eq!(texels[0], [255, 0, 0, 255]);
+        assert_eq!(texels[1], [170, 0, 85, 255]);
+    }
+
+    #[test]
+    fn bc1_three_color_mode_has_transparent_black() {
+        let mut block = [0u8; 8];
+        block[2..4].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        block[4] = 0b11;
+
+        assert_eq!(decode_bc1(&block)[0], [0, 0, 0, 0]);
+        assert_eq!(decode_bc1_opaque(&block)[0], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn bc4_uses_eight_values_when_first_endpoint_is_larger() {
+        let mut block = [0u8; 8];
+        block[0] = 70;
+        block[1] = 0;
+        // Texel 0 index 2, texel 1 index 1
+        block[2] = 0b001_010;
+
+        let values = bc4_values(&block);
+        assert_eq!(values[0], 60);
+        assert_eq!(values[1], 0);
+    }
+
+    #[test]
+    fn bc7_mode_6_interpolates_with_pbits() {
+        let mut fields = vec![(1 << 6, 7)];
+        // Endpoint 0 red, endpoint 1 black, both opaque
+        fields.extend([(127, 7), (0, 7), (0, 7), (0, 7), (0, 7), (0, 7), (127, 7), (127, 7)]);
+        // p-bits
+        fields.extend([(1, 1), (1, 1)]);
+        // Texel 0 (anchor, 3 bits) index 0, texel 1 index 15, the rest 0
+        fields.push((0, 3));
+        fields.push((15, 4));
+        fields.extend(std::iter::repeat_n((0, 4), 14));
+
+        let texels = decode_bc7(&pack_bits(&fields));
+
+        assert_eq!(texels[0], [255, 1, 1, 255]);
+        assert_eq!(texels[1], [1, 1, 1, 255]);
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        assert_eq!(decode_bc7(&[0u8; 16])[5], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bc7_anchors_lie_in_their_subsets() {
+        for partition in 0..64 {
+            assert_eq!(BC7_PARTITIONS_2[partition][0], 0);
+            assert_eq!(BC7_PARTITIONS_2[partition][BC7_ANCHORS_2[partition]], 1);
+            assert_eq!(BC7_PARTITIONS_3[partition][0], 0);
+            assert_eq!(BC7_PARTITIONS_3[partition][BC7_ANCHORS_3[0][partition]], 1);
+            assert_eq!(BC7_PARTITIONS_3[partition][BC7_ANCHORS_3[1][partition]], 2);
+        }
+    }
+
+    #[test]
+    fn etc1_individual_mode_applies_modifiers() {
+        // Both sub-blocks 0x8 gray, codeword 0, all pixel indices 0
+        let block = 0x8888_8800_0000_0000u64.to_be_bytes();
+
+        let texels = decode_etc2_rgb(&block);
+
+        assert!(texels.iter().all(|&texel| texel == [138, 138, 138, 255]));
+    }
+
+    #[test]
+    fn etc2_punchthrough_index_2_is_transparent() {
+        // Differential bit clear means not opaque, pixel 0 has index 2
+        let block = (0x8000_0000_0000_0000u64 | (1 << 16)).to_be_bytes();
+
+        let texels = decode_etc2_rgb_a1(&block);
+
+        assert_eq!(texels[0], [0, 0, 0, 0]);
+        assert_eq!(texels[1][3], 255);
+    }
+
+    #[test]
+    fn eac_alpha_uses_the_modifier_table() {
+        // Base 128, multiplier 1, table 0, every index 0
+        let alpha = 0x8010_0000_0000_0000u64.to_be_bytes();
+        let mut block = [0u8; 16];
+        block[0..8].copy_from_slice(&alpha);
+
+        assert!(decode_etc2_rgba(&block).iter().all(|texel| texel[3] == 125));
+    }
+
+    #[test]
+    fn partial_blocks_are_cropped() {
+        let mut block = [0u8; 8];
+        block[0..2].copy_from_slice(&0xFFFFu16.to_le_bytes());
+
+        let (format, pixels) = decompress(vk::Format::BC1_RGBA_SRGB_BLOCK, 2, 1, &block).unwrap();
+
+        assert_eq!(format, vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(pixels, vec![255; 8]);
+    }
+}
@@ -0,0 +1,206 @@
+use super::lve_texture_file::*;
+
+use ash::vk;
+
+/// Compresses an RGBA8 level, `BC1_RGB` for opaque images and `BC3` for everything else. Returns
+/// `None` for formats there is no encoder for.
+pub fn compress(format: vk::Format, width: u32, height: u32, rgba: &[u8]) -> Option<Vec<u8>> {
+    use vk::Format as F;
+
+    let encode_block: fn(&[[u8; 4]; 16], &mut Vec<u8>) = match format {
+        F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK => encode_bc1,
+        F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK => encode_bc3,
+        _ => return None,
+    };
+
+    let (width, height) = (width as usize, height as usize);
+    let mut blocks = Vec::with_capacity(LveTextureFile::level_size(format, width as u32, height as u32)?);
+
+    for block_y in (0..height.max(1)).step_by(4) {
+        for block_x in (0..width.max(1)).step_by(4) {
+            // Texels past the edge repeat the last row and column, so they don't pull the endpoints
+            let mut texels = [[0u8; 4]; 16];
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let x = (block_x + i % 4).min(width - 1);
+                let y = (block_y + i / 4).min(height - 1);
+                let offset = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[offset..offset + 4]);
+            }
+
+            encode_block(&texels, &mut blocks);
+        }
+    }
+
+    Some(blocks)
+}
+
+/// The format `compress` should use for an image, BC1 drops alpha
+pub fn block_format(rgba: &[u8], srgb: bool) -> vk::Format {
+    let opaque = rgba.chunks_exact(4).all(|texel| texel[3] == 255);
+    let format = if opaque { vk::Format::BC1_RGB_UNORM_BLOCK } else { vk::Format::BC3_UNORM_BLOCK };
+
+    LveTextureFile::with_srgb(format, srgb)
+}
+
+fn to_rgb565(color: [f32; 3]) -> u16 {
+    let r = (color[0].clamp(0.0, 255.0) * 31.0 / 255.0).round() as u16;
+    let g = (color[1].clamp(0.0, 255.0) * 63.0 / 255.0).round() as u16;
+    let b = (color[2].clamp(0.0, 255.0) * 31.0 / 255.0).round() as u16;
+    (r << 11) | (g << 5) | b
+}
+
+fn from_rgb565(color: u16) -> [f32; 3] {
+    let (r, g, b) = ((color >> 11) & 31, (color >> 5) & 63, color & 31);
+    [((r << 3) | (r >> 2)) as f32, ((g << 2) | (g >> 4)) as f32, ((b << 3) | (b >> 2)) as f32]
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+/// Endpoints at the extremes of the block's principal axis, found with a few power iterations
+fn principal_endpoints(texels: &[[u8; 4]; 16]) -> ([f32; 3], [f32; 3]) {
+    let colors = texels.map(|texel| [texel[0] as f32, texel[1] as f32, texel[2] as f32]);
+    let mean = (0..3).map(|c| colors.iter().map(|color| color[c]).sum::<f32>() / 16.0).collect::<Vec<_>>();
+
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in &colors {
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += (color[i] - mean[i]) * (color[j] - mean[j]);
+            }
+        }
+    }
+
+    let mut axis = [1.0f32, 1.0, 1.0];
+    for _ in 0..8 {
+        let next = [0, 1, 2].map(|i| (0..3).map(|j| covariance[i][j] * axis[j]).sum::<f32>());
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < f32::EPSILON {
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+
+    let project = |color: &[f32; 3]| (0..3).map(|c| (color[c] - mean[c]) * axis[c]).sum::<f32>();
+    let (mut low, mut high) = (colors[0], colors[0]);
+    for color in &colors {
+        if project(color) < project(&low) {
+            low = *color;
+        }
+        if project(color) > project(&high) {
+            high = *color;
+        }
+    }
+
+    (high, low)
+}
+
+/// Four color mode only, the first endpoint is always the larger one
+fn encode_bc1(texels: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let (high, low) = principal_endpoints(texels);
+    let (mut c0, mut c1) = (to_rgb565(high), to_rgb565(low));
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let mut indices = 0u32;
+    if c0 != c1 {
+        let (a, b) = (from_rgb565(c0), from_rgb565(c1));
+        let palette = [a, b, [0, 1, 2].map(|i| (2.0 * a[i] + b[i]) / 3.0), [0, 1, 2].map(|i| (a[i] + 2.0 * b[i]) / 3.0)];
+
+        for (i, texel) in texels.iter().enumerate() {
+            let color = [texel[0] as f32, texel[1] as f32, texel[2] as f32];
+            let nearest = (0..4).min_by(|&x, &y| distance(color, palette[x]).total_cmp(&distance(color, palette[y]))).unwrap();
+            indices |= (nearest as u32) << (2 * i);
+        }
+    }
+
+    out.extend_from_slice(&c0.to_le_bytes());
+    out.extend_from_slice(&c1.to_le_bytes());
+    out.extend_from_slice(&indices.to_le_bytes());
+}
+
+/// Eight value mode between the smallest and largest alpha
+fn encode_bc4_alpha(texels: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    let a0 = texels.iter().map(|texel| texel[3]).max().unwrap();
+    let a1 = texels.iter().map(|texel| texel[3]).min().unwrap();
+
+    let mut palette = [a0 as u32, a1 as u32, 0, 0, 0, 0, 0, 0];
+    for i in 1..7 {
+        palette[i + 1] = ((7 - i as u32) * a0 as u32 + i as u32 * a1 as u32) / 7;
+    }
+
+    let mut indices = 0u64;
+    if a0 != a1 {
+        for (i, texel) in texels.iter().enumerate() {
+            let nearest = (0..8).min_by_key(|&index| (palette[index] as i32 - texel[3] as i32).abs()).unwrap();
+            indices |= (nearest as u64) << (3 * i);
+        }
+    }
+
+    out.push(a0);
+    out.push(a1);
+    out.extend_from_slice(&indices.to_le_bytes()[..6]);
+}
+
+fn encode_bc3(texels: &[[u8; 4]; 16], out: &mut Vec<u8>) {
+    encode_bc4_alpha(texels, out);
+    encode_bc1(texels, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::lve_block_decode::*;
+
+    /// Varies along x only, so every block lies on a line BC1 can represent
+    fn gradient(width: u32, height: u32, alpha: bool) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|i| {
+                let x = i % width;
+                let a = if alpha { (x * 255 / width.max(2)) as u8 } else { 255 };
+                [(x * 30) as u8, (x * 15) as u8, 128, a]
+            })
+            .collect()
+    }
+
+    fn max_error(a: &[u8], b: &[u8]) -> i32 {
+        a.iter().zip(b).map(|(&a, &b)| (a as i32 - b as i32).abs()).max().unwrap()
+    }
+
+    #[test]
+    fn bc1_round_trip_is_close() {
+        let rgba = gradient(6, 5, false);
+        let format = block_format(&rgba, false);
+        assert_eq!(format, vk::Format::BC1_RGB_UNORM_BLOCK);
+
+        let blocks = compress(format, 6, 5, &rgba).unwrap();
+        assert_eq!(blocks.len(), 4 * 8);
+
+        let (_, decoded) = decompress(format, 6, 5, &blocks).unwrap();
+        assert!(max_error(&rgba, &decoded) <= 24);
+    }
+
+    #[test]
+    fn bc3_keeps_alpha() {
+        let rgba = gradient(8, 4, true);
+        let format = block_format(&rgba, true);
+        assert_eq!(format, vk::Format::BC3_SRGB_BLOCK);
+
+        let blocks = compress(format, 8, 4, &rgba).unwrap();
+        let (_, decoded) = decompress(format, 8, 4, &blocks).unwrap();
+
+        let alpha = |pixels: &[u8]| pixels.chunks_exact(4).map(|texel| texel[3]).collect::<Vec<_>>();
+        assert!(max_error(&alpha(&rgba), &alpha(&decoded)) <= 10);
+    }
+
+    #[test]
+    fn solid_block_is_exact() {
+        let rgba = [255u8, 0, 0, 255].repeat(16);
+        let blocks = compress(vk::Format::BC1_RGB_UNORM_BLOCK, 4, 4, &rgba).unwrap();
+        let (_, decoded) = decompress(vk::Format::BC1_RGB_UNORM_BLOCK, 4, 4, &blocks).unwrap();
+
+        assert_eq!(decoded, rgba);
+    }
+}
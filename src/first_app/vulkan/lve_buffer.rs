@@ -1,5 +1,7 @@
 use std::{marker::PhantomData, ffi::c_void, rc::Rc};
 use super::lve_device::*;
+use super::lve_allocator::*;
+use super::lve_error::*;
 pub struct LveBuffer<T>
 where
     T: PartialEq,
@@ -7,7 +9,7 @@ where
     lve_device: Rc<LveDevice>,
     pub mapped: Option<*mut c_void>,
     pub buffer: ash::vk::Buffer,
-    memory: ash::vk::DeviceMemory,
+    allocation: LveAllocation,
     capacity: usize,
 
     _p: PhantomData<T>,
@@ -22,37 +24,36 @@ where
         size: usize,
         usage_flags: ash::vk::BufferUsageFlags,
         memory_property_flags: ash::vk::MemoryPropertyFlags,
-    ) -> Self {
+    ) -> LveResult<Self> {
         let byte_len = std::mem::size_of::<T>() * size;
 
-        let (buffer, memory) = lve_device.create_buffer(
+        let (buffer, allocation) = lve_device.create_buffer(
             byte_len as u64,
             usage_flags,
             memory_property_flags
-        );
+        )?;
 
-        Self {
+        Ok(Self {
             lve_device,
             mapped: None,
             buffer,
-            memory,
+            allocation,
             capacity: size,
 
             _p: PhantomData {},
-        }
+        })
     }
 
     pub fn null(lve_device: Rc<LveDevice>) -> Self {
         let size = 0;
 
         let buffer = ash::vk::Buffer::null();
-        let memory = ash::vk::DeviceMemory::null();
 
         Self {
             lve_device,
             mapped: None,
             buffer,
-            memory,
+            allocation: LveAllocation::null(),
             capacity: size,
 
             _p: PhantomData {},
@@ -71,32 +72,21 @@ where
         }
     }
 
+    /// Host visible memory is mapped by the allocator for as long as it lives, so this only
+    /// hands out a pointer into it
     pub fn map(&mut self, element_offset: usize) /*-> Result<(), ash::vk::Result>*/ {
-        let size = self.capacity - element_offset;
-        let mem_size = (std::mem::size_of::<T>() * size) as u64;
-        let mem_offset = (std::mem::size_of::<T>() * element_offset) as u64;
+        assert!(element_offset <= self.capacity, "Cannot map past the end of the buffer");
+        let mem_offset = std::mem::size_of::<T>() * element_offset;
 
-        unsafe {
-            self.mapped = Some(self.lve_device.device.map_memory(
-                self.memory,
-                mem_offset,
-                mem_size,
-                ash::vk::MemoryMapFlags::empty()
-            ).unwrap() )
-        };
+        let base = self.allocation
+            .mapped_ptr()
+            .expect("Cannot map a buffer that isn't host visible");
+
+        self.mapped = Some(unsafe { (base as *mut u8).add(mem_offset) as *mut c_void });
     }
 
     pub fn unmap(&mut self) {
-        match self.mapped {
-            Some(_) => {
-                unsafe {
-                    self.lve_device.device.unmap_memory(self.memory);
-                }
-
-                self.mapped = None;
-            },
-            None => { },
-        }
+        self.mapped = None;
     }
 
     pub fn write_to_buffer(&mut self, elements: &[T]) {
@@ -109,17 +99,10 @@ where
 
     #[allow(dead_code)]
     pub fn flush(&self) {
-        let mapped_range = [ash::vk::MappedMemoryRange::builder()
-            .memory(self.memory)
-            .offset(0)
-            .size(ash::vk::WHOLE_SIZE)
-            .build()];
-
-        unsafe {
-            self.lve_device.device.flush_mapped_memory_ranges(&mapped_range)
+        self.lve_device.allocator
+            .flush(&self.allocation)
             .map_err(|e| log::error!("Unable to flush buffer: {}", e))
-                .unwrap();
-        }
+            .unwrap();
     }
 
     pub fn descriptor_info(&self) -> ash::vk::DescriptorBufferInfo {
@@ -141,7 +124,8 @@ where
         unsafe {
             self.unmap();
             self.lve_device.device.destroy_buffer(self.buffer, None);
-            self.lve_device.device.free_memory(self.memory, None);
         }
+
+        self.lve_device.allocator.free(std::mem::take(&mut self.allocation));
     }
 }
\ No newline at end of file
@@ -230,7 +230,6 @@ impl LveDescriptorPool {
         })
     }
 
-    #[allow(dead_code)]
     pub fn free_descriptors(
         &self,
         descriptors: &Vec<ash::vk::DescriptorSet>
@@ -280,7 +279,6 @@ impl LveDescriptorPoolBuilder {
         self
     }
 
-    #[allow(dead_code)]
     pub fn set_pool_flags(
         mut self,
         flags: ash::vk::DescriptorPoolCreateFlags,
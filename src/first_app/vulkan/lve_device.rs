@@ -14,6 +14,10 @@ use ash_window;
 
 use winit::window::Window;
 
+use super::lve_error::*;
+use super::lve_allocator::*;
+use super::lve_upload::*;
+
 use std::{
     ffi::{CStr, CString},
     os::raw::c_void,
@@ -57,6 +61,8 @@ pub struct SwapChainSupportDetails {
 pub struct QueueFamilyIndices {
     pub graphics_family: u32,
     pub present_family: u32,
+    /// A family that can copy but not draw, uploads run there so they don't queue behind frames
+    pub transfer_family: Option<u32>,
     graphics_family_has_value: bool,
     present_family_has_value: bool,
 }
@@ -71,93 +77,143 @@ pub struct LveDevice {
     _entry: Entry,
     pub instance: Instance,
     debug_messenger: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
-    surface: Surface,
+    surface: Option<Surface>,
     pub surface_khr: vk::SurfaceKHR,
     pub physical_device: vk::PhysicalDevice,
-    _properties: vk::PhysicalDeviceProperties,
+    pub properties: vk::PhysicalDeviceProperties,
     pub device: Device,
     pub command_pool: vk::CommandPool,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    pub allocator: LveAllocator,
+    pub uploads: LveUploadContext,
 }
 
 impl LveDevice {
-    pub fn new(window: &Window) -> Rc<Self> {
+    pub fn new(window: &Window) -> LveResult<Rc<Self>> {
         let entry = Entry::linked();
-        let instance = Self::create_instance(&entry);
-        let debug_messenger = Self::setup_debug_messenger(&entry, &instance);
-        let (surface, surface_khr) = Self::create_surface(&entry, &instance, window);
+
+        if ENABLE_VALIDATION_LAYERS && !Self::check_validation_layer_support(&entry) {
+            return Err(LveError::Unsupported("Validation layers requested, but not available"));
+        }
+
+        let instance = Self::create_instance(&entry, false, ENABLE_VALIDATION_LAYERS)?;
+        let debug_messenger = Self::setup_debug_messenger(&entry, &instance, ENABLE_VALIDATION_LAYERS)?;
+        let (surface, surface_khr) = Self::create_surface(&entry, &instance, window)?;
+        let surface_info = Some((&surface, surface_khr));
         let (physical_device, properties) =
-            Self::pick_physical_device(&instance, &surface, surface_khr);
-        let (device, graphics_queue, present_queue) =
-            Self::create_logical_device(&instance, &surface, surface_khr, physical_device);
+            Self::pick_physical_device(&instance, surface_info)?;
+        let (device, graphics_queue, present_queue, upload_queues) =
+            Self::create_logical_device(&instance, surface_info, physical_device, ENABLE_VALIDATION_LAYERS)?;
         let command_pool =
-            Self::create_command_pool(&instance, &surface, surface_khr, physical_device, &device);
+            Self::create_command_pool(&instance, surface_info, physical_device, &device)?;
+        let allocator = LveAllocator::new(&instance, physical_device, device.clone());
+        let uploads = LveUploadContext::new(device.clone(), &allocator, upload_queues)?;
 
-        Rc::new(Self {
+        Ok(Rc::new(Self {
             _entry: entry,
             instance,
             debug_messenger,
-            surface,
+            surface: Some(surface),
             surface_khr,
             physical_device,
-            _properties: properties,
+            properties,
             device,
             graphics_queue,
             present_queue,
             command_pool,
-        })
+            allocator,
+            uploads,
+        }))
     }
 
-    pub fn get_swapchain_support(&self) -> SwapChainSupportDetails {
-        Self::query_swapchain_support(&self.surface, self.surface_khr, self.physical_device)
-    }
+    /// Creates a device without a window, surface or swapchain support, for offscreen rendering
+    /// in CI or on render farms. Software implementations like lavapipe are accepted.
+    #[allow(dead_code)]
+    pub fn new_headless() -> LveResult<Rc<Self>> {
+        let entry = Entry::linked();
 
-    pub fn find_memory_type(
-        &self,
-        type_filter: u32,
-        properties: vk::MemoryPropertyFlags,
-    ) -> Option<u32> {
-        let mem_properties = unsafe {
-            self.instance
-                .get_physical_device_memory_properties(self.physical_device)
-        };
+        let enable_validation = ENABLE_VALIDATION_LAYERS && Self::check_validation_layer_support(&entry);
+        if ENABLE_VALIDATION_LAYERS && !enable_validation {
+            log::warn!("Validation layers requested, but not available, continuing without them");
+        }
 
-        let mut memory_type = None;
+        let instance = Self::create_instance(&entry, true, enable_validation)?;
+        let debug_messenger = Self::setup_debug_messenger(&entry, &instance, enable_validation)?;
+        let (physical_device, properties) = Self::pick_physical_device(&instance, None)?;
+        let (device, graphics_queue, present_queue, upload_queues) =
+            Self::create_logical_device(&instance, None, physical_device, enable_validation)?;
+        let command_pool = Self::create_command_pool(&instance, None, physical_device, &device)?;
+        let allocator = LveAllocator::new(&instance, physical_device, device.clone());
+        let uploads = LveUploadContext::new(device.clone(), &allocator, upload_queues)?;
 
-        for (index, m_type) in mem_properties.memory_types.iter().enumerate() {
-            if (type_filter & (1 << index )) != 0 &&
-                (m_type.property_flags & properties) == properties
-            {
-                memory_type = Some(index as u32);
-                break;
-            }
-        }
+        Ok(Rc::new(Self {
+            _entry: entry,
+            instance,
+            debug_messenger,
+            surface: None,
+            surface_khr: vk::SurfaceKHR::null(),
+            physical_device,
+            properties,
+            device,
+            graphics_queue,
+            present_queue,
+            command_pool,
+            allocator,
+            uploads,
+        }))
+    }
+
+    #[allow(dead_code)]
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
 
-        memory_type
+    pub fn get_swapchain_support(&self) -> SwapChainSupportDetails {
+        let surface = self
+            .surface
+            .as_ref()
+            .expect("Cannot query swapchain support on a headless device");
+
+        Self::query_swapchain_support(surface, self.surface_khr, self.physical_device)
     }
 
     pub fn find_physical_queue_families(&self) -> QueueFamilyIndices {
         Self::find_queue_families(
             &self.instance,
-            &self.surface,
-            self.surface_khr,
+            self.surface.as_ref().map(|surface| (surface, self.surface_khr)),
             self.physical_device,
         )
     }
 
+    /// True if images of `format` can be blitted with linear filtering, needed to build mip chains on the GPU
+    pub fn supports_linear_blit(&self, format: vk::Format) -> bool {
+        let props = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        };
+
+        props.optimal_tiling_features.contains(
+            vk::FormatFeatureFlags::BLIT_SRC
+                | vk::FormatFeatureFlags::BLIT_DST
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        )
+    }
+
+    /// First of `candidates` that supports `features` with `tiling`, `None` if none does
     pub fn find_supported_format(
         &self,
         candidates: &Vec<vk::Format>,
         tiling: vk::ImageTiling,
         features: vk::FormatFeatureFlags,
-    ) -> vk::Format {
-        *candidates
+    ) -> Option<vk::Format> {
+        candidates
             .iter()
+            .copied()
             .find(|format| {
                 let props = unsafe {
                     self.instance
-                        .get_physical_device_format_properties(self.physical_device, **format)
+                        .get_physical_device_format_properties(self.physical_device, *format)
                 };
 
                 if tiling == vk::ImageTiling::LINEAR {
@@ -167,7 +223,6 @@ impl LveDevice {
                 }
                 false
             })
-            .expect("failed to find supported format!")
     }
 
     pub fn create_buffer(
@@ -175,7 +230,7 @@ impl LveDevice {
         size: vk::DeviceSize,
         usage: vk::BufferUsageFlags,
         properties: vk::MemoryPropertyFlags,
-    ) -> (vk::Buffer, vk::DeviceMemory) {
+    ) -> LveResult<(vk::Buffer, LveAllocation)> {
         let create_info = vk::BufferCreateInfo::builder()
             .size(size)
             .usage(usage)
@@ -184,34 +239,27 @@ impl LveDevice {
         let buffer = unsafe {
             self.device
                 .create_buffer(&create_info, None)
-                .map_err(|e| log::error!("Unable to create buffer: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create buffer"))?
         };
 
-        let mem_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
-
-        let alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(
-                self.find_memory_type(mem_requirements.memory_type_bits, properties)
-                    .unwrap(),
-            );
-
-        let buffer_memory = unsafe {
-            self.device
-                .allocate_memory(&alloc_info, None)
-                .map_err(|e| log::error!("Unable to allocate memory: {}", e))
-                .unwrap()
-        };
+        match self.allocator.allocate_buffer(buffer, properties) {
+            Ok(allocation) => Ok((buffer, allocation)),
+            Err(e) => {
+                unsafe { self.device.destroy_buffer(buffer, None) };
+                Err(e)
+            }
+        }
+    }
 
+    /// Blocks until all queued work has finished, needed before destroying anything a frame
+    /// in flight might still use
+    pub fn wait_idle(&self) {
         unsafe {
             self.device
-                .bind_buffer_memory(buffer, buffer_memory, 0)
-                .map_err(|e| log::error!("Unable to bind memory to buffer: {}", e))
+                .device_wait_idle()
+                .map_err(|e| log::error!("Cannot wait: {}", e))
                 .unwrap()
         };
-
-        (buffer, buffer_memory)
     }
 
     #[allow(dead_code)]
@@ -273,113 +321,59 @@ impl LveDevice {
         };
     }
 
-    #[allow(dead_code)]
-    pub fn copy_buffer(
-        &self,
-        src_buffer: vk::Buffer,
-        dst_buffer: vk::Buffer,
-        size: vk::DeviceSize,
-    ) {
-        let command_buffer = self.begin_single_time_commands();
-
-        let copy_region = vk::BufferCopy::builder()
-            .src_offset(0)
-            .dst_offset(0)
-            .size(size);
-
-        unsafe {
-            self.device.cmd_copy_buffer(command_buffer, src_buffer, dst_buffer, std::slice::from_ref(&copy_region))
+    /// Queues a copy of `data` into `buffer`, which needs `TRANSFER_DST` usage. The copy is
+    /// submitted by the next `flush_uploads` and is visible to anything submitted after that.
+    pub fn upload_to_buffer<T: Copy>(&self, buffer: vk::Buffer, data: &[T]) -> LveResult<()> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
         };
 
-        self.end_single_time_commands(command_buffer);
+        self.uploads.upload_to_buffer(&self.allocator, buffer, bytes)
     }
 
-    #[allow(dead_code)]
-    pub fn copy_buffer_to_image(
+    /// Queues copies of `data` into every level of a freshly created `image`, see `upload_to_buffer`
+    pub fn upload_to_image(
         &self,
-        buffer: vk::Buffer,
         image: vk::Image,
-        width: u32,
-        height: u32,
-        layer_count: u32,
-    ) {
-        let command_buffer = self.begin_single_time_commands();
-
-        let image_subresource_info = vk::ImageSubresourceLayers::builder()
-            .aspect_mask(vk::ImageAspectFlags::COLOR)
-            .mip_level(0)
-            .base_array_layer(0)
-            .layer_count(layer_count)
-            .build();
-
-        let offset = vk::Offset3D::builder().x(0).y(0).z(0).build();
-
-        let extent = vk::Extent3D::builder()
-            .width(width)
-            .height(height)
-            .depth(1)
-            .build();
-
-        let region = vk::BufferImageCopy::builder()
-            .buffer_offset(0)
-            .buffer_row_length(0)
-            .buffer_image_height(0)
-            .image_subresource(image_subresource_info)
-            .image_offset(offset)
-            .image_extent(extent);
-
-        unsafe {
-            self.device.cmd_copy_buffer_to_image(
-                command_buffer,
-                buffer,
-                image,
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                std::slice::from_ref(&region),
-            )
-        };
+        mip_levels: u32,
+        data: &[u8],
+        regions: &[vk::BufferImageCopy],
+        finish: ImageUploadFinish,
+    ) -> LveResult<()> {
+        self.uploads.upload_to_image(&self.allocator, image, mip_levels, data, regions, finish)
+    }
 
-        self.end_single_time_commands(command_buffer);
+    /// Submits all queued uploads without waiting for them
+    pub fn flush_uploads(&self) -> LveResult<()> {
+        self.uploads.flush(&self.allocator)
     }
 
     pub fn create_image_with_info(
         &self,
         image_info: &vk::ImageCreateInfo,
         properties: vk::MemoryPropertyFlags,
-    ) -> (vk::Image, vk::DeviceMemory) {
+    ) -> LveResult<(vk::Image, LveAllocation)> {
         let image = unsafe {
             self.device
                 .create_image(image_info, None)
-                .map_err(|e| log::error!("Unable to create image: {}", e))
-                .unwrap()
-        };
-
-        let mem_requirements = unsafe { self.device.get_image_memory_requirements(image) };
-
-        let alloc_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(
-                self.find_memory_type(mem_requirements.memory_type_bits, properties)
-                    .unwrap(),
-            );
-
-        let image_memory = unsafe {
-            self.device
-                .allocate_memory(&alloc_info, None)
-                .map_err(|e| log::error!("Unable to allocate image memory: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create image"))?
         };
 
-        unsafe {
-            self.device
-                .bind_image_memory(image, image_memory, 0)
-                .map_err(|e| log::error!("Unable to bind image memory: {}", e))
-                .unwrap()
-        };
+        // Render targets are recreated on resize, keeping them out of the blocks avoids fragmentation
+        let is_render_target = image_info.usage.intersects(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+        );
 
-        (image, image_memory)
+        match self.allocator.allocate_image(image, properties, is_render_target) {
+            Ok(allocation) => Ok((image, allocation)),
+            Err(e) => {
+                unsafe { self.device.destroy_image(image, None) };
+                Err(e)
+            }
+        }
     }
 
-    fn create_instance(entry: &Entry) -> Instance {
+    fn create_instance(entry: &Entry, headless: bool, enable_validation: bool) -> LveResult<Instance> {
         let app_name = CString::new("LittleVulkanEngine App").unwrap();
         let engine_name = CString::new("No Engine").unwrap();
 
@@ -390,7 +384,7 @@ impl LveDevice {
             .engine_version(vk::make_api_version(0, 0, 1, 0))
             .api_version(vk::make_api_version(0, 1, 2, 176));
 
-        let extensions = Self::get_required_extensions();
+        let extensions = Self::get_required_extensions(headless, enable_validation);
 
         let mut create_info = vk::InstanceCreateInfo::builder()
         .application_info(&app_info)
@@ -398,25 +392,24 @@ impl LveDevice {
         
         let (_layer_names, layer_name_ptrs) = Self::get_enabled_layers();
 
-        if ENABLE_VALIDATION_LAYERS {
-            Self::check_validation_layer_support(entry);
+        if enable_validation {
             create_info = create_info.enabled_layer_names(&layer_name_ptrs);
         }
 
         unsafe {
             entry
                 .create_instance(&create_info, None)
-                .map_err(|e| log::error!("Unable to create instance: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create instance"))
         }
     }
 
     fn setup_debug_messenger(
         entry: &Entry,
         instance: &Instance,
-    ) -> Option<(DebugUtils, vk::DebugUtilsMessengerEXT)> {
-        if !ENABLE_VALIDATION_LAYERS {
-            return None;
+        enable_validation: bool,
+    ) -> LveResult<Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>> {
+        if !enable_validation {
+            return Ok(None);
         }
 
         let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
@@ -428,45 +421,61 @@ impl LveDevice {
         let debug_report_callback = unsafe {
             debug_report
                 .create_debug_utils_messenger(&create_info, None)
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create debug messenger"))?
         };
 
-        Some((debug_report, debug_report_callback))
+        Ok(Some((debug_report, debug_report_callback)))
     }
 
     fn create_surface(
         entry: &Entry,
         instance: &Instance,
         window: &Window,
-    ) -> (Surface, vk::SurfaceKHR) {
+    ) -> LveResult<(Surface, vk::SurfaceKHR)> {
         let surface = Surface::new(entry, instance);
         let surface_khr = unsafe {
             ash_window::create_surface(entry, instance, window, None)
-                .map_err(|e| log::error!("Unable to create surface: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create surface"))?
         };
 
-        (surface, surface_khr)
+        Ok((surface, surface_khr))
     }
 
     fn pick_physical_device(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
-    ) -> (vk::PhysicalDevice, vk::PhysicalDeviceProperties) {
+        surface_info: Option<(&Surface, vk::SurfaceKHR)>,
+    ) -> LveResult<(vk::PhysicalDevice, vk::PhysicalDeviceProperties)> {
         let devices = unsafe {
             instance
                 .enumerate_physical_devices()
-                .map_err(|e| log::error!("Failed to find GPUs with Vulkan Support: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Failed to find GPUs with Vulkan Support"))?
         };
 
         log::info!("Device Count: {}", devices.len());
 
-        let device = devices
+        let mut suitable_devices = devices
             .into_iter()
-            .find(|device| Self::is_device_suitable(instance, surface, surface_khr, *device))
-            .expect("No suitable physical device");
+            .filter(|device| Self::is_device_suitable(instance, surface_info, *device))
+            .collect::<Vec<_>>();
+
+        // Without a surface we may end up on a machine with both a GPU and a software
+        // implementation (lavapipe, swiftshader), prefer real hardware when there is a choice
+        if surface_info.is_none() {
+            suitable_devices.sort_by_key(|device| {
+                let properties = unsafe { instance.get_physical_device_properties(*device) };
+                match properties.device_type {
+                    vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+                    vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+                    vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+                    vk::PhysicalDeviceType::CPU => 3,
+                    _ => 4,
+                }
+            });
+        }
+
+        let device = *suitable_devices
+            .first()
+            .ok_or(LveError::Unsupported("No suitable physical device"))?;
 
         let device_properties = unsafe { instance.get_physical_device_properties(device) };
 
@@ -474,16 +483,24 @@ impl LveDevice {
             CStr::from_ptr(device_properties.device_name.as_ptr())
         });
 
-        (device, device_properties)
+        Ok((device, device_properties))
     }
 
     fn is_device_suitable(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface_info: Option<(&Surface, vk::SurfaceKHR)>,
         device: vk::PhysicalDevice,
     ) -> bool {
-        let indices = Self::find_queue_families(instance, surface, surface_khr, device);
+        let indices = Self::find_queue_families(instance, surface_info, device);
+
+        let supported_features = unsafe { instance.get_physical_device_features(device) };
+
+        let (surface, surface_khr) = match surface_info {
+            Some(surface_info) => surface_info,
+            None => {
+                return indices.is_complete() && supported_features.sampler_anisotropy != 0;
+            }
+        };
 
         let extensions_supported = Self::check_device_extension_support(instance, device);
 
@@ -497,8 +514,6 @@ impl LveDevice {
             };
         }
 
-        let supported_features = unsafe { instance.get_physical_device_features(device) };
-
         {
             indices.is_complete()
                 && extensions_supported
@@ -509,17 +524,19 @@ impl LveDevice {
 
     fn create_logical_device(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface_info: Option<(&Surface, vk::SurfaceKHR)>,
         physical_device: vk::PhysicalDevice,
-    ) -> (Device, vk::Queue, vk::Queue) {
+        enable_validation: bool,
+    ) -> LveResult<(Device, vk::Queue, vk::Queue, UploadQueues)> {
         let queue_indices =
-            Self::find_queue_families(instance, surface, surface_khr, physical_device);
+            Self::find_queue_families(instance, surface_info, physical_device);
 
         let queue_priorities = [1.0f32];
 
         let queue_create_infos = {
             let mut indices = vec![queue_indices.graphics_family, queue_indices.present_family];
+            indices.extend(queue_indices.transfer_family);
+            indices.sort_unstable();
             indices.dedup();
 
             indices
@@ -533,9 +550,12 @@ impl LveDevice {
                 .collect::<Vec<_>>()
         };
 
-        let physical_device_features = vk::PhysicalDeviceFeatures::builder().build();
+        // is_device_suitable only accepts devices with anisotropic filtering
+        let physical_device_features = vk::PhysicalDeviceFeatures::builder()
+            .sampler_anisotropy(true)
+            .build();
 
-        let (_, device_extensions_ptrs) = Self::get_device_extensions();
+        let (_, device_extensions_ptrs) = Self::get_device_extensions(surface_info.is_none());
 
         let mut create_info = vk::DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
@@ -544,32 +564,37 @@ impl LveDevice {
 
         let (_layer_names, layer_name_ptrs) = Self::get_enabled_layers();
 
-        if ENABLE_VALIDATION_LAYERS {
+        if enable_validation {
             create_info = create_info.enabled_layer_names(&layer_name_ptrs);
         }
 
         let device = unsafe {
             instance
                 .create_device(physical_device, &create_info, None)
-                .map_err(|e| log::error!("Unable to create logical device: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create logical device"))?
         };
 
         let graphics_queue = unsafe { device.get_device_queue(queue_indices.graphics_family, 0) };
         let present_queue = unsafe { device.get_device_queue(queue_indices.present_family, 0) };
 
-        (device, graphics_queue, present_queue)
+        let upload_queues = UploadQueues {
+            graphics_family: queue_indices.graphics_family,
+            graphics_queue,
+            transfer: queue_indices.transfer_family
+                .map(|family| (family, unsafe { device.get_device_queue(family, 0) })),
+        };
+
+        Ok((device, graphics_queue, present_queue, upload_queues))
     }
 
     fn create_command_pool(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface_info: Option<(&Surface, vk::SurfaceKHR)>,
         physical_device: vk::PhysicalDevice,
         device: &Device,
-    ) -> vk::CommandPool {
+    ) -> LveResult<vk::CommandPool> {
         let queue_family_indices =
-            Self::find_queue_families(instance, surface, surface_khr, physical_device);
+            Self::find_queue_families(instance, surface_info, physical_device);
 
         let create_info = vk::CommandPoolCreateInfo::builder()
             .queue_family_index(queue_family_indices.graphics_family)
@@ -581,22 +606,23 @@ impl LveDevice {
         unsafe {
             device
                 .create_command_pool(&create_info, None)
-                .map_err(|e| log::error!("Unable to create command pool: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create command pool"))
         }
     }
 
-    fn get_required_extensions() -> Vec<*const i8> {
+    fn get_required_extensions(headless: bool, enable_validation: bool) -> Vec<*const i8> {
         let mut extensions: Vec<*const i8> = Vec::new();
 
-        extensions.push(Surface::name().as_ptr());
+        if !headless {
+            extensions.push(Surface::name().as_ptr());
 
-        #[cfg(target_os="windows")]
-        extensions.push(Win32Surface::name().as_ptr());
-        #[cfg(target_os="linux")]
-        extensions.push(XlibSurface::name().as_ptr());
+            #[cfg(target_os="windows")]
+            extensions.push(Win32Surface::name().as_ptr());
+            #[cfg(target_os="linux")]
+            extensions.push(XlibSurface::name().as_ptr());
+        }
 
-        if ENABLE_VALIDATION_LAYERS {
+        if enable_validation {
             extensions.push(DebugUtils::name().as_ptr());
         }
 
@@ -605,7 +631,7 @@ impl LveDevice {
         extensions
     }
 
-    fn check_validation_layer_support(entry: &Entry) {
+    fn check_validation_layer_support(entry: &Entry) -> bool {
         for required in VALIDATION_LAYERS.iter() {
             let found = entry
                 .enumerate_instance_layer_properties()
@@ -621,11 +647,13 @@ impl LveDevice {
                 });
 
             if !found {
-                panic!("Validation layer not supported: {}", required);
-            } else {
-                log::debug!("Found required validation layers");
+                log::error!("Validation layer not supported: {}", required);
+                return false;
             }
         }
+
+        log::debug!("Found required validation layers");
+        true
     }
 
     fn get_enabled_layers() -> (Vec<CString>, Vec<*const i8>) {
@@ -642,8 +670,12 @@ impl LveDevice {
         (layer_names, layer_names_ptrs)
     }
 
-    fn get_device_extensions() -> ([&'static CStr; 1], Vec<*const i8>) {
-        let device_extensions: [&'static CStr; 1] = [Swapchain::name()];
+    fn get_device_extensions(headless: bool) -> (Vec<&'static CStr>, Vec<*const i8>) {
+        let device_extensions: Vec<&'static CStr> = if headless {
+            Vec::new()
+        } else {
+            vec![Swapchain::name()]
+        };
 
         let ext_names_pts = device_extensions
             .iter()
@@ -655,8 +687,7 @@ impl LveDevice {
 
     fn find_queue_families(
         instance: &Instance,
-        surface: &Surface,
-        surface_khr: vk::SurfaceKHR,
+        surface_info: Option<(&Surface, vk::SurfaceKHR)>,
         device: vk::PhysicalDevice,
     ) -> QueueFamilyIndices {
         let mut graphics_family: u32 = 0;
@@ -674,20 +705,33 @@ impl LveDevice {
         {
             let index = index as u32;
 
-            if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                graphics_family = index;
-                graphics_family_has_value = true;
-            }
-
-            let present_support = unsafe {
-                surface
-                    .get_physical_device_surface_support(device, index, surface_khr)
-                    .unwrap()
-            };
-
-            if present_support {
-                present_family = index;
-                present_family_has_value = true;
+            match surface_info {
+                Some((surface, surface_khr)) => {
+                    if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
+                        graphics_family = index;
+                        graphics_family_has_value = true;
+                    }
+
+                    let present_support = unsafe {
+                        surface
+                            .get_physical_device_surface_support(device, index, surface_khr)
+                            .unwrap()
+                    };
+
+                    if present_support {
+                        present_family = index;
+                        present_family_has_value = true;
+                    }
+                }
+                None => {
+                    // Nothing is presented, so the graphics family doubles as the present family
+                    if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE) {
+                        graphics_family = index;
+                        graphics_family_has_value = true;
+                        present_family = index;
+                        present_family_has_value = true;
+                    }
+                }
             }
 
             if graphics_family_has_value && present_family_has_value {
@@ -695,9 +739,19 @@ impl LveDevice {
             }
         }
 
+        let transfer_family = queue_families
+            .iter()
+            .position(|f| {
+                f.queue_count > 0
+                    && f.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !f.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|index| index as u32);
+
         QueueFamilyIndices {
             graphics_family,
             present_family,
+            transfer_family,
             graphics_family_has_value,
             present_family_has_value,
         }
@@ -710,7 +764,7 @@ impl LveDevice {
                 .unwrap()
         };
 
-        let (required_extensions, _) = Self::get_device_extensions();
+        let (required_extensions, _) = Self::get_device_extensions(false);
 
         for extension in required_extensions.iter() {
             let found = available_extensions.iter().any(|ext| {
@@ -767,12 +821,17 @@ impl Drop for LveDevice {
         unsafe {
             // log::debug!("Destroying command pool");
             self.device.destroy_command_pool(self.command_pool, None);
+
+            self.uploads.destroy(&self.allocator);
+            self.allocator.free_all();
     
             // log::debug!("Destroying device");
             self.device.destroy_device(None);
     
             // log::debug!("Destroying surface");
-            self.surface.destroy_surface(self.surface_khr, None);
+            if let Some(surface) = self.surface.as_ref() {
+                surface.destroy_surface(self.surface_khr, None);
+            }
     
             // log::debug!("Destroying debug messenger");
             if let Some((report, callback)) = self.debug_messenger.take() {
@@ -0,0 +1,72 @@
+use ash::vk;
+
+use std::fmt;
+
+pub type LveResult<T> = Result<T, LveError>;
+
+/// Everything that can go wrong while creating engine objects or loading assets
+#[derive(Debug)]
+pub enum LveError {
+    /// A Vulkan call failed, `context` says which one
+    Vulkan { context: &'static str, result: vk::Result },
+    Io { path: String, source: std::io::Error },
+    ShaderCompilation { path: String, message: String },
+    Gltf { path: String, source: gltf::Error },
+    /// The glTF file loaded, but a primitive is missing data the renderer needs
+    MissingAttribute { path: String, attribute: &'static str },
+    Image { path: String, source: image::ImageError },
+    /// Writing a screenshot or capture failed
+    ImageSave { path: String, source: image::ImageError },
+    Obj { path: String, source: tobj::LoadError },
+    /// A cooked model that is truncated or was written for another engine version
+    Cooked { path: String, reason: &'static str },
+    /// The hardware or driver lacks something the engine requires
+    Unsupported(&'static str),
+}
+
+impl LveError {
+    /// For use with `map_err`, e.g. `.map_err(LveError::vulkan("Unable to create sampler"))?`
+    pub fn vulkan(context: &'static str) -> impl FnOnce(vk::Result) -> Self {
+        move |result| Self::Vulkan { context, result }
+    }
+}
+
+impl fmt::Display for LveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vulkan { context, result } => write!(f, "{}: {}", context, result),
+            Self::Io { path, source } => write!(f, "Unable to read {}: {}", path, source),
+            Self::ShaderCompilation { path, message } => {
+                write!(f, "Unable to compile shader {}:\n{}", path, message)
+            }
+            Self::Gltf { path, source } => write!(f, "Unable to load glTF {}: {}", path, source),
+            Self::MissingAttribute { path, attribute } => {
+                write!(f, "Mesh in {} has no {} attribute", path, attribute)
+            }
+            Self::Image { path, source } => write!(f, "Unable to load image {}: {}", path, source),
+            Self::ImageSave { path, source } => write!(f, "Unable to save image {}: {}", path, source),
+            Self::Obj { path, source } => write!(f, "Unable to load OBJ {}: {}", path, source),
+            Self::Cooked { path, reason } => write!(f, "Unable to load cooked model {}: {}", path, reason),
+            Self::Unsupported(what) => write!(f, "Unsupported: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for LveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Gltf { source, .. } => Some(source),
+            Self::Image { source, .. } => Some(source),
+            Self::ImageSave { source, .. } => Some(source),
+            Self::Obj { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<vk::Result> for LveError {
+    fn from(result: vk::Result) -> Self {
+        Self::Vulkan { context: "Vulkan call failed", result }
+    }
+}
@@ -1,26 +1,48 @@
 use super::lve_device::*;
-use super::lve_buffer::*;
+use super::lve_upload::*;
+use super::lve_error::*;
+use super::lve_allocator::*;
+use super::lve_texture_file::*;
+use super::lve_block_decode::*;
 
 use ash::vk;
 
 use std::rc::Rc;
 
-#[derive(Clone)]
+/// 1x1 texture bound to material slots that have no texture of their own
+pub const DEFAULT_TEXTURE_PATH: &str = "./assets/textures/default_texture.png";
+
+/// What a texture is used for, color textures are gamma decoded when sampled, data textures aren't
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureRole {
+    BaseColor,
+    Emissive,
+    Normal,
+    MetallicRoughness,
+    Occlusion,
+}
+
+impl TextureRole {
+    pub fn format(self) -> vk::Format {
+        match self {
+            Self::BaseColor | Self::Emissive => vk::Format::R8G8B8A8_SRGB,
+            Self::Normal | Self::MetallicRoughness | Self::Occlusion => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
 pub struct LveImage {
     lve_device: Rc<LveDevice>,
     pub image: vk::Image,
-    pub image_memory: vk::DeviceMemory,
+    allocation: LveAllocation,
     pub image_view: vk::ImageView,
     pub image_sampler: vk::Sampler,
     pub image_info: vk::DescriptorImageInfo,
-    pub format: vk::Format
+    pub format: vk::Format,
+    pub mip_levels: u32
 }
 
 impl LveImage {
-    pub fn default(lve_device: Rc<LveDevice>) -> Self {
-        Self::new(lve_device, "./assets/textures/default_texture.png")
-    }
-
     #[allow(dead_code)]
     pub fn null(lve_device: Rc<LveDevice>) -> Self {
 
@@ -36,131 +58,233 @@ impl LveImage {
         LveImage {
             lve_device,
             image: ash::vk::Image::null(),
-            image_memory: ash::vk::DeviceMemory::null(),
+            allocation: LveAllocation::null(),
             image_view,
             image_sampler,
             image_info,
-            format: vk::Format::UNDEFINED
+            format: vk::Format::UNDEFINED,
+            mip_levels: 1
+        }
+    }
+
+    pub fn new(lve_device: Rc<LveDevice>, path: &str, role: TextureRole) -> LveResult<Self> {
+        if LveTextureFile::is_container(path) {
+            let texture = LveTextureFile::open(path)?;
+            return Self::from_texture_file(lve_device, &texture, role.format() == vk::Format::R8G8B8A8_SRGB);
         }
+
+        Self::with_format(lve_device, path, role.format())
+    }
+
+    /// Loads the image as RGBA8, `format` has to be `R8G8B8A8_SRGB` or `R8G8B8A8_UNORM`
+    pub fn with_format(lve_device: Rc<LveDevice>, path: &str, format: vk::Format) -> LveResult<Self> {
+        let image = image::open(path)
+            .map(|img| img.to_rgba8())
+            .map_err(|source| LveError::Image { path: path.to_string(), source })?;
+
+        Self::from_rgba8(lve_device, image, format)
     }
 
-    pub fn new(lve_device: Rc<LveDevice>, path: &str) -> Self {
-        let image = image::open(path).map(|img| img.to_rgba8()).expect("unable to open image");
+    /// Uploads already decoded pixels, e.g. images embedded in a glTF file
+    pub fn from_rgba8(lve_device: Rc<LveDevice>, image: image::RgbaImage, format: vk::Format) -> LveResult<Self> {
+        if format != vk::Format::R8G8B8A8_SRGB && format != vk::Format::R8G8B8A8_UNORM {
+            return Err(LveError::Unsupported("Textures are loaded as RGBA8, use an R8G8B8A8 format"));
+        }
+
         let (width, height) = image.dimensions();
-        let size = (width * height * 4) as usize;
+        let mip_levels = Self::mip_levels(width, height);
+
+        // Without linear blits every level is built on the CPU and uploaded together with the base level
+        let blit_mipmaps = lve_device.supports_linear_blit(format);
+        let levels = if blit_mipmaps {
+            vec![image]
+        } else {
+            Self::generate_mipmaps_cpu(image, mip_levels)
+        };
+
+        let (data, level_sizes) = Self::pack_levels(&levels);
+
+        let finish = if blit_mipmaps {
+            ImageUploadFinish::Mipmaps { width, height }
+        } else {
+            ImageUploadFinish::ShaderRead
+        };
+
+        Self::upload(lve_device, format, width, height, mip_levels, &data, &level_sizes, finish)
+    }
+
+    /// Uploads a KTX2 or DDS texture with the mip levels it comes with. `srgb` picks the sRGB or
+    /// UNORM variant of the stored format. Formats the device can't sample are decompressed to RGBA8
+    /// on the CPU, BC6H and ASTC have no decoder and fail.
+    pub fn from_texture_file(lve_device: Rc<LveDevice>, texture: &LveTextureFile, srgb: bool) -> LveResult<Self> {
+        let mip_levels = texture.levels.len() as u32;
+        Self::from_compressed(lve_device, texture.format, texture.width, texture.height, mip_levels, &texture.levels.concat(), srgb)
+    }
+
+    /// Same as `from_texture_file` for levels that are already packed one after the other, like
+    /// the images of a memory mapped cooked model
+    pub fn from_compressed(
+        lve_device: Rc<LveDevice>,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        data: &[u8],
+        srgb: bool,
+    ) -> LveResult<Self> {
+        let format = LveTextureFile::with_srgb(format, srgb);
+        let level_sizes = (0..mip_levels)
+            .map(|level| {
+                let (width, height) = mip_extent(width, height, level);
+                LveTextureFile::level_size(format, width, height).map(|size| (width, height, size))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(LveError::Unsupported("Texture format isn't supported"))?;
+
+        if level_sizes.iter().map(|&(_, _, size)| size).sum::<usize>() > data.len() {
+            return Err(LveError::Unsupported("Texture level is truncated"));
+        }
+
+        let supported = lve_device.find_supported_format(
+            &vec![format],
+            vk::ImageTiling::OPTIMAL,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+        );
+
+        if supported.is_some() {
+            return Self::upload(lve_device, format, width, height, mip_levels, data, &level_sizes, ImageUploadFinish::ShaderRead);
+        }
+
+        log::info!("{:?} can't be sampled on this device, decompressing it on the CPU", format);
+
+        let mut rgba_format = vk::Format::R8G8B8A8_UNORM;
+        let mut levels = Vec::with_capacity(level_sizes.len());
+        let mut offset = 0;
+        for &(width, height, size) in &level_sizes {
+            let (decoded_format, pixels) = decompress(format, width, height, &data[offset..offset + size])
+                .ok_or(LveError::Unsupported("Texture format isn't supported by the device and can't be decompressed"))?;
+
+            rgba_format = decoded_format;
+            levels.push(image::RgbaImage::from_raw(width, height, pixels).ok_or(LveError::Unsupported("Texture level is truncated"))?);
+            offset += size;
+        }
+
+        // A lone base level still gets a full mip chain
+        if levels.len() == 1 {
+            return Self::from_rgba8(lve_device, levels.remove(0), rgba_format);
+        }
 
-        let mut staging_buffer = LveBuffer::new(lve_device.clone() , size, vk::BufferUsageFlags::TRANSFER_SRC, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
-        staging_buffer.map(0);
-        staging_buffer.write_to_buffer(&image.into_raw());
-        staging_buffer.unmap();
+        let (data, level_sizes) = Self::pack_levels(&levels);
+
+        Self::upload(lve_device, rgba_format, width, height, levels.len() as u32, &data, &level_sizes, ImageUploadFinish::ShaderRead)
+    }
 
+    /// Creates the image and uploads tightly packed levels, `level_sizes` holds the extent and byte
+    /// size of every level in `data`
+    #[allow(clippy::too_many_arguments)]
+    fn upload(
+        lve_device: Rc<LveDevice>,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        data: &[u8],
+        level_sizes: &[(u32, u32, usize)],
+        finish: ImageUploadFinish,
+    ) -> LveResult<Self> {
         let image_create_info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
             .extent(vk::Extent3D {width, height, depth: 1})
-            .mip_levels(1)
+            .mip_levels(mip_levels)
             .array_layers(1)
-            .format(vk::Format::R8G8B8A8_SRGB)
+            .format(format)
             .samples(vk::SampleCountFlags::TYPE_1)
-            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED);
+            .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED);
 
-        let (image, image_memory) = lve_device.create_image_with_info(&image_create_info, vk::MemoryPropertyFlags::DEVICE_LOCAL);
-        Self::transition_image_layout(&lve_device, image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL, 1);
-        lve_device.copy_buffer_to_image(staging_buffer.buffer, image, width, height, 1);
-        Self::transition_image_layout(&lve_device, image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, 1);
+        let (image, allocation) = lve_device.create_image_with_info(&image_create_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
 
-        let image_view = Self::create_image_view(&lve_device, image, vk::Format::R8G8B8A8_SRGB);
-        let image_sampler = Self::create_texture_sampler(&lve_device);
+        // Dropping on an early return below destroys whatever was created so far
+        let mut lve_image = Self::null(lve_device.clone());
+        lve_image.image = image;
+        lve_image.allocation = allocation;
+        lve_image.format = format;
+        lve_image.mip_levels = mip_levels;
 
-        let image_info = ash::vk::DescriptorImageInfo::builder()
+        lve_device.upload_to_image(image, mip_levels, data, &Self::level_regions(level_sizes), finish)?;
+
+        lve_image.image_view = Self::create_image_view(&lve_device, image, format, mip_levels)?;
+        lve_image.image_sampler = Self::create_texture_sampler(&lve_device, mip_levels)?;
+
+        lve_image.image_info = ash::vk::DescriptorImageInfo::builder()
             .image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(image_view)
-            .sampler(image_sampler)
+            .image_view(lve_image.image_view)
+            .sampler(lve_image.image_sampler)
             .build();
 
-        LveImage {
-            lve_device,
-            image,
-            image_memory,
-            image_view,
-            image_sampler,
-            image_info,
-            format: vk::Format::UNDEFINED
-        }
+        Ok(lve_image)
     }
 
-    fn transition_image_layout(lve_device: &Rc<LveDevice>, image: vk::Image, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, mip_levels: u32) {
-        let command_buffer = lve_device.begin_single_time_commands();
-
-        let src_access_mask;
-        let dst_access_mask;
-        let source_stage;
-        let destination_stage;
-
-        if old_layout == vk::ImageLayout::UNDEFINED
-            && new_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-        {
-            src_access_mask = vk::AccessFlags::empty();
-            dst_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-            source_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
-            destination_stage = vk::PipelineStageFlags::TRANSFER;
-        } else if old_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-            && new_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-        {
-            src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-            dst_access_mask = vk::AccessFlags::SHADER_READ;
-            source_stage = vk::PipelineStageFlags::TRANSFER;
-            destination_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
-        } else if old_layout == vk::ImageLayout::UNDEFINED
-            && new_layout == vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
-        {
-            src_access_mask = vk::AccessFlags::empty();
-            dst_access_mask =
-                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE;
-            source_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
-            destination_stage = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
-        } else {
-            panic!("Unsupported layout transition!")
-        }
+    /// Number of levels in a full mip chain, down to 1x1
+    pub fn mip_levels(width: u32, height: u32) -> u32 {
+        32 - width.max(height).max(1).leading_zeros()
+    }
 
-        let image_barriers = [vk::ImageMemoryBarrier {
-            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-            p_next: std::ptr::null(),
-            src_access_mask,
-            dst_access_mask,
-            old_layout,
-            new_layout,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: mip_levels,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-        }];
+    /// Halves the image until it is 1x1, the first level is `image` itself. For sRGB images the
+    /// filtering happens on encoded values, slightly darker than the GPU path but only a fallback.
+    pub fn generate_mipmaps_cpu(image: image::RgbaImage, mip_levels: u32) -> Vec<image::RgbaImage> {
+        let mut levels = vec![image];
 
-        unsafe {
-            lve_device.device.cmd_pipeline_barrier(
-                command_buffer,
-                source_stage,
-                destination_stage,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &image_barriers,
-            );
+        for _ in 1..mip_levels {
+            let previous = levels.last().unwrap();
+            let width = (previous.width() / 2).max(1);
+            let height = (previous.height() / 2).max(1);
+            levels.push(image::imageops::resize(previous, width, height, image::imageops::FilterType::Triangle));
         }
 
-        lve_device.end_single_time_commands(command_buffer);
+        levels
+    }
+
+    /// Concatenates the levels and returns the extent and byte size of each
+    fn pack_levels(levels: &[image::RgbaImage]) -> (Vec<u8>, Vec<(u32, u32, usize)>) {
+        let data = levels.iter().map(|level| level.as_raw().as_slice()).collect::<Vec<_>>().concat();
+        let level_sizes = levels.iter().map(|level| (level.width(), level.height(), level.as_raw().len())).collect();
+
+        (data, level_sizes)
+    }
+
+    /// Copy regions for tightly packed levels, level `i` goes into mip level `i`
+    fn level_regions(level_sizes: &[(u32, u32, usize)]) -> Vec<vk::BufferImageCopy> {
+        let mut buffer_offset = 0;
+        level_sizes
+            .iter()
+            .enumerate()
+            .map(|(level, &(width, height, size))| {
+                let region = vk::BufferImageCopy::builder()
+                    .buffer_offset(buffer_offset)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level as u32,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D { width, height, depth: 1 })
+                    .build();
+
+                buffer_offset += size as vk::DeviceSize;
+                region
+            })
+            .collect()
     }
 
     fn create_image_view(
         lve_device: &Rc<LveDevice>,
         image: vk::Image,
         format: vk::Format,
-    ) -> vk::ImageView {
+        mip_levels: u32,
+    ) -> LveResult<vk::ImageView> {
         let imageview_create_info = vk::ImageViewCreateInfo {
             s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
             p_next: std::ptr::null(),
@@ -176,7 +300,7 @@ impl LveImage {
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask: vk::ImageAspectFlags::COLOR,
                 base_mip_level: 0,
-                level_count: 1,
+                level_count: mip_levels,
                 base_array_layer: 0,
                 layer_count: 1,
             },
@@ -186,11 +310,11 @@ impl LveImage {
         unsafe {
             lve_device.device
                 .create_image_view(&imageview_create_info, None)
-                .expect("Failed to create Image View!")
+                .map_err(LveError::vulkan("Failed to create Image View"))
         }
     }
 
-    fn create_texture_sampler(lve_device: &Rc<LveDevice>) -> vk::Sampler {
+    fn create_texture_sampler(lve_device: &Rc<LveDevice>, mip_levels: u32) -> LveResult<vk::Sampler> {
         let sampler_create_info = vk::SamplerCreateInfo {
             s_type: vk::StructureType::SAMPLER_CREATE_INFO,
             p_next: std::ptr::null(),
@@ -202,12 +326,12 @@ impl LveImage {
             address_mode_v: vk::SamplerAddressMode::REPEAT,
             address_mode_w: vk::SamplerAddressMode::REPEAT,
             mip_lod_bias: 0.0,
-            anisotropy_enable: vk::FALSE,
-            max_anisotropy: 16.0,
+            anisotropy_enable: vk::TRUE,
+            max_anisotropy: lve_device.properties.limits.max_sampler_anisotropy,
             compare_enable: vk::FALSE,
             compare_op: vk::CompareOp::ALWAYS,
             min_lod: 0.0,
-            max_lod: 0.0,
+            max_lod: mip_levels as f32,
             border_color: vk::BorderColor::INT_OPAQUE_BLACK,
             unnormalized_coordinates: vk::FALSE,
         };
@@ -215,7 +339,7 @@ impl LveImage {
         unsafe {
             lve_device.device
                 .create_sampler(&sampler_create_info, None)
-                .expect("Failed to create Sampler!")
+                .map_err(LveError::vulkan("Failed to create Sampler"))
         }
     }
 }
@@ -226,9 +350,10 @@ impl Drop for LveImage {
 
         unsafe {
             self.lve_device.device.destroy_image(self.image, None);
-            self.lve_device.device.free_memory(self.image_memory, None);
             self.lve_device.device.destroy_image_view(self.image_view, None);
             self.lve_device.device.destroy_sampler(self.image_sampler, None);
         }
+
+        self.lve_device.allocator.free(std::mem::take(&mut self.allocation));
     }
 }
\ No newline at end of file
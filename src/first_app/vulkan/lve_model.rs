@@ -1,13 +1,17 @@
 use super::lve_device::*;
 use super::lve_buffer::*;
+use super::lve_error::*;
 
 use ash::{vk, Device};
 
 use std::mem::size_of;
+use std::ops::Range;
 use std::rc::Rc;
 
 extern crate nalgebra as na;
 
+/// `repr(C)` so cooked models can store vertices in exactly the layout the GPU reads
+#[repr(C)]
 #[derive(Clone, Copy, PartialEq)]
 pub struct Vertex {
     pub position: na::Vector3<f32>,
@@ -15,33 +19,65 @@ pub struct Vertex {
     pub normal: na::Vector3<f32>,
     pub tangent: na::Vector4<f32>,
     pub tex_coord: na::Vector2<f32>,
+    /// The second texture coordinate set, e.g. for lightmaps. A copy of `tex_coord` for meshes with one set.
+    pub tex_coord_1: na::Vector2<f32>,
+    /// Skin joints the vertex follows, indices into the skin's joint list
+    pub joints: na::Vector4<u32>,
+    /// Zero for vertices that aren't skinned
+    pub weights: na::Vector4<f32>,
+}
+
+/// One object or group of an OBJ file, drawn with a single material
+pub struct SubModel {
+    pub name: String,
+    /// Range of `Builder::vertices`
+    pub vertices: Range<usize>,
+    /// Range of `Builder::indices`, which index all of `Builder::vertices`
+    pub indices: Range<usize>,
+    /// Index into `Builder::materials`
+    pub material: Option<usize>,
+    /// Without normals in the file they are left zero
+    pub has_normals: bool,
 }
 
 pub struct Builder {
     pub vertices: Vec<Vertex>,
-    pub indices: Vec<u32>
+    pub indices: Vec<u32>,
+    pub sub_models: Vec<SubModel>,
+    /// From the MTL files the OBJ references, texture paths are relative to the OBJ file
+    pub materials: Vec<tobj::Material>,
 }
 
 impl Builder {
     pub fn new() -> Self {
         Self {
             vertices: Vec::new(),
-            indices: Vec::new()
+            indices: Vec::new(),
+            sub_models: Vec::new(),
+            materials: Vec::new(),
         }
     }
 
-    pub fn load_from_file(&mut self, file_path: &str) {
-        let (models, _) = tobj::load_obj(
+    /// Appends every object in the file, with its materials
+    pub fn load_from_file(&mut self, file_path: &str) -> LveResult<()> {
+        let (models, materials) = tobj::load_obj(
             file_path,
             &tobj::LoadOptions {
                 single_index: true,
                 triangulate: true,
                 ..Default::default()
             },
-        ).unwrap();
+        ).map_err(|source| LveError::Obj { path: file_path.to_string(), source })?;
+
+        // The geometry is still useful without its materials
+        let materials = materials.unwrap_or_else(|e| {
+            log::warn!("Unable to load materials of {}: {}", file_path, e);
+            Vec::new()
+        });
+        let first_material = self.materials.len();
 
-        for _model in models.iter() {
-            let mesh = &models[0].mesh;
+        for model in models.iter() {
+            let mesh = &model.mesh;
 
             let positions = mesh.positions.as_slice();
             let colors = mesh.vertex_color.as_slice();
@@ -49,51 +85,44 @@ impl Builder {
             let coords = mesh.texcoords.as_slice();
 
             let vertex_count = mesh.positions.len() / 3;
+            let has_normals = normals.len() >= vertex_count * 3;
+            let has_colors = colors.len() >= vertex_count * 3;
+            let has_coords = coords.len() >= vertex_count * 2;
+
+            let first_vertex = self.vertices.len();
+            let first_index = self.indices.len();
 
-            let mut vertices = Vec::with_capacity(vertex_count);
             for i in 0..vertex_count {
-                let x = positions[3 * i + 0];
-                let y = positions[3 * i + 1];
-                let z = positions[3 * i + 2];
-
-                let color_x;
-                let color_y;
-                let color_z;
-
-                //aint working
-
-                let color_index = 3 * i + 2;
-                if color_index < colors.len() {
-                    color_x = colors[3 * i - 2];
-                    color_y = colors[3 * i - 1];
-                    color_z = colors[3 * i - 0];
-                } else {
-                    color_x = 1.0;
-                    color_y = 1.0;
-                    color_z = 1.0;
-                }
-
-                let normal_x = normals[3 * i + 0];
-                let normal_y = normals[3 * i + 1];
-                let normal_z = normals[3 * i + 2];
-
-                let u = coords[2 * i + 0];
-                let v = coords[2 * i + 1];
-
-                let vertex = Vertex {
-                    position: na::vector!(x, y, z),
-                    color: na::vector!(color_x, color_y, color_z),
-                    normal: na::vector!(normal_x, normal_y, normal_z),
-                    tangent: na::vector![0.0, 0.0, 0.0, 0.0],
-                    tex_coord: na::vector!(u, v),
-                };
+                let vector3 = |data: &[f32]| na::vector![data[3 * i], data[3 * i + 1], data[3 * i + 2]];
+                let (u, v) = if has_coords { (coords[2 * i], coords[2 * i + 1]) } else { (0.0, 0.0) };
 
-                vertices.push(vertex);
+                self.vertices.push(Vertex {
+                    position: vector3(positions),
+                    color: if has_colors { vector3(colors) } else { na::vector![1.0, 1.0, 1.0] },
+                    normal: if has_normals { vector3(normals) } else { na::Vector3::zeros() },
+                    tangent: na::vector![0.0, 0.0, 0.0, 0.0],
+                    // OBJ has v pointing up, Vulkan samples with v pointing down
+                    tex_coord: na::vector![u, 1.0 - v],
+                    tex_coord_1: na::vector![u, 1.0 - v],
+                    joints: na::Vector4::zeros(),
+                    weights: na::Vector4::zeros(),
+                });
             }
 
-            self.vertices.append(&mut vertices);
-            self.indices.append(&mut mesh.indices.clone());
+            self.indices.extend(mesh.indices.iter().map(|index| index + first_vertex as u32));
+
+            self.sub_models.push(SubModel {
+                name: model.name.clone(),
+                vertices: first_vertex..self.vertices.len(),
+                indices: first_index..self.indices.len(),
+                material: mesh.material_id.filter(|&id| id < materials.len()).map(|id| id + first_material),
+                has_normals,
+            });
         }
+
+        self.materials.extend(materials);
+
+        Ok(())
     }
 }
 
@@ -142,6 +171,24 @@ impl Vertex {
                 .format(vk::Format::R32G32_SFLOAT)
                 .offset(memoffset::offset_of!(Vertex, tex_coord) as u32) // Using size of the position field
                 .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(5)
+                .format(vk::Format::R32G32B32A32_UINT)
+                .offset(memoffset::offset_of!(Vertex, joints) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(6)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(memoffset::offset_of!(Vertex, weights) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(7)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(memoffset::offset_of!(Vertex, tex_coord_1) as u32)
+                .build(),
         ]
     }
 }
@@ -155,22 +202,22 @@ pub struct LveModel {
 }
 
 impl LveModel {
-    pub fn new(lve_device: Rc<LveDevice>, builder: &Builder) -> Rc<Self> {
-        let (vertex_buffer, vertex_count) = Self::create_vertex_buffers(&lve_device, &builder.vertices);
-        let (has_index_buffer, index_buffer, index_count) = Self::create_index_buffers(&lve_device, &builder.indices);
+    pub fn new(lve_device: Rc<LveDevice>, builder: &Builder) -> LveResult<Rc<Self>> {
+        let (vertex_buffer, vertex_count) = Self::create_vertex_buffers(&lve_device, &builder.vertices)?;
+        let (has_index_buffer, index_buffer, index_count) = Self::create_index_buffers(&lve_device, &builder.indices)?;
         
-        Rc::new(Self {
+        Ok(Rc::new(Self {
             vertex_buffer,
             vertex_count,
             has_index_buffer,
             index_buffer,
             index_count,
-        })
+        }))
     }
 
-    pub fn new_from_file(lve_device: Rc<LveDevice>, file_path: &str) -> Rc<Self> {
+    pub fn new_from_file(lve_device: Rc<LveDevice>, file_path: &str) -> LveResult<Rc<Self>> {
         let mut builder = Builder::new();
-        builder.load_from_file(file_path);
+        builder.load_from_file(file_path)?;
 
         LveModel::new(lve_device, &builder)
     }
@@ -188,6 +235,8 @@ impl LveModel {
         })
     }
 
+    /// # Safety
+    /// `command_buffer` must be recording inside a render pass with this model bound by `bind`.
     #[allow(dead_code)]
     pub unsafe fn draw(&self, device: &Device, command_buffer: vk::CommandBuffer) {
         if self.has_index_buffer {
@@ -197,6 +246,8 @@ impl LveModel {
         }
     }
 
+    /// # Safety
+    /// `command_buffer` must be recording, and the model's buffers must outlive its execution.
     #[allow(dead_code)]
     pub unsafe fn bind(&self, command_buffer: vk::CommandBuffer) {
         self.vertex_buffer.bind_vertex(command_buffer);
@@ -207,65 +258,43 @@ impl LveModel {
         }
     }
 
-    fn create_vertex_buffers(lve_device: &Rc<LveDevice>, vertices: &Vec<Vertex>) -> (LveBuffer<Vertex>, u32) {
+    fn create_vertex_buffers(lve_device: &Rc<LveDevice>, vertices: &Vec<Vertex>) -> LveResult<(LveBuffer<Vertex>, u32)> {
         let vertex_count = vertices.len();
-        assert!(vertex_count >= 3, "Vertex count must be at least 3");
-
-        let buffer_size: vk::DeviceSize = (size_of::<Vertex>() * vertex_count) as u64;
-
-        let mut staging_buffer = LveBuffer::new(
-            lve_device.clone(),
-            vertex_count,
-            ash::vk::BufferUsageFlags::TRANSFER_SRC,
-            ash::vk::MemoryPropertyFlags::HOST_VISIBLE | ash::vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-
-        staging_buffer.map(0);
-        staging_buffer.write_to_buffer(vertices);
+        if vertex_count < 3 {
+            return Err(LveError::Unsupported("meshes with fewer than 3 vertices"));
+        }
 
         let vertex_buffer = LveBuffer::new(
             lve_device.clone(),
             vertex_count,
             ash::vk::BufferUsageFlags::VERTEX_BUFFER | ash::vk::BufferUsageFlags::TRANSFER_DST,
             ash::vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        );
+        )?;
 
-        lve_device.copy_buffer(staging_buffer.buffer, vertex_buffer.buffer, buffer_size);
+        lve_device.upload_to_buffer(vertex_buffer.buffer, vertices)?;
 
-        (vertex_buffer, vertex_count as u32)
+        Ok((vertex_buffer, vertex_count as u32))
     }
 
-    fn create_index_buffers(lve_device: &Rc<LveDevice>, indices: &Vec<u32>) -> (bool, LveBuffer<u32>, u32) {
+    fn create_index_buffers(lve_device: &Rc<LveDevice>, indices: &Vec<u32>) -> LveResult<(bool, LveBuffer<u32>, u32)> {
         let index_count = indices.len();
         let has_index_buffer = index_count > 0;
 
         if !has_index_buffer {
             let index_buffer = LveBuffer::null(lve_device.clone());
             //let index_buffer_memory = vk::DeviceMemory::null();
-            return (has_index_buffer, index_buffer, 0);
+            return Ok((has_index_buffer, index_buffer, 0));
         }
 
-        let buffer_size: vk::DeviceSize = (size_of::<u32>() * index_count) as u64;
-
-        let mut staging_buffer = LveBuffer::new(
-            lve_device.clone(),
-            index_count,
-            ash::vk::BufferUsageFlags::TRANSFER_SRC,
-            ash::vk::MemoryPropertyFlags::HOST_VISIBLE | ash::vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-
-        staging_buffer.map(0);
-        staging_buffer.write_to_buffer(indices);
-
         let index_buffer = LveBuffer::new(
             lve_device.clone(),
             index_count,
             ash::vk::BufferUsageFlags::INDEX_BUFFER | ash::vk::BufferUsageFlags::TRANSFER_DST,
             ash::vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        );
+        )?;
 
-        lve_device.copy_buffer(staging_buffer.buffer, index_buffer.buffer, buffer_size);
+        lve_device.upload_to_buffer(index_buffer.buffer, indices)?;
 
-        (has_index_buffer, index_buffer, index_count as u32)
+        Ok((has_index_buffer, index_buffer, index_count as u32))
     }
 }
\ No newline at end of file
@@ -0,0 +1,597 @@
+use super::lve_device::*;
+use super::lve_swapchain::*;
+use super::lve_readback::*;
+use super::lve_allocator::*;
+use super::lve_error::*;
+
+use ash::{vk, Device};
+use std::rc::Rc;
+
+pub const OFFSCREEN_COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+pub struct OffscreenTarget {
+    pub color_image: vk::Image,
+    color_allocation: LveAllocation,
+    pub color_image_view: vk::ImageView,
+    depth_image: vk::Image,
+    depth_allocation: LveAllocation,
+    depth_image_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+}
+
+/// Renders into images owned by the renderer instead of swapchain images, so frames can be
+/// produced without a window. Every frame in flight has its own color and depth target.
+pub struct LveOffscreenRenderer {
+    lve_device: Rc<LveDevice>,
+    extent: vk::Extent2D,
+    depth_format: vk::Format,
+    render_pass: vk::RenderPass,
+    pub targets: Vec<OffscreenTarget>,
+    pub sampler: vk::Sampler,
+    command_buffers: Vec<vk::CommandBuffer>,
+    in_flight_fences: Vec<vk::Fence>,
+    current_frame_index: usize,
+    pub is_frame_started: bool,
+}
+
+#[allow(dead_code)]
+impl LveOffscreenRenderer {
+    pub fn new(lve_device: Rc<LveDevice>, extent: vk::Extent2D, frames_in_flight: usize) -> LveResult<Self> {
+        assert!(frames_in_flight > 0, "Need at least one frame in flight");
+
+        let depth_format = LveSwapchain::find_depth_format(&lve_device)?;
+
+        let render_pass = Self::create_render_pass(&lve_device.device, depth_format);
+
+        let targets = (0..frames_in_flight)
+            .map(|_| Self::create_target(&lve_device, extent, depth_format, render_pass))
+            .collect::<LveResult<Vec<_>>>()?;
+
+        let sampler = Self::create_sampler(&lve_device.device);
+
+        let command_buffers =
+            Self::create_command_buffers(&lve_device.device, lve_device.command_pool, frames_in_flight);
+
+        let in_flight_fences = Self::create_sync_objects(&lve_device.device, frames_in_flight);
+
+        Ok(Self {
+            lve_device,
+            extent,
+            depth_format,
+            render_pass,
+            targets,
+            sampler,
+            command_buffers,
+            in_flight_fences,
+            current_frame_index: 0,
+            is_frame_started: false,
+        })
+    }
+
+    pub fn get_frames_in_flight(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn get_frame_index(&self) -> usize {
+        assert!(
+            self.is_frame_started,
+            "Cannot get frame index when frame is not in progress"
+        );
+        self.current_frame_index
+    }
+
+    pub fn get_current_command_buffer(&self) -> vk::CommandBuffer {
+        assert!(
+            self.is_frame_started,
+            "Cannot get command buffer when frame not in progress"
+        );
+        self.command_buffers[self.current_frame_index]
+    }
+
+    pub fn get_render_pass(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn get_aspect_ratio(&self) -> f32 {
+        self.extent.width as f32 / self.extent.height as f32
+    }
+
+    /// Descriptor info for sampling the color target of a frame, valid once that frame has ended
+    pub fn color_image_info(&self, frame_index: usize) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(self.targets[frame_index].color_image_view)
+            .sampler(self.sampler)
+            .build()
+    }
+
+    pub fn begin_frame(&mut self) -> Option<vk::CommandBuffer> {
+        assert!(
+            !self.is_frame_started,
+            "Can't call begin_frame while already in progress"
+        );
+
+        let fence = self.in_flight_fences[self.current_frame_index];
+
+        unsafe {
+            self.lve_device
+                .device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .map_err(|e| log::error!("Unable to wait for fences: {}", e))
+                .unwrap();
+
+            self.lve_device
+                .device
+                .reset_fences(&[fence])
+                .map_err(|e| log::error!("Unable to reset fences: {}", e))
+                .unwrap();
+        }
+
+        self.is_frame_started = true;
+
+        let command_buffer = self.get_current_command_buffer();
+
+        let begin_info = vk::CommandBufferBeginInfo::builder().build();
+
+        unsafe {
+            self.lve_device
+                .device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .map_err(|e| log::error!("Unable to begin command buffer: {}", e))
+                .unwrap()
+        };
+
+        Some(command_buffer)
+    }
+
+    pub fn end_frame(&mut self) {
+        assert!(
+            self.is_frame_started,
+            "Can't call end_frame while frame is not in progress"
+        );
+        let command_buffer = self.get_current_command_buffer();
+
+        unsafe {
+            self.lve_device
+                .device
+                .end_command_buffer(command_buffer)
+                .map_err(|e| log::error!("Unable to end command buffer: {}", e))
+                .unwrap()
+        };
+
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(std::slice::from_ref(&command_buffer));
+
+        unsafe {
+            self.lve_device
+                .device
+                .queue_submit(
+                    self.lve_device.graphics_queue,
+                    std::slice::from_ref(&submit_info),
+                    self.in_flight_fences[self.current_frame_index],
+                )
+                .map_err(|e| log::error!("Unable to submit draw command buffer: {}", e))
+                .unwrap()
+        };
+
+        self.is_frame_started = false;
+        self.current_frame_index = (self.current_frame_index + 1) % self.targets.len();
+    }
+
+    /// Blocks until every submitted frame has finished rendering
+    pub fn wait_idle(&self) {
+        unsafe {
+            self.lve_device
+                .device
+                .wait_for_fences(&self.in_flight_fences, true, u64::MAX)
+                .map_err(|e| log::error!("Unable to wait for fences: {}", e))
+                .unwrap()
+        };
+    }
+
+    pub fn begin_render_pass(&self, command_buffer: vk::CommandBuffer) {
+        assert!(
+            self.is_frame_started,
+            "Can't call begin_render_pass while frame is not in progress"
+        );
+
+        assert_eq!(
+            command_buffer,
+            self.get_current_command_buffer(),
+            "Can't begin render pass on a command buffer from a different frame"
+        );
+
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+
+        let color_clear = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.01, 0.01, 0.01, 1.0],
+            },
+        };
+
+        let depth_clear = vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: 1.0,
+                stencil: 0,
+            },
+        };
+
+        let clear_values = [color_clear, depth_clear];
+
+        let render_pass_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.targets[self.current_frame_index].framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values)
+            .build();
+
+        unsafe {
+            self.lve_device.device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+
+            let viewport = vk::Viewport::builder()
+                .x(0.0)
+                .y(0.0)
+                .width(self.extent.width as f32)
+                .height(self.extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build();
+
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            };
+
+            self.lve_device
+                .device
+                .cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.lve_device
+                .device
+                .cmd_set_scissor(command_buffer, 0, &[scissor]);
+        };
+    }
+
+    pub fn end_render_pass(&self, command_buffer: vk::CommandBuffer) {
+        assert!(
+            self.is_frame_started,
+            "Can't call end_render_pass while frame is not in progress"
+        );
+
+        assert_eq!(
+            command_buffer,
+            self.get_current_command_buffer(),
+            "Can't end render pass on a command buffer from a different frame"
+        );
+
+        unsafe {
+            self.lve_device.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// Records a copy of the color target of the current frame, call after `end_render_pass`
+    pub fn capture_color_target(&self, command_buffer: vk::CommandBuffer) -> LveResult<LveReadback> {
+        assert!(
+            self.is_frame_started,
+            "Can't capture the color target while frame is not in progress"
+        );
+
+        LveReadback::record(
+            self.lve_device.clone(),
+            command_buffer,
+            self.targets[self.current_frame_index].color_image,
+            OFFSCREEN_COLOR_FORMAT,
+            self.extent,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+
+    /// Recreates the render targets with a new size, the render pass stays compatible
+    pub fn resize(&mut self, extent: vk::Extent2D) -> LveResult<()> {
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
+
+        self.wait_idle();
+
+        for target in self.targets.drain(..) {
+            Self::destroy_target(&self.lve_device, target);
+        }
+
+        self.targets = (0..self.in_flight_fences.len())
+            .map(|_| Self::create_target(&self.lve_device, extent, self.depth_format, self.render_pass))
+            .collect::<LveResult<Vec<_>>>()?;
+
+        self.extent = extent;
+
+        Ok(())
+    }
+
+    fn create_render_pass(device: &Device, depth_format: vk::Format) -> vk::RenderPass {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(OFFSCREEN_COLOR_FORMAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build();
+
+        let depth_attachment = vk::AttachmentDescription::builder()
+            .format(depth_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let depth_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .build();
+
+        let attachment_refs = [color_attachment_ref];
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)
+            .build();
+
+        let dependency_1 = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER)
+            .dst_stage_mask(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            )
+            .src_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .build();
+
+        let dependency_2 = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        let attachments = [color_attachment, depth_attachment];
+        let dependencies = [dependency_1, dependency_2];
+
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(std::slice::from_ref(&subpass))
+            .dependencies(&dependencies);
+
+        unsafe {
+            device
+                .create_render_pass(&render_pass_info, None)
+                .map_err(|e| log::error!("Unable to create render pass: {}", e))
+                .unwrap()
+        }
+    }
+
+    fn create_target(
+        lve_device: &Rc<LveDevice>,
+        extent: vk::Extent2D,
+        depth_format: vk::Format,
+        render_pass: vk::RenderPass,
+    ) -> LveResult<OffscreenTarget> {
+        let (color_image, color_allocation, color_image_view) = Self::create_image(
+            lve_device,
+            extent,
+            OFFSCREEN_COLOR_FORMAT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let (depth_image, depth_allocation, depth_image_view) = Self::create_image(
+            lve_device,
+            extent,
+            depth_format,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let attachments = [color_image_view, depth_image_view];
+
+        let frame_buffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+
+        let framebuffer = unsafe {
+            lve_device
+                .device
+                .create_framebuffer(&frame_buffer_info, None)
+                .map_err(|e| log::error!("Unable to create framebuffer: {}", e))
+                .unwrap()
+        };
+
+        Ok(OffscreenTarget {
+            color_image,
+            color_allocation,
+            color_image_view,
+            depth_image,
+            depth_allocation,
+            depth_image_view,
+            framebuffer,
+        })
+    }
+
+    fn create_image(
+        lve_device: &Rc<LveDevice>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> LveResult<(vk::Image, LveAllocation, vk::ImageView)> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .format(format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (image, allocation) = lve_device
+            .create_image_with_info(&image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+
+        let image_view = unsafe {
+            lve_device
+                .device
+                .create_image_view(&view_info, None)
+                .map_err(|e| log::error!("Unable to create image view: {}", e))
+                .unwrap()
+        };
+
+        Ok((image, allocation, image_view))
+    }
+
+    fn create_sampler(device: &Device) -> vk::Sampler {
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .mip_lod_bias(0.0)
+            .max_anisotropy(1.0)
+            .min_lod(0.0)
+            .max_lod(0.0)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
+            .build();
+
+        unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .map_err(|e| log::error!("Unable to create sampler: {}", e))
+                .unwrap()
+        }
+    }
+
+    fn create_command_buffers(
+        device: &Device,
+        command_pool: vk::CommandPool,
+        count: usize,
+    ) -> Vec<vk::CommandBuffer> {
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(command_pool)
+            .command_buffer_count(count as u32)
+            .build();
+
+        unsafe {
+            device
+                .allocate_command_buffers(&alloc_info)
+                .map_err(|e| log::error!("Unable to allocate command buffer: {}", e))
+                .unwrap()
+        }
+    }
+
+    fn create_sync_objects(device: &Device, count: usize) -> Vec<vk::Fence> {
+        let fence_info = vk::FenceCreateInfo::builder()
+            .flags(vk::FenceCreateFlags::SIGNALED);
+
+        (0..count)
+            .map(|_| unsafe {
+                device
+                    .create_fence(&fence_info, None)
+                    .map_err(|e| log::error!("Unable to create in flight fence: {}", e))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+    }
+
+    fn destroy_target(lve_device: &LveDevice, target: OffscreenTarget) {
+        let device = &lve_device.device;
+
+        unsafe {
+            device.destroy_framebuffer(target.framebuffer, None);
+
+            device.destroy_image_view(target.color_image_view, None);
+            device.destroy_image(target.color_image, None);
+
+            device.destroy_image_view(target.depth_image_view, None);
+            device.destroy_image(target.depth_image, None);
+        }
+
+        lve_device.allocator.free(target.color_allocation);
+        lve_device.allocator.free(target.depth_allocation);
+    }
+}
+
+impl Drop for LveOffscreenRenderer {
+    fn drop(&mut self) {
+        log::debug!("Dropping offscreen renderer");
+
+        self.wait_idle();
+
+        for target in self.targets.drain(..) {
+            Self::destroy_target(&self.lve_device, target);
+        }
+
+        unsafe {
+            self.in_flight_fences
+                .iter()
+                .for_each(|f| self.lve_device.device.destroy_fence(*f, None));
+
+            self.lve_device.device.destroy_sampler(self.sampler, None);
+            self.lve_device.device.destroy_render_pass(self.render_pass, None);
+            self.lve_device.device.free_command_buffers(self.lve_device.command_pool, &self.command_buffers);
+            self.command_buffers.clear();
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use super::lve_device::LveDevice;
+use super::lve_error::*;
 use super::lve_model::*;
 
 use ash::{vk, Device};
@@ -17,6 +18,8 @@ pub struct PipelineConfigInfo {
     pub _dynamic_state_enables: Vec<vk::DynamicState>,
     pub dynamic_state_info: vk::PipelineDynamicStateCreateInfo,
     pub subpass: u32,
+    /// Defined in both shaders, for variants that share one source file
+    pub shader_defines: Vec<&'static str>,
 }
 
 pub struct LvePipeline {
@@ -34,7 +37,7 @@ impl LvePipeline {
         config_info: PipelineConfigInfo,
         render_pass: &vk::RenderPass,
         pipeline_layout: &vk::PipelineLayout,
-    ) -> Self {
+    ) -> LveResult<Self> {
         let (graphics_pipeline, vert_shader_module, frag_shader_module) =
             Self::create_graphics_pipeline(
                 &lve_device.device,
@@ -43,16 +46,19 @@ impl LvePipeline {
                 config_info,
                 render_pass,
                 pipeline_layout,
-            );
+            )?;
 
-        Self {
+        Ok(Self {
             lve_device,
             graphics_pipeline,
             vert_shader_module,
             frag_shader_module,
-        }
+        })
     }
 
+    /// # Safety
+    /// `command_buffer` must be recording inside a render pass compatible with the one this
+    /// pipeline was created for.
     pub unsafe fn bind(&self, device: &Device, command_buffer: vk::CommandBuffer) {
         device.cmd_bind_pipeline(
             command_buffer,
@@ -145,6 +151,7 @@ impl LvePipeline {
             _dynamic_state_enables: dynamic_state_enables,
             dynamic_state_info,
             subpass: 0,
+            shader_defines: Vec::new(),
         }
     }
 
@@ -155,7 +162,7 @@ impl LvePipeline {
         config_info: PipelineConfigInfo,
         render_pass: &vk::RenderPass,
         pipeline_layout: &vk::PipelineLayout,
-    ) -> (vk::Pipeline, vk::ShaderModule, vk::ShaderModule) {
+    ) -> LveResult<(vk::Pipeline, vk::ShaderModule, vk::ShaderModule)> {
         assert_ne!(
             pipeline_layout,
             &vk::PipelineLayout::null(),
@@ -167,16 +174,17 @@ impl LvePipeline {
             "Cannot create graphics pipeline:: no render_pass provided in config_info"
         );
 
-        let vert_source: &str = &std::fs::read_to_string(vert_file_path).expect("Something went wrong reading the file");
-        let frag_source: &str = &std::fs::read_to_string(frag_file_path).expect("Something went wrong reading the file");
-
-        let mut compiler = shaderc::Compiler::new().unwrap();
+        let vert_code = Self::compile_shader(vert_file_path, shaderc::ShaderKind::Vertex, &config_info.shader_defines)?;
+        let frag_code = Self::compile_shader(frag_file_path, shaderc::ShaderKind::Fragment, &config_info.shader_defines)?;
 
-        let vert_code = compiler.compile_into_spirv(vert_source, shaderc::ShaderKind::Vertex,vert_file_path, "main", None).unwrap().as_binary().to_vec();
-        let frag_code = compiler.compile_into_spirv(frag_source, shaderc::ShaderKind::Fragment,frag_file_path, "main", None).unwrap().as_binary().to_vec();
-
-        let vert_shader_module = Self::create_shader_module(device, &vert_code);
-        let frag_shader_module = Self::create_shader_module(device, &frag_code);
+        let vert_shader_module = Self::create_shader_module(device, &vert_code)?;
+        let frag_shader_module = match Self::create_shader_module(device, &frag_code) {
+            Ok(module) => module,
+            Err(e) => {
+                unsafe { device.destroy_shader_module(vert_shader_module, None) };
+                return Err(e);
+            }
+        };
 
         let entry_point_name = CString::new("main").unwrap();
 
@@ -226,21 +234,48 @@ impl LvePipeline {
         let graphics_pipeline = unsafe {
             device
                 .create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&pipeline_info), None)
-                .map_err(|e| log::error!("Unable to create graphics pipeline: {:?}", e))
-                .unwrap()[0]
         };
 
-        (graphics_pipeline, vert_shader_module, frag_shader_module)
+        match graphics_pipeline {
+            Ok(pipelines) => Ok((pipelines[0], vert_shader_module, frag_shader_module)),
+            Err((_, result)) => {
+                unsafe {
+                    device.destroy_shader_module(vert_shader_module, None);
+                    device.destroy_shader_module(frag_shader_module, None);
+                }
+
+                Err(LveError::Vulkan { context: "Unable to create graphics pipeline", result })
+            }
+        }
+    }
+
+    fn compile_shader(path: &str, kind: shaderc::ShaderKind, defines: &[&str]) -> LveResult<Vec<u32>> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|source| LveError::Io { path: path.to_string(), source })?;
+
+        let mut compiler = shaderc::Compiler::new()
+            .ok_or(LveError::Unsupported("Unable to create the shader compiler"))?;
+
+        let mut options = shaderc::CompileOptions::new()
+            .ok_or(LveError::Unsupported("Unable to create the shader compiler options"))?;
+        for define in defines {
+            options.add_macro_definition(define, None);
+        }
+
+        let artifact = compiler
+            .compile_into_spirv(&source, kind, path, "main", Some(&options))
+            .map_err(|e| LveError::ShaderCompilation { path: path.to_string(), message: e.to_string() })?;
+
+        Ok(artifact.as_binary().to_vec())
     }
 
-    fn create_shader_module(device: &Device, code: &Vec<u32>) -> vk::ShaderModule {
+    fn create_shader_module(device: &Device, code: &Vec<u32>) -> LveResult<vk::ShaderModule> {
         let create_info = vk::ShaderModuleCreateInfo::builder().code(code).build();
 
         unsafe {
             device
                 .create_shader_module(&create_info, None)
-                .map_err(|e| log::error!("Unable to create shader module: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create shader module"))
         }
     }
 }
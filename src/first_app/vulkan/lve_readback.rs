@@ -0,0 +1,351 @@
+use super::lve_device::*;
+use super::lve_buffer::*;
+use super::lve_error::*;
+
+use ash::vk;
+
+use std::rc::Rc;
+
+/// A pending copy of an image into a host visible buffer. The copy is recorded into a command
+/// buffer, the data can be read once that command buffer has finished executing.
+pub struct LveReadback {
+    lve_device: Rc<LveDevice>,
+    buffer: LveBuffer<u8>,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+}
+
+/// Pixels copied back from the GPU, tightly packed in the format of the source image
+pub struct LveCapture {
+    pub width: u32,
+    pub height: u32,
+    pub format: vk::Format,
+    pub data: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl LveReadback {
+    /// Records a copy of `image` into a new readback buffer. The image is expected in `layout`
+    /// and is put back into that layout after the copy.
+    pub fn record(
+        lve_device: Rc<LveDevice>,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        layout: vk::ImageLayout,
+    ) -> LveResult<Self> {
+        let size = readback_size(format, extent)?;
+
+        let buffer = LveBuffer::new(
+            lve_device.clone(),
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let (access_mask, stage) = Self::access_for_layout(layout);
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let to_transfer = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(access_mask)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let from_transfer = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(access_mask)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .build();
+
+        let to_host = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        unsafe {
+            lve_device.device.cmd_pipeline_barrier(
+                command_buffer,
+                stage,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+
+            lve_device.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[region],
+            );
+
+            lve_device.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                stage | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[to_host],
+                &[from_transfer],
+            );
+        }
+
+        Ok(Self {
+            lve_device,
+            buffer,
+            extent,
+            format,
+        })
+    }
+
+    /// Copies `image` right away using a one time command buffer
+    pub fn capture_now(
+        lve_device: Rc<LveDevice>,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        layout: vk::ImageLayout,
+    ) -> LveResult<LveCapture> {
+        let command_buffer = lve_device.begin_single_time_commands();
+        let readback = Self::record(lve_device.clone(), command_buffer, image, format, extent, layout);
+        lve_device.end_single_time_commands(command_buffer);
+
+        readback?.read()
+    }
+
+    /// Waits for the GPU to finish and copies the pixels out of the readback buffer
+    pub fn read(&mut self) -> LveResult<LveCapture> {
+        self.lve_device.wait_idle();
+
+        let size = readback_size(self.format, self.extent)?;
+        let mut data = vec![0u8; size];
+
+        self.buffer.map(0);
+        unsafe {
+            (self.buffer.mapped.unwrap() as *const u8).copy_to_nonoverlapping(data.as_mut_ptr(), size);
+        }
+        self.buffer.unmap();
+
+        Ok(LveCapture {
+            width: self.extent.width,
+            height: self.extent.height,
+            format: self.format,
+            data,
+        })
+    }
+
+    fn access_for_layout(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+        match layout {
+            vk::ImageLayout::PRESENT_SRC_KHR => (
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            _ => (vk::AccessFlags::MEMORY_WRITE, vk::PipelineStageFlags::ALL_COMMANDS),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl LveCapture {
+    /// True for formats that are written as EXR instead of PNG
+    pub fn is_hdr(&self) -> bool {
+        matches!(
+            self.format,
+            vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32B32A32_SFLOAT
+        )
+    }
+
+    /// Converts the capture to 8 bit RGBA. sRGB formats keep their encoded values, float formats
+    /// are clamped and gamma encoded.
+    pub fn to_rgba8(&self) -> LveResult<image::RgbaImage> {
+        let pixels = match self.format {
+            vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => self.data.clone(),
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => self
+                .data
+                .chunks_exact(4)
+                .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])
+                .collect(),
+            vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32B32A32_SFLOAT => self
+                .to_rgba32f()?
+                .into_raw()
+                .chunks_exact(4)
+                .flat_map(|rgba| {
+                    [
+                        (linear_to_srgb(rgba[0]) * 255.0).round() as u8,
+                        (linear_to_srgb(rgba[1]) * 255.0).round() as u8,
+                        (linear_to_srgb(rgba[2]) * 255.0).round() as u8,
+                        (rgba[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+                    ]
+                })
+                .collect(),
+            _ => return Err(LveError::Unsupported("converting this capture format to RGBA8")),
+        };
+
+        Ok(image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap())
+    }
+
+    /// Converts the capture to linear float RGBA, sRGB formats are decoded. Reads the data directly
+    /// for every format, `to_rgba8` builds on this one and not the other way round.
+    pub fn to_rgba32f(&self) -> LveResult<image::Rgba32FImage> {
+        let pixels: Vec<f32> = match self.format {
+            vk::Format::R16G16B16A16_SFLOAT => self
+                .data
+                .chunks_exact(2)
+                .map(|half| f16_to_f32(u16::from_le_bytes([half[0], half[1]])))
+                .collect(),
+            vk::Format::R32G32B32A32_SFLOAT => self
+                .data
+                .chunks_exact(4)
+                .map(|float| f32::from_le_bytes([float[0], float[1], float[2], float[3]]))
+                .collect(),
+            vk::Format::R8G8B8A8_SRGB
+            | vk::Format::R8G8B8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM => {
+                let srgb = matches!(self.format, vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB);
+                let bgra = matches!(self.format, vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM);
+
+                self.data
+                    .chunks_exact(4)
+                    .flat_map(|texel| {
+                        let rgba = if bgra { [texel[2], texel[1], texel[0], texel[3]] } else { [texel[0], texel[1], texel[2], texel[3]] };
+                        let decode = |value: u8| {
+                            let value = value as f32 / 255.0;
+                            if srgb { srgb_to_linear(value) } else { value }
+                        };
+                        [decode(rgba[0]), decode(rgba[1]), decode(rgba[2]), rgba[3] as f32 / 255.0]
+                    })
+                    .collect()
+            }
+            _ => return Err(LveError::Unsupported("converting this capture format to RGBA32F")),
+        };
+
+        Ok(image::Rgba32FImage::from_raw(self.width, self.height, pixels).unwrap())
+    }
+
+    /// Writes PNG for LDR formats and EXR for float formats, the extension of `path` is replaced
+    /// accordingly. Returns the path that was written.
+    pub fn save(&self, path: &std::path::Path) -> LveResult<std::path::PathBuf> {
+        let save_error = |path: &std::path::Path| {
+            let path = path.display().to_string();
+            move |source| LveError::ImageSave { path, source }
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(image::ImageError::IoError)
+                .map_err(save_error(path))?;
+        }
+
+        if self.is_hdr() {
+            let path = path.with_extension("exr");
+            self.to_rgba32f()?.save(&path).map_err(save_error(&path))?;
+            Ok(path)
+        } else {
+            let path = path.with_extension("png");
+            self.to_rgba8()?.save(&path).map_err(save_error(&path))?;
+            Ok(path)
+        }
+    }
+}
+
+/// Size of a tightly packed copy of an image, unsupported formats are an error rather than a panic
+fn readback_size(format: vk::Format, extent: vk::Extent2D) -> LveResult<usize> {
+    let bytes_per_pixel = bytes_per_pixel(format).ok_or(LveError::Unsupported("readback of this image format"))?;
+    Ok((extent.width * extent.height) as usize * bytes_per_pixel)
+}
+
+pub fn bytes_per_pixel(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 => {
+            if mantissa == 0.0 { sign * f32::INFINITY } else { f32::NAN }
+        }
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
@@ -1,5 +1,7 @@
 use super::lve_device::*;
 use super::lve_swapchain::*;
+use super::lve_readback::*;
+use super::lve_error::*;
 
 use winit::window::Window;
 
@@ -10,28 +12,39 @@ pub struct LveRenderer {
     lve_device: Rc<LveDevice>,
     pub lve_swapchain: LveSwapchain,
     command_buffers: Vec<vk::CommandBuffer>,
+    frames_in_flight: usize,
     current_image_index: usize,
     current_frame_index: usize,
     pub is_frame_started: bool,
 }
 
 impl LveRenderer {
-    pub fn new(lve_device: Rc<LveDevice>, window: &Window) -> Self {
+    /// `frames_in_flight` is how many frames the CPU may record ahead of the GPU, anything the
+    /// CPU writes every frame (like uniform buffers) needs that many copies
+    pub fn new(lve_device: Rc<LveDevice>, window: &Window, frames_in_flight: usize) -> LveResult<Self> {
+        assert!(frames_in_flight > 0, "Need at least one frame in flight");
+
         let window_extent = Self::get_window_extent(window);
 
-        let lve_swapchain = LveSwapchain::new(Rc::clone(&lve_device), window_extent, None);
+        let lve_swapchain = LveSwapchain::new(Rc::clone(&lve_device), window_extent, None, frames_in_flight)?;
 
         let command_buffers =
-            Self::create_command_buffers(&lve_device.device, lve_device.command_pool);
+            Self::create_command_buffers(&lve_device.device, lve_device.command_pool, frames_in_flight)?;
 
-        Self {
+        Ok(Self {
             lve_device,
             lve_swapchain,
             command_buffers,
+            frames_in_flight,
             current_image_index: 0,
             current_frame_index: 0,
             is_frame_started: false,
-        }
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn get_frames_in_flight(&self) -> usize {
+        self.frames_in_flight
     }
 
     #[allow(dead_code)]
@@ -59,7 +72,7 @@ impl LveRenderer {
         self.lve_swapchain.extent_aspect_ratio()
     }
 
-    pub fn begin_frame(&mut self, window: &Window) -> Option<vk::CommandBuffer> {
+    pub fn begin_frame(&mut self, window: &Window) -> LveResult<Option<vk::CommandBuffer>> {
         assert!(
             !self.is_frame_started,
             "Can't call begin_frame while already in progress"
@@ -67,28 +80,20 @@ impl LveRenderer {
 
         let result = unsafe {
             self.lve_swapchain
-                .acquire_next_image(&self.lve_device.device)
+                .acquire_next_image(&self.lve_device.device, self.current_frame_index)
         };
 
         match result {
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                 //log::error!("Out of date KHR!");
-                self.recreate_swapchain(window);
-                return None;
+                self.recreate_swapchain(window)?;
+                return Ok(None);
             }
-            Err(_) => {
-                log::error!("Unable to acquire next image");
-                panic!("Unable to handle this error")
+            Err(result) => {
+                return Err(LveError::Vulkan { context: "Unable to acquire next image", result });
             }
-            Ok((current_image_index, is_subopt)) => {
-                match is_subopt {
-                    true => {
-                        log::warn!("Swapchain is suboptimal for surface");
-                        self.recreate_swapchain(window);
-                    }
-                    false => {}
-                }
-
+            Ok((current_image_index, _)) => {
+                // A suboptimal swapchain can still be presented to, it is recreated in end_frame
                 self.is_frame_started = true;
                 self.current_image_index = current_image_index as usize;
             }
@@ -102,14 +107,13 @@ impl LveRenderer {
             self.lve_device
                 .device
                 .begin_command_buffer(command_buffer, &begin_info)
-                .map_err(|e| log::error!("Unable to begin command buffer: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to begin command buffer"))?
         };
 
-        return Some(command_buffer);
+        Ok(Some(command_buffer))
     }
 
-    pub fn end_frame(&mut self, window: &Window) {
+    pub fn end_frame(&mut self, window: &Window) -> LveResult<()> {
         assert!(
             self.is_frame_started,
             "Can't call end_frame while frame is not in progress"
@@ -120,8 +124,7 @@ impl LveRenderer {
             self.lve_device
                 .device
                 .end_command_buffer(command_buffer)
-                .map_err(|e| log::error!("Unable to end command buffer: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to end command buffer"))?
         };
 
         let result = self
@@ -132,31 +135,24 @@ impl LveRenderer {
                 &self.lve_device.present_queue,
                 command_buffer,
                 self.current_image_index,
+                self.current_frame_index,
         );
 
+        self.is_frame_started = false;
+        self.current_frame_index = (self.current_frame_index + 1) % self.frames_in_flight;
+
         match result {
             Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                 // log::error!("Out of date KHR!");
-                self.recreate_swapchain(window);
+                self.recreate_swapchain(window)
             }
-            Err(_) => {
-                log::error!("Unable to acquire next image");
-                panic!("Unable to handle this error")
+            Ok(true) => {
+                log::warn!("Swapchain is suboptimal for surface");
+                self.recreate_swapchain(window)
             }
-            Ok(_) => {},
+            Err(result) => Err(LveError::Vulkan { context: "Unable to submit or present frame", result }),
+            Ok(false) => Ok(()),
         }
-
-
-        unsafe {
-            self.lve_device
-                .device
-                .device_wait_idle()
-                .map_err(|e| log::error!("Cannot wait: {}", e))
-                .unwrap()
-        };
-
-        self.is_frame_started = false;
-        self.current_frame_index = (self.current_frame_index + 1) % MAX_FRAMES_IN_FLIGHT;
     }
 
     pub fn begin_swapchain_render_pass(&self, command_buffer: vk::CommandBuffer) {
@@ -245,32 +241,65 @@ impl LveRenderer {
         }
     }
 
-    pub fn recreate_swapchain(&mut self, window: &Window) {
+    /// Records a copy of the current swapchain image, call after `end_swapchain_render_pass`
+    /// and read the result once the frame has ended. Fails on surfaces whose images can't be
+    /// a transfer source.
+    pub fn capture_swapchain_image(&self, command_buffer: vk::CommandBuffer) -> LveResult<LveReadback> {
+        assert!(
+            self.is_frame_started,
+            "Can't capture the swapchain image while frame is not in progress"
+        );
+
+        if !self.lve_swapchain.image_usage().contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Err(LveError::Unsupported("screenshots, the surface's images can't be copied from"));
+        }
+
+        LveReadback::record(
+            self.lve_device.clone(),
+            command_buffer,
+            self.lve_swapchain.get_image(self.current_image_index),
+            self.lve_swapchain.image_format(),
+            self.lve_swapchain.swapchain_extent,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    /// Records a copy of any color image used during this frame, e.g. a G-buffer attachment
+    pub fn capture_image(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        layout: vk::ImageLayout,
+    ) -> LveResult<LveReadback> {
+        assert!(
+            self.is_frame_started,
+            "Can't capture an image while frame is not in progress"
+        );
+
+        LveReadback::record(self.lve_device.clone(), command_buffer, image, format, extent, layout)
+    }
+
+    pub fn recreate_swapchain(&mut self, window: &Window) -> LveResult<()> {
         let extent = Self::get_window_extent(window);
 
         if extent.width == 0 || extent.height == 0 {
-            return;
+            return Ok(());
         }
 
         log::debug!("Recreating swapchain");
 
-        unsafe {
-            self.lve_device
-                .device
-                .device_wait_idle()
-                .map_err(|e| log::error!("Cannot wait: {}", e))
-                .unwrap()
-        };
+        self.lve_device.wait_idle();
 
         let new_lve_swapchain =
-            LveSwapchain::new(Rc::clone(&self.lve_device), extent, Some(self.lve_swapchain.swapchain_khr));
+            LveSwapchain::new(Rc::clone(&self.lve_device), extent, Some(self.lve_swapchain.swapchain_khr), self.frames_in_flight)?;
 
-        self.lve_swapchain
-            .compare_swap_formats(&new_lve_swapchain)
-            .map_err(|_| log::error!("Swapchain image (or depth) format has changed"))
-            .unwrap();
+        self.lve_swapchain.compare_swap_formats(&new_lve_swapchain)?;
 
         self.lve_swapchain = new_lve_swapchain;
+
+        Ok(())
     }
 
     pub fn get_window_extent(window: &Window) -> vk::Extent2D {
@@ -284,21 +313,19 @@ impl LveRenderer {
     fn create_command_buffers(
         device: &Device,
         command_pool: vk::CommandPool,
-    ) -> Vec<vk::CommandBuffer> {
+        count: usize,
+    ) -> LveResult<Vec<vk::CommandBuffer>> {
         let alloc_info = vk::CommandBufferAllocateInfo::builder()
             .level(vk::CommandBufferLevel::PRIMARY)
             .command_pool(command_pool)
-            .command_buffer_count(MAX_FRAMES_IN_FLIGHT as u32)
+            .command_buffer_count(count as u32)
             .build();
 
-        let command_buffers = unsafe {
+        unsafe {
             device
                 .allocate_command_buffers(&alloc_info)
-                .map_err(|e| log::error!("Unable to allocate command buffer: {}", e))
-                .unwrap()
-        };
-
-        command_buffers
+                .map_err(LveError::vulkan("Unable to allocate command buffer"))
+        }
     }
 }
 
@@ -0,0 +1,114 @@
+use super::lve_device::*;
+use super::lve_error::*;
+
+use ash::vk;
+
+use std::rc::Rc;
+
+/// Filtering and wrapping of a material texture, glTF textures pick these per texture
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SamplerSettings {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Without mipmaps only the base level is sampled
+    pub mipmaps: bool,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+}
+
+impl Default for SamplerSettings {
+    /// Trilinear and repeating, what glTF leaves up to the implementation when a texture has no sampler
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            mipmaps: true,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+        let wrap = |mode| match mode {
+            WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+            WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+        };
+
+        let (min_filter, mipmap_mode, mipmaps) = match sampler.min_filter() {
+            Some(MinFilter::Nearest) => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, false),
+            Some(MinFilter::Linear) => (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST, false),
+            Some(MinFilter::NearestMipmapNearest) => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, true),
+            Some(MinFilter::LinearMipmapNearest) => (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST, true),
+            Some(MinFilter::NearestMipmapLinear) => (vk::Filter::NEAREST, vk::SamplerMipmapMode::LINEAR, true),
+            Some(MinFilter::LinearMipmapLinear) | None => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR, true),
+        };
+
+        Self {
+            mag_filter: match sampler.mag_filter() {
+                Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+                Some(MagFilter::Linear) | None => vk::Filter::LINEAR,
+            },
+            min_filter,
+            mipmap_mode,
+            mipmaps,
+            address_mode_u: wrap(sampler.wrap_s()),
+            address_mode_v: wrap(sampler.wrap_t()),
+        }
+    }
+}
+
+/// A sampler that isn't tied to one image, so every texture with the same settings shares it
+pub struct LveSampler {
+    lve_device: Rc<LveDevice>,
+    pub sampler: vk::Sampler,
+    pub settings: SamplerSettings,
+}
+
+impl LveSampler {
+    pub fn new(lve_device: Rc<LveDevice>, settings: SamplerSettings) -> LveResult<Self> {
+        // Anisotropy only makes sense on top of linear, mipmapped filtering
+        let anisotropic = settings.mipmaps && settings.min_filter == vk::Filter::LINEAR && settings.mag_filter == vk::Filter::LINEAR;
+
+        let sampler_create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(settings.mag_filter)
+            .min_filter(settings.min_filter)
+            .mipmap_mode(settings.mipmap_mode)
+            .address_mode_u(settings.address_mode_u)
+            .address_mode_v(settings.address_mode_v)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .anisotropy_enable(anisotropic)
+            .max_anisotropy(lve_device.properties.limits.max_sampler_anisotropy)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .min_lod(0.0)
+            // 0.25 keeps the minification filter while staying on the base level
+            .max_lod(if settings.mipmaps { vk::LOD_CLAMP_NONE } else { 0.25 })
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false);
+
+        let sampler = unsafe {
+            lve_device.device
+                .create_sampler(&sampler_create_info, None)
+                .map_err(LveError::vulkan("Failed to create Sampler"))?
+        };
+
+        Ok(Self { lve_device, sampler, settings })
+    }
+}
+
+impl Drop for LveSampler {
+    fn drop(&mut self) {
+        log::debug!("Dropping sampler");
+
+        unsafe {
+            self.lve_device.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -1,17 +1,28 @@
 use super::lve_device::*;
+use super::lve_error::*;
+use super::lve_allocator::*;
 
 use ash::extensions::khr::Swapchain;
 use ash::{vk, Device};
 
 use std::rc::Rc;
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+/// Used when no frame count is configured, see `LveRenderer::new`
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+/// Depth images, their memory and views, one per swapchain image, and the format they share
+type DepthResources = (Vec<vk::Image>, Vec<LveAllocation>, Vec<vk::ImageView>, vk::Format);
+
+/// Image available and render finished semaphores, in flight fences and images in flight
+type SyncObjects = (Vec<vk::Semaphore>, Vec<vk::Semaphore>, Vec<vk::Fence>, Vec<vk::Fence>);
 
 pub struct LveSwapchain {
     lve_device: Rc<LveDevice>,
     swapchain: Swapchain,
     pub swapchain_khr: vk::SwapchainKHR,
     swapchain_image_format: vk::Format,
+    /// Includes `TRANSFER_SRC` only if the surface supports it
+    swapchain_image_usage: vk::ImageUsageFlags,
     swapchain_depth_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
     _swapchain_images: Vec<vk::Image>,
@@ -19,13 +30,12 @@ pub struct LveSwapchain {
     pub swapchain_framebuffers: Vec<vk::Framebuffer>,
     pub render_pass: vk::RenderPass,
     depth_images: Vec<vk::Image>,
-    depth_image_memories: Vec<vk::DeviceMemory>,
+    depth_image_allocations: Vec<LveAllocation>,
     depth_image_views: Vec<vk::ImageView>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
     in_flight_fences: Vec<vk::Fence>,
     images_in_flight: Vec<vk::Fence>,
-    current_frame: usize,
 }
 
 impl LveSwapchain {
@@ -33,25 +43,26 @@ impl LveSwapchain {
         lve_device: Rc<LveDevice>,
         window_extent: vk::Extent2D,
         old_swapchain: Option<vk::SwapchainKHR>,
-    ) -> Self {
+        frames_in_flight: usize,
+    ) -> LveResult<Self> {
         let old_swapchain = match old_swapchain {
             Some(swapchain) => swapchain,
             None => vk::SwapchainKHR::null(),
         };
 
-        let (swapchain, swapchain_khr, swapchain_images, swapchain_image_format, swapchain_extent) =
-            Self::create_swapchain(&lve_device, window_extent, old_swapchain);
+        let (swapchain, swapchain_khr, swapchain_images, swapchain_image_format, swapchain_image_usage, swapchain_extent) =
+            Self::create_swapchain(&lve_device, window_extent, old_swapchain)?;
 
         let swapchain_image_views = Self::create_image_views(
             &lve_device.device,
             &swapchain_images,
             swapchain_image_format,
-        );
+        )?;
 
-        let render_pass = Self::create_render_pass(&lve_device, swapchain_image_format);
+        let render_pass = Self::create_render_pass(&lve_device, swapchain_image_format)?;
 
-        let (depth_images, depth_image_memories, depth_image_views, swapchain_depth_format) =
-            Self::create_depth_resources(&lve_device, &swapchain_images, swapchain_extent);
+        let (depth_images, depth_image_allocations, depth_image_views, swapchain_depth_format) =
+            Self::create_depth_resources(&lve_device, &swapchain_images, swapchain_extent)?;
 
         let swapchain_framebuffers = Self::create_framebuffers(
             &lve_device.device,
@@ -59,20 +70,21 @@ impl LveSwapchain {
             &swapchain_image_views,
             &depth_image_views,
             render_pass,
-        );
+        )?;
 
         let (
             image_available_semaphores,
             render_finished_semaphores,
             in_flight_fences,
             images_in_flight,
-        ) = Self::create_sync_objects(&lve_device.device, &swapchain_images);
+        ) = Self::create_sync_objects(&lve_device.device, &swapchain_images, frames_in_flight)?;
 
-        Self {
+        Ok(Self {
             lve_device,
             swapchain,
             swapchain_khr,
             swapchain_image_format,
+            swapchain_image_usage,
             swapchain_depth_format,
             swapchain_extent,
             _swapchain_images: swapchain_images,
@@ -80,23 +92,22 @@ impl LveSwapchain {
             swapchain_framebuffers,
             render_pass,
             depth_images,
-            depth_image_memories,
+            depth_image_allocations,
             depth_image_views,
             image_available_semaphores,
             render_finished_semaphores,
             in_flight_fences,
             images_in_flight,
-            current_frame: 0,
-        }
+        })
     }
 
-    pub fn compare_swap_formats(&self, other_swapchain: &Self) -> Result<(), ()> {
+    pub fn compare_swap_formats(&self, other_swapchain: &Self) -> LveResult<()> {
         if other_swapchain.swapchain_depth_format == self.swapchain_depth_format
             && other_swapchain.swapchain_image_format == self.swapchain_image_format
         {
             Ok(())
         } else {
-            Err(())
+            Err(LveError::Unsupported("Swapchain image (or depth) format has changed"))
         }
     }
 
@@ -105,6 +116,18 @@ impl LveSwapchain {
         self._swapchain_images.len()
     }
 
+    pub fn get_image(&self, index: usize) -> vk::Image {
+        self._swapchain_images[index]
+    }
+
+    pub fn image_format(&self) -> vk::Format {
+        self.swapchain_image_format
+    }
+
+    pub fn image_usage(&self) -> vk::ImageUsageFlags {
+        self.swapchain_image_usage
+    }
+
     pub fn width(&self) -> u32 {
         self.swapchain_extent.width
     }
@@ -117,7 +140,7 @@ impl LveSwapchain {
         self.swapchain_extent.width as f32 / self.swapchain_extent.height as f32
     }
 
-    pub fn find_depth_format(lve_device: &Rc<LveDevice>) -> vk::Format {
+    pub fn find_depth_format(lve_device: &Rc<LveDevice>) -> LveResult<vk::Format> {
         let candidates = vec![
             vk::Format::D32_SFLOAT,
             vk::Format::D32_SFLOAT_S8_UINT,
@@ -127,23 +150,27 @@ impl LveSwapchain {
             &candidates,
             vk::ImageTiling::OPTIMAL,
             vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
-        )
+        ).ok_or(LveError::Unsupported("a depth attachment format"))
     }
 
-    pub unsafe fn acquire_next_image(&mut self, device: &Device) -> Result<(u32, bool), vk::Result> {
+    /// Waits until `frame_index` is no longer in flight and acquires the next image. After this
+    /// returns, resources owned by `frame_index` (command buffer, uniform buffers) can be reused.
+    ///
+    /// # Safety
+    /// `device` must be the device the swapchain was created on and `frame_index` less than the
+    /// number of frames in flight.
+    pub unsafe fn acquire_next_image(&mut self, device: &Device, frame_index: usize) -> Result<(u32, bool), vk::Result> {
         device
             .wait_for_fences(
-                &[self.in_flight_fences[self.current_frame]],
-                false,
+                &[self.in_flight_fences[frame_index]],
+                true,
                 u64::MAX,
-            )
-            .map_err(|e| log::error!("Unable to wait for fences: {}", e))
-            .unwrap();
+            )?;
 
         self.swapchain.acquire_next_image(
             self.swapchain_khr,
             u64::MAX,
-            self.image_available_semaphores[self.current_frame],
+            self.image_available_semaphores[frame_index],
             vk::Fence::null(),
         ) // Return the result of acquire next image
     }
@@ -155,24 +182,23 @@ impl LveSwapchain {
         present_queue: &vk::Queue,
         buffer: vk::CommandBuffer,
         image_index: usize,
+        frame_index: usize,
     ) -> Result<bool, vk::Result> {
+        // The image may still be used by an older frame if images are acquired out of order
 
         if self.images_in_flight[image_index] != vk::Fence::null() {
             unsafe {
-                device
-                    .wait_for_fences(&[self.images_in_flight[image_index]], true, u64::MAX)
-                    .map_err(|e| log::error!("Unable to wait for fences: {}", e))
-                    .unwrap()
+                device.wait_for_fences(&[self.images_in_flight[image_index]], true, u64::MAX)?
             };
         }
 
-        self.images_in_flight[image_index] = self.in_flight_fences[self.current_frame];
+        self.images_in_flight[image_index] = self.in_flight_fences[frame_index];
 
-        let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
+        let wait_semaphores = [self.image_available_semaphores[frame_index]];
 
         let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
 
-        let signal_semaphores = [self.render_finished_semaphores[self.current_frame]];
+        let signal_semaphores = [self.render_finished_semaphores[frame_index]];
 
         let submit_info = vk::SubmitInfo::builder()
             .wait_semaphores(&wait_semaphores)
@@ -182,18 +208,14 @@ impl LveSwapchain {
 
         unsafe {
             device
-                .reset_fences(&[self.in_flight_fences[self.current_frame]])
-                .map_err(|e| log::error!("Unable to reset fences: {}", e))
-                .unwrap();
+                .reset_fences(&[self.in_flight_fences[frame_index]])?;
 
             device
                 .queue_submit(
                     *graphics_queue,
                     std::slice::from_ref(&submit_info),
-                    self.in_flight_fences[self.current_frame],
-                )
-                .map_err(|e| log::error!("Unable to submit draw command buffer: {}", e))
-                .unwrap();
+                    self.in_flight_fences[frame_index],
+                )?;
         };
 
         let swapchains = [self.swapchain_khr];
@@ -205,8 +227,6 @@ impl LveSwapchain {
             .swapchains(&swapchains)
             .image_indices(std::slice::from_ref(&image_index));
 
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
-
         unsafe { self.swapchain.queue_present(*present_queue, &present_info) }
     }
 
@@ -214,13 +234,14 @@ impl LveSwapchain {
         lve_device: &Rc<LveDevice>,
         window_extent: vk::Extent2D,
         old_swapchain: vk::SwapchainKHR,
-    ) -> (
+    ) -> LveResult<(
         Swapchain,
         vk::SwapchainKHR,
         Vec<vk::Image>,
         vk::Format,
+        vk::ImageUsageFlags,
         vk::Extent2D,
-    ) {
+    )> {
         let swapchain_support = lve_device.get_swapchain_support();
 
         let surface_format = Self::choose_swap_surface_format(&swapchain_support.formats);
@@ -237,6 +258,12 @@ impl LveSwapchain {
             image_count = swapchain_support.capabilities.max_image_count;
         }
 
+        // Transfer source lets us copy the presented image back for screenshots
+        let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if swapchain_support.capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
         let mut create_info = vk::SwapchainCreateInfoKHR::builder()
             .surface(lve_device.surface_khr)
             .min_image_count(image_count)
@@ -244,7 +271,7 @@ impl LveSwapchain {
             .image_color_space(surface_format.color_space)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            .image_usage(image_usage);
 
         let indices = lve_device.find_physical_queue_families();
 
@@ -270,35 +297,34 @@ impl LveSwapchain {
         let swapchain_khr = unsafe {
             swapchain
                 .create_swapchain(&create_info, None)
-                .map_err(|e| log::error!("Unable to create swapchain: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create swapchain"))?
         };
 
         let swapchain_images = unsafe {
             swapchain
                 .get_swapchain_images(swapchain_khr)
-                .map_err(|e| log::error!("Unable to get swapchain images: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to get swapchain images"))?
         };
 
         let swapchain_image_format = surface_format.format;
 
         let swapchain_extent = extent;
 
-        (
+        Ok((
             swapchain,
             swapchain_khr,
             swapchain_images,
             swapchain_image_format,
+            image_usage,
             swapchain_extent,
-        )
+        ))
     }
 
     fn create_image_views(
         device: &Device,
         swapchain_images: &Vec<vk::Image>,
         swapchain_image_format: vk::Format,
-    ) -> Vec<vk::ImageView> {
+    ) -> LveResult<Vec<vk::ImageView>> {
         swapchain_images
             .iter()
             .map(|image| {
@@ -317,26 +343,20 @@ impl LveSwapchain {
                 unsafe {
                     device
                         .create_image_view(&view_info, None)
-                        .map_err(|e| log::error!("Unable to create image view: {}", e))
-                        .unwrap()
+                        .map_err(LveError::vulkan("Unable to create image view"))
                 }
             })
-            .collect::<Vec<_>>()
+            .collect::<LveResult<Vec<_>>>()
     }
 
     fn create_depth_resources(
         lve_device: &Rc<LveDevice>,
         swapchain_images: &Vec<vk::Image>,
         swapchain_extent: vk::Extent2D,
-    ) -> (
-        Vec<vk::Image>,
-        Vec<vk::DeviceMemory>,
-        Vec<vk::ImageView>,
-        vk::Format,
-    ) {
-        let depth_format = Self::find_depth_format(lve_device);
+    ) -> LveResult<DepthResources> {
+        let depth_format = Self::find_depth_format(lve_device)?;
 
-        let (images, image_memories): (Vec<vk::Image>, Vec<vk::DeviceMemory>) = swapchain_images
+        let (images, image_allocations): (Vec<vk::Image>, Vec<LveAllocation>) = swapchain_images
             .iter()
             .map(|_| {
                 let extent = vk::Extent3D {
@@ -361,6 +381,8 @@ impl LveSwapchain {
                 lve_device
                     .create_image_with_info(&image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)
             })
+            .collect::<LveResult<Vec<_>>>()?
+            .into_iter()
             .unzip();
 
         let image_views = images
@@ -382,21 +404,20 @@ impl LveSwapchain {
                     lve_device
                         .device
                         .create_image_view(&view_info, None)
-                        .map_err(|e| log::error!("Unable to create depth image view: {}", e))
-                        .unwrap()
+                        .map_err(LveError::vulkan("Unable to create depth image view"))
                 }
             })
-            .collect::<Vec<_>>();
+            .collect::<LveResult<Vec<_>>>();
 
-        (images, image_memories, image_views, depth_format)
+        Ok((images, image_allocations, image_views?, depth_format))
     }
 
     fn create_render_pass(
         lve_device: &Rc<LveDevice>,
         swapchain_image_format: vk::Format,
-    ) -> vk::RenderPass {
+    ) -> LveResult<vk::RenderPass> {
         let depth_attachment = vk::AttachmentDescription::builder()
-            .format(Self::find_depth_format(lve_device))
+            .format(Self::find_depth_format(lve_device)?)
             .samples(vk::SampleCountFlags::TYPE_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::DONT_CARE)
@@ -460,8 +481,7 @@ impl LveSwapchain {
             lve_device
                 .device
                 .create_render_pass(&render_pass_info, None)
-                .map_err(|e| log::error!("Unable to create render pass: {}", e))
-                .unwrap()
+                .map_err(LveError::vulkan("Unable to create render pass"))
         }
     }
 
@@ -471,7 +491,7 @@ impl LveSwapchain {
         swapchain_image_views: &Vec<vk::ImageView>,
         depth_image_views: &Vec<vk::ImageView>,
         render_pass: vk::RenderPass,
-    ) -> Vec<vk::Framebuffer> {
+    ) -> LveResult<Vec<vk::Framebuffer>> {
         swapchain_image_views
             .iter()
             .zip(depth_image_views)
@@ -487,22 +507,17 @@ impl LveSwapchain {
                 unsafe {
                     device
                         .create_framebuffer(&frame_buffer_info, None)
-                        .map_err(|e| log::error!("Unable to create framebuffer: {}", e))
-                        .unwrap()
+                        .map_err(LveError::vulkan("Unable to create framebuffer"))
                 }
             })
-            .collect::<Vec<_>>()
+            .collect::<LveResult<Vec<_>>>()
     }
 
     fn create_sync_objects(
         device: &Device,
         swapchain_images: &Vec<vk::Image>,
-    ) -> (
-        Vec<vk::Semaphore>,
-        Vec<vk::Semaphore>,
-        Vec<vk::Fence>,
-        Vec<vk::Fence>,
-    ) {
+        frames_in_flight: usize,
+    ) -> LveResult<SyncObjects> {
         let semaphore_info = vk::SemaphoreCreateInfo::builder();
 
         let fence_info = vk::FenceCreateInfo::builder()
@@ -512,43 +527,36 @@ impl LveSwapchain {
         let mut render_finished_semaphore = Vec::new();
         let mut in_flight_fences = Vec::new();
 
-        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+        for _ in 0..frames_in_flight {
             unsafe {
                 image_available_semaphores.push(
                     device
                         .create_semaphore(&semaphore_info, None)
-                        .map_err(|e| {
-                            log::error!("Unable to create image available semaphore: {}", e)
-                        })
-                        .unwrap(),
+                        .map_err(LveError::vulkan("Unable to create image available semaphore"))?,
                 );
 
                 render_finished_semaphore.push(
                     device
                         .create_semaphore(&semaphore_info, None)
-                        .map_err(|e| {
-                            log::error!("Unable to create render finished semaphore: {}", e)
-                        })
-                        .unwrap(),
+                        .map_err(LveError::vulkan("Unable to create render finished semaphore"))?,
                 );
 
                 in_flight_fences.push(
                     device
                         .create_fence(&fence_info, None)
-                        .map_err(|e| log::error!("Unable to create in flight fence: {}", e))
-                        .unwrap(),
+                        .map_err(LveError::vulkan("Unable to create in flight fence"))?,
                 );
             }
         }
 
         let images_in_flight = vec![vk::Fence::null(); swapchain_images.len()];
 
-        (
+        Ok((
             image_available_semaphores,
             render_finished_semaphore,
             in_flight_fences,
             images_in_flight,
-        )
+        ))
     }
 
     fn choose_swap_surface_format(
@@ -632,9 +640,9 @@ impl Drop for LveSwapchain {
                 .iter()
                 .for_each(|i| self.lve_device.device.destroy_image(*i, None));
 
-            self.depth_image_memories
-                .iter()
-                .for_each(|m| self.lve_device.device.free_memory(*m, None));
+            self.depth_image_allocations
+                .drain(..)
+                .for_each(|a| self.lve_device.allocator.free(a));
 
             self.swapchain_framebuffers
                 .iter()
@@ -0,0 +1,330 @@
+use super::lve_error::*;
+
+use ash::vk;
+
+use std::path::Path;
+
+/// Formats a KTX2 or DDS file may hold, as `(UNORM, SRGB)` pairs. Formats without an sRGB variant
+/// repeat the first one.
+const FORMAT_PAIRS: [(vk::Format, vk::Format); 27] = [
+    (vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB),
+    (vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_SRGB),
+    (vk::Format::BC1_RGB_UNORM_BLOCK, vk::Format::BC1_RGB_SRGB_BLOCK),
+    (vk::Format::BC1_RGBA_UNORM_BLOCK, vk::Format::BC1_RGBA_SRGB_BLOCK),
+    (vk::Format::BC2_UNORM_BLOCK, vk::Format::BC2_SRGB_BLOCK),
+    (vk::Format::BC3_UNORM_BLOCK, vk::Format::BC3_SRGB_BLOCK),
+    (vk::Format::BC4_UNORM_BLOCK, vk::Format::BC4_UNORM_BLOCK),
+    (vk::Format::BC5_UNORM_BLOCK, vk::Format::BC5_UNORM_BLOCK),
+    (vk::Format::BC6H_UFLOAT_BLOCK, vk::Format::BC6H_UFLOAT_BLOCK),
+    (vk::Format::BC6H_SFLOAT_BLOCK, vk::Format::BC6H_SFLOAT_BLOCK),
+    (vk::Format::BC7_UNORM_BLOCK, vk::Format::BC7_SRGB_BLOCK),
+    (vk::Format::ETC2_R8G8B8_UNORM_BLOCK, vk::Format::ETC2_R8G8B8_SRGB_BLOCK),
+    (vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK, vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK),
+    (vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK, vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK),
+    (vk::Format::ASTC_4X4_UNORM_BLOCK, vk::Format::ASTC_4X4_SRGB_BLOCK),
+    (vk::Format::ASTC_5X4_UNORM_BLOCK, vk::Format::ASTC_5X4_SRGB_BLOCK),
+    (vk::Format::ASTC_5X5_UNORM_BLOCK, vk::Format::ASTC_5X5_SRGB_BLOCK),
+    (vk::Format::ASTC_6X5_UNORM_BLOCK, vk::Format::ASTC_6X5_SRGB_BLOCK),
+    (vk::Format::ASTC_6X6_UNORM_BLOCK, vk::Format::ASTC_6X6_SRGB_BLOCK),
+    (vk::Format::ASTC_8X5_UNORM_BLOCK, vk::Format::ASTC_8X5_SRGB_BLOCK),
+    (vk::Format::ASTC_8X6_UNORM_BLOCK, vk::Format::ASTC_8X6_SRGB_BLOCK),
+    (vk::Format::ASTC_8X8_UNORM_BLOCK, vk::Format::ASTC_8X8_SRGB_BLOCK),
+    (vk::Format::ASTC_10X5_UNORM_BLOCK, vk::Format::ASTC_10X5_SRGB_BLOCK),
+    (vk::Format::ASTC_10X6_UNORM_BLOCK, vk::Format::ASTC_10X6_SRGB_BLOCK),
+    (vk::Format::ASTC_10X8_UNORM_BLOCK, vk::Format::ASTC_10X8_SRGB_BLOCK),
+    (vk::Format::ASTC_10X10_UNORM_BLOCK, vk::Format::ASTC_10X10_SRGB_BLOCK),
+    (vk::Format::ASTC_12X12_UNORM_BLOCK, vk::Format::ASTC_12X12_SRGB_BLOCK),
+];
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// A texture with its whole mip chain as stored in the file, level 0 is the largest. Only the
+/// first layer and face of arrays and cube maps is kept.
+pub struct LveTextureFile {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl LveTextureFile {
+    /// True for the containers `open` understands, everything else goes through the `image` crate
+    pub fn is_container(path: &str) -> bool {
+        Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2") || extension.eq_ignore_ascii_case("dds"))
+    }
+
+    pub fn open(path: &str) -> LveResult<Self> {
+        let bytes = std::fs::read(path).map_err(|source| LveError::Io { path: path.to_string(), source })?;
+
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            Self::parse_ktx2(&bytes)
+        } else if bytes.starts_with(b"DDS ") {
+            Self::parse_dds(&bytes)
+        } else {
+            Err(LveError::Unsupported("Texture file is neither KTX2 nor DDS"))
+        }
+    }
+
+    /// Width, height and byte size of one block, 1x1 for uncompressed formats
+    pub fn block_size(format: vk::Format) -> Option<(u32, u32, usize)> {
+        use vk::Format as F;
+
+        let size = match format {
+            F::R8G8B8A8_UNORM | F::R8G8B8A8_SRGB | F::B8G8R8A8_UNORM | F::B8G8R8A8_SRGB => (1, 1, 4),
+            F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK | F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGBA_SRGB_BLOCK
+            | F::BC4_UNORM_BLOCK | F::ETC2_R8G8B8_UNORM_BLOCK | F::ETC2_R8G8B8_SRGB_BLOCK
+            | F::ETC2_R8G8B8A1_UNORM_BLOCK | F::ETC2_R8G8B8A1_SRGB_BLOCK => (4, 4, 8),
+            F::BC2_UNORM_BLOCK | F::BC2_SRGB_BLOCK | F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK | F::BC5_UNORM_BLOCK
+            | F::BC6H_UFLOAT_BLOCK | F::BC6H_SFLOAT_BLOCK | F::BC7_UNORM_BLOCK | F::BC7_SRGB_BLOCK
+            | F::ETC2_R8G8B8A8_UNORM_BLOCK | F::ETC2_R8G8B8A8_SRGB_BLOCK => (4, 4, 16),
+            F::ASTC_4X4_UNORM_BLOCK | F::ASTC_4X4_SRGB_BLOCK => (4, 4, 16),
+            F::ASTC_5X4_UNORM_BLOCK | F::ASTC_5X4_SRGB_BLOCK => (5, 4, 16),
+            F::ASTC_5X5_UNORM_BLOCK | F::ASTC_5X5_SRGB_BLOCK => (5, 5, 16),
+            F::ASTC_6X5_UNORM_BLOCK | F::ASTC_6X5_SRGB_BLOCK => (6, 5, 16),
+            F::ASTC_6X6_UNORM_BLOCK | F::ASTC_6X6_SRGB_BLOCK => (6, 6, 16),
+            F::ASTC_8X5_UNORM_BLOCK | F::ASTC_8X5_SRGB_BLOCK => (8, 5, 16),
+            F::ASTC_8X6_UNORM_BLOCK | F::ASTC_8X6_SRGB_BLOCK => (8, 6, 16),
+            F::ASTC_8X8_UNORM_BLOCK | F::ASTC_8X8_SRGB_BLOCK => (8, 8, 16),
+            F::ASTC_10X5_UNORM_BLOCK | F::ASTC_10X5_SRGB_BLOCK => (10, 5, 16),
+            F::ASTC_10X6_UNORM_BLOCK | F::ASTC_10X6_SRGB_BLOCK => (10, 6, 16),
+            F::ASTC_10X8_UNORM_BLOCK | F::ASTC_10X8_SRGB_BLOCK => (10, 8, 16),
+            F::ASTC_10X10_UNORM_BLOCK | F::ASTC_10X10_SRGB_BLOCK => (10, 10, 16),
+            F::ASTC_12X12_UNORM_BLOCK | F::ASTC_12X12_SRGB_BLOCK => (12, 12, 16),
+            _ => return None,
+        };
+
+        Some(size)
+    }
+
+    /// Byte size of a `width` x `height` level
+    pub fn level_size(format: vk::Format, width: u32, height: u32) -> Option<usize> {
+        let (block_width, block_height, block_bytes) = Self::block_size(format)?;
+        let blocks_x = width.div_ceil(block_width).max(1) as usize;
+        let blocks_y = height.div_ceil(block_height).max(1) as usize;
+
+        Some(blocks_x * blocks_y * block_bytes)
+    }
+
+    /// The sRGB or UNORM variant of `format`, unchanged if it only comes in one of them
+    pub fn with_srgb(format: vk::Format, srgb: bool) -> vk::Format {
+        FORMAT_PAIRS
+            .iter()
+            .find(|&&(unorm, srgb_format)| format == unorm || format == srgb_format)
+            .map_or(format, |&(unorm, srgb_format)| if srgb { srgb_format } else { unorm })
+    }
+
+    pub fn is_srgb(format: vk::Format) -> bool {
+        FORMAT_PAIRS.iter().any(|&(unorm, srgb)| format == srgb && srgb != unorm)
+    }
+
+    fn parse_ktx2(bytes: &[u8]) -> LveResult<Self> {
+        let truncated = || LveError::Unsupported("KTX2 file is truncated");
+        let u32_at = |offset: usize| read_u32(bytes, offset).ok_or_else(truncated);
+        let u64_at = |offset: usize| read_u64(bytes, offset).ok_or_else(truncated);
+
+        let format = vk::Format::from_raw(u32_at(12)? as i32);
+        let width = u32_at(20)?;
+        let height = u32_at(24)?.max(1);
+        let level_count = u32_at(40)?.max(1);
+        let supercompression = u32_at(44)?;
+
+        if format == vk::Format::UNDEFINED || supercompression != 0 {
+            return Err(LveError::Unsupported("Supercompressed KTX2 (Basis Universal, zstd) isn't supported"));
+        }
+
+        if u32_at(28)? > 1 {
+            return Err(LveError::Unsupported("3D KTX2 textures aren't supported"));
+        }
+
+        // The level index follows the 80 byte header
+        let levels = (0..level_count)
+            .map(|level| {
+                let entry = 80 + level as usize * 24;
+                let offset = u64_at(entry)? as usize;
+                let (level_width, level_height) = mip_extent(width, height, level);
+                let size = Self::level_size(format, level_width, level_height)
+                    .ok_or(LveError::Unsupported("KTX2 texture format isn't supported"))?;
+
+                // Layers and faces follow each other within a level, the first one comes first
+                bytes.get(offset..offset + size).map(<[u8]>::to_vec).ok_or_else(truncated)
+            })
+            .collect::<LveResult<Vec<_>>>()?;
+
+        Ok(Self { format, width, height, levels })
+    }
+
+    fn parse_dds(bytes: &[u8]) -> LveResult<Self> {
+        const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+        const DDPF_FOURCC: u32 = 0x4;
+        const DDPF_RGB: u32 = 0x40;
+
+        let truncated = || LveError::Unsupported("DDS file is truncated");
+        let u32_at = |offset: usize| read_u32(bytes, offset).ok_or_else(truncated);
+
+        let flags = u32_at(8)?;
+        let height = u32_at(12)?;
+        let width = u32_at(16)?;
+        let mip_count = u32_at(28)?;
+        let pixel_flags = u32_at(80)?;
+        let four_cc = bytes.get(84..88).ok_or_else(truncated)?;
+
+        let mut data_offset = 128;
+        let unsupported = LveError::Unsupported("DDS pixel format isn't supported");
+
+        let format = if pixel_flags & DDPF_FOURCC != 0 {
+            match four_cc {
+                b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+                b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+                b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+                b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+                b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+                b"DX10" => {
+                    data_offset += 20;
+                    if u32_at(128 + 12)? > 1 {
+                        log::warn!("Only the first layer of a DDS texture array is loaded");
+                    }
+                    Self::dxgi_format(u32_at(128)?).ok_or(unsupported)?
+                }
+                _ => return Err(unsupported),
+            }
+        } else if pixel_flags & DDPF_RGB != 0 && u32_at(88)? == 32 {
+            // Uncompressed, the red mask tells RGBA from BGRA
+            match u32_at(92)? {
+                0x0000_00FF => vk::Format::R8G8B8A8_UNORM,
+                0x00FF_0000 => vk::Format::B8G8R8A8_UNORM,
+                _ => return Err(unsupported),
+            }
+        } else {
+            return Err(unsupported);
+        };
+
+        let level_count = if flags & DDSD_MIPMAPCOUNT != 0 { mip_count.max(1) } else { 1 };
+
+        let mut offset = data_offset;
+        let levels = (0..level_count)
+            .map(|level| {
+                let (level_width, level_height) = mip_extent(width, height, level);
+                let size = Self::level_size(format, level_width, level_height).unwrap();
+                let data = bytes.get(offset..offset + size).map(<[u8]>::to_vec).ok_or_else(truncated)?;
+                offset += size;
+
+                Ok(data)
+            })
+            .collect::<LveResult<Vec<_>>>()?;
+
+        Ok(Self { format, width, height: height.max(1), levels })
+    }
+
+    fn dxgi_format(dxgi: u32) -> Option<vk::Format> {
+        let format = match dxgi {
+            28 => vk::Format::R8G8B8A8_UNORM,
+            29 => vk::Format::R8G8B8A8_SRGB,
+            87 => vk::Format::B8G8R8A8_UNORM,
+            91 => vk::Format::B8G8R8A8_SRGB,
+            70 | 71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+            73 | 74 => vk::Format::BC2_UNORM_BLOCK,
+            75 => vk::Format::BC2_SRGB_BLOCK,
+            76 | 77 => vk::Format::BC3_UNORM_BLOCK,
+            78 => vk::Format::BC3_SRGB_BLOCK,
+            79 | 80 => vk::Format::BC4_UNORM_BLOCK,
+            82 | 83 => vk::Format::BC5_UNORM_BLOCK,
+            94 | 95 => vk::Format::BC6H_UFLOAT_BLOCK,
+            96 => vk::Format::BC6H_SFLOAT_BLOCK,
+            97 | 98 => vk::Format::BC7_UNORM_BLOCK,
+            99 => vk::Format::BC7_SRGB_BLOCK,
+            _ => return None,
+        };
+
+        Some(format)
+    }
+}
+
+/// Size of mip `level` of a `width` x `height` texture
+pub fn mip_extent(width: u32, height: u32, level: u32) -> (u32, u32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dds_header(width: u32, height: u32, mip_count: u32, four_cc: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 128];
+        bytes[0..4].copy_from_slice(b"DDS ");
+        bytes[4..8].copy_from_slice(&124u32.to_le_bytes());
+        bytes[8..12].copy_from_slice(&0x20000u32.to_le_bytes());
+        bytes[12..16].copy_from_slice(&height.to_le_bytes());
+        bytes[16..20].copy_from_slice(&width.to_le_bytes());
+        bytes[28..32].copy_from_slice(&mip_count.to_le_bytes());
+        bytes[80..84].copy_from_slice(&0x4u32.to_le_bytes());
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes
+    }
+
+    #[test]
+    fn dds_mip_chain_is_split_into_levels() {
+        let mut bytes = dds_header(8, 4, 4, b"DXT5");
+        // 8x4 is 2 blocks, 4x2, 2x1 and 1x1 are one block each
+        for (level, blocks) in [2, 1, 1, 1].iter().enumerate() {
+            bytes.extend(std::iter::repeat_n(level as u8, blocks * 16));
+        }
+
+        let texture = LveTextureFile::parse_dds(&bytes).unwrap();
+
+        assert_eq!(texture.format, vk::Format::BC3_UNORM_BLOCK);
+        assert_eq!((texture.width, texture.height), (8, 4));
+        assert_eq!(texture.levels.iter().map(Vec::len).collect::<Vec<_>>(), vec![32, 16, 16, 16]);
+        assert!(texture.levels[3].iter().all(|&byte| byte == 3));
+    }
+
+    #[test]
+    fn truncated_dds_is_an_error() {
+        let mut bytes = dds_header(8, 8, 1, b"DXT1");
+        bytes.extend([0u8; 16]);
+
+        assert!(LveTextureFile::parse_dds(&bytes).is_err());
+    }
+
+    #[test]
+    fn ktx2_levels_are_read_from_the_index() {
+        let mut bytes = vec![0u8; 80 + 2 * 24];
+        bytes[0..12].copy_from_slice(&KTX2_IDENTIFIER);
+        bytes[12..16].copy_from_slice(&(vk::Format::BC7_SRGB_BLOCK.as_raw() as u32).to_le_bytes());
+        bytes[20..24].copy_from_slice(&8u32.to_le_bytes());
+        bytes[24..28].copy_from_slice(&8u32.to_le_bytes());
+        bytes[40..44].copy_from_slice(&2u32.to_le_bytes());
+
+        // KTX2 stores the smallest level first
+        let level_1 = bytes.len();
+        bytes.extend([1u8; 16]);
+        let level_0 = bytes.len();
+        bytes.extend([0u8; 64]);
+
+        bytes[80..88].copy_from_slice(&(level_0 as u64).to_le_bytes());
+        bytes[104..112].copy_from_slice(&(level_1 as u64).to_le_bytes());
+
+        let texture = LveTextureFile::parse_ktx2(&bytes).unwrap();
+
+        assert_eq!(texture.format, vk::Format::BC7_SRGB_BLOCK);
+        assert_eq!(texture.levels, vec![vec![0u8; 64], vec![1u8; 16]]);
+    }
+
+    #[test]
+    fn srgb_variants() {
+        assert_eq!(LveTextureFile::with_srgb(vk::Format::BC7_UNORM_BLOCK, true), vk::Format::BC7_SRGB_BLOCK);
+        assert_eq!(LveTextureFile::with_srgb(vk::Format::BC3_SRGB_BLOCK, false), vk::Format::BC3_UNORM_BLOCK);
+        assert_eq!(LveTextureFile::with_srgb(vk::Format::BC5_UNORM_BLOCK, true), vk::Format::BC5_UNORM_BLOCK);
+        assert!(!LveTextureFile::is_srgb(vk::Format::BC5_UNORM_BLOCK));
+    }
+}
@@ -0,0 +1,716 @@
+use super::lve_allocator::*;
+use super::lve_error::*;
+
+use ash::{vk, Device};
+
+use std::{cell::RefCell, collections::VecDeque};
+
+/// Size of the persistently mapped staging ring, bigger uploads get a staging buffer of their own
+const RING_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Keeps every staged copy aligned for any texel size and `optimalBufferCopyOffsetAlignment`
+const STAGING_ALIGNMENT: vk::DeviceSize = 256;
+
+/// Everything a buffer upload can be read by afterwards
+const BUFFER_READ_STAGES: vk::PipelineStageFlags = vk::PipelineStageFlags::from_raw(
+    vk::PipelineStageFlags::VERTEX_INPUT.as_raw()
+        | vk::PipelineStageFlags::VERTEX_SHADER.as_raw()
+        | vk::PipelineStageFlags::FRAGMENT_SHADER.as_raw(),
+);
+const BUFFER_READ_ACCESS: vk::AccessFlags = vk::AccessFlags::from_raw(
+    vk::AccessFlags::VERTEX_ATTRIBUTE_READ.as_raw()
+        | vk::AccessFlags::INDEX_READ.as_raw()
+        | vk::AccessFlags::UNIFORM_READ.as_raw()
+        | vk::AccessFlags::SHADER_READ.as_raw(),
+);
+
+/// What an uploaded image is left as, `Mipmaps` blits levels 1.. from level 0 on the graphics queue
+#[derive(Clone, Copy)]
+pub enum ImageUploadFinish {
+    ShaderRead,
+    Mipmaps { width: u32, height: u32 },
+}
+
+/// Queue the copies are recorded for. With a dedicated transfer family the copies run there and
+/// the graphics queue takes ownership afterwards, otherwise everything goes to the graphics queue.
+#[derive(Clone, Copy)]
+pub struct UploadQueues {
+    pub graphics_family: u32,
+    pub graphics_queue: vk::Queue,
+    pub transfer: Option<(u32, vk::Queue)>,
+}
+
+/// Copies recorded since the last submit, plus what they need until the GPU is done with them
+struct UploadBatch {
+    transfer_command_buffer: vk::CommandBuffer,
+    /// Same as `transfer_command_buffer` without a dedicated transfer queue
+    graphics_command_buffer: vk::CommandBuffer,
+    /// Signalled by the transfer submit, waited on by the graphics one
+    semaphore: vk::Semaphore,
+    fence: vk::Fence,
+    /// Ring position of the first byte staged for this batch. Positions only ever grow,
+    /// `% RING_SIZE` gives the offset into the buffer.
+    ring_start: vk::DeviceSize,
+    dedicated_staging: Vec<(vk::Buffer, LveAllocation)>,
+}
+
+struct UploadState {
+    /// Next free ring position
+    head: vk::DeviceSize,
+    recording: Option<UploadBatch>,
+    in_flight: VecDeque<UploadBatch>,
+}
+
+/// Batches buffer and image uploads so hundreds of meshes and textures cost a couple of submits
+/// instead of one queue wait each. Nothing waits for an upload to finish, barriers at the end of
+/// each batch make later submits on the graphics queue see the data.
+pub struct LveUploadContext {
+    device: Device,
+    queues: UploadQueues,
+    graphics_pool: vk::CommandPool,
+    /// Only with a dedicated transfer family
+    transfer_pool: Option<vk::CommandPool>,
+    ring_buffer: vk::Buffer,
+    ring_allocation: LveAllocation,
+    state: RefCell<UploadState>,
+}
+
+impl LveUploadContext {
+    pub fn new(device: Device, allocator: &LveAllocator, queues: UploadQueues) -> LveResult<Self> {
+        let create_pool = |family| {
+            let create_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(family)
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+
+            unsafe {
+                device
+                    .create_command_pool(&create_info, None)
+                    .map_err(LveError::vulkan("Unable to create upload command pool"))
+            }
+        };
+
+        let graphics_pool = create_pool(queues.graphics_family)?;
+        let transfer_pool = match queues.transfer {
+            Some((family, _)) => Some(create_pool(family)?),
+            None => None,
+        };
+
+        let (ring_buffer, ring_allocation) = Self::create_staging_buffer(&device, allocator, RING_SIZE)?;
+
+        if queues.transfer.is_some() {
+            log::debug!("Uploading through a dedicated transfer queue");
+        }
+
+        Ok(Self {
+            device,
+            queues,
+            graphics_pool,
+            transfer_pool,
+            ring_buffer,
+            ring_allocation,
+            state: RefCell::new(UploadState {
+                head: 0,
+                recording: None,
+                in_flight: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Records a copy of `data` into `buffer`, which needs `TRANSFER_DST` usage
+    pub fn upload_to_buffer(&self, allocator: &LveAllocator, buffer: vk::Buffer, data: &[u8]) -> LveResult<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let (staging_buffer, staging_offset) = self.stage(allocator, data)?;
+        let state = self.state.borrow();
+        let batch = state.recording.as_ref().unwrap();
+
+        let region = vk::BufferCopy {
+            src_offset: staging_offset,
+            dst_offset: 0,
+            size: data.len() as vk::DeviceSize,
+        };
+
+        let (release, acquire) = self.ownership_families();
+        let barrier = |src_access_mask, dst_access_mask| {
+            vk::BufferMemoryBarrier::builder()
+                .buffer(buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE)
+                .src_queue_family_index(release)
+                .dst_queue_family_index(acquire)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .build()
+        };
+
+        unsafe {
+            self.device.cmd_copy_buffer(batch.transfer_command_buffer, staging_buffer, buffer, &[region]);
+
+            if self.queues.transfer.is_some() {
+                // Release on the transfer queue, the matching acquire makes the data visible on the graphics queue
+                self.device.cmd_pipeline_barrier(
+                    batch.transfer_command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty())],
+                    &[],
+                );
+                self.device.cmd_pipeline_barrier(
+                    batch.graphics_command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    BUFFER_READ_STAGES,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[barrier(vk::AccessFlags::empty(), BUFFER_READ_ACCESS)],
+                    &[],
+                );
+            } else {
+                self.device.cmd_pipeline_barrier(
+                    batch.graphics_command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    BUFFER_READ_STAGES,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[barrier(vk::AccessFlags::TRANSFER_WRITE, BUFFER_READ_ACCESS)],
+                    &[],
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records copies of `data` into a fresh `image` and leaves every level `SHADER_READ_ONLY_OPTIMAL`.
+    /// `regions` have their buffer offsets relative to the start of `data`.
+    pub fn upload_to_image(
+        &self,
+        allocator: &LveAllocator,
+        image: vk::Image,
+        mip_levels: u32,
+        data: &[u8],
+        regions: &[vk::BufferImageCopy],
+        finish: ImageUploadFinish,
+    ) -> LveResult<()> {
+        let (staging_buffer, staging_offset) = self.stage(allocator, data)?;
+        let state = self.state.borrow();
+        let batch = state.recording.as_ref().unwrap();
+
+        let regions = regions
+            .iter()
+            .map(|region| vk::BufferImageCopy {
+                buffer_offset: region.buffer_offset + staging_offset,
+                ..*region
+            })
+            .collect::<Vec<_>>();
+
+        let all_levels = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: mip_levels,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let barrier = |old_layout, new_layout, src_access_mask, dst_access_mask, src_family, dst_family| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .subresource_range(all_levels)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .build()
+        };
+
+        // Layout the graphics queue gets the image in
+        let (final_layout, final_stage, final_access) = match finish {
+            ImageUploadFinish::ShaderRead => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            // The blits start with their own barriers
+            ImageUploadFinish::Mipmaps { .. } => (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        };
+
+        let (release, acquire) = self.ownership_families();
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                batch.transfer_command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::QUEUE_FAMILY_IGNORED,
+                    vk::QUEUE_FAMILY_IGNORED,
+                )],
+            );
+
+            self.device.cmd_copy_buffer_to_image(
+                batch.transfer_command_buffer,
+                staging_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &regions,
+            );
+
+            if self.queues.transfer.is_some() {
+                // Release and acquire have to agree on the layout transition, it happens once between them
+                let transition = |src_access_mask, dst_access_mask| barrier(
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    final_layout,
+                    src_access_mask,
+                    dst_access_mask,
+                    release,
+                    acquire,
+                );
+
+                self.device.cmd_pipeline_barrier(
+                    batch.transfer_command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[transition(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty())],
+                );
+                self.device.cmd_pipeline_barrier(
+                    batch.graphics_command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    final_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[transition(vk::AccessFlags::empty(), final_access)],
+                );
+            } else if let ImageUploadFinish::ShaderRead = finish {
+                self.device.cmd_pipeline_barrier(
+                    batch.graphics_command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    final_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier(
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        final_layout,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        final_access,
+                        vk::QUEUE_FAMILY_IGNORED,
+                        vk::QUEUE_FAMILY_IGNORED,
+                    )],
+                );
+            }
+
+            if let ImageUploadFinish::Mipmaps { width, height } = finish {
+                self.record_mipmaps(batch.graphics_command_buffer, image, width, height, mip_levels);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Submits everything recorded so far, returns without waiting for the GPU
+    pub fn flush(&self, allocator: &LveAllocator) -> LveResult<()> {
+        self.retire_finished(allocator)?;
+
+        let batch = match self.state.borrow_mut().recording.take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+
+        unsafe {
+            self.device
+                .end_command_buffer(batch.transfer_command_buffer)
+                .map_err(LveError::vulkan("Unable to end upload command buffer"))?;
+
+            if let Some((_, transfer_queue)) = self.queues.transfer {
+                self.device
+                    .end_command_buffer(batch.graphics_command_buffer)
+                    .map_err(LveError::vulkan("Unable to end upload command buffer"))?;
+
+                let transfer_submit = vk::SubmitInfo::builder()
+                    .command_buffers(std::slice::from_ref(&batch.transfer_command_buffer))
+                    .signal_semaphores(std::slice::from_ref(&batch.semaphore))
+                    .build();
+
+                self.device
+                    .queue_submit(transfer_queue, &[transfer_submit], vk::Fence::null())
+                    .map_err(LveError::vulkan("Unable to submit uploads"))?;
+
+                let wait_stage = vk::PipelineStageFlags::ALL_COMMANDS;
+                let graphics_submit = vk::SubmitInfo::builder()
+                    .wait_semaphores(std::slice::from_ref(&batch.semaphore))
+                    .wait_dst_stage_mask(std::slice::from_ref(&wait_stage))
+                    .command_buffers(std::slice::from_ref(&batch.graphics_command_buffer))
+                    .build();
+
+                self.device
+                    .queue_submit(self.queues.graphics_queue, &[graphics_submit], batch.fence)
+                    .map_err(LveError::vulkan("Unable to submit uploads"))?;
+            } else {
+                let submit = vk::SubmitInfo::builder()
+                    .command_buffers(std::slice::from_ref(&batch.transfer_command_buffer))
+                    .build();
+
+                self.device
+                    .queue_submit(self.queues.graphics_queue, &[submit], batch.fence)
+                    .map_err(LveError::vulkan("Unable to submit uploads"))?;
+            }
+        }
+
+        self.state.borrow_mut().in_flight.push_back(batch);
+
+        Ok(())
+    }
+
+    /// Waits for every upload and frees everything, call before the allocator is torn down
+    pub fn destroy(&mut self, allocator: &LveAllocator) {
+        let mut state = self.state.borrow_mut();
+        let batches = state.recording.take().into_iter().chain(state.in_flight.drain(..)).collect::<Vec<_>>();
+        drop(state);
+
+        unsafe {
+            let _ = self.device.device_wait_idle();
+        }
+
+        for batch in batches {
+            self.free_batch(allocator, batch);
+        }
+
+        unsafe {
+            self.device.destroy_command_pool(self.graphics_pool, None);
+            if let Some(pool) = self.transfer_pool {
+                self.device.destroy_command_pool(pool, None);
+            }
+            self.device.destroy_buffer(self.ring_buffer, None);
+        }
+
+        allocator.free(std::mem::take(&mut self.ring_allocation));
+    }
+
+    /// Copies `data` into staging memory of the recording batch, starting one if needed
+    fn stage(&self, allocator: &LveAllocator, data: &[u8]) -> LveResult<(vk::Buffer, vk::DeviceSize)> {
+        let size = data.len() as vk::DeviceSize;
+
+        if size > RING_SIZE {
+            let (buffer, allocation) = Self::create_staging_buffer(&self.device, allocator, size)?;
+            unsafe {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), allocation.mapped_ptr().unwrap() as *mut u8, data.len());
+            }
+
+            self.begin_batch()?;
+            self.state.borrow_mut().recording.as_mut().unwrap().dedicated_staging.push((buffer, allocation));
+
+            return Ok((buffer, 0));
+        }
+
+        let position = self.reserve(allocator, size)?;
+        let offset = position % RING_SIZE;
+
+        unsafe {
+            let ring = self.ring_allocation.mapped_ptr().unwrap() as *mut u8;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ring.add(offset as usize), data.len());
+        }
+
+        Ok((self.ring_buffer, offset))
+    }
+
+    /// Finds `size` contiguous ring bytes no batch still reads from, waiting for the GPU if the ring is full
+    fn reserve(&self, allocator: &LveAllocator, size: vk::DeviceSize) -> LveResult<vk::DeviceSize> {
+        loop {
+            self.begin_batch()?;
+
+            let mut state = self.state.borrow_mut();
+            let mut position = align_up(state.head, STAGING_ALIGNMENT);
+            // Staged data can't wrap around the end of the ring
+            if position % RING_SIZE + size > RING_SIZE {
+                position = align_up(position, RING_SIZE);
+            }
+
+            let tail = state.in_flight.front()
+                .or(state.recording.as_ref())
+                .map_or(position, |batch| batch.ring_start);
+
+            if position + size - tail <= RING_SIZE {
+                state.head = position + size;
+                return Ok(position);
+            }
+
+            // The oldest staged data is still needed, wait for whatever batch owns it
+            if state.in_flight.is_empty() {
+                drop(state);
+                self.flush(allocator)?;
+            } else {
+                let batch = state.in_flight.pop_front().unwrap();
+                drop(state);
+                unsafe {
+                    self.device
+                        .wait_for_fences(&[batch.fence], true, u64::MAX)
+                        .map_err(LveError::vulkan("Unable to wait for uploads"))?;
+                }
+                self.free_batch(allocator, batch);
+            }
+        }
+    }
+
+    fn begin_batch(&self) -> LveResult<()> {
+        if self.state.borrow().recording.is_some() {
+            return Ok(());
+        }
+
+        let allocate = |pool| -> LveResult<vk::CommandBuffer> {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(pool)
+                .command_buffer_count(1);
+
+            unsafe {
+                let command_buffer = self.device
+                    .allocate_command_buffers(&alloc_info)
+                    .map_err(LveError::vulkan("Unable to allocate upload command buffer"))?[0];
+
+                let begin_info = vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+                self.device
+                    .begin_command_buffer(command_buffer, &begin_info)
+                    .map_err(LveError::vulkan("Unable to begin upload command buffer"))?;
+
+                Ok(command_buffer)
+            }
+        };
+
+        let graphics_command_buffer = allocate(self.graphics_pool)?;
+        let (transfer_command_buffer, semaphore) = match self.transfer_pool {
+            Some(pool) => {
+                let semaphore = unsafe {
+                    self.device
+                        .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                        .map_err(LveError::vulkan("Unable to create upload semaphore"))?
+                };
+                (allocate(pool)?, semaphore)
+            }
+            None => (graphics_command_buffer, vk::Semaphore::null()),
+        };
+
+        let fence = unsafe {
+            self.device
+                .create_fence(&vk::FenceCreateInfo::default(), None)
+                .map_err(LveError::vulkan("Unable to create upload fence"))?
+        };
+
+        let mut state = self.state.borrow_mut();
+        let head = state.head;
+        state.recording = Some(UploadBatch {
+            transfer_command_buffer,
+            graphics_command_buffer,
+            semaphore,
+            fence,
+            ring_start: head,
+            dedicated_staging: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    /// Frees batches the GPU is done with, without waiting for the others
+    fn retire_finished(&self, allocator: &LveAllocator) -> LveResult<()> {
+        loop {
+            let mut state = self.state.borrow_mut();
+            let finished = match state.in_flight.front() {
+                Some(batch) => unsafe {
+                    self.device
+                        .get_fence_status(batch.fence)
+                        .map_err(LveError::vulkan("Unable to query upload fence"))?
+                },
+                None => false,
+            };
+
+            if !finished {
+                return Ok(());
+            }
+
+            let batch = state.in_flight.pop_front().unwrap();
+            drop(state);
+            self.free_batch(allocator, batch);
+        }
+    }
+
+    /// `(release, acquire)` queue families for ownership transfer barriers
+    fn ownership_families(&self) -> (u32, u32) {
+        match self.queues.transfer {
+            Some((family, _)) => (family, self.queues.graphics_family),
+            None => (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+        }
+    }
+
+    fn free_batch(&self, allocator: &LveAllocator, mut batch: UploadBatch) {
+        for (buffer, allocation) in batch.dedicated_staging.drain(..) {
+            unsafe { self.device.destroy_buffer(buffer, None) };
+            allocator.free(allocation);
+        }
+
+        unsafe {
+            self.device.free_command_buffers(self.graphics_pool, &[batch.graphics_command_buffer]);
+            if let Some(pool) = self.transfer_pool {
+                self.device.free_command_buffers(pool, &[batch.transfer_command_buffer]);
+                self.device.destroy_semaphore(batch.semaphore, None);
+            }
+            self.device.destroy_fence(batch.fence, None);
+        }
+    }
+
+    fn create_staging_buffer(device: &Device, allocator: &LveAllocator, size: vk::DeviceSize) -> LveResult<(vk::Buffer, LveAllocation)> {
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let buffer = unsafe {
+            device
+                .create_buffer(&create_info, None)
+                .map_err(LveError::vulkan("Unable to create staging buffer"))?
+        };
+
+        let allocation = allocator.allocate_buffer(
+            buffer,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok((buffer, allocation))
+    }
+
+    /// Fills mip levels 1.. by blitting each level from the one above it. Expects every level in
+    /// `TRANSFER_DST_OPTIMAL` and leaves them all in `SHADER_READ_ONLY_OPTIMAL`.
+    unsafe fn record_mipmaps(&self, command_buffer: vk::CommandBuffer, image: vk::Image, width: u32, height: u32, mip_levels: u32) {
+        let barrier = |level: u32, old_layout, new_layout, src_access_mask, dst_access_mask| {
+            vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: level,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .build()
+        };
+
+        let mut mip_width = width as i32;
+        let mut mip_height = height as i32;
+
+        for level in 1..mip_levels {
+            let to_transfer_src = barrier(
+                level - 1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            );
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer_src],
+            );
+
+            let next_width = (mip_width / 2).max(1);
+            let next_height = (mip_height / 2).max(1);
+
+            let blit = vk::ImageBlit::builder()
+                .src_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: mip_width, y: mip_height, z: 1 }])
+                .src_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level - 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .dst_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, vk::Offset3D { x: next_width, y: next_height, z: 1 }])
+                .dst_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .build();
+
+            self.device.cmd_blit_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+
+            let to_shader_read = barrier(
+                level - 1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::SHADER_READ,
+            );
+
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_shader_read],
+            );
+
+            mip_width = next_width;
+            mip_height = next_height;
+        }
+
+        // The last level was only ever written to
+        let last_to_shader_read = barrier(
+            mip_levels - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        );
+
+        self.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[last_to_shader_read],
+        );
+    }
+}
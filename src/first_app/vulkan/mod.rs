@@ -8,4 +8,13 @@ pub mod lve_renderer;
 pub mod lve_swapchain;
 pub mod lve_buffer;
 pub mod lve_descriptor_set;
-pub mod lve_image;
\ No newline at end of file
+pub mod lve_image;
+pub mod lve_offscreen_renderer;
+pub mod lve_readback;
+pub mod lve_error;
+pub mod lve_allocator;
+pub mod lve_upload;
+pub mod lve_texture_file;
+pub mod lve_block_decode;
+pub mod lve_block_encode;
+pub mod lve_sampler;
\ No newline at end of file
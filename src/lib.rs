@@ -0,0 +1 @@
+pub mod first_app;
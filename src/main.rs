@@ -1,6 +1,4 @@
-mod first_app;
-
-use first_app::*;
+use little_vulkan_engine::first_app::*;
 
 use winit::{
     dpi::{PhysicalSize},
@@ -13,7 +11,13 @@ use std::time::Instant;
 fn main() {
     env_logger::init();
 
-    let ( mut vulkan_app, event_loop) = VulkanApp::new();
+    let (mut vulkan_app, event_loop) = match VulkanApp::new() {
+        Ok(app) => app,
+        Err(e) => {
+            log::error!("Unable to start: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     log::debug!("Running Application");
 